@@ -0,0 +1,77 @@
+use bytes::{BufMut, Bytes};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis_starter_rust::client::{write_resp, RedisClientConnection, ResponseType};
+
+fn command_frame(parts: &[&[u8]]) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n", parts.len()).into_bytes();
+    for part in parts {
+        frame.extend_from_slice(format!("${}\r\n", part.len()).as_bytes());
+        frame.extend_from_slice(part);
+        frame.extend_from_slice(b"\r\n");
+    }
+    frame
+}
+
+fn pipeline(depth: usize) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for i in 0..depth {
+        let key = format!("key:{}", i);
+        if i % 2 == 0 {
+            buffer.extend(command_frame(&[b"SET", key.as_bytes(), b"some moderately sized value"]));
+        } else {
+            buffer.extend(command_frame(&[b"GET", key.as_bytes()]));
+        }
+    }
+    buffer
+}
+
+/// Splits frames off the buffer the way a connection does, so arguments are slices of it
+fn parse_all(buffer: &Bytes) -> usize {
+    let mut buffer = buffer.clone();
+    let mut frames = 0;
+    while let Some(length) = RedisClientConnection::frame_length(&buffer).unwrap() {
+        let frame = buffer.split_to(length);
+        black_box(RedisClientConnection::parse_frame(&frame).unwrap());
+        frames += 1;
+    }
+    frames
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_pipeline");
+    for depth in [1, 16, 128] {
+        let buffer = Bytes::from(pipeline(depth));
+        group.throughput(Throughput::Bytes(buffer.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &buffer, |b, buffer| {
+            b.iter(|| assert_eq!(parse_all(buffer), depth));
+        });
+    }
+    group.finish();
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let mut group = c.benchmark_group("serialize_reply");
+
+    let replies = [
+        ("simple_string", ResponseType::SimpleString("OK".to_string())),
+        ("bulk_string_1k", ResponseType::BulkString(vec![b'x'; 1024].into())),
+        ("array_100", ResponseType::Array(
+            (0..100).map(|i| ResponseType::BulkString(format!("key:{}", i).into_bytes().into())).collect()
+        )),
+    ];
+
+    for (name, reply) in replies.iter() {
+        group.bench_function(*name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                let mut buffer = Vec::with_capacity(2048).writer();
+                write_resp(&mut buffer, reply).await.unwrap();
+                black_box(buffer.into_inner())
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_serialize);
+criterion_main!(benches);
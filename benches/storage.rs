@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::SystemTime;
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use redis_starter_rust::database::{db_get, db_load, db_set};
+use redis_starter_rust::dict::KeyHasher;
+use redis_starter_rust::persistence::{DataType, RdbData, RdbWriter, RDB_VERSION};
+
+const KEYS: usize = 10_000;
+
+fn multi_thread_runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap()
+}
+
+fn populate(runtime: &Runtime) {
+    runtime.block_on(async {
+        for i in 0..KEYS {
+            db_set(0, Bytes::from(format!("key:{}", i)), Bytes::from(format!("value:{}", i)), None).await.unwrap();
+        }
+    });
+}
+
+fn bench_get_set(c: &mut Criterion) {
+    let runtime = multi_thread_runtime();
+    populate(&runtime);
+
+    let mut group = c.benchmark_group("keyspace");
+    group.bench_function("get", |b| {
+        let key = Bytes::from_static(b"key:42");
+        b.to_async(&runtime).iter(|| async { black_box(db_get(0, &key).await.unwrap()) });
+    });
+    group.bench_function("set", |b| {
+        b.to_async(&runtime).iter(|| async {
+            db_set(0, Bytes::from_static(b"key:42"), Bytes::from_static(b"updated"), None).await.unwrap()
+        });
+    });
+
+    let large = Bytes::from(vec![b'x'; 1024 * 1024]);
+    runtime.block_on(db_set(0, Bytes::from_static(b"large"), large, None)).unwrap();
+    group.bench_function("get_1mb", |b| {
+        let key = Bytes::from_static(b"large");
+        b.to_async(&runtime).iter(|| async { black_box(db_get(0, &key).await.unwrap()) });
+    });
+    group.finish();
+}
+
+/// Many tasks hammering the keyspace at once, a quarter of them writing
+fn bench_contention(c: &mut Criterion) {
+    const OPS_PER_TASK: usize = 100;
+
+    let runtime = multi_thread_runtime();
+    populate(&runtime);
+
+    let mut group = c.benchmark_group("keyspace_contention");
+    for tasks in [4, 16, 64] {
+        group.throughput(Throughput::Elements((tasks * OPS_PER_TASK) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+            b.to_async(&runtime).iter(|| async move {
+                let handles = (0..tasks).map(|task| tokio::spawn(async move {
+                    for op in 0..OPS_PER_TASK {
+                        let key = Bytes::from(format!("key:{}", (task * OPS_PER_TASK + op) % KEYS));
+                        if task % 4 == 0 {
+                            db_set(0, key, Bytes::from_static(b"contended"), None).await.unwrap();
+                        } else {
+                            black_box(db_get(0, &key).await.unwrap());
+                        }
+                    }
+                })).collect::<Vec<_>>();
+
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Lookups of short keys in a table hashed with SipHash against one hashed with the keyspace's
+/// hasher. The keyspace hashes every key twice per command, once to pick a table of the dict
+/// and once within it.
+fn bench_hashers(c: &mut Criterion) {
+    fn lookups<S: BuildHasher + Default>(c: &mut Criterion, name: &str) {
+        let mut table: HashMap<Bytes, usize, S> = HashMap::default();
+        for i in 0..KEYS {
+            table.insert(Bytes::from(format!("key:{}", i)), i);
+        }
+        let keys = (0..KEYS).step_by(97).map(|i| Bytes::from(format!("key:{}", i))).collect::<Vec<_>>();
+
+        let mut group = c.benchmark_group("hasher");
+        group.throughput(Throughput::Elements(keys.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for key in keys.iter() {
+                    black_box(table.get(key.as_ref()));
+                }
+            });
+        });
+        group.finish();
+    }
+
+    lookups::<RandomState>(c, "siphash");
+    lookups::<KeyHasher>(c, "keyspace");
+}
+
+fn large_rdb(keys: usize) -> Vec<u8> {
+    let mut database = HashMap::with_capacity(keys);
+    let mut expirations = HashMap::new();
+    let expire_at = SystemTime::now() + std::time::Duration::from_secs(3600);
+    for i in 0..keys {
+        let key = Bytes::from(format!("key:{:08}", i));
+        if i % 10 == 0 {
+            expirations.insert(key.clone(), expire_at);
+        }
+        database.insert(key, DataType::String(Bytes::from(format!("value:{}", i))));
+    }
+
+    let data = RdbData {
+        rdb_version: RDB_VERSION,
+        metadata: HashMap::new(),
+        databases: HashMap::from([(0, database)]),
+        expirations: HashMap::from([(0, expirations)]),
+    };
+    RdbWriter::write(&data).unwrap()
+}
+
+fn bench_rdb_load(c: &mut Criterion) {
+    let runtime = multi_thread_runtime();
+    let path = std::env::temp_dir().join(format!("bench-{}.rdb", std::process::id()));
+    let rdb = large_rdb(200_000);
+    std::fs::write(&path, &rdb).unwrap();
+
+    let mut group = c.benchmark_group("rdb");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(rdb.len() as u64));
+    group.bench_function("load_200k_keys", |b| {
+        b.to_async(&runtime).iter(|| db_load(&path));
+    });
+    group.finish();
+
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, bench_get_set, bench_contention, bench_hashers, bench_rdb_load);
+criterion_main!(benches);
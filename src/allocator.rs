@@ -0,0 +1,67 @@
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Byte counts from the allocator, only available with the jemalloc feature since the system
+/// allocator doesn't report them
+pub struct AllocatorStats {
+    /// Bytes handed out to the server
+    pub allocated: usize,
+    /// Bytes in the pages holding those allocations
+    pub active: usize,
+    /// Bytes the allocator holds in physical memory, including its own metadata
+    pub resident: usize,
+}
+
+impl AllocatorStats {
+    /// How much of the active pages is wasted between allocations
+    pub fn fragmentation_ratio(&self) -> f64 {
+        ratio(self.active, self.allocated)
+    }
+}
+
+pub fn allocator_name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc-5.3.0"
+    } else {
+        "libc"
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc caches its statistics until the epoch is advanced
+    epoch::advance().ok()?;
+    Some(AllocatorStats {
+        allocated: stats::allocated::read().ok()?,
+        active: stats::active::read().ok()?,
+        resident: stats::resident::read().ok()?,
+    })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    None
+}
+
+/// Resident set size of the whole process, as the operating system sees it. Assumes 4KiB pages.
+#[cfg(target_os = "linux")]
+pub fn process_rss() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(pages * 4096)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_rss() -> Option<usize> {
+    None
+}
+
+pub fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    numerator as f64 / denominator as f64
+}
@@ -0,0 +1,89 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+use crate::client::{RedisClientConnection, ResponseType};
+
+/// Outcome of scanning an append only file
+pub struct AofCheck {
+    pub size: u64,
+    /// Length of the valid prefix, everything after it would be lost by a fix
+    pub valid_up_to: u64,
+    /// Line the valid prefix stops at, counting from 1
+    pub valid_up_to_line: u64,
+    /// Why the rest of the file can't be used, None when the whole file is valid
+    pub error: Option<String>,
+}
+
+impl AofCheck {
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Checks that `path` holds a sequence of commands, with every MULTI closed by an EXEC, the same
+/// way the server parses them from clients. A file that was cut short while a command or a
+/// transaction was being appended is only valid up to the start of it.
+pub fn check_aof(path: impl AsRef<Path>) -> std::io::Result<AofCheck> {
+    let contents = std::fs::read(path)?;
+
+    let mut offset = 0;
+    let mut line = 0;
+    // Where the open transaction started, it only counts once its EXEC has been read
+    let mut multi: Option<(usize, u64)> = None;
+    let mut error = None;
+    while offset < contents.len() {
+        let frame = match RedisClientConnection::parse_resp(&contents[offset..]) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                error = Some(format!("0x{:x}: Unexpected EOF reading a command", offset));
+                break;
+            }
+            Err(e) => {
+                error = Some(format!("0x{:x}: {}", offset, e));
+                break;
+            }
+        };
+
+        let ResponseType::Array(arguments) = &frame.request else {
+            error = Some(format!("0x{:x}: Expected a command, got {:?}", offset, frame.request));
+            break;
+        };
+        let Some(ResponseType::BulkString(name)) = arguments.first() else {
+            error = Some(format!("0x{:x}: Expected a command name", offset));
+            break;
+        };
+
+        if name.eq_ignore_ascii_case(b"multi") {
+            if multi.is_some() {
+                error = Some(format!("0x{:x}: Unexpected MULTI", offset));
+                break;
+            }
+            multi = Some((offset, line));
+        } else if name.eq_ignore_ascii_case(b"exec") && multi.take().is_none() {
+            error = Some(format!("0x{:x}: Unexpected EXEC", offset));
+            break;
+        }
+
+        line += contents[offset..offset + frame.consumed].iter().filter(|b| **b == b'\n').count() as u64;
+        offset += frame.consumed;
+    }
+
+    if let Some((multi_offset, multi_line)) = multi {
+        error.get_or_insert_with(|| "Reached EOF before reading EXEC for MULTI".to_string());
+        offset = multi_offset;
+        line = multi_line;
+    }
+
+    Ok(AofCheck {
+        size: contents.len() as u64,
+        valid_up_to: offset as u64,
+        valid_up_to_line: line + 1,
+        error,
+    })
+}
+
+/// Cuts `path` down to the valid prefix found by `check`
+pub fn truncate_aof(path: impl AsRef<Path>, check: &AofCheck) -> std::io::Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(check.valid_up_to)?;
+    file.sync_all()
+}
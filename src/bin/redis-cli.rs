@@ -0,0 +1,177 @@
+use std::io::Write;
+use bytes::{BufMut, Bytes};
+use clap::Parser;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use redis_starter_rust::client::{write_resp, RedisClientConnection, ResponseType};
+use redis_starter_rust::util::{random_hex_string, split_arguments};
+
+/// A minimal redis-cli. Runs the command given on the command line, or starts a prompt without
+/// one.
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+struct Args {
+    #[arg(short = 'h', default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(short = 'p', default_value_t = 6379)]
+    port: u16,
+
+    /// Database number
+    #[arg(short = 'n')]
+    db: Option<usize>,
+
+    /// Sends the raw protocol read from stdin and reports how many replies were errors
+    #[arg(long)]
+    pipe: bool,
+
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
+
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+    let stream = TcpStream::connect((args.host.as_str(), args.port)).await?;
+
+    if args.pipe {
+        return pipe(stream).await;
+    }
+
+    let mut connection = RedisClientConnection::new(stream);
+    if let Some(db) = args.db {
+        connection.send_command(&["SELECT", db.to_string().as_str()]).await?;
+        if let ResponseType::Error(e) = connection.read().await? {
+            anyhow::bail!("{}", e);
+        }
+    }
+
+    if !args.command.is_empty() {
+        connection.send_command(&args.command).await?;
+        println!("{}", format_reply(&connection.read().await?, 0));
+        return Ok(());
+    }
+
+    let prompt = match args.db {
+        Some(db) if db != 0 => format!("{}:{}[{}]> ", args.host, args.port, db),
+        _ => format!("{}:{}> ", args.host, args.port),
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("{}", prompt);
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        let Some(command) = split_arguments(&line) else {
+            println!("Invalid argument(s)");
+            continue;
+        };
+        if command.is_empty() {
+            continue;
+        }
+        if command[0].eq_ignore_ascii_case("quit") || command[0].eq_ignore_ascii_case("exit") {
+            return Ok(());
+        }
+
+        connection.send_command(&command).await?;
+        println!("{}", format_reply(&connection.read().await?, 0));
+    }
+}
+
+/// Formats a reply the way redis-cli shows it. `indent` is the width nested array elements are
+/// lined up to.
+fn format_reply(reply: &ResponseType, indent: usize) -> String {
+    match reply {
+        ResponseType::SimpleString(s) => s.clone(),
+        ResponseType::Error(e) => format!("(error) {}", e),
+        ResponseType::Integer(i) => format!("(integer) {}", i),
+        ResponseType::BulkString(bytes) => format!("\"{}\"", escape(bytes)),
+        ResponseType::NullBulkString | ResponseType::NullArray => "(nil)".to_string(),
+        ResponseType::Array(elements) if elements.is_empty() => "(empty array)".to_string(),
+        ResponseType::Array(elements) => {
+            let width = elements.len().to_string().len();
+            let mut lines = Vec::with_capacity(elements.len());
+            for (i, element) in elements.iter().enumerate() {
+                let label = format!("{:>width$}) ", i + 1, width = width);
+                let padding = if i == 0 { String::new() } else { " ".repeat(indent) };
+                lines.push(format!("{}{}{}", padding, label, format_reply(element, indent + label.len())));
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+/// Quotes non printable bytes like redis-cli does
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            0x20..=0x7e => escaped.push(*byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Mass insertion: streams stdin to the server, then an ECHO of a random marker. Every reply up
+/// to the marker's belongs to the piped commands.
+async fn pipe(stream: TcpStream) -> Result<(), anyhow::Error> {
+    let (mut reader, mut writer) = stream.into_split();
+    let marker = random_hex_string(20);
+
+    let mut echo = Vec::new().writer();
+    write_resp(&mut echo, &ResponseType::Array(vec![
+        ResponseType::BulkString(Bytes::from_static(b"ECHO")),
+        ResponseType::BulkString(Bytes::copy_from_slice(marker.as_bytes())),
+    ])).await?;
+
+    let sender = tokio::spawn(async move {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        writer.write_all(echo.get_ref()).await?;
+        eprintln!("All data transferred. Waiting for the last reply...");
+        Ok::<_, std::io::Error>(())
+    });
+
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    let (mut replies, mut errors) = (0, 0);
+    'read: loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            anyhow::bail!("Connection closed before the last reply arrived");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut consumed = 0;
+        while let Some(frame) = RedisClientConnection::parse_resp(&buffer[consumed..])? {
+            consumed += frame.consumed;
+            match frame.request {
+                ResponseType::BulkString(bytes) if bytes == marker.as_bytes() => break 'read,
+                ResponseType::Error(e) => {
+                    errors += 1;
+                    eprintln!("{}", e);
+                }
+                _ => {}
+            }
+            replies += 1;
+        }
+        buffer.drain(..consumed);
+    }
+
+    sender.await??;
+    eprintln!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+
+    Ok(())
+}
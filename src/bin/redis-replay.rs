@@ -0,0 +1,134 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use clap::Parser;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinSet;
+
+use redis_starter_rust::client::{RedisClientConnection, ResponseType};
+use redis_starter_rust::recorder::RecordedCommand;
+
+/// Plays a capture written by the server's --record-file back against a server, with one
+/// connection per recorded client.
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+struct Args {
+    capture_file: String,
+
+    #[arg(short = 'h', default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(short = 'p', default_value_t = 6379)]
+    port: u16,
+
+    /// How many times faster than recorded to replay, 0 sends commands as fast as possible
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
+}
+
+#[derive(Default)]
+struct Totals {
+    commands: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Starts a connection that sends the commands of one recorded client in order, waiting for each
+/// reply before sending the next
+fn replay_connection(
+    stream: TcpStream,
+    totals: Arc<Totals>,
+    connections: &mut JoinSet<Result<(), anyhow::Error>>
+) -> UnboundedSender<RecordedCommand> {
+    let (sender, mut receiver) = unbounded_channel::<RecordedCommand>();
+    connections.spawn(async move {
+        let mut connection = RedisClientConnection::new(stream);
+        let mut db = 0;
+        while let Some(command) = receiver.recv().await {
+            // Keeps the db right when the capture starts part way into a session
+            if command.db != db {
+                connection.send_command(&["SELECT", command.db.to_string().as_str()]).await?;
+                connection.read().await?;
+                db = command.db;
+            }
+
+            let parts = command.command.iter().filter_map(|part| part.bytes()).collect::<Vec<_>>();
+            if parts.first().is_some_and(|name| name.eq_ignore_ascii_case(b"select")) {
+                db = parts.get(1).and_then(|id| std::str::from_utf8(id).ok()?.parse().ok()).unwrap_or(db);
+            }
+            connection.send_command(&parts).await?;
+            if let ResponseType::Error(_) = connection.read().await? {
+                totals.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            totals.commands.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    });
+    sender
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+    if args.speed < 0.0 || !args.speed.is_finite() {
+        anyhow::bail!("--speed has to be 0 or more");
+    }
+
+    let capture = tokio::fs::read(&args.capture_file).await?;
+    let totals = Arc::new(Totals::default());
+    let mut clients: HashMap<u64, UnboundedSender<RecordedCommand>> = HashMap::new();
+    let mut connections = JoinSet::new();
+
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    let mut buffer = capture.as_slice();
+    while let Some(frame) = RedisClientConnection::parse_resp(buffer)? {
+        buffer = &buffer[frame.consumed..];
+        let Some(command) = RecordedCommand::from_frame(frame.request) else {
+            anyhow::bail!("{} is not a capture file", args.capture_file);
+        };
+
+        let first_timestamp = *first_timestamp.get_or_insert(command.timestamp);
+        if args.speed > 0.0 {
+            let offset = Duration::from_micros(command.timestamp.saturating_sub(first_timestamp));
+            tokio::time::sleep_until((start + offset.div_f64(args.speed)).into()).await;
+        }
+
+        let sender = match clients.entry(command.client_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let stream = TcpStream::connect((args.host.as_str(), args.port)).await?;
+                entry.insert(replay_connection(stream, totals.clone(), &mut connections))
+            }
+        };
+        let _ = sender.send(command);
+    }
+
+    if !buffer.is_empty() {
+        eprintln!("Ignoring {} bytes of an incomplete command at the end of the capture", buffer.len());
+    }
+
+    // Closing the channels lets each connection finish once its commands have been answered
+    let client_count = clients.len();
+    clients.clear();
+    while let Some(result) = connections.join_next().await {
+        if let Err(e) = result? {
+            eprintln!("Connection failed: {}", e);
+        }
+    }
+
+    println!(
+        "Replayed {} commands from {} clients in {:.2}s, {} errors",
+        totals.commands.load(Ordering::Relaxed),
+        client_count,
+        start.elapsed().as_secs_f64(),
+        totals.errors.load(Ordering::Relaxed)
+    );
+
+    Ok(())
+}
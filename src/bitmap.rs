@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+/// Whether bit `index` of `bytes` is set, counting from the most significant bit of the first
+/// byte like SETBIT and GETBIT do. Bits past the end read as clear.
+pub fn bit_at(bytes: &[u8], index: usize) -> bool {
+    bytes.get(index / 8).is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+}
+
+/// How many bits in the range of bit indexes `bits` are set
+pub fn count_bits(bytes: &[u8], bits: Range<usize>) -> usize {
+    let mut count = 0;
+    let mut index = bits.start;
+    while index < bits.end {
+        // Whole bytes are counted at once
+        if index & 7 == 0 && index + 8 <= bits.end {
+            count += bytes[index / 8].count_ones() as usize;
+            index += 8;
+        } else {
+            count += bit_at(bytes, index) as usize;
+            index += 1;
+        }
+    }
+    count
+}
+
+/// Index of the first bit in `bits` that is `bit`, if any
+pub fn find_bit(bytes: &[u8], bits: Range<usize>, bit: bool) -> Option<usize> {
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut index = bits.start;
+    while index < bits.end {
+        // Whole bytes without a match are skipped at once
+        if index & 7 == 0 && index + 8 <= bits.end && bytes[index / 8] == skip {
+            index += 8;
+            continue;
+        }
+        if bit_at(bytes, index) == bit {
+            return Some(index);
+        }
+        index += 1;
+    }
+    None
+}
+
+/// How BITOP combines its sources
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl BitOp {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "AND" => Some(BitOp::And),
+            "OR" => Some(BitOp::Or),
+            "XOR" => Some(BitOp::Xor),
+            "NOT" => Some(BitOp::Not),
+            _ => None,
+        }
+    }
+}
+
+/// Combines `sources` byte by byte, padding the shorter ones with zero bytes up to the length of
+/// the longest. NOT only looks at the first source.
+pub fn combine(op: BitOp, sources: &[&[u8]]) -> Vec<u8> {
+    let length = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    let byte = |source: &[u8], index: usize| source.get(index).copied().unwrap_or(0);
+    (0..length)
+        .map(|index| {
+            let mut bytes = sources.iter().map(|source| byte(source, index));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |result, byte| result & byte),
+                BitOp::Or => bytes.fold(first, |result, byte| result | byte),
+                BitOp::Xor => bytes.fold(first, |result, byte| result ^ byte),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use crate::database::{on_keyspace_write, KeyspaceEvent};
+
+/// The clients blocked on each key of a database, in the order they blocked
+type BlockedOnKeys = HashMap<Bytes, Vec<Arc<Notify>>>;
+
+/// The clients blocked on each key, by database. Created along with the keyspace hook that
+/// wakes them, the first time a client blocks.
+static BLOCKED: Lazy<Mutex<HashMap<usize, BlockedOnKeys>>> = Lazy::new(|| {
+    on_keyspace_write(|write| {
+        if write.event == KeyspaceEvent::Set {
+            wake(write.db, write.key);
+        }
+    });
+    Mutex::new(HashMap::new())
+});
+
+/// Clients currently blocked, for INFO
+static BLOCKED_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn blocked_clients() -> usize {
+    BLOCKED_CLIENTS.load(Ordering::Relaxed)
+}
+
+/// A client's registration as blocked on some keys, it's taken off them again when this is
+/// dropped. The client isn't holding any lock while it waits, a write that gives one of the keys
+/// a new value wakes it to try again.
+///
+/// Like in Redis, the clients blocked on a key are served in the order they blocked: a write
+/// only wakes the first of them, and each one that leaves wakes the next in line in case there
+/// is more left to serve.
+pub struct BlockedKeys {
+    db: usize,
+    keys: Vec<Bytes>,
+    ready: Arc<Notify>,
+}
+
+impl BlockedKeys {
+    pub fn new(db: usize, keys: Vec<Bytes>) -> Self {
+        let ready = Arc::new(Notify::new());
+        {
+            let mut blocked = BLOCKED.lock().unwrap();
+            let database = blocked.entry(db).or_default();
+            for key in keys.iter() {
+                database.entry(key.clone()).or_default().push(ready.clone());
+            }
+        }
+        BLOCKED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+        Self {
+            db,
+            keys,
+            ready,
+        }
+    }
+
+    /// Waits until one of the keys has been written to. A write that lands while the client is
+    /// busy checking the keys is remembered, so checking and then waiting never misses one.
+    pub async fn ready(&self) {
+        self.ready.notified().await
+    }
+}
+
+impl Drop for BlockedKeys {
+    fn drop(&mut self) {
+        let mut blocked = BLOCKED.lock().unwrap();
+        if let Some(database) = blocked.get_mut(&self.db) {
+            for key in self.keys.iter() {
+                if let Some(waiters) = database.get_mut(key) {
+                    let was_first = waiters.first().is_some_and(|waiter| Arc::ptr_eq(waiter, &self.ready));
+                    waiters.retain(|waiter| !Arc::ptr_eq(waiter, &self.ready));
+                    match waiters.first() {
+                        Some(next) if was_first => next.notify_one(),
+                        Some(_) => {}
+                        None => {
+                            database.remove(key);
+                        }
+                    }
+                }
+            }
+            if database.is_empty() {
+                blocked.remove(&self.db);
+            }
+        }
+        BLOCKED_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wakes the client that has been blocked on `key` the longest. Runs for every write, so it does
+/// as little as possible when nobody is blocked.
+fn wake(db: usize, key: &[u8]) {
+    let blocked = BLOCKED.lock().unwrap();
+    if let Some(first) = blocked.get(&db).and_then(|database| database.get(key)).and_then(|waiters| waiters.first()) {
+        first.notify_one();
+    }
+}
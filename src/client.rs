@@ -1,26 +1,74 @@
 use std::fmt::{Display, Formatter};
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
 use std::io::Write;
-use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use bytes::buf::Writer;
-use bytes::BufMut;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::future::BoxFuture;
 use thiserror::Error;
+use tracing::Instrument;
 use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use crate::CONFIG;
-use crate::database::{db_get, db_list_keys, db_set};
-use crate::persistence::DataType;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{OwnedRwLockReadGuard, RwLock};
+use once_cell::sync::Lazy;
+use crate::{audit, server_log};
+use crate::{clock, CONFIG};
+use crate::allocator::{allocator_name, allocator_stats, process_rss, ratio};
+use crate::blocking::{blocked_clients, BlockedKeys};
+use crate::command::{Command, CommandFlags, CommandSpec, COMMAND_TABLE};
+use crate::config::{config_get, config_set, parse_memory};
+use crate::clients::{ClientHandle, CLIENTS};
+use crate::export::export_database;
+use crate::io_threads::ReplyWriter;
+use crate::logging::audit_enabled;
+use crate::module::{call_command, command_specs, loaded_modules, ModuleContext};
+use crate::cluster::{cluster_redirection, key_hash_slot, meet, migrate_keys, MigrateOptions, CLUSTER, CLUSTER_SLOTS};
+use crate::database::{changes_since_last_save, db_copy, db_delete, db_exists, db_expiration, db_get, db_read, db_count_keys_in_slot, db_get_with_expiration, db_hash_delete, db_hash_persist, db_hash_set, db_hash_set_expiry, db_incr_by, db_insert, db_list_insert, db_list_move, db_list_remove, db_list_set, db_list_trim, db_keys_in_slot, db_list_keys, db_memory_stats, db_memory_usage, db_peek, db_pop, db_push, db_random_key, db_remove, db_scan, db_get_and_set, db_set_expiring_at, db_set_add, db_set_bit, db_set_move, db_set_remove, db_zadd, db_zpop, db_zrem, db_set_if_missing, db_set_expiry, ExpireCondition, ExpiryChange, ScanFilter, ValueError, db_touch, keyspace_hits, keyspace_misses, last_bgsave_failed, reset_keyspace_stats, DATABASES, last_save_time, save_in_progress, LOADING};
+use crate::persistence::{DataType, SetMembers, RdbReadError, RdbReader, RdbWriter};
+use crate::session::ClientSession;
+use crate::recorder::record;
+use crate::replication::{acknowledge, advance_offset, attach_replica, propagate, propagate_transaction, tap_writes, FullResync, TappedWrite, REPLICATION};
+use crate::bitmap::{bit_at, combine, count_bits, find_bit, BitOp};
+use crate::hash::scan_fields;
+use crate::list::InsertPosition;
+use crate::memory::ENTRY_OVERHEAD;
+use crate::quicklist::{set_packed_threshold, ListEnd, MAX_PACKED_THRESHOLD};
+use crate::script::{delete_library, flush_libraries, list_libraries, load_library, ScriptRun, ScriptStep};
+use crate::set::scan_members;
+use crate::zset::{parse_score, AddOptions, AddOutcome, LexBound, ScoreBound, ScoreEnd};
+use crate::util::{format_double, from_unix_millis, glob_match, normalize_index, normalize_range, sample_distinct, sample_with_repetition, unix_millis};
 
-#[derive(Debug)]
+/// Initial size of a connection's read buffer, enough for a deep pipeline of small commands
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+/// Pipelined requests handled before their replies are flushed and the connection waits for
+/// the socket to take them, bounding what a single client can make the server buffer
+const MAX_UNANSWERED_COMMANDS: usize = 1024;
+/// A single request may not grow the read buffer past this, matching client-query-buffer-limit
+const MAX_QUERY_BUFFER_SIZE: usize = 1024 * 1024 * 1024;
+/// Elements a single array may declare, like redis' proto-max-multibulk-len
+const MAX_MULTIBULK_LENGTH: usize = 1024 * 1024;
+/// Elements in a frame counting those of nested arrays, so nesting can't multiply the above
+const MAX_FRAME_ELEMENTS: usize = 1024 * 1024;
+/// How deep arrays may be nested in one another
+const MAX_NESTING_DEPTH: usize = 32;
+/// Longest a line may get without its CRLF, which bounds the headers in front of every value
+const MAX_INLINE_LENGTH: usize = 64 * 1024;
+/// Longest a single bulk string may be, like redis' proto-max-bulk-len
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ResponseType {
-    //Error(String),
-    //SimpleString(String),
-    //Integer(i64),
-    BulkString(Vec<u8>),
+    Error(String),
+    SimpleString(String),
+    Integer(i64),
+    BulkString(Bytes),
     Array(Vec<ResponseType>),
-    //NullArray,
-    //NullBulkString,
+    NullArray,
+    NullBulkString,
 }
 
 impl Display for ResponseType {
@@ -32,10 +80,28 @@ impl Display for ResponseType {
 impl ResponseType {
     pub fn string(&self) -> Option<String> {
         match self {
-            ResponseType::BulkString(bytes) => Some(String::from_utf8_lossy(bytes).to_string()),
+            ResponseType::BulkString(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<Bytes> {
+        match self {
+            ResponseType::BulkString(bytes) => Some(bytes.clone()),
             _ => None,
         }
     }
+
+    /// The character a value of this type starts with on the wire
+    fn type_char(&self) -> char {
+        match self {
+            ResponseType::Error(_) => '-',
+            ResponseType::SimpleString(_) => '+',
+            ResponseType::Integer(_) => ':',
+            ResponseType::BulkString(_) | ResponseType::NullBulkString => '$',
+            ResponseType::Array(_) | ResponseType::NullArray => '*',
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -49,173 +115,673 @@ pub enum RespProtocolError {
     #[error("Array number of elements specifier is not a valid integer: '{0}'")]
     ArrayNumElementsInvalidLength(String),
 
+    #[error("invalid multibulk length")]
+    MultibulkTooLong,
+
+    #[error("too many elements in request")]
+    TooManyElements,
+
+    #[error("arrays nested too deeply")]
+    NestedTooDeeply,
+
+    #[error("too big inline request")]
+    InlineTooLong,
+
+    #[error("Integer is not valid: '{0}'")]
+    IntegerInvalid(String),
+
     #[error("BulkString length specifier is not a valid integer: '{0}'")]
     BulkStringInvalidLength(String),
+
+    #[error("invalid bulk length")]
+    InvalidBulkLength,
+
+    #[error("expected '{expected}', got '{got}'")]
+    UnexpectedType { expected: char, got: char },
+
+    #[error("BulkString of {0} bytes is not followed by CRLF")]
+    BulkStringMissingTerminator(usize),
+
+    #[error("Connection closed with {0} bytes of an incomplete message buffered")]
+    UnexpectedEof(usize),
+
+    #[error("Connection closed")]
+    ConnectionClosed,
+
+    #[error("Client evicted to bring client memory under maxmemory-clients")]
+    ClientEvicted,
 }
 
 pub struct RedisClientConnection {
-    stream: TcpStream,
-    read_buffer: [u8; 512],
-    write_index: usize,
-    selected_db: usize,
+    reader: OwnedReadHalf,
+    /// Replies are buffered and only flushed once every pipelined request has been handled
+    stream: ReplyWriter,
+    /// Grows to fit a request that doesn't fit, and shrinks back once it has been handled.
+    /// Parsed frames are split off it, so their arguments share its memory instead of being copied.
+    read_buffer: BytesMut,
+    /// Requests taken off the read buffer since replies were last flushed
+    unanswered: usize,
+    session: ClientSession,
+    /// Set on the replica side for the connection to its master, replies are not sent back
+    is_master_link: bool,
+    /// Set on the master side once this connection has completed a PSYNC
+    replica_stream: Option<UnboundedReceiver<Bytes>>,
+    /// Set once this connection has issued REPLTAP
+    tap_stream: Option<UnboundedReceiver<TappedWrite>>,
+    /// Address a replica announced through REPLCONF before it issued PSYNC
+    announced_ip: Option<String>,
+    announced_port: Option<u16>,
+    replica_id: Option<u64>,
+    /// Writes held back while a transaction executes, along with the db each applied to
+    pending_writes: Option<Vec<(usize, Vec<ResponseType>)>>,
+    /// This connection's entry in the client registry
+    handle: Arc<ClientHandle>,
+    peer_addr: Option<SocketAddr>,
+    /// What the running command did, replicated in its place when set. Commands whose outcome
+    /// depends on when or where they run record their concrete effects here, so replicas end up
+    /// with the same data rather than running the command again. Each is paired with its db.
+    effects: Option<Vec<(usize, Vec<ResponseType>)>>,
+    /// Held while the current command runs and replicates, see `EXCLUSION`
+    exclusion: Option<OwnedRwLockReadGuard<()>>,
+}
+
+/// Keeps a transaction's commands together. Commands that touch the keyspace hold this shared
+/// from when they run until their writes are replicated, EXEC holds it exclusively, so no other
+/// client's command lands in between the queued ones, on this node or on its replicas.
+static EXCLUSION: Lazy<Arc<RwLock<()>>> = Lazy::new(|| Arc::new(RwLock::new(())));
+
+impl RespProtocolError {
+    /// Whether the peer sent something that isn't valid RESP, as opposed to the connection ending
+    pub fn is_malformed(&self) -> bool {
+        !matches!(
+            self,
+            RespProtocolError::UnexpectedEof(_) | RespProtocolError::ConnectionClosed | RespProtocolError::ClientEvicted
+        )
+    }
 }
 
 impl RedisClientConnection {
-    pub const fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: TcpStream) -> Self {
+        let peer_addr = stream.peer_addr().ok();
+        let local_addr = stream.local_addr().ok();
+        let (reader, writer) = stream.into_split();
+        let handle = CLIENTS.register();
+        {
+            let mut details = handle.details.lock().unwrap();
+            details.addr = peer_addr.map(|addr| addr.to_string()).unwrap_or_default();
+            details.laddr = local_addr.map(|addr| addr.to_string()).unwrap_or_default();
+        }
         Self {
-            stream,
-            read_buffer: [0u8; 512],
-            write_index: 0,
-            selected_db: 0,
+            reader,
+            stream: ReplyWriter::new(writer),
+            read_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            unanswered: 0,
+            session: ClientSession::new(handle.id),
+            is_master_link: false,
+            replica_stream: None,
+            tap_stream: None,
+            announced_ip: None,
+            announced_port: None,
+            replica_id: None,
+            pending_writes: None,
+            handle,
+            peer_addr,
+            effects: None,
+            exclusion: None,
+        }
+    }
+
+    /// Keeps EXEC from running until the current command is done, unless that's already the
+    /// case or the command is one EXEC itself runs
+    async fn hold_exclusion(&mut self) {
+        if self.exclusion.is_none() && self.pending_writes.is_none() {
+            self.exclusion = Some(EXCLUSION.clone().read_owned().await);
         }
     }
 
+    pub fn set_master_link(&mut self) {
+        self.is_master_link = true;
+        self.handle.no_evict.store(true, Ordering::Relaxed);
+    }
+
     pub async fn process(&mut self) -> Result<(), anyhow::Error> {
         loop {
-            let request = self.read().await?;
-            match request {
-                ResponseType::Array(elements) => {
-                    if !elements.is_empty() {
-                        if let ResponseType::BulkString(command) = &elements[0] {
-                            let command = String::from_utf8_lossy(command).to_string();
-                            handle_command(self, command, &elements[1..]).await?;
-                        }
-                    }
+            let frame = match self.read_frame().await {
+                Ok(frame) => frame,
+                Err(e) => {
+                    self.reply_protocol_error(&e).await;
+                    return Err(e);
                 }
+            };
+            let Some(RespParseResult { request, consumed }) = frame else {
+                return Ok(());
+            };
+            self.handle_request(request).await?;
+            if self.is_master_link {
+                advance_offset(consumed).await;
+            }
+            self.track_memory();
 
-                _ => todo!("Unhandled datatype in request")
+            if let Some(stream) = self.replica_stream.take() {
+                return self.serve_replica(stream).await;
+            }
+            if let Some(stream) = self.tap_stream.take() {
+                return self.serve_tap(stream).await;
             }
         }
     }
 
-    pub async fn read(&mut self) -> Result<ResponseType, anyhow::Error> {
-        fn slide_window(buffer: &mut [u8; 512], start: usize, length: usize) {
-            let (from, to) = buffer.split_at_mut(start);
-            to[..length].copy_from_slice(&from[..length]);
+    async fn handle_request(&mut self, request: ResponseType) -> Result<(), anyhow::Error> {
+        let elements = match Self::request_elements(request) {
+            Ok(elements) => elements,
+            Err(e) => {
+                let e = e.into();
+                self.reply_protocol_error(&e).await;
+                return Err(e);
+            }
+        };
+
+        if let Some(ResponseType::BulkString(command)) = elements.first() {
+            record(self.session.id, self.session.selected_db, &elements).await?;
+            let command = String::from_utf8_lossy(command).to_string();
+            handle_command(self, command, &elements[1..]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The arguments of a request, which has to be an array of bulk strings. Empty and null
+    /// arrays are skipped over as redis does.
+    fn request_elements(request: ResponseType) -> Result<Vec<ResponseType>, RespProtocolError> {
+        let elements = match request {
+            ResponseType::Array(elements) => elements,
+            ResponseType::NullArray => Vec::new(),
+            other => return Err(RespProtocolError::UnexpectedType { expected: '*', got: other.type_char() }),
+        };
+
+        match elements.iter().find(|element| !matches!(element, ResponseType::BulkString(_))) {
+            None => Ok(elements),
+            Some(ResponseType::NullBulkString) => Err(RespProtocolError::InvalidBulkLength),
+            Some(other) => Err(RespProtocolError::UnexpectedType { expected: '$', got: other.type_char() }),
         }
+    }
 
+    /// Streams writes to a connection that issued REPLTAP, each command as a
+    /// `write <offset> <command>` array where the offset is the one just past the command.
+    /// Anything the consumer sends is ignored.
+    async fn serve_tap(&mut self, mut stream: UnboundedReceiver<TappedWrite>) -> Result<(), anyhow::Error> {
         loop {
-            if self.write_index >= self.read_buffer.len() {
-                return Err(RespProtocolError::MessageTooBig.into());
+            tokio::select! {
+                write = stream.recv() => {
+                    let Some(TappedWrite { offset, frames }) = write else {
+                        return Ok(());
+                    };
+                    let mut frame_offset = offset - frames.len() as u64;
+                    let mut remaining = &frames[..];
+                    while let Some(length) = Self::frame_length(remaining)? {
+                        frame_offset += length as u64;
+                        self.stream.write_all(format!("*3\r\n$5\r\nwrite\r\n:{}\r\n", frame_offset).as_bytes()).await?;
+                        self.stream.write_all(&remaining[..length]).await?;
+                        remaining = &remaining[length..];
+                    }
+                    self.stream.flush().await?;
+                }
+
+                frame = self.read_frame() => {
+                    if frame?.is_none() {
+                        return Ok(());
+                    }
+                }
             }
+        }
+    }
 
-            let bytes_read = self.stream.read(&mut self.read_buffer[self.write_index..]).await?;
-            if bytes_read == 0 {
-                continue;
+    /// Forwards the replication stream to a connected replica while still answering the
+    /// REPLCONF traffic it sends back.
+    async fn serve_replica(&mut self, mut stream: UnboundedReceiver<Bytes>) -> Result<(), anyhow::Error> {
+        loop {
+            tokio::select! {
+                data = stream.recv() => {
+                    let Some(data) = data else {
+                        return Ok(());
+                    };
+                    self.stream.write_all(&data).await?;
+                    self.stream.flush().await?;
+                }
+
+                frame = self.read_frame() => {
+                    let Some(frame) = frame? else {
+                        return Ok(());
+                    };
+                    self.handle_request(frame.request).await?;
+                }
             }
+        }
+    }
+
+    async fn propagate_write(&mut self, write: Vec<ResponseType>) -> Result<(), anyhow::Error> {
+        self.propagate_write_to(self.session.selected_db, write).await
+    }
+
+    async fn propagate_write_to(&mut self, db_id: usize, write: Vec<ResponseType>) -> Result<(), anyhow::Error> {
+        if let Some(pending_writes) = self.pending_writes.as_mut() {
+            pending_writes.push((db_id, write));
+            Ok(())
+        } else {
+            propagate(db_id, &write).await
+        }
+    }
 
-            self.write_index += bytes_read;
-            let end_index = self.write_index;
-            let request = Self::parse_resp(&self.read_buffer[0..end_index])?;
-            if let Some(RespParseResult { request, consumed }) = request {
-                slide_window(&mut self.read_buffer, consumed, self.write_index);
-                self.write_index -= consumed;
+    /// Replicates `command` in place of the command being run, which is then not propagated
+    /// verbatim
+    fn also_propagate(&mut self, command: Vec<ResponseType>) {
+        let db_id = self.session.selected_db;
+        self.effects.get_or_insert_with(Vec::new).push((db_id, command));
+    }
+
+    /// Keeps the command being run out of the replication stream, for one that turned out to
+    /// change nothing replicas need to know about
+    fn suppress_propagation(&mut self) {
+        self.effects.get_or_insert_with(Vec::new);
+    }
 
-                return Ok(request);
+    /// Replicates the effects a command recorded, as one MULTI/EXEC block when there are several
+    /// so a replica never applies only some of them
+    async fn propagate_effects(&mut self, effects: Vec<(usize, Vec<ResponseType>)>) -> Result<(), anyhow::Error> {
+        // A transaction's writes are already wrapped as a whole
+        if self.pending_writes.is_some() || effects.len() <= 1 {
+            for (db_id, effect) in effects {
+                self.propagate_write_to(db_id, effect).await?;
             }
+            return Ok(());
         }
+
+        propagate_transaction(&effects).await
     }
 
+    pub async fn send_command<T: AsRef<[u8]>>(&mut self, parts: &[T]) -> Result<(), anyhow::Error> {
+        let command = ResponseType::Array(
+            parts
+                .iter()
+                .map(|part| ResponseType::BulkString(Bytes::copy_from_slice(part.as_ref())))
+                .collect()
+        );
 
-    fn parse_resp(buffer: &[u8]) -> Result<Option<RespParseResult>, RespProtocolError> {
-        let part_end = Self::get_next_part_end(buffer);
-        if part_end.is_none() {
-            return Ok(None);
+        let mut buffer = Vec::with_capacity(64).writer();
+        write_resp(&mut buffer, &command).await?;
+        self.stream.write_all(buffer.get_ref()).await?;
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    pub async fn read(&mut self) -> Result<ResponseType, anyhow::Error> {
+        match self.read_frame().await? {
+            Some(frame) => Ok(frame.request),
+            None => Err(RespProtocolError::ConnectionClosed.into()),
         }
+    }
 
-        let part_end = part_end.unwrap();
-        if part_end == 0 {
-            return Ok(None);
+    /// Returns the next complete frame, or None once the peer has closed the connection between
+    /// frames.
+    async fn read_frame(&mut self) -> Result<Option<RespParseResult>, anyhow::Error> {
+        loop {
+            // A deep pipeline is answered in chunks, so its replies don't all pile up in memory
+            // before the first of them is sent
+            if self.unanswered >= MAX_UNANSWERED_COMMANDS {
+                self.flush_replies().await?;
+                self.unanswered = 0;
+            }
+
+            if let Some(length) = Self::frame_length(&self.read_buffer)? {
+                let frame = self.read_buffer.split_to(length).freeze();
+                self.release_read_buffer();
+                self.unanswered += 1;
+                return Ok(Some(RespParseResult {
+                    request: Self::parse_frame(&frame)?,
+                    consumed: length,
+                }));
+            }
+
+            // Everything that's already buffered has been answered, send the replies before
+            // waiting on the next batch.
+            self.flush_replies().await?;
+            self.unanswered = 0;
+            if self.fill_read_buffer().await? == 0 {
+                if self.read_buffer.is_empty() {
+                    return Ok(None);
+                }
+
+                return Err(RespProtocolError::UnexpectedEof(self.read_buffer.len()).into());
+            }
         }
+    }
 
-        let prefix_end = part_end - 1;
-        let request = match buffer[0] {
-            b'*' => Self::parse_array(&buffer[1..prefix_end], &buffer[part_end + 1..])?,
-            b'$' => Self::parse_bulk_string(&buffer[1..prefix_end], &buffer[part_end + 1..])?,
-            x => return Err(RespProtocolError::UnhandledRespDataType(x as char))
+    /// Queues a reply, which writes straight to the socket once the buffer is full
+    async fn write_reply(&mut self, reply: &[u8]) -> Result<(), anyhow::Error> {
+        tokio::select! {
+            written = self.stream.write_all(reply) => written?,
+            _ = self.handle.evicted() => return Err(RespProtocolError::ClientEvicted.into()),
         };
+        self.track_memory();
 
-        if request.is_none() {
-            return Ok(None);
+        Ok(())
+    }
+
+    /// Sends the buffered replies. A client that isn't reading them can hold this up for as long
+    /// as it likes, so it gives up as soon as the client is picked for eviction.
+    async fn flush_replies(&mut self) -> Result<(), anyhow::Error> {
+        tokio::select! {
+            flushed = self.stream.flush() => flushed?,
+            _ = self.handle.evicted() => return Err(RespProtocolError::ClientEvicted.into()),
+        };
+
+        Ok(())
+    }
+
+    /// Tells a client what was wrong with what it sent before the connection is dropped over it
+    async fn reply_protocol_error(&mut self, error: &anyhow::Error) {
+        let Some(error) = error.downcast_ref::<RespProtocolError>() else {
+            return;
+        };
+        if !error.is_malformed() || self.is_master_link {
+            return;
         }
 
-        let RespParseResult { request, consumed } = request.unwrap();
-        let consumed = consumed + (prefix_end + 2);
+        let reply = format!("-ERR Protocol error: {}\r\n", error);
+        if self.stream.write_all(reply.as_bytes()).await.is_ok() {
+            let _ = self.stream.flush().await;
+        }
+    }
 
-        Ok(Some(
-            RespParseResult {
-                request,
-                consumed,
+    /// Reads the `$<length>\r\n<payload>` rdb transfer sent by a master during a full resync,
+    /// which unlike a bulk string has no trailing CRLF.
+    pub async fn read_rdb_payload(&mut self) -> Result<Vec<u8>, anyhow::Error> {
+        let header_end = loop {
+            if let Some(end) = Self::get_next_part_end(&self.read_buffer) {
+                break end;
             }
-        ))
-    }
 
-    fn get_next_part_end(buffer: &[u8]) -> Option<usize> {
-        for i in 0..buffer.len() {
-            if buffer[i] == b'\r' && (i + 1) < buffer.len() && buffer[i + 1] == b'\n' {
-                return Some(i + 1);
+            if self.fill_read_buffer().await? == 0 {
+                return Err(RespProtocolError::UnexpectedEof(self.read_buffer.len()).into());
             }
+        };
+
+        if self.read_buffer[0] != b'$' {
+            return Err(RespProtocolError::UnhandledRespDataType(self.read_buffer[0] as char).into());
         }
 
-        None
+        let length = String::from_utf8_lossy(&self.read_buffer[1..header_end - 1]).to_string();
+        let Ok(length) = length.parse::<usize>() else {
+            return Err(RespProtocolError::BulkStringInvalidLength(length).into());
+        };
+
+        let buffered = (self.read_buffer.len() - (header_end + 1)).min(length);
+        let mut payload = Vec::with_capacity(length);
+        payload.extend_from_slice(&self.read_buffer[header_end + 1..header_end + 1 + buffered]);
+        self.read_buffer.advance(header_end + 1 + buffered);
+        self.release_read_buffer();
+
+        payload.resize(length, 0);
+        self.reader.read_exact(&mut payload[buffered..]).await?;
+
+        Ok(payload)
     }
 
-    fn parse_bulk_string(string_part: &[u8], remainder: &[u8]) -> Result<Option<RespParseResult>, RespProtocolError> {
-        let length = String::from_utf8_lossy(string_part);
-        let Ok(length) = length.parse::<i32>() else {
-            return Err(RespProtocolError::BulkStringInvalidLength(length.to_string()));
+    /// Reads whatever is available from the socket, returning 0 at end of stream
+    async fn fill_read_buffer(&mut self) -> Result<usize, anyhow::Error> {
+        if self.read_buffer.len() >= MAX_QUERY_BUFFER_SIZE {
+            return Err(RespProtocolError::MessageTooBig.into());
+        }
+        self.read_buffer.reserve(READ_BUFFER_SIZE);
+
+        let bytes_read = tokio::select! {
+            read = self.reader.read_buf(&mut self.read_buffer) => read?,
+            _ = self.handle.evicted() => return Err(RespProtocolError::ClientEvicted.into()),
         };
+        self.track_memory();
+
+        Ok(bytes_read)
+    }
+
+    /// Reports the memory held by this connection's buffers to the client registry
+    fn track_memory(&self) {
+        let queued = self.session.transaction.iter().flatten().map(|command| command_size(command)).sum::<usize>()
+            + self.pending_writes.iter().flatten().map(|(_, command)| command_size(command)).sum::<usize>();
+        let usage = self.read_buffer.capacity() + self.stream.buffered() + queued;
+        CLIENTS.update_memory(&self.handle, usage);
+    }
+
+    /// Publishes what CLIENT LIST shows about this connection, having just run `command`
+    fn update_details(&self, command: &str) {
+        let mut flags = String::new();
+        if self.is_master_link {
+            flags.push('M');
+        }
+        if self.replica_id.is_some() {
+            flags.push('S');
+        }
+        if self.session.is_subscribed() {
+            flags.push('P');
+        }
+        if self.session.in_transaction() {
+            flags.push('x');
+        }
+        if self.handle.no_evict.load(Ordering::Relaxed) {
+            flags.push('e');
+        }
+        if self.session.cluster_flags.readonly {
+            flags.push('r');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+
+        let mut details = self.handle.details.lock().unwrap();
+        details.name = self.session.name.clone().unwrap_or_default();
+        details.flags = flags;
+        details.db = self.session.selected_db;
+        details.channels = self.session.channels.len();
+        details.patterns = self.session.patterns.len();
+        details.queued = self.session.transaction.as_ref().map_or(-1, |queued| queued.len() as i64);
+        details.query_buffer = self.read_buffer.len();
+        details.output_buffer = self.stream.buffered();
+        details.last_command = command.to_string();
+        details.user = self.session.user.clone();
+        details.resp = self.session.resp_version;
+        details.lib_name = self.session.lib_name.clone().unwrap_or_default();
+        details.lib_ver = self.session.lib_ver.clone().unwrap_or_default();
+        details.last_interaction = Some(clock::monotonic());
+    }
 
-        if length < 0 {
-            return Err(RespProtocolError::BulkStringInvalidLength(length.to_string()));
+    /// Drops a buffer that grew for a large request once everything in it has been handled, so
+    /// the connection doesn't hold on to it
+    fn release_read_buffer(&mut self) {
+        if self.read_buffer.is_empty() && self.read_buffer.capacity() > READ_BUFFER_SIZE {
+            self.read_buffer = BytesMut::with_capacity(READ_BUFFER_SIZE);
         }
+    }
+
+
+    /// Parses one frame off the front of `buffer`, None if it doesn't hold a complete frame yet.
+    /// The frame is copied once and its bulk strings are slices of that copy.
+    pub fn parse_resp(buffer: &[u8]) -> Result<Option<RespParseResult>, RespProtocolError> {
+        let Some(length) = Self::frame_length(buffer)? else {
+            return Ok(None);
+        };
 
-        let length = length as usize;
         Ok(Some(
             RespParseResult {
-                request: ResponseType::BulkString(remainder[..length].to_vec()),
+                request: Self::parse_frame(&Bytes::copy_from_slice(&buffer[..length]))?,
                 consumed: length,
             }
         ))
     }
 
-    fn parse_array(array_part: &[u8], mut remainder: &[u8]) -> Result<Option<RespParseResult>, RespProtocolError> {
-        let num_elements = String::from_utf8_lossy(array_part);
-        let Ok(num_elements) = num_elements.parse::<i32>() else {
-            return Err(RespProtocolError::ArrayNumElementsInvalidLength(num_elements.to_string()));
+    /// Length of the frame at the front of `buffer`, None if it doesn't hold a complete frame yet.
+    /// Checks the frame is well formed without allocating anything for it.
+    pub fn frame_length(buffer: &[u8]) -> Result<Option<usize>, RespProtocolError> {
+        Self::frame_end(buffer, 0, 0, &mut 0)
+    }
+
+    /// Builds the value of a complete frame, as found by `frame_length`. Bulk strings are slices
+    /// of `frame` rather than copies, they're only copied if they end up stored.
+    pub fn parse_frame(frame: &Bytes) -> Result<ResponseType, RespProtocolError> {
+        Self::build_value(frame, 0).map(|(value, _)| value)
+    }
+
+    /// `depth` is how many arrays the value at `start` is nested in, `elements` counts the array
+    /// elements seen in the frame so far
+    fn frame_end(buffer: &[u8], start: usize, depth: usize, elements: &mut usize) -> Result<Option<usize>, RespProtocolError> {
+        let Some((kind, header, mut position)) = Self::read_header(buffer, start) else {
+            if buffer.len() - start > MAX_INLINE_LENGTH {
+                return Err(RespProtocolError::InlineTooLong);
+            }
+            return Ok(None);
         };
 
-        let mut consumed = 0;
-        let mut elements = vec![];
-        for _ in 0..num_elements {
-            let next_part_end = Self::get_next_part_end(remainder);
-            let Some(_) = next_part_end else {
-                return Ok(None);
-            };
+        match kind {
+            b'+' | b'-' => {}
+            b':' => {
+                Self::parse_integer(header)?;
+            }
+            b'$' => {
+                if let Some(length) = Self::bulk_string_length(header)? {
+                    if length > MAX_BULK_LENGTH {
+                        return Err(RespProtocolError::InvalidBulkLength);
+                    }
+                    if buffer.len() < position + length + 2 {
+                        return Ok(None);
+                    }
+                    if &buffer[position + length..position + length + 2] != b"\r\n" {
+                        return Err(RespProtocolError::BulkStringMissingTerminator(length));
+                    }
+                    position += length + 2;
+                }
+            }
+            b'*' => {
+                let length = Self::array_length(header)?.unwrap_or(0);
+                if length > MAX_MULTIBULK_LENGTH {
+                    return Err(RespProtocolError::MultibulkTooLong);
+                }
+                if depth >= MAX_NESTING_DEPTH {
+                    return Err(RespProtocolError::NestedTooDeeply);
+                }
+                *elements += length;
+                if *elements > MAX_FRAME_ELEMENTS {
+                    return Err(RespProtocolError::TooManyElements);
+                }
 
-            let result = Self::parse_resp(remainder)?;
-            let Some(element) = result else {
-                return Ok(None);
-            };
+                for _ in 0..length {
+                    let Some(end) = Self::frame_end(buffer, position, depth + 1, elements)? else {
+                        return Ok(None);
+                    };
+                    position = end;
+                }
+            }
+            x => return Err(RespProtocolError::UnhandledRespDataType(x as char)),
+        }
+
+        Ok(Some(position))
+    }
+
+    fn build_value(frame: &Bytes, start: usize) -> Result<(ResponseType, usize), RespProtocolError> {
+        let Some((kind, header, position)) = Self::read_header(frame, start) else {
+            return Err(RespProtocolError::UnexpectedEof(frame.len()));
+        };
+
+        let value = match kind {
+            b'+' => (ResponseType::SimpleString(String::from_utf8_lossy(header).into_owned()), position),
+            b'-' => (ResponseType::Error(String::from_utf8_lossy(header).into_owned()), position),
+            b':' => (ResponseType::Integer(Self::parse_integer(header)?), position),
+            b'$' => match Self::bulk_string_length(header)? {
+                None => (ResponseType::NullBulkString, position),
+                Some(length) if frame.len() >= position + length + 2 => {
+                    (ResponseType::BulkString(frame.slice(position..position + length)), position + length + 2)
+                }
+                Some(_) => return Err(RespProtocolError::UnexpectedEof(frame.len())),
+            },
+            b'*' => match Self::array_length(header)? {
+                None => (ResponseType::NullArray, position),
+                Some(length) => {
+                    let mut elements = Vec::with_capacity(length);
+                    let mut position = position;
+                    for _ in 0..length {
+                        let (element, end) = Self::build_value(frame, position)?;
+                        elements.push(element);
+                        position = end;
+                    }
+                    (ResponseType::Array(elements), position)
+                }
+            },
+            x => return Err(RespProtocolError::UnhandledRespDataType(x as char)),
+        };
+
+        Ok(value)
+    }
+
+    /// The type byte and the rest of the line starting at `start`, along with where the line
+    /// after it begins. None if the line isn't complete yet, or is longer than any valid header.
+    fn read_header(buffer: &[u8], start: usize) -> Option<(u8, &[u8], usize)> {
+        let line = buffer.get(start..(start + MAX_INLINE_LENGTH + 2).min(buffer.len()))?;
+        let line_end = Self::get_next_part_end(line)? + start;
+        let kind = buffer[start];
+        let header = &buffer[(start + 1).min(line_end - 1)..line_end - 1];
+        Some((kind, header, line_end + 1))
+    }
 
-            consumed += element.consumed + 2;
-            elements.push(element.request);
+    fn get_next_part_end(buffer: &[u8]) -> Option<usize> {
+        buffer.windows(2).position(|pair| pair == b"\r\n").map(|i| i + 1)
+    }
+
+    fn parse_integer(header: &[u8]) -> Result<i64, RespProtocolError> {
+        std::str::from_utf8(header)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| RespProtocolError::IntegerInvalid(String::from_utf8_lossy(header).into_owned()))
+    }
 
-            remainder = &remainder[element.consumed + 2..];
+    /// None for the `$-1` null bulk string
+    fn bulk_string_length(header: &[u8]) -> Result<Option<usize>, RespProtocolError> {
+        match std::str::from_utf8(header).ok().and_then(|length| length.parse::<i64>().ok()) {
+            Some(-1) => Ok(None),
+            Some(length) if length >= 0 => Ok(Some(length as usize)),
+            _ => Err(RespProtocolError::BulkStringInvalidLength(String::from_utf8_lossy(header).into_owned())),
         }
+    }
 
-        Ok(Some(
-            RespParseResult {
-                consumed,
-                request: ResponseType::Array(elements),
-            }
-        ))
+    /// None for the `*-1` null array
+    fn array_length(header: &[u8]) -> Result<Option<usize>, RespProtocolError> {
+        match std::str::from_utf8(header).ok().and_then(|length| length.parse::<i64>().ok()) {
+            Some(-1) => Ok(None),
+            Some(length) if length >= 0 => Ok(Some(length as usize)),
+            _ => Err(RespProtocolError::ArrayNumElementsInvalidLength(String::from_utf8_lossy(header).into_owned())),
+        }
+    }
+}
+
+impl Drop for RedisClientConnection {
+    fn drop(&mut self) {
+        CLIENTS.deregister(&self.handle);
     }
 }
 
-struct RespParseResult {
-    request: ResponseType,
-    consumed: usize,
+/// Rough number of bytes a parsed command holds on to
+fn command_size(command: &[ResponseType]) -> usize {
+    command
+        .iter()
+        .map(|argument| match argument {
+            ResponseType::BulkString(bytes) => bytes.len(),
+            ResponseType::SimpleString(s) | ResponseType::Error(s) => s.len(),
+            ResponseType::Array(elements) => command_size(elements),
+            _ => 0,
+        } + std::mem::size_of::<ResponseType>())
+        .sum()
+}
+
+pub struct RespParseResult {
+    pub request: ResponseType,
+    pub consumed: usize,
 }
 
 fn write_ok(buffer: &mut Writer<Vec<u8>>) -> tokio::io::Result<()> {
@@ -224,12 +790,49 @@ fn write_ok(buffer: &mut Writer<Vec<u8>>) -> tokio::io::Result<()> {
 }
 
 fn write_simple_string(buffer: &mut Writer<Vec<u8>>, string: &[u8]) -> tokio::io::Result<()> {
-    buffer.write_all(format!("+{}\r\n", String::from_utf8_lossy(string)).as_bytes())?;
-    Ok(())
+    buffer.write_all(b"+")?;
+    write_line(buffer, string)
+}
+
+/// How a command went. Only one that didn't fail is replicated as it was sent.
+#[must_use]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Outcome {
+    Done,
+    Failed,
+}
+
+type CommandResult = Result<Outcome, anyhow::Error>;
+
+/// Replies with an error and reports the command as failed
+fn fail(buffer: &mut Writer<Vec<u8>>, message: &[u8]) -> CommandResult {
+    write_simple_error(buffer, message)?;
+    Ok(Outcome::Failed)
 }
 
 fn write_simple_error(buffer: &mut Writer<Vec<u8>>, string: &[u8]) -> tokio::io::Result<()> {
-    buffer.write_all(format!("-{}\r\n", String::from_utf8_lossy(string)).as_bytes())?;
+    buffer.write_all(b"-")?;
+    write_line(buffer, string)
+}
+
+/// Writes the body of a simple string or error. They can't hold line breaks, so any are replaced
+/// with spaces rather than letting them end the frame early.
+fn write_line(buffer: &mut Writer<Vec<u8>>, string: &[u8]) -> tokio::io::Result<()> {
+    if string.iter().any(|b| *b == b'\r' || *b == b'\n') {
+        let cleaned = string
+            .iter()
+            .map(|b| if *b == b'\r' || *b == b'\n' { b' ' } else { *b })
+            .collect::<Vec<_>>();
+        buffer.write_all(&cleaned)?;
+    } else {
+        buffer.write_all(string)?;
+    }
+    buffer.write_all(b"\r\n")?;
+    Ok(())
+}
+
+fn write_integer(buffer: &mut Writer<Vec<u8>>, value: i64) -> tokio::io::Result<()> {
+    buffer.write_all(format!(":{}\r\n", value).as_bytes())?;
     Ok(())
 }
 
@@ -238,71 +841,248 @@ fn write_nil_bulk_string(buffer: &mut Writer<Vec<u8>>) -> tokio::io::Result<()>
     Ok(())
 }
 
+fn write_nil_array(buffer: &mut Writer<Vec<u8>>) -> tokio::io::Result<()> {
+    buffer.write_all(b"*-1\r\n")?;
+    Ok(())
+}
+
 fn write_bulk_string(buffer: &mut Writer<Vec<u8>>, string: &[u8]) -> tokio::io::Result<()> {
-    let length_str = string.len().to_string();
-    buffer.write_all(format!("${}\r\n{}\r\n", length_str, String::from_utf8_lossy(string)).as_bytes())?;
+    buffer.write_all(format!("${}\r\n", string.len()).as_bytes())?;
+    buffer.write_all(string)?;
+    buffer.write_all(b"\r\n")?;
     Ok(())
 }
 
-enum Command {
-    Echo,
-    Ping,
-    #[allow(clippy::enum_variant_names)]
-    Command,
-    Select,
-    Set,
-    Get,
-    Config,
-    Keys,
-    Info
-}
-
-impl FromStr for Command {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let res = match s.to_lowercase().as_str() {
-            "echo" => Command::Echo,
-            "ping" => Command::Ping,
-            "command" => Command::Command,
-            "select" => Command::Select,
-            "set" => Command::Set,
-            "get" => Command::Get,
-            "config" => Command::Config,
-            "keys" => Command::Keys,
-            "info" => Command::Info,
-            _ => anyhow::bail!("Invalid Command {}", s)
-        };
+async fn handle_command(client: &mut RedisClientConnection, command: String, arguments: &[ResponseType]) -> Result<(), anyhow::Error> {
+    let mut response_buff = Vec::with_capacity(256).writer();
+    let spec = CommandSpec::lookup(command.as_str());
+    if let Some(error) = command_rejection(client, spec, &command, arguments).await {
+        write_simple_error(&mut response_buff, error.as_bytes())?;
+    } else if let Some(spec) = spec {
+        let is_transaction_control = matches!(spec.command, Command::Multi | Command::Exec | Command::Discard);
+        if let Some(transaction) = client.session.transaction.as_mut().filter(|_| !is_transaction_control) {
+            let mut queued = vec![ResponseType::BulkString(command.into_bytes().into())];
+            queued.extend_from_slice(arguments);
+            transaction.push(queued);
+            write_simple_string(&mut response_buff, b"QUEUED")?;
+            client.update_details(spec.name);
+        } else {
+            // Blocking commands only hold it while they pop, not while they wait
+            let touches_keyspace = spec.flags.intersects(CommandFlags::WRITE.union(CommandFlags::READONLY));
+            if touches_keyspace && !spec.flags.contains(CommandFlags::BLOCKING) {
+                client.hold_exclusion().await;
+            }
+            let result = dispatch(client, spec, &command, arguments, &mut response_buff).await;
+            client.exclusion = None;
+            let _ = result?;
+        }
+    }
 
-        Ok(res)
+    // ASKING only applies to the command right after it
+    if spec.map(|spec| spec.command) != Some(Command::Asking) {
+        client.session.cluster_flags.asking = false;
     }
+
+    // Commands applied from the master are silent, apart from the acknowledgements it asks for.
+    if client.is_master_link && spec.map(|spec| spec.command) != Some(Command::Replconf) {
+        return Ok(());
+    }
+
+    client.write_reply(response_buff.get_ref()).await?;
+
+    Ok(())
 }
 
-async fn handle_command(client: &mut RedisClientConnection, command: String, arguments: &[ResponseType]) -> Result<(), anyhow::Error> {
-    let mut response_buff = Vec::with_capacity(256).writer();
-    match Command::from_str(command.as_str())? {
+const MISCONF_ERROR: &str = "MISCONF Redis is configured to save RDB snapshots, but it's currently unable to persist to disk. \
+    Commands that may modify the data set are disabled, because this instance is configured to report errors during writes \
+    if RDB snapshotting fails (stop-writes-on-bgsave-error option). Please check the Redis logs for details about the RDB error.";
+
+/// Returns the error a command should be refused with before it runs, if any
+async fn command_rejection(
+    client: &RedisClientConnection,
+    spec: Option<&'static CommandSpec>,
+    command: &str,
+    arguments: &[ResponseType]
+) -> Option<String> {
+    let Some(spec) = spec else {
+        let mut error = format!("ERR unknown command '{}', with args beginning with: ", command);
+        for argument in arguments {
+            error.push_str(&format!("'{}' ", argument.string().unwrap_or_default()));
+        }
+        return Some(error);
+    };
+
+    if !spec.accepts_arity(arguments.len() + 1) {
+        return Some(format!("ERR wrong number of arguments for '{}' command", spec.name));
+    }
+
+    if LOADING.in_progress.load(Ordering::Relaxed) && !spec.flags.contains(CommandFlags::LOADING_OK) {
+        return Some("LOADING Redis is loading the dataset in memory".to_string());
+    }
+
+    if client.is_master_link {
+        return None;
+    }
+
+    let (is_replica, serve_stale_data, cluster_enabled, stop_writes) = {
+        let config = CONFIG.read().await;
+        let stop_writes = config.stop_writes_on_bgsave_error && !config.save_rules.is_empty();
+        (config.replica_of.is_some(), config.replica_serve_stale_data, config.cluster_enabled, stop_writes)
+    };
+
+    if cluster_enabled {
+        let keys = spec.key_arguments(arguments);
+        if let Some(redirection) = cluster_redirection(spec, &keys, client.session.selected_db, &client.session.cluster_flags).await {
+            return Some(redirection);
+        }
+    }
+
+    if stop_writes && !is_replica && spec.is_write() && last_bgsave_failed() {
+        return Some(MISCONF_ERROR.to_string());
+    }
+
+    if is_replica {
+        if spec.is_write() {
+            return Some("READONLY You can't write against a read only replica.".to_string());
+        }
+
+        if !serve_stale_data && !spec.flags.contains(CommandFlags::STALE_OK) && !REPLICATION.read().await.master_link_up {
+            return Some("MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.".to_string());
+        }
+    }
+
+    None
+}
+
+/// Runs a command and passes successful writes on to the replication stream verbatim
+async fn dispatch(
+    client: &mut RedisClientConnection,
+    spec: &'static CommandSpec,
+    command: &str,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>
+) -> CommandResult {
+    let span = tracing::info_span!(
+        "command",
+        otel.name = spec.name,
+        db.system = "redis",
+        db.operation.name = spec.name,
+        db.namespace = client.session.selected_db,
+        client.id = client.session.id,
+        client.address = client.peer_addr.map(|addr| addr.to_string()),
+        master_link = client.is_master_link,
+    );
+    let audited = audit_enabled()
+        && !client.is_master_link
+        && spec.flags.intersects(CONFIG.read().await.audit_log_categories);
+    let result = run_command(client, spec, command, arguments, response_buff).instrument(span).await;
+    client.update_details(spec.name);
+
+    if audited {
+        let failed = !matches!(result, Ok(Outcome::Done));
+        audit!(
+            "addr={} id={} name={} user={} db={} cmd={} keys={} result={}",
+            client.peer_addr.map(|addr| addr.to_string()).unwrap_or_default(),
+            client.session.id,
+            client.session.name.as_deref().unwrap_or_default(),
+            client.session.user,
+            client.session.selected_db,
+            spec.name,
+            spec.key_arguments(arguments).iter().map(|key| String::from_utf8_lossy(key)).collect::<Vec<_>>().join(","),
+            if failed { "err" } else { "ok" }
+        );
+    }
+    result
+}
+
+async fn run_command(
+    client: &mut RedisClientConnection,
+    spec: &'static CommandSpec,
+    command: &str,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>
+) -> CommandResult {
+    let outcome = execute_command(client, spec, arguments, response_buff).await?;
+
+    // Effects a command recorded happened even if it failed afterwards
+    match client.effects.take() {
+        Some(effects) => client.propagate_effects(effects).await?,
+        None if outcome == Outcome::Failed => {}
+        None if spec.is_write() => {
+            let mut write = vec![ResponseType::BulkString(Bytes::copy_from_slice(command.as_bytes()))];
+            write.extend_from_slice(arguments);
+            client.propagate_write(write).await?;
+        }
+        None => {}
+    }
+
+    Ok(outcome)
+}
+
+async fn execute_command(
+    client: &mut RedisClientConnection,
+    spec: &'static CommandSpec,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>
+) -> CommandResult {
+    let parsed_command = spec.command;
+    match parsed_command {
         Command::Echo => {
             if let Some(string) = arguments[0].string() {
-                write_bulk_string(&mut response_buff, string.as_bytes())?;
+                write_bulk_string(response_buff, string.as_bytes())?;
             }
         }
 
         Command::Ping => {
-            write_simple_string(&mut response_buff, b"PONG")?;
+            write_simple_string(response_buff, b"PONG")?;
         }
 
         Command::Command => {
-            // TODO: Need to implement this I guess
-            write_simple_error(&mut response_buff, b"COMMAND not implemented")?;
+            let subcommand = arguments.first().and_then(|a| a.string()).unwrap_or_default();
+            match subcommand.to_lowercase().as_str() {
+                "" => {
+                    let entries = COMMAND_TABLE.iter().chain(command_specs()).map(command_info).collect();
+                    write_resp(response_buff, &ResponseType::Array(entries)).await?;
+                }
+
+                "count" => {
+                    write_integer(response_buff, (COMMAND_TABLE.len() + command_specs().len()) as i64)?;
+                }
+
+                "info" => {
+                    let mut entries = Vec::new();
+                    for name in arguments[1..].iter().filter_map(|a| a.string()) {
+                        match CommandSpec::lookup(name.as_str()) {
+                            Some(spec) => entries.push(command_info(spec)),
+                            None => entries.push(ResponseType::NullBulkString),
+                        }
+                    }
+                    write_resp(response_buff, &ResponseType::Array(entries)).await?;
+                }
+
+                "docs" => {
+                    write_resp(response_buff, &ResponseType::Array(vec![])).await?;
+                }
+
+                _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+            }
         }
 
         Command::Select => {
             if !arguments.is_empty() {
                 if let Some(id_string) = arguments[0].string() {
-                    let id = id_string.parse::<usize>()?;
-                    client.selected_db = id;
-                    write_ok(&mut response_buff)?;
-                    println!("Client selected db {}", client.selected_db);
+                    let Ok(id) = id_string.parse::<i64>() else {
+                        return fail(response_buff, b"ERR value is not an integer or out of range");
+                    };
+                    let Some(id) = usize::try_from(id).ok().filter(|id| *id < DATABASES) else {
+                        return fail(response_buff, b"ERR DB index is out of range");
+                    };
+                    if id != 0 && CONFIG.read().await.cluster_enabled {
+                        return fail(response_buff, b"ERR SELECT is not allowed in cluster mode");
+                    }
+                    client.session.selected_db = id;
+                    write_ok(response_buff)?;
+                    server_log!(Debug, "Client selected db {}", client.session.selected_db);
                 }
             }
         }
@@ -310,52 +1090,699 @@ async fn handle_command(client: &mut RedisClientConnection, command: String, arg
         Command::Set => {
             let mut success = false;
             if arguments.len() >= 2 {
-                let mut timeout = None;
+                let mut expiration = None;
+                let mut relative = false;
                 if arguments.len() >= 4 {
                     if let Some(option) = arguments[2].string() {
                         if let Some(value) = arguments[3].string() {
-                            match option.as_str() {
-                                "PX" | "px" => {
-                                    timeout = Some(Duration::from_millis(value.parse::<u64>()?));
-                                }
-
-                                "EX" | "ex" => {
-                                    timeout = Some(Duration::from_secs(value.parse::<u64>()?));
-                                }
+                            // Milliseconds per unit, and whether the time is a UNIX timestamp
+                            let timing = match option.to_uppercase().as_str() {
+                                "PX" => Some((1, false)),
+                                "EX" => Some((1000, false)),
+                                "PXAT" => Some((1, true)),
+                                "EXAT" => Some((1000, true)),
+                                _ => None,
+                            };
 
-                                _ => { }
+                            if let Some((unit_ms, absolute)) = timing {
+                                let Ok(time) = value.parse::<i64>() else {
+                                    return fail(response_buff, b"ERR value is not an integer or out of range");
+                                };
+                                let base_ms = if absolute { 0 } else { unix_millis(clock::now()) };
+                                let Some(at_ms) = time.checked_mul(unit_ms).and_then(|time| time.checked_add(base_ms)).filter(|_| time > 0) else {
+                                    return fail(response_buff, b"ERR invalid expire time in 'set' command");
+                                };
+                                expiration = Some(from_unix_millis(at_ms));
+                                relative = !absolute;
                             }
                         }
                     }
                 }
 
-                if let Some(key) = arguments[0].string() {
-                    if let Some(value) = arguments[1].string() {
-                        db_set(client.selected_db, key, value, timeout).await?;
-                        write_ok(&mut response_buff)?;
+                if let Some(key) = arguments[0].bytes() {
+                    if let Some(value) = arguments[1].bytes() {
+                        // The argument is a slice of the connection's read buffer, copied so the
+                        // stored value doesn't keep the whole buffer alive
+                        let value = Bytes::copy_from_slice(&value);
+                        db_set_expiring_at(client.session.selected_db, key.clone(), value.clone(), expiration).await?;
+                        if let (true, Some(expiration)) = (relative, expiration) {
+                            propagate_set_at(client, key, value, expiration);
+                        }
+                        write_ok(response_buff)?;
                         success = true;
                     }
                 }
             }
 
             if !success {
-                write_simple_error(&mut response_buff, b"Failed to set")?;
+                return fail(response_buff, b"Failed to set");
             }
         }
 
-        Command::Get => {
-            let mut success = false;
-            if !arguments.is_empty() {
-                if let Some(key) = arguments[0].string() {
-                    if let Ok(Some(DataType::String(value))) = db_get(client.selected_db, &key).await {
-                        write_bulk_string(&mut response_buff, value.as_bytes())?;
-                        success = true;
+        Command::Setex | Command::Psetex => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(ttl) = arguments[1].string().and_then(|ttl| ttl.parse::<i64>().ok()) else {
+                return fail(response_buff, b"ERR value is not an integer or out of range");
+            };
+            let ttl = match parsed_command {
+                Command::Setex => ttl.checked_mul(1000),
+                _ => Some(ttl),
+            };
+            let at_ms = ttl.filter(|ttl| *ttl > 0).and_then(|ttl| ttl.checked_add(unix_millis(clock::now())));
+            let Some(at_ms) = at_ms else {
+                return fail(response_buff, format!("ERR invalid expire time in '{}' command", spec.name).as_bytes());
+            };
+
+            let value = Bytes::copy_from_slice(&arguments[2].bytes().unwrap_or_default());
+            let expiration = from_unix_millis(at_ms);
+            db_set_expiring_at(client.session.selected_db, key.clone(), value.clone(), Some(expiration)).await?;
+            propagate_set_at(client, key, value, expiration);
+            write_ok(response_buff)?;
+        }
+
+        Command::Setnx => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let value = Bytes::copy_from_slice(&arguments[1].bytes().unwrap_or_default());
+            let set = db_set_if_missing(client.session.selected_db, key, value).await;
+            if !set {
+                client.suppress_propagation();
+            }
+            write_integer(response_buff, set as i64)?;
+        }
+
+        Command::Getset => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let value = Bytes::copy_from_slice(&arguments[1].bytes().unwrap_or_default());
+            match db_get_and_set(client.session.selected_db, key, value).await {
+                Ok(Some(old)) => write_bulk_string(response_buff, &old)?,
+                Ok(None) => write_nil_bulk_string(response_buff)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Setbit | Command::Getbit => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(offset) = arguments[1].string().and_then(|offset| parse_bit_offset(&offset)) else {
+                return fail(response_buff, b"ERR bit offset is not an integer or out of range");
+            };
+
+            let bit = if parsed_command == Command::Setbit {
+                let bit = match arguments[2].string().as_deref() {
+                    Some("0") => false,
+                    Some("1") => true,
+                    _ => {
+                        return fail(response_buff, b"ERR bit is not an integer or out of range");
                     }
+                };
+                db_set_bit(client.session.selected_db, key, offset, bit).await
+            } else {
+                match db_get(client.session.selected_db, &key).await? {
+                    Some(DataType::String(value)) => Ok(bit_at(&value, offset)),
+                    Some(_) => Err(ValueError::WrongType),
+                    None => Ok(false),
                 }
+            };
+            match bit {
+                Ok(bit) => write_integer(response_buff, bit as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
             }
+        }
 
-            if !success {
-                write_nil_bulk_string(&mut response_buff)?;
+        Command::Bitcount | Command::Bitpos => {
+            return execute_bit_search(client, parsed_command, arguments, response_buff).await;
+        }
+
+        Command::Bitop => {
+            return execute_bitop(client, arguments, response_buff).await;
+        }
+
+        Command::Lpush | Command::Rpush | Command::Lpushx | Command::Rpushx => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let values = arguments[1..].iter().map(|value| value.bytes().unwrap_or_default()).collect();
+            let end = if matches!(parsed_command, Command::Lpush | Command::Lpushx) { ListEnd::Left } else { ListEnd::Right };
+            let create = matches!(parsed_command, Command::Lpush | Command::Rpush);
+            match db_push(client.session.selected_db, key, values, end, create).await {
+                Ok(length) => write_integer(response_buff, length as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Lpop | Command::Rpop => {
+            return execute_pop(client, parsed_command, arguments, response_buff).await;
+        }
+
+        Command::Blpop | Command::Brpop | Command::Blmove | Command::Blmpop | Command::Bzpopmin | Command::Bzpopmax | Command::Bzmpop => {
+            return execute_blocking_pop(client, parsed_command, arguments, response_buff).await;
+        }
+
+        Command::Lmove => {
+            let ends = arguments[2..4].iter().map(|end| end.string().and_then(|end| ListEnd::parse(&end))).collect::<Option<Vec<_>>>();
+            let Some(ends) = ends else {
+                return fail(response_buff, b"ERR syntax error");
+            };
+
+            let (source, destination) = (arguments[0].bytes().unwrap_or_default(), arguments[1].bytes().unwrap_or_default());
+            match db_list_move(client.session.selected_db, source, destination, ends[0], ends[1]).await {
+                Ok(Some(element)) => write_bulk_string(response_buff, &element)?,
+                Ok(None) => write_nil_bulk_string(response_buff)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Lmpop => {
+            let (keys, end, count) = match parse_multi_pop(arguments, ListEnd::parse) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    return fail(response_buff, e.as_bytes());
+                }
+            };
+            match try_pop(client, &BlockingPop::MultiPop { keys, end, count }).await {
+                Ok(Some(reply)) => write_resp(response_buff, &reply).await?,
+                Ok(None) => {
+                    client.suppress_propagation();
+                    write_nil_array(response_buff)?;
+                }
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Hset | Command::Hmset => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let pairs = &arguments[1..];
+            if pairs.len() & 1 == 1 {
+                return fail(response_buff, format!("ERR wrong number of arguments for '{}' command", spec.name).as_bytes());
+            }
+
+            let pairs = pairs.chunks(2).map(|pair| (pair[0].bytes().unwrap_or_default(), pair[1].bytes().unwrap_or_default())).collect();
+            match db_hash_set(client.session.selected_db, key, pairs, true).await {
+                Ok(_) if parsed_command == Command::Hmset => write_ok(response_buff)?,
+                Ok(added) => write_integer(response_buff, added as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Hget | Command::Hexists => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let field = arguments[1].bytes().unwrap_or_default();
+            let value = db_read(client.session.selected_db, &key, move |value| match value {
+                DataType::Hash(hash) => Ok(hash.fields().get(&field).cloned()),
+                _ => Err(ValueError::WrongType),
+            }).await;
+            let value = match value.transpose() {
+                Ok(value) => value.flatten(),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+
+            match value {
+                _ if parsed_command == Command::Hexists => write_integer(response_buff, value.is_some() as i64)?,
+                Some(value) => write_bulk_string(response_buff, &value)?,
+                None => write_nil_bulk_string(response_buff)?,
+            }
+        }
+
+        Command::Hgetall | Command::Hkeys | Command::Hvals | Command::Hlen => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let reply = db_read(client.session.selected_db, &key, move |value| {
+                let DataType::Hash(hash) = value else {
+                    return Err(ValueError::WrongType);
+                };
+                let fields = hash.fields();
+                Ok(match parsed_command {
+                    Command::Hlen => ResponseType::Integer(fields.len() as i64),
+                    Command::Hkeys => ResponseType::Array(fields.keys().cloned().map(ResponseType::BulkString).collect()),
+                    Command::Hvals => ResponseType::Array(fields.values().cloned().map(ResponseType::BulkString).collect()),
+                    // Fields and values take turns in a flat array
+                    _ => ResponseType::Array(
+                        fields
+                            .iter()
+                            .flat_map(|(field, value)| [ResponseType::BulkString(field.clone()), ResponseType::BulkString(value.clone())])
+                            .collect()
+                    ),
+                })
+            }).await;
+
+            let reply = match reply.transpose() {
+                Ok(Some(reply)) => reply,
+                // A missing key reads as an empty hash
+                Ok(None) if parsed_command == Command::Hlen => ResponseType::Integer(0),
+                Ok(None) => ResponseType::Array(Vec::new()),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+            write_resp(response_buff, &reply).await?;
+        }
+
+        Command::Hscan => {
+            return execute_hscan(client, arguments, response_buff).await;
+        }
+
+        Command::Hexpire => {
+            return execute_hexpire(client, arguments, response_buff, "hexpire", 1000, false).await;
+        }
+
+        Command::Hpexpire => {
+            return execute_hexpire(client, arguments, response_buff, "hpexpire", 1, false).await;
+        }
+
+        Command::Hexpireat => {
+            return execute_hexpire(client, arguments, response_buff, "hexpireat", 1000, true).await;
+        }
+
+        Command::Hpexpireat => {
+            return execute_hexpire(client, arguments, response_buff, "hpexpireat", 1, true).await;
+        }
+
+        Command::Httl | Command::Hpttl | Command::Hexpiretime | Command::Hpexpiretime => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let fields = match parse_hash_fields(&arguments[1..]) {
+                Ok(fields) => fields,
+                Err(e) => {
+                    return fail(response_buff, e.as_bytes());
+                }
+            };
+            let field_count = fields.len();
+            let now_ms = unix_millis(clock::now());
+            let replies = db_read(client.session.selected_db, &key, move |value| {
+                let DataType::Hash(hash) = value else {
+                    return Err(ValueError::WrongType);
+                };
+                Ok(fields
+                    .iter()
+                    .map(|field| {
+                        let reply = match hash.expiration(field).map(unix_millis) {
+                            _ if !hash.contains_key(field) => -2,
+                            None => -1,
+                            // Rounded to the nearest second like TTL
+                            Some(at_ms) if parsed_command == Command::Httl => (at_ms - now_ms + 500) / 1000,
+                            Some(at_ms) if parsed_command == Command::Hpttl => at_ms - now_ms,
+                            Some(at_ms) if parsed_command == Command::Hexpiretime => at_ms / 1000,
+                            Some(at_ms) => at_ms,
+                        };
+                        ResponseType::Integer(reply)
+                    })
+                    .collect())
+            }).await;
+
+            let replies = match replies.transpose() {
+                Ok(Some(replies)) => replies,
+                // None of the fields exist in a missing hash
+                Ok(None) => vec![ResponseType::Integer(-2); field_count],
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+            write_resp(response_buff, &ResponseType::Array(replies)).await?;
+        }
+
+        Command::Hpersist => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let fields = match parse_hash_fields(&arguments[1..]) {
+                Ok(fields) => fields,
+                Err(e) => {
+                    return fail(response_buff, e.as_bytes());
+                }
+            };
+            match db_hash_persist(client.session.selected_db, key, fields).await {
+                Ok(persisted) => {
+                    if !persisted.contains(&Some(true)) {
+                        client.suppress_propagation();
+                    }
+                    let replies = persisted
+                        .into_iter()
+                        .map(|persisted| ResponseType::Integer(match persisted {
+                            None => -2,
+                            Some(false) => -1,
+                            Some(true) => 1,
+                        }))
+                        .collect();
+                    write_resp(response_buff, &ResponseType::Array(replies)).await?;
+                }
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Hrandfield => {
+            return execute_hrandfield(client, arguments, response_buff).await;
+        }
+
+        Command::Hsetnx => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let pair = (arguments[1].bytes().unwrap_or_default(), arguments[2].bytes().unwrap_or_default());
+            match db_hash_set(client.session.selected_db, key, vec![pair], false).await {
+                Ok(added) => write_integer(response_buff, added as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Hmget => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let fields: Vec<Bytes> = arguments[1..].iter().map(|field| field.bytes().unwrap_or_default()).collect();
+            let field_count = fields.len();
+            let values = db_read(client.session.selected_db, &key, move |value| {
+                let DataType::Hash(hash) = value else {
+                    return Err(ValueError::WrongType);
+                };
+                Ok(fields
+                    .iter()
+                    .map(|field| match hash.fields().get(field) {
+                        Some(value) => ResponseType::BulkString(value.clone()),
+                        None => ResponseType::NullBulkString,
+                    })
+                    .collect())
+            }).await;
+
+            let values = match values.transpose() {
+                Ok(Some(values)) => values,
+                Ok(None) => vec![ResponseType::NullBulkString; field_count],
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+            write_resp(response_buff, &ResponseType::Array(values)).await?;
+        }
+
+        Command::Hdel => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let fields = arguments[1..].iter().map(|field| field.bytes().unwrap_or_default()).collect();
+            match db_hash_delete(client.session.selected_db, key, fields).await {
+                Ok(removed) => write_integer(response_buff, removed as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Sadd | Command::Srem => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let members = arguments[1..].iter().map(|member| member.bytes().unwrap_or_default()).collect();
+            let result = if parsed_command == Command::Sadd {
+                db_set_add(client.session.selected_db, key, members).await
+            } else {
+                db_set_remove(client.session.selected_db, key, members).await
+            };
+            match result {
+                Ok(changed) => write_integer(response_buff, changed as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Smembers | Command::Scard | Command::Sismember | Command::Smismember => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let queried: Vec<Bytes> = arguments[1..].iter().map(|member| member.bytes().unwrap_or_default()).collect();
+            let reply = move |members: &SetMembers| match parsed_command {
+                Command::Scard => ResponseType::Integer(members.len() as i64),
+                Command::Sismember => ResponseType::Integer(members.contains(&queried[0]) as i64),
+                Command::Smismember => ResponseType::Array(
+                    queried.iter().map(|member| ResponseType::Integer(members.contains(member) as i64)).collect()
+                ),
+                _ => ResponseType::Array(members.iter().cloned().map(ResponseType::BulkString).collect()),
+            };
+
+            let read = db_read(client.session.selected_db, &key, {
+                let reply = reply.clone();
+                move |value| match value {
+                    DataType::Set(set) => Ok(reply(set.members())),
+                    _ => Err(ValueError::WrongType),
+                }
+            }).await;
+            let reply = match read.transpose() {
+                Ok(Some(read)) => read,
+                // A missing key reads as an empty set
+                Ok(None) => reply(&SetMembers::default()),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+            write_resp(response_buff, &reply).await?;
+        }
+
+        Command::Smove => {
+            let source = arguments[0].bytes().unwrap_or_default();
+            let destination = arguments[1].bytes().unwrap_or_default();
+            let member = arguments[2].bytes().unwrap_or_default();
+            match db_set_move(client.session.selected_db, source, destination, member).await {
+                Ok(moved) => {
+                    if !moved {
+                        client.suppress_propagation();
+                    }
+                    write_integer(response_buff, moved as i64)?;
+                }
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Sscan => {
+            return execute_sscan(client, arguments, response_buff).await;
+        }
+
+        Command::Sintercard => {
+            return execute_sintercard(client, arguments, response_buff).await;
+        }
+
+        Command::Zadd => {
+            return execute_zadd(client, arguments, response_buff).await;
+        }
+
+        Command::Zrem => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let members = arguments[1..].iter().map(|member| member.bytes().unwrap_or_default()).collect();
+            match db_zrem(client.session.selected_db, key, members).await {
+                Ok(removed) => write_integer(response_buff, removed as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Zscore | Command::Zcard => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let member = arguments.get(1).and_then(|member| member.bytes()).unwrap_or_default();
+            let read = db_read(client.session.selected_db, &key, move |value| match value {
+                DataType::SortedSet(zset) => Ok((zset.len(), zset.score(&member))),
+                _ => Err(ValueError::WrongType),
+            }).await;
+            let (len, score) = match read.transpose() {
+                Ok(read) => read.unwrap_or_default(),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+
+            if parsed_command == Command::Zcard {
+                write_integer(response_buff, len as i64)?;
+            } else {
+                match score {
+                    Some(score) => write_bulk_string(response_buff, format_double(score).as_bytes())?,
+                    None => write_nil_bulk_string(response_buff)?,
+                }
+            }
+        }
+
+        Command::Zrange
+        | Command::Zrangebyscore
+        | Command::Zrevrangebyscore
+        | Command::Zrangebylex
+        | Command::Zrevrangebylex
+        | Command::Zrevrange => {
+            return execute_zrange(client, parsed_command, arguments, response_buff).await;
+        }
+
+        Command::Zrank | Command::Zrevrank => {
+            let name = if parsed_command == Command::Zrank { "zrank" } else { "zrevrank" };
+            if arguments.len() > 3 {
+                return fail(response_buff, format!("ERR wrong number of arguments for '{}' command", name).as_bytes());
+            }
+            let with_score = match arguments.get(2).and_then(|option| option.string()) {
+                Some(option) if option.eq_ignore_ascii_case("withscore") => true,
+                Some(_) => {
+                    return fail(response_buff, b"ERR syntax error");
+                }
+                None => false,
+            };
+
+            let key = arguments[0].bytes().unwrap_or_default();
+            let member = arguments[1].bytes().unwrap_or_default();
+            let ranked = db_read(client.session.selected_db, &key, move |value| match value {
+                DataType::SortedSet(zset) => Ok(zset.rank(&member).map(|rank| {
+                    let rank = if parsed_command == Command::Zrank { rank } else { zset.len() - 1 - rank };
+                    (rank, zset.score(&member).unwrap_or_default())
+                })),
+                _ => Err(ValueError::WrongType),
+            }).await;
+            let ranked = match ranked.transpose() {
+                Ok(ranked) => ranked.flatten(),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+
+            match ranked {
+                Some((rank, score)) if with_score => {
+                    write_resp(response_buff, &ResponseType::Array(vec![ResponseType::Integer(rank as i64), bulk_string(&format_double(score))])).await?;
+                }
+                Some((rank, _)) => write_integer(response_buff, rank as i64)?,
+                None if with_score => write_nil_array(response_buff)?,
+                None => write_nil_bulk_string(response_buff)?,
+            }
+        }
+
+        Command::Zpopmin | Command::Zpopmax => {
+            if arguments.len() > 2 {
+                return fail(response_buff, b"ERR syntax error");
+            }
+            let key = arguments[0].bytes().unwrap_or_default();
+            let end = if parsed_command == Command::Zpopmin { ScoreEnd::Min } else { ScoreEnd::Max };
+            let count = match arguments.get(1).map(|count| count.string().and_then(|count| count.parse::<i64>().ok())) {
+                None => 1,
+                Some(Some(count)) if count >= 0 => count as usize,
+                Some(_) => {
+                    return fail(response_buff, b"ERR value is out of range, must be positive");
+                }
+            };
+
+            match db_zpop(client.session.selected_db, key, end, count).await {
+                Ok(popped) => {
+                    let popped = popped.unwrap_or_default();
+                    if popped.is_empty() {
+                        client.suppress_propagation();
+                    }
+                    let reply = popped
+                        .into_iter()
+                        .flat_map(|(member, score)| [ResponseType::BulkString(member), bulk_string(&format_double(score))])
+                        .collect();
+                    write_resp(response_buff, &ResponseType::Array(reply)).await?;
+                }
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Zmpop => {
+            let (keys, end, count) = match parse_multi_pop(arguments, ScoreEnd::parse) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    return fail(response_buff, e.as_bytes());
+                }
+            };
+            match try_zset_pop(client, &keys, end, count).await {
+                Ok(Some((key, popped))) => write_resp(response_buff, &ResponseType::Array(vec![ResponseType::BulkString(key), scored_pairs(popped)])).await?,
+                Ok(None) => {
+                    client.suppress_propagation();
+                    write_nil_array(response_buff)?;
+                }
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Llen => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let length = db_read(client.session.selected_db, &key, |value| match value {
+                DataType::List(list) => Ok(list.len()),
+                _ => Err(ValueError::WrongType),
+            }).await;
+            match length.transpose() {
+                Ok(length) => write_integer(response_buff, length.unwrap_or(0) as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Lrange => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let bounds = arguments[1..3].iter().map(|bound| bound.string().and_then(|bound| bound.parse::<i64>().ok())).collect::<Option<Vec<_>>>();
+            let Some(bounds) = bounds else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+
+            let elements = db_read(client.session.selected_db, &key, move |value| match value {
+                DataType::List(list) => {
+                    let range = normalize_range(bounds[0], bounds[1], list.len());
+                    Ok(list.iter().skip(range.start).take(range.len()).cloned().map(ResponseType::BulkString).collect())
+                }
+                _ => Err(ValueError::WrongType),
+            }).await;
+            let elements = match elements.transpose() {
+                Ok(elements) => elements.unwrap_or_default(),
+                Err(e) => {
+                    return fail(response_buff, e.to_string().as_bytes());
+                }
+            };
+            write_resp(response_buff, &ResponseType::Array(elements)).await?;
+        }
+
+        Command::Lindex => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(index) = arguments[1].string().and_then(|index| index.parse::<i64>().ok()) else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+
+            let element = db_read(client.session.selected_db, &key, move |value| match value {
+                DataType::List(list) => Ok(normalize_index(index, list.len()).and_then(|index| list.get(index)).cloned()),
+                _ => Err(ValueError::WrongType),
+            }).await;
+            match element.transpose() {
+                Ok(Some(Some(element))) => write_bulk_string(response_buff, &element)?,
+                Ok(_) => write_nil_bulk_string(response_buff)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Lset => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(index) = arguments[1].string().and_then(|index| index.parse::<i64>().ok()) else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+
+            let value = arguments[2].bytes().unwrap_or_default();
+            match db_list_set(client.session.selected_db, key, index, value).await {
+                Ok(()) => write_ok(response_buff)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Linsert => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(position) = arguments[1].string().and_then(|position| InsertPosition::parse(&position)) else {
+                return fail(response_buff, b"ERR syntax error");
+            };
+
+            let (pivot, value) = (arguments[2].bytes().unwrap_or_default(), arguments[3].bytes().unwrap_or_default());
+            match db_list_insert(client.session.selected_db, key, position, pivot, value).await {
+                Ok(Some(length)) => write_integer(response_buff, length as i64)?,
+                Ok(None) => write_integer(response_buff, -1)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Lrem => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let Some(count) = arguments[1].string().and_then(|count| count.parse::<i64>().ok()) else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+
+            let value = arguments[2].bytes().unwrap_or_default();
+            match db_list_remove(client.session.selected_db, key, count, value).await {
+                Ok(removed) => write_integer(response_buff, removed as i64)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Ltrim => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let bounds = arguments[1..3].iter().map(|bound| bound.string().and_then(|bound| bound.parse::<i64>().ok())).collect::<Option<Vec<_>>>();
+            let Some(bounds) = bounds else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+
+            match db_list_trim(client.session.selected_db, key, bounds[0], bounds[1]).await {
+                Ok(()) => write_ok(response_buff)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
+            }
+        }
+
+        Command::Get => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            match db_get(client.session.selected_db, &key).await? {
+                Some(DataType::String(value)) => write_bulk_string(response_buff, &value)?,
+                Some(_) => return fail(response_buff, ValueError::WrongType.to_string().as_bytes()),
+                None => write_nil_bulk_string(response_buff)?,
             }
         }
 
@@ -364,38 +1791,35 @@ async fn handle_command(client: &mut RedisClientConnection, command: String, arg
                 if let Some(command) = arguments[0].string() {
                     match command.as_str() {
                         "GET" | "get" => {
-                            let mut responses: Vec<(&str, Option<String>)> = vec![];
-                            for data_arg in &arguments[1..] {
-                                if let Some(option) = data_arg.string() {
-                                    match option.as_str() {
-                                        "DIR" | "dir" => {
-                                            let value = CONFIG.read().await.dir.clone();
-                                            responses.push(("dir", value));
-                                        }
-
-                                        "DBFILENAME" | "dbfilename" => {
-                                            let value = CONFIG.read().await.dir.clone();
-                                            responses.push(("dbfilename", value));
-                                        }
-
-                                        _ => { }
-                                    }
-                                }
-                            }
+                            let responses = {
+                                let config = CONFIG.read().await;
+                                arguments[1..]
+                                    .iter()
+                                    .filter_map(|a| a.string())
+                                    .filter_map(|name| config_get(&config, &name))
+                                    .collect::<Vec<_>>()
+                            };
 
                             response_buff.write_all(format!("*{}\r\n", responses.len() * 2).as_bytes())?;
-                            for (key, value) in &responses {
-                                write_bulk_string(&mut response_buff, key.as_bytes())?;
-                                if let Some(value) = value {
-                                    write_bulk_string(&mut response_buff, value.as_bytes())?;
-                                } else {
-                                    write_nil_bulk_string(&mut response_buff)?;
-                                }
+                            for (name, value) in &responses {
+                                write_bulk_string(response_buff, name.as_bytes())?;
+                                write_bulk_string(response_buff, value.as_bytes())?;
                             }
                         }
 
                         "SET" | "set" => {
+                            let pairs = arguments[1..].chunks(2);
+                            if arguments.len() < 3 || pairs.len() * 2 != arguments.len() - 1 {
+                                return fail(response_buff, b"ERR wrong number of arguments for 'config|set' command");
+                            }
 
+                            let changes = pairs
+                                .map(|pair| (pair[0].string().unwrap_or_default(), pair[1].string().unwrap_or_default()))
+                                .collect::<Vec<_>>();
+                            match config_set(&changes).await {
+                                Ok(_) => write_ok(response_buff)?,
+                                Err(e) => return fail(response_buff, format!("ERR {}", e).as_bytes()),
+                            }
                         }
 
                         "REWRITE" | "rewrite" => {
@@ -403,7 +1827,9 @@ async fn handle_command(client: &mut RedisClientConnection, command: String, arg
                         }
 
                         "RESETSTAT" | "resetstat" => {
-
+                            reset_keyspace_stats();
+                            CLIENTS.evicted_clients.store(0, Ordering::Relaxed);
+                            write_ok(response_buff)?;
                         }
 
                         _ => { }
@@ -413,61 +1839,2382 @@ async fn handle_command(client: &mut RedisClientConnection, command: String, arg
         }
 
         Command::Keys => {
-            if !arguments.is_empty() {
-                if let Some(arg) = arguments[0].string() {
-                    if arg == "*" {
-                        let keys = db_list_keys(client.selected_db).await?;
-                        let mut resp_keys = Vec::new();
-                        for key in keys.iter() {
-                            resp_keys.push(ResponseType::BulkString(key.as_bytes().to_vec()))
-                        }
-                        let resp = ResponseType::Array(resp_keys);
-                        write_resp(&mut response_buff, &resp).await?;
+            let pattern = arguments[0].bytes().unwrap_or_default();
+            let keys = db_list_keys(client.session.selected_db, &pattern).await?;
+            let keys = keys.into_iter().map(ResponseType::BulkString).collect();
+            write_resp(response_buff, &ResponseType::Array(keys)).await?;
+        }
+
+        Command::Info => {
+            let requested = arguments
+                .iter()
+                .filter_map(|a| a.string())
+                .map(|section| section.to_lowercase())
+                .collect::<Vec<_>>();
+            let include_all = requested.is_empty()
+                || requested.iter().any(|section| matches!(section.as_str(), "all" | "default" | "everything"));
+
+            let mut info = String::new();
+            for section in INFO_SECTIONS {
+                if include_all || requested.iter().any(|requested| requested == section) {
+                    if !info.is_empty() {
+                        info.push('\n');
                     }
+                    info.push_str(&info_section(section).await);
                 }
             }
+
+            write_bulk_string(response_buff, info.as_bytes())?;
         }
 
-        Command::Info => {
-            if !arguments.is_empty() {
-                if let Some(category) = arguments[0].string() {
-                    if category.to_lowercase() == "replication" {
-                        let mut replication_info = String::new();
-                        replication_info.push_str("# Replication\n");
-                        if let Some(_replica_of) = CONFIG.read().await.replica_of.as_ref() {
-                            replication_info.push_str("role:slave\n");
-                        } else {
-                            replication_info.push_str("role:master\n");
-                            replication_info.push_str("master_replid:8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb\n");
-                            replication_info.push_str("master_repl_offset:0\n");
-                        }
+        Command::Replconf => {
+            let subcommand = arguments.first().and_then(|a| a.string()).unwrap_or_default();
+            match subcommand.to_lowercase().as_str() {
+                "getack" => {
+                    let offset = REPLICATION.read().await.offset;
+                    let ack = ResponseType::Array(vec![
+                        ResponseType::BulkString(Bytes::from_static(b"REPLCONF")),
+                        ResponseType::BulkString(Bytes::from_static(b"ACK")),
+                        ResponseType::BulkString(offset.to_string().into_bytes().into()),
+                    ]);
+                    write_resp(response_buff, &ack).await?;
+                }
 
-                        write_bulk_string(&mut response_buff, replication_info.as_bytes())?;
+                "ack" => {
+                    let offset = arguments.get(1).and_then(|a| a.string()).and_then(|o| o.parse::<u64>().ok());
+                    if let (Some(replica_id), Some(offset)) = (client.replica_id, offset) {
+                        acknowledge(replica_id, offset).await;
                     }
                 }
+
+                "listening-port" => {
+                    client.announced_port = arguments.get(1).and_then(|a| a.string()).and_then(|p| p.parse::<u16>().ok());
+                    write_ok(response_buff)?;
+                }
+
+                "ip-address" => {
+                    client.announced_ip = arguments.get(1).and_then(|a| a.string());
+                    write_ok(response_buff)?;
+                }
+
+                _ => {
+                    write_ok(response_buff)?;
+                }
             }
         }
-    }
 
-    client.stream.write_all(response_buff.get_ref()).await?;
-    client.stream.flush().await?;
+        Command::Psync | Command::Sync => {
+            let ip = match client.announced_ip.clone() {
+                Some(ip) => ip,
+                None => client.reader.peer_addr()?.ip().to_string(),
+            };
+            let port = client.announced_port.unwrap_or(0);
+            // No command may write between the snapshot and the replica joining the stream, or
+            // the replica would never see that write
+            let FullResync { replica_id, replid, offset, rdb, stream } = {
+                let _exclusive = EXCLUSION.write().await;
+                attach_replica(ip, port).await?
+            };
+            // Legacy SYNC consumers expect the rdb straight away, without the FULLRESYNC header.
+            if parsed_command == Command::Psync {
+                write_simple_string(response_buff, format!("FULLRESYNC {} {}", replid, offset).as_bytes())?;
+            }
+            response_buff.write_all(format!("${}\r\n", rdb.len()).as_bytes())?;
+            response_buff.write_all(&rdb)?;
+            client.replica_stream = Some(stream);
+            client.replica_id = Some(replica_id);
+            client.handle.no_evict.store(true, Ordering::Relaxed);
+        }
 
-    Ok(())
-}
+        Command::Repltap => {
+            let (replid, offset, stream) = tap_writes().await;
+            write_simple_string(response_buff, format!("TAPPING {} {}", replid, offset).as_bytes())?;
+            client.tap_stream = Some(stream);
+        }
 
-fn write_resp<'a>(buffer: &'a mut Writer<Vec<u8>>, value: &'a ResponseType)
-    -> BoxFuture<'a, Result<(), anyhow::Error>> {
-    Box::pin(async move {
-        match value {
-            ResponseType::Array(elements) => {
-                write_array(buffer, elements).await?;
+        Command::Role => {
+            let replication = REPLICATION.read().await;
+            let role = if let Some(replica_of) = CONFIG.read().await.replica_of.as_ref() {
+                let link_status = if replication.master_link_up { "connected" } else { "connect" };
+                vec![
+                    ResponseType::BulkString(Bytes::from_static(b"slave")),
+                    ResponseType::BulkString(Bytes::copy_from_slice(replica_of.host.as_bytes())),
+                    ResponseType::Integer(replica_of.port as i64),
+                    ResponseType::BulkString(Bytes::copy_from_slice(link_status.as_bytes())),
+                    ResponseType::Integer(replication.offset as i64),
+                ]
+            } else {
+                let replicas = replication
+                    .connected_replicas()
+                    .into_iter()
+                    .map(|replica| ResponseType::Array(vec![
+                        ResponseType::BulkString(replica.ip.into_bytes().into()),
+                        ResponseType::BulkString(replica.port.to_string().into_bytes().into()),
+                        ResponseType::BulkString(replica.ack_offset.to_string().into_bytes().into()),
+                    ]))
+                    .collect();
+                vec![
+                    ResponseType::BulkString(Bytes::from_static(b"master")),
+                    ResponseType::Integer(replication.offset as i64),
+                    ResponseType::Array(replicas),
+                ]
+            };
+            write_resp(response_buff, &ResponseType::Array(role)).await?;
+        }
+
+        Command::Multi => {
+            if client.session.in_transaction() {
+                return fail(response_buff, b"ERR MULTI calls can not be nested");
             }
+            client.session.transaction = Some(Vec::new());
+            write_ok(response_buff)?;
+        }
 
-            ResponseType::BulkString(s) => {
-                write_bulk_string(buffer, s)?;
+        Command::Exec => {
+            let Some(queued) = client.session.transaction.take() else {
+                return fail(response_buff, b"ERR EXEC without MULTI");
+            };
+            return execute_transaction(client, queued, response_buff).await;
+        }
+
+        Command::Discard => {
+            if client.session.transaction.take().is_none() {
+                return fail(response_buff, b"ERR DISCARD without MULTI");
+            }
+            write_ok(response_buff)?;
+        }
+
+        Command::Cluster => {
+            if !CONFIG.read().await.cluster_enabled {
+                return fail(response_buff, b"ERR This instance has cluster support disabled");
+            }
+            return execute_cluster(client, arguments, response_buff).await;
+        }
+
+        Command::Asking => {
+            client.session.cluster_flags.asking = true;
+            write_ok(response_buff)?;
+        }
+
+        Command::Readonly | Command::Readwrite => {
+            client.session.cluster_flags.readonly = parsed_command == Command::Readonly;
+            write_ok(response_buff)?;
+        }
+
+        Command::Migrate => {
+            return execute_migrate(client, arguments, response_buff).await;
+        }
+
+        Command::Debug => {
+            return execute_debug(client, arguments, response_buff).await;
+        }
+
+        Command::Touch => {
+            let keys = arguments.iter().filter_map(|a| a.bytes()).collect();
+            write_integer(response_buff, db_touch(client.session.selected_db, keys).await as i64)?;
+        }
+
+        Command::Incr | Command::Decr | Command::Incrby | Command::Decrby => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let parse_delta = || arguments[1].string().and_then(|delta| delta.parse::<i64>().ok()).ok_or(ValueError::NotAnInteger);
+            let delta = match parsed_command {
+                Command::Incr => Ok(1),
+                Command::Decr => Ok(-1),
+                Command::Incrby => parse_delta(),
+                // The smallest i64 has no positive counterpart to add
+                _ => parse_delta().and_then(|delta| delta.checked_neg().ok_or(ValueError::Overflow)),
+            };
+            let result = match delta {
+                Ok(delta) => db_incr_by(client.session.selected_db, key, delta).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(value) => write_integer(response_buff, value)?,
+                Err(e) => return fail(response_buff, e.to_string().as_bytes()),
             }
+        }
 
-            //_ => todo!("Need to implement writing {}", value)
+        Command::Scan => {
+            return execute_scan(client, arguments, response_buff).await;
+        }
+
+        Command::Randomkey => {
+            match db_random_key(client.session.selected_db).await {
+                Some(key) => write_bulk_string(response_buff, &key)?,
+                None => write_nil_bulk_string(response_buff)?,
+            }
+        }
+
+        Command::Exists => {
+            let keys = arguments.iter().filter_map(|a| a.bytes()).collect();
+            write_integer(response_buff, db_exists(client.session.selected_db, keys).await as i64)?;
+        }
+
+        Command::Expire => {
+            return execute_expire(client, arguments, response_buff, "expire", 1000, false).await;
+        }
+
+        Command::Pexpire => {
+            return execute_expire(client, arguments, response_buff, "pexpire", 1, false).await;
+        }
+
+        Command::Expireat => {
+            return execute_expire(client, arguments, response_buff, "expireat", 1000, true).await;
+        }
+
+        Command::Pexpireat => {
+            return execute_expire(client, arguments, response_buff, "pexpireat", 1, true).await;
+        }
+
+        Command::Expiretime | Command::Pexpiretime => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            let reply = match db_expiration(client.session.selected_db, &key).await {
+                None => -2,
+                Some(None) => -1,
+                Some(Some(expiration)) if parsed_command == Command::Expiretime => unix_millis(expiration) / 1000,
+                Some(Some(expiration)) => unix_millis(expiration),
+            };
+            write_integer(response_buff, reply)?;
+        }
+
+        Command::Copy => {
+            return execute_copy(client, arguments, response_buff).await;
+        }
+
+        Command::Dump => {
+            let key = arguments[0].bytes().unwrap_or_default();
+            match db_get(client.session.selected_db, &key).await? {
+                Some(value) => match RdbWriter::dump(&value) {
+                    Ok(payload) => write_bulk_string(response_buff, &payload)?,
+                    Err(e) => return fail(response_buff, format!("ERR {}", e).as_bytes()),
+                },
+                None => write_nil_bulk_string(response_buff)?,
+            }
+        }
+
+        Command::Restore => {
+            return execute_restore(client, arguments, response_buff).await;
+        }
+
+        Command::Del => {
+            let keys = arguments.iter().filter_map(|a| a.bytes()).collect();
+            write_integer(response_buff, db_remove(client.session.selected_db, keys).await as i64)?;
+        }
+
+        Command::Object => {
+            return execute_object(client, arguments, response_buff).await;
+        }
+
+        Command::Memory => {
+            return execute_memory(client, arguments, response_buff).await;
+        }
+
+        Command::Module => {
+            let subcommand = arguments[0].string().unwrap_or_default();
+            match subcommand.to_lowercase().as_str() {
+                "list" => {
+                    let modules = loaded_modules()
+                        .into_iter()
+                        .map(|(name, version)| ResponseType::Array(vec![
+                            ResponseType::BulkString(Bytes::from_static(b"name")),
+                            ResponseType::BulkString(Bytes::copy_from_slice(name.as_bytes())),
+                            ResponseType::BulkString(Bytes::from_static(b"ver")),
+                            ResponseType::Integer(version),
+                        ]))
+                        .collect();
+                    write_resp(response_buff, &ResponseType::Array(modules)).await?;
+                }
+
+                "load" | "loadex" | "unload" => return fail(response_buff, b"ERR Modules are compiled into the server and can't be loaded or unloaded at runtime"),
+
+                _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+            }
+        }
+
+        Command::ModuleDefined => {
+            let arguments = arguments.iter().filter_map(|a| a.bytes()).collect::<Vec<_>>();
+            let mut ctx = ModuleContext::new(client.session.selected_db);
+            match call_command(spec.name, &mut ctx, &arguments).await {
+                Ok(reply) => write_resp(response_buff, &reply).await?,
+                Err(e) => {
+                    let message = e.to_string();
+                    // Errors that already carry a code, like WRONGTYPE, are sent as they are
+                    let has_code = message.split(' ').next().is_some_and(|code| code.len() > 1 && code.chars().all(|c| c.is_ascii_uppercase()));
+                    let error = if has_code { message } else { format!("ERR {}", message) };
+                    return fail(response_buff, error.as_bytes());
+                }
+            }
+        }
+
+        Command::Eval | Command::EvalRo | Command::Fcall | Command::FcallRo => {
+            return execute_script(client, parsed_command, arguments, response_buff).await;
+        }
+
+        Command::Function => {
+            return execute_function(client, arguments, response_buff).await;
+        }
+
+        Command::Client => {
+            let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+            match subcommand.as_str() {
+                "id" => {
+                    write_integer(response_buff, client.session.id as i64)?;
+                }
+
+                "setinfo" => {
+                    let (Some(attribute), Some(value), 3) = (
+                        arguments.get(1).and_then(|a| a.string()),
+                        arguments.get(2).and_then(|a| a.string()),
+                        arguments.len(),
+                    ) else {
+                        return fail(response_buff, b"ERR wrong number of arguments for 'client|setinfo' command");
+                    };
+                    let attribute = attribute.to_lowercase();
+                    if attribute != "lib-name" && attribute != "lib-ver" {
+                        return fail(response_buff, format!("ERR Unrecognized option '{}'", attribute).as_bytes());
+                    }
+                    // Each attribute is one space separated field of CLIENT LIST
+                    if value.chars().any(|c| !('!'..='~').contains(&c)) {
+                        let error = format!("ERR {} cannot contain spaces, newlines or special characters.", attribute);
+                        return fail(response_buff, error.as_bytes());
+                    }
+
+                    let value = Some(value).filter(|value| !value.is_empty());
+                    if attribute == "lib-name" {
+                        client.session.lib_name = value;
+                    } else {
+                        client.session.lib_ver = value;
+                    }
+                    write_ok(response_buff)?;
+                }
+
+                "info" => {
+                    client.update_details("client|info");
+                    write_bulk_string(response_buff, format!("{}\n", client.handle.describe()).as_bytes())?;
+                }
+
+                "list" => {
+                    if arguments.len() > 1 {
+                        return fail(response_buff, b"ERR syntax error");
+                    }
+                    client.update_details("client|list");
+                    let list = CLIENTS.list().iter().map(|handle| format!("{}\n", handle.describe())).collect::<String>();
+                    write_bulk_string(response_buff, list.as_bytes())?;
+                }
+
+                "no-evict" => {
+                    let mode = arguments.get(1).and_then(|a| a.string()).unwrap_or_default().to_lowercase();
+                    match mode.as_str() {
+                        "on" | "off" => {
+                            client.handle.no_evict.store(mode == "on", Ordering::Relaxed);
+                            write_ok(response_buff)?;
+                        }
+                        _ => return fail(response_buff, b"ERR syntax error"),
+                    }
+                }
+
+                _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+            }
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+async fn execute_debug(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+    match subcommand.as_str() {
+        // DEBUG EXPORT [db], the database as sorted JSON for comparing two servers
+        "export" => {
+            let db_id = match arguments.get(1) {
+                Some(db) => db.string().and_then(|db| db.parse::<usize>().ok()),
+                None => Some(client.session.selected_db),
+            };
+            let Some(db_id) = db_id.filter(|db_id| *db_id < DATABASES) else {
+                return fail(response_buff, b"ERR DB index is out of range");
+            };
+            write_bulk_string(response_buff, export_database(db_id).await.as_bytes())?;
+        }
+
+        // DEBUG LISTPACK key, how a compactly encoded value is laid out. A list reports each of
+        // its nodes, the other types the entries of their single listpack.
+        "listpack" => {
+            let Some(key) = arguments.get(1).and_then(|key| key.bytes()) else {
+                return fail(response_buff, b"ERR wrong number of arguments for 'debug|listpack' command");
+            };
+            let Some(entry) = db_peek(client.session.selected_db, &key).await else {
+                return fail(response_buff, b"ERR no such key");
+            };
+
+            let encoding = entry.value.encoding_name();
+            let layout = match &entry.value {
+                DataType::List(list) => {
+                    let mut layout = format!("encoding:{} entries:{} nodes:{}\n", encoding, list.len(), list.node_count());
+                    for (index, (entries, bytes)) in list.node_sizes().enumerate() {
+                        layout.push_str(&format!("node:{} entries:{} bytes:{}\n", index, entries, bytes));
+                    }
+                    layout
+                }
+                // Fields and values, or members and scores, each take an entry
+                DataType::Hash(hash) if encoding.starts_with("listpack") => format!("encoding:{} entries:{}\n", encoding, hash.len() * 2),
+                DataType::SortedSet(zset) if encoding == "listpack" => format!("encoding:{} entries:{}\n", encoding, zset.len() * 2),
+                DataType::Set(members) if encoding == "listpack" => format!("encoding:{} entries:{}\n", encoding, members.len()),
+                _ => return fail(response_buff, b"ERR The value stored at the specified key is not represented using an listpack"),
+            };
+            server_log!(Debug, "DEBUG LISTPACK {}\n{}", String::from_utf8_lossy(&key), layout);
+            write_bulk_string(response_buff, layout.as_bytes())?;
+        }
+
+        // DEBUG QUICKLIST-PACKED-THRESHOLD size, list elements bigger than this get a node of
+        // their own from now on
+        "quicklist-packed-threshold" => {
+            let threshold = arguments.get(1).and_then(|size| size.string()).and_then(|size| parse_memory(&size).ok());
+            let Some(threshold) = threshold.filter(|threshold| (1..=MAX_PACKED_THRESHOLD).contains(threshold)) else {
+                return fail(response_buff, b"ERR argument must be a memory value bigger than 1 and smaller than 4gb");
+            };
+            set_packed_threshold(threshold);
+            write_ok(response_buff)?;
+        }
+
+        // Crash on purpose, skipping the shutdown save and leaving files as they are, so tests
+        // can check what a restart recovers
+        "panic" | "segfault" | "oom" => {
+            if !CONFIG.read().await.enable_fault_injection {
+                return fail(response_buff, b"ERR DEBUG fault injection is disabled, set enable-fault-injection to yes to allow it");
+            }
+
+            server_log!(Warning, "DEBUG {} requested by client {}, crashing", subcommand.to_uppercase(), client.session.id);
+            match subcommand.as_str() {
+                "panic" => std::process::exit(1),
+                "segfault" => std::process::abort(),
+                _ => std::alloc::handle_alloc_error(std::alloc::Layout::from_size_align(usize::MAX / 2, 1)?),
+            }
+        }
+
+        _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+    }
+
+    Ok(Outcome::Done)
+}
+
+async fn execute_object(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+    if !matches!(subcommand.as_str(), "encoding" | "refcount" | "idletime" | "freq") {
+        return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes());
+    }
+    let Some(key) = arguments.get(1).and_then(|a| a.bytes()).filter(|_| arguments.len() == 2) else {
+        return fail(response_buff, format!("ERR wrong number of arguments for 'object|{}' command", subcommand).as_bytes());
+    };
+    let Some(entry) = db_peek(client.session.selected_db, &key).await else {
+        write_nil_bulk_string(response_buff)?;
+        return Ok(Outcome::Done);
+    };
+
+    match subcommand.as_str() {
+        "encoding" => write_bulk_string(response_buff, entry.value.encoding_name().as_bytes())?,
+        "refcount" => write_integer(response_buff, entry.value.refcount())?,
+        "idletime" => write_integer(response_buff, entry.last_access.idle_seconds() as i64)?,
+        // Access frequency is only kept for the LFU eviction policies, which don't exist here
+        _ => return fail(response_buff, b"ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust."),
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// Elements MEMORY USAGE looks at in a collection unless told otherwise
+const MEMORY_USAGE_SAMPLES: usize = 5;
+
+async fn execute_memory(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+    match subcommand.as_str() {
+        "usage" => {
+            let Some(key) = arguments.get(1).and_then(|a| a.bytes()) else {
+                return fail(response_buff, b"ERR wrong number of arguments for 'memory|usage' command");
+            };
+            let samples = match &arguments[2..] {
+                [] => MEMORY_USAGE_SAMPLES,
+                [option, count] if option.string().is_some_and(|o| o.eq_ignore_ascii_case("samples")) => {
+                    let Some(count) = count.string().and_then(|c| c.parse::<usize>().ok()) else {
+                        return fail(response_buff, b"ERR value is not an integer or out of range");
+                    };
+                    count
+                }
+                _ => {
+                    return fail(response_buff, b"ERR syntax error");
+                }
+            };
+            match db_memory_usage(client.session.selected_db, &key, samples).await {
+                Some(bytes) => write_integer(response_buff, bytes as i64)?,
+                None => write_nil_bulk_string(response_buff)?,
+            }
+        }
+
+        "stats" => {
+            let bulk = |s: String| ResponseType::BulkString(s.into_bytes().into());
+            let mut stats = Vec::new();
+            let allocated = allocator_stats().map(|stats| stats.allocated);
+            if let Some(allocated) = allocated {
+                stats.push(bulk("total.allocated".to_string()));
+                stats.push(ResponseType::Integer(allocated as i64));
+            }
+            let clients = CLIENTS.total_memory();
+            stats.push(bulk("clients.normal".to_string()));
+            stats.push(ResponseType::Integer(clients as i64));
+
+            let (mut keys, mut used, mut overhead) = (0, 0, 0);
+            for (id, database) in db_memory_stats().await.into_iter().enumerate() {
+                if database.keys == 0 {
+                    continue;
+                }
+                let table = database.keys * ENTRY_OVERHEAD;
+                stats.push(bulk(format!("db.{}", id)));
+                stats.push(ResponseType::Array(vec![
+                    bulk("overhead.hashtable.main".to_string()),
+                    ResponseType::Integer(table as i64),
+                ]));
+                keys += database.keys;
+                used += database.used;
+                overhead += table;
+            }
+
+            let dataset = used.saturating_sub(overhead);
+            stats.push(bulk("overhead.total".to_string()));
+            stats.push(ResponseType::Integer((overhead + clients) as i64));
+            stats.push(bulk("keys.count".to_string()));
+            stats.push(ResponseType::Integer(keys as i64));
+            stats.push(bulk("keys.bytes-per-key".to_string()));
+            stats.push(ResponseType::Integer(used.checked_div(keys).unwrap_or(0) as i64));
+            stats.push(bulk("dataset.bytes".to_string()));
+            stats.push(ResponseType::Integer(dataset as i64));
+            if let Some(allocated) = allocated {
+                stats.push(bulk("dataset.percentage".to_string()));
+                stats.push(bulk(format_double(ratio(dataset, allocated) * 100.0)));
+            }
+            write_resp(response_buff, &ResponseType::Array(stats)).await?;
+        }
+
+        _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+    }
+
+    Ok(Outcome::Done)
+}
+
+async fn execute_scan(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let Some(cursor) = arguments[0].string().and_then(|cursor| cursor.parse::<usize>().ok()) else {
+        return fail(response_buff, b"ERR invalid cursor");
+    };
+
+    let mut count = 10;
+    let mut filter = ScanFilter::default();
+    let mut options = arguments[1..].iter().map(|a| a.string().unwrap_or_default());
+    while let Some(option) = options.next() {
+        let option = option.to_uppercase();
+        let Some(value) = options.next() else {
+            return fail(response_buff, b"ERR syntax error");
+        };
+        match option.as_str() {
+            "MATCH" => filter.pattern = Some(value.into()),
+            "TYPE" => filter.type_name = Some(value),
+            "COUNT" => match value.parse::<usize>() {
+                Ok(value) if value > 0 => count = value,
+                Ok(_) => {
+                    return fail(response_buff, b"ERR syntax error");
+                }
+                Err(_) => {
+                    return fail(response_buff, b"ERR value is not an integer or out of range");
+                }
+            },
+            _ => {
+                return fail(response_buff, b"ERR syntax error");
+            }
+        }
+    }
+
+    let (cursor, keys) = db_scan(client.session.selected_db, cursor, count, filter).await;
+    write_resp(response_buff, &ResponseType::Array(vec![
+        ResponseType::BulkString(cursor.to_string().into_bytes().into()),
+        ResponseType::Array(keys.into_iter().map(ResponseType::BulkString).collect()),
+    ])).await?;
+    Ok(Outcome::Done)
+}
+
+/// The FIELDS numfields field [field ...] block that ends the commands dealing with hash field
+/// expiration
+fn parse_hash_fields(arguments: &[ResponseType]) -> Result<Vec<Bytes>, &'static str> {
+    if !arguments.first().and_then(|a| a.string()).is_some_and(|a| a.eq_ignore_ascii_case("fields")) {
+        return Err("ERR Mandatory argument FIELDS is missing or not at the right position");
+    }
+    let numfields = arguments
+        .get(1)
+        .and_then(|a| a.string())
+        .and_then(|a| a.parse::<usize>().ok())
+        .filter(|numfields| *numfields > 0)
+        .ok_or("ERR Parameter `numFields` should be greater than 0")?;
+    if numfields != arguments.len() - 2 {
+        return Err("ERR The `numfields` parameter must match the number of arguments");
+    }
+    Ok(arguments[2..].iter().map(|field| field.bytes().unwrap_or_default()).collect())
+}
+
+/// HEXPIRE, HPEXPIRE, HEXPIREAT and HPEXPIREAT, which work like `execute_expire` on the fields
+/// of a hash. Replicas are sent the absolute expiration of the fields it was set on and an HDEL
+/// of those it removed.
+async fn execute_hexpire(
+    client: &mut RedisClientConnection,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>,
+    name: &str,
+    unit_ms: i64,
+    absolute: bool
+) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let Some(time) = arguments[1].string().and_then(|time| time.parse::<i64>().ok()) else {
+        return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+    };
+
+    // At most one of NX, XX, GT and LT, ahead of the fields
+    let mut condition = ExpireCondition::default();
+    let mut rest = &arguments[2..];
+    let option = rest[0].string().unwrap_or_default();
+    match option.to_uppercase().as_str() {
+        "NX" => condition.nx = true,
+        "XX" => condition.xx = true,
+        "GT" => condition.gt = true,
+        "LT" => condition.lt = true,
+        _ => {}
+    }
+    if condition.nx || condition.xx || condition.gt || condition.lt {
+        rest = &rest[1..];
+    }
+    let fields = match parse_hash_fields(rest) {
+        Ok(fields) => fields,
+        Err(e) => {
+            return fail(response_buff, e.as_bytes());
+        }
+    };
+
+    let base_ms = if absolute { 0 } else { unix_millis(clock::now()) };
+    let Some(at_ms) = time.checked_mul(unit_ms).and_then(|time| time.checked_add(base_ms)).filter(|_| time >= 0) else {
+        return fail(response_buff, format!("ERR invalid expire time in '{}' command", name).as_bytes());
+    };
+
+    let changes = match db_hash_set_expiry(client.session.selected_db, key.clone(), fields.clone(), from_unix_millis(at_ms), condition).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+
+    let changed = |kind: ExpiryChange| -> Vec<ResponseType> {
+        fields
+            .iter()
+            .zip(changes.iter())
+            .filter(|(_, change)| **change == Some(kind))
+            .map(|(field, _)| ResponseType::BulkString(field.clone()))
+            .collect()
+    };
+    let (updated, deleted) = (changed(ExpiryChange::Updated), changed(ExpiryChange::Deleted));
+    client.suppress_propagation();
+    if !updated.is_empty() {
+        let mut command = vec![
+            bulk_string("HPEXPIREAT"),
+            ResponseType::BulkString(key.clone()),
+            bulk_string(&at_ms.to_string()),
+            bulk_string("FIELDS"),
+            bulk_string(&updated.len().to_string()),
+        ];
+        command.extend(updated);
+        client.also_propagate(command);
+    }
+    if !deleted.is_empty() {
+        let mut command = vec![bulk_string("HDEL"), ResponseType::BulkString(key)];
+        command.extend(deleted);
+        client.also_propagate(command);
+    }
+
+    let replies = changes
+        .into_iter()
+        .map(|change| ResponseType::Integer(match change {
+            None => -2,
+            Some(ExpiryChange::Unchanged) => 0,
+            Some(ExpiryChange::Updated) => 1,
+            Some(ExpiryChange::Deleted) => 2,
+        }))
+        .collect();
+    write_resp(response_buff, &ResponseType::Array(replies)).await?;
+    Ok(Outcome::Done)
+}
+
+/// SINTERCARD numkeys key [key ...] [LIMIT limit]
+async fn execute_sintercard(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let (keys, rest) = match parse_numkeys(arguments) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return fail(response_buff, e.as_bytes());
+        }
+    };
+    let limit = match rest {
+        [] => 0,
+        [option, limit] if option.string().is_some_and(|option| option.eq_ignore_ascii_case("limit")) => {
+            match limit.string().and_then(|limit| limit.parse::<i64>().ok()) {
+                Some(limit) if limit >= 0 => limit as usize,
+                _ => {
+                    return fail(response_buff, b"ERR LIMIT can't be negative");
+                }
+            }
+        }
+        _ => {
+            return fail(response_buff, b"ERR syntax error");
+        }
+    };
+
+    // Every key is type checked, even once one turns out to be missing
+    let db_id = client.session.selected_db;
+    let mut sizes = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        let read = db_read(db_id, key, |value| match value {
+            DataType::Set(set) => Ok(set.len()),
+            _ => Err(ValueError::WrongType),
+        }).await;
+        match read.transpose() {
+            Ok(size) => sizes.push((size.unwrap_or(0), key)),
+            Err(e) => {
+                return fail(response_buff, e.to_string().as_bytes());
+            }
+        }
+    }
+
+    // The smallest set's members are the candidates, each of the other sets, smallest first,
+    // keeps those it has too. The sets themselves are only looked at where they're stored.
+    sizes.sort_by_key(|(size, _)| *size);
+    let mut candidates = Vec::new();
+    for (i, (_, key)) in sizes.into_iter().enumerate() {
+        if i > 0 && candidates.is_empty() {
+            break;
+        }
+        candidates = db_read(db_id, key, move |value| match value {
+            DataType::Set(set) if i == 0 => set.iter().cloned().collect(),
+            DataType::Set(set) => candidates.into_iter().filter(|member: &Bytes| set.contains(member)).collect(),
+            _ => Vec::new(),
+        }).await.unwrap_or_default();
+    }
+    let size = if limit == 0 { candidates.len() } else { candidates.len().min(limit) };
+    write_integer(response_buff, size as i64)?;
+    Ok(Outcome::Done)
+}
+
+/// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+async fn execute_zadd(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let mut options = AddOptions::default();
+    let mut changed = false;
+    let mut next = 1;
+    while let Some(option) = arguments.get(next).and_then(|option| option.string()) {
+        match option.to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "GT" => options.gt = true,
+            "LT" => options.lt = true,
+            "CH" => changed = true,
+            "INCR" => options.incr = true,
+            _ => break,
+        }
+        next += 1;
+    }
+
+    let pairs = &arguments[next..];
+    if pairs.is_empty() || pairs.len() & 1 == 1 {
+        return fail(response_buff, b"ERR syntax error");
+    }
+    if options.nx && options.xx {
+        return fail(response_buff, b"ERR XX and NX options at the same time are not compatible");
+    }
+    if (options.gt || options.lt) && (options.nx || (options.gt && options.lt)) {
+        return fail(response_buff, b"ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    if options.incr && pairs.len() > 2 {
+        return fail(response_buff, b"ERR INCR option supports a single increment-element pair");
+    }
+
+    let mut members = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        let Some(score) = pair[0].bytes().and_then(|score| parse_score(&score)) else {
+            return fail(response_buff, ValueError::NotAFloat.to_string().as_bytes());
+        };
+        members.push((score, pair[1].bytes().unwrap_or_default()));
+    }
+
+    let outcomes = match db_zadd(client.session.selected_db, key, members, options).await {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+
+    // INCR replies with the new score, or nil when the options ruled the change out
+    if options.incr {
+        match outcomes.first() {
+            Some(AddOutcome::Added(score) | AddOutcome::Updated(score) | AddOutcome::Unchanged(score)) => {
+                write_bulk_string(response_buff, format_double(*score).as_bytes())?;
+            }
+            _ => {
+                client.suppress_propagation();
+                write_nil_bulk_string(response_buff)?;
+            }
+        }
+        return Ok(Outcome::Done);
+    }
+
+    let counted = outcomes
+        .iter()
+        .filter(|outcome| match outcome {
+            AddOutcome::Added(_) => true,
+            AddOutcome::Updated(_) => changed,
+            _ => false,
+        })
+        .count();
+    write_integer(response_buff, counted as i64)?;
+    Ok(Outcome::Done)
+}
+
+/// How ZRANGE picks members: by rank, by score or by comparing the members themselves
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ZrangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// A ZRANGE range once its ends are parsed
+enum ZrangeRange {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// ZRANGE, and the older commands that each do one kind of range it can do. Only ZRANGE itself
+/// takes BYSCORE, BYLEX and REV, the others are tied to one kind and direction.
+async fn execute_zrange(client: &mut RedisClientConnection, command: Command, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let (by, rev) = match command {
+        Command::Zrangebyscore => (Some(ZrangeBy::Score), false),
+        Command::Zrevrangebyscore => (Some(ZrangeBy::Score), true),
+        Command::Zrangebylex => (Some(ZrangeBy::Lex), false),
+        Command::Zrevrangebylex => (Some(ZrangeBy::Lex), true),
+        Command::Zrevrange => (Some(ZrangeBy::Rank), true),
+        _ => (None, false),
+    };
+    let flexible = by.is_none();
+    let (mut by, mut rev) = (by, rev);
+    let mut with_scores = false;
+    let mut limit = None;
+
+    let mut options = arguments[3..].iter();
+    while let Some(option) = options.next() {
+        match option.string().unwrap_or_default().to_uppercase().as_str() {
+            "WITHSCORES" => with_scores = true,
+            "LIMIT" if options.len() >= 2 => {
+                let mut next = || options.next().and_then(|value| value.string()).and_then(|value| value.parse::<i64>().ok());
+                let (Some(offset), Some(count)) = (next(), next()) else {
+                    return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+                };
+                limit = Some((offset, count));
+            }
+            "BYSCORE" if flexible && by.is_none() => by = Some(ZrangeBy::Score),
+            "BYLEX" if flexible && by.is_none() => by = Some(ZrangeBy::Lex),
+            "REV" if flexible && !rev => rev = true,
+            _ => {
+                return fail(response_buff, b"ERR syntax error");
+            }
+        }
+    }
+
+    let by = by.unwrap_or(ZrangeBy::Rank);
+    if limit.is_some() && by == ZrangeBy::Rank {
+        return fail(response_buff, b"ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX");
+    }
+    if with_scores && by == ZrangeBy::Lex {
+        return fail(response_buff, b"ERR syntax error, WITHSCORES not supported in combination with BYLEX");
+    }
+
+    // Reversed score and lex ranges are given highest end first
+    let (min, max) = match (by, rev) {
+        (ZrangeBy::Score | ZrangeBy::Lex, true) => (&arguments[2], &arguments[1]),
+        _ => (&arguments[1], &arguments[2]),
+    };
+    let min = min.bytes().unwrap_or_default();
+    let max = max.bytes().unwrap_or_default();
+    let range = match by {
+        ZrangeBy::Rank => {
+            let parse = |rank: &[u8]| std::str::from_utf8(rank).ok().and_then(|rank| rank.parse::<i64>().ok());
+            let (Some(start), Some(stop)) = (parse(&min), parse(&max)) else {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            };
+            ZrangeRange::Rank(start, stop)
+        }
+        ZrangeBy::Score => {
+            let (Some(min), Some(max)) = (ScoreBound::parse(&min), ScoreBound::parse(&max)) else {
+                return fail(response_buff, b"ERR min or max is not a float");
+            };
+            ZrangeRange::Score(min, max)
+        }
+        ZrangeBy::Lex => {
+            let (Some(min), Some(max)) = (LexBound::parse(&min), LexBound::parse(&max)) else {
+                return fail(response_buff, b"ERR min or max not valid string range item");
+            };
+            ZrangeRange::Lex(min, max)
+        }
+    };
+
+    // A negative offset leaves nothing, a negative count no limit
+    let (offset, count) = limit.unwrap_or((0, -1));
+    let reply = db_read(client.session.selected_db, &key, move |value| {
+        let DataType::SortedSet(zset) = value else {
+            return Err(ValueError::WrongType);
+        };
+        let members = match range {
+            _ if offset < 0 => Vec::new(),
+            ZrangeRange::Rank(start, stop) => {
+                // Reversed ranks count from the highest score, which is the same stretch of
+                // members counted from the other end
+                let len = zset.len();
+                let ranks = normalize_range(start, stop, len);
+                let ranks = if rev { len - ranks.end..len - ranks.start } else { ranks };
+                pick_range(zset.range_by_rank(ranks), rev, 0, count)
+            }
+            ZrangeRange::Score(min, max) => pick_range(zset.range_by_score(min, max), rev, offset as usize, count),
+            ZrangeRange::Lex(min, max) => pick_range(zset.range_by_lex(&min, &max), rev, offset as usize, count),
+        };
+
+        let mut reply = Vec::with_capacity(members.len() * if with_scores { 2 } else { 1 });
+        for (member, score) in members {
+            reply.push(ResponseType::BulkString(member.clone()));
+            if with_scores {
+                reply.push(bulk_string(&format_double(score)));
+            }
+        }
+        Ok(reply)
+    }).await;
+
+    let reply = match reply.transpose() {
+        Ok(reply) => reply.unwrap_or_default(),
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+    write_resp(response_buff, &ResponseType::Array(reply)).await?;
+    Ok(Outcome::Done)
+}
+
+/// The members of a range in the order asked for, after skipping `offset` of them and keeping at
+/// most `count`, or all when it's negative
+fn pick_range<'a>(members: impl DoubleEndedIterator<Item = (&'a Bytes, f64)>, rev: bool, offset: usize, count: i64) -> Vec<(&'a Bytes, f64)> {
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    if rev {
+        members.rev().skip(offset).take(count).collect()
+    } else {
+        members.skip(offset).take(count).collect()
+    }
+}
+
+/// The cursor and options HSCAN and SSCAN take after the key, NOVALUES being HSCAN's alone
+struct ElementScan {
+    cursor: u64,
+    count: usize,
+    pattern: Option<String>,
+    with_values: bool,
+}
+
+impl ElementScan {
+    fn parse(arguments: &[ResponseType], allow_novalues: bool) -> Result<Self, &'static str> {
+        let Some(cursor) = arguments[0].string().and_then(|cursor| cursor.parse::<u64>().ok()) else {
+            return Err("ERR invalid cursor");
+        };
+
+        let mut scan = ElementScan {
+            cursor,
+            count: 10,
+            pattern: None,
+            with_values: true,
+        };
+        let mut options = arguments[1..].iter().map(|a| a.string().unwrap_or_default());
+        while let Some(option) = options.next() {
+            let option = option.to_uppercase();
+            if allow_novalues && option == "NOVALUES" {
+                scan.with_values = false;
+                continue;
+            }
+            let Some(value) = options.next() else {
+                return Err("ERR syntax error");
+            };
+            match option.as_str() {
+                "MATCH" => scan.pattern = Some(value),
+                "COUNT" => match value.parse::<usize>() {
+                    Ok(value) if value > 0 => scan.count = value,
+                    Ok(_) => return Err("ERR syntax error"),
+                    Err(_) => return Err("ERR value is not an integer or out of range"),
+                },
+                _ => return Err("ERR syntax error"),
+            }
+        }
+        Ok(scan)
+    }
+
+    fn matches(&self, element: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob_match(pattern.as_bytes(), element, false),
+            None => true,
+        }
+    }
+}
+
+/// The reply to HSCAN and SSCAN
+async fn write_element_scan(response_buff: &mut Writer<Vec<u8>>, cursor: u64, elements: Vec<ResponseType>) -> Result<(), anyhow::Error> {
+    write_resp(response_buff, &ResponseType::Array(vec![
+        ResponseType::BulkString(cursor.to_string().into_bytes().into()),
+        ResponseType::Array(elements),
+    ])).await?;
+    Ok(())
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES], see `hash::scan_fields`
+async fn execute_hscan(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let scan = match ElementScan::parse(&arguments[1..], true) {
+        Ok(scan) => scan,
+        Err(e) => {
+            return fail(response_buff, e.as_bytes());
+        }
+    };
+
+    let batch = db_read(client.session.selected_db, &key, move |value| {
+        let DataType::Hash(hash) = value else {
+            return Err(ValueError::WrongType);
+        };
+        let (cursor, batch) = scan_fields(hash.fields(), scan.cursor, scan.count);
+        let mut elements = Vec::new();
+        for (field, value) in batch.into_iter().filter(|(field, _)| scan.matches(field)) {
+            elements.push(ResponseType::BulkString(field.clone()));
+            if scan.with_values {
+                elements.push(ResponseType::BulkString(value.clone()));
+            }
+        }
+        Ok((cursor, elements))
+    }).await;
+
+    // A missing key scans as an empty hash
+    let (cursor, elements) = match batch.transpose() {
+        Ok(batch) => batch.unwrap_or_default(),
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+    write_element_scan(response_buff, cursor, elements).await?;
+    Ok(Outcome::Done)
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count], see `set::scan_members`
+async fn execute_sscan(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let scan = match ElementScan::parse(&arguments[1..], false) {
+        Ok(scan) => scan,
+        Err(e) => {
+            return fail(response_buff, e.as_bytes());
+        }
+    };
+
+    let batch = db_read(client.session.selected_db, &key, move |value| {
+        let DataType::Set(set) = value else {
+            return Err(ValueError::WrongType);
+        };
+        let (cursor, batch) = scan_members(set.members(), scan.cursor, scan.count);
+        let elements = batch
+            .into_iter()
+            .filter(|member| scan.matches(member))
+            .map(|member| ResponseType::BulkString(member.clone()))
+            .collect();
+        Ok((cursor, elements))
+    }).await;
+
+    // A missing key scans as an empty set
+    let (cursor, elements) = match batch.transpose() {
+        Ok(batch) => batch.unwrap_or_default(),
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+    write_element_scan(response_buff, cursor, elements).await?;
+    Ok(Outcome::Done)
+}
+
+async fn execute_restore(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let Some(ttl) = arguments[1].string().and_then(|ttl| ttl.parse::<i64>().ok()) else {
+        return fail(response_buff, b"ERR value is not an integer or out of range");
+    };
+    if ttl < 0 {
+        return fail(response_buff, b"ERR Invalid TTL value, must be >= 0");
+    }
+    let payload = arguments[2].bytes().unwrap_or_default();
+
+    let (mut replace, mut absolute) = (false, false);
+    for option in &arguments[3..] {
+        match option.string().unwrap_or_default().to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absolute = true,
+            _ => {
+                return fail(response_buff, b"ERR syntax error");
+            }
+        }
+    }
+
+    let value = match RdbReader::restore(&payload).await {
+        Ok(value) => value,
+        Err(e @ RdbReadError::InvalidDumpPayload) => {
+            return fail(response_buff, format!("ERR {}", e).as_bytes());
+        }
+        Err(_) => {
+            return fail(response_buff, b"ERR Bad data format");
+        }
+    };
+
+    // A TTL of 0 means the key doesn't expire
+    let at_ms = match (ttl, absolute) {
+        (0, _) => None,
+        (ttl, true) => Some(ttl),
+        (ttl, false) => Some(unix_millis(clock::now()).saturating_add(ttl)),
+    };
+    if !db_insert(client.session.selected_db, key.clone(), value, at_ms.map(from_unix_millis), replace).await {
+        return fail(response_buff, b"BUSYKEY Target key name already exists.");
+    }
+
+    // Replicas get the absolute expiration, which doesn't move with the replication delay
+    if let (Some(at_ms), false) = (at_ms, absolute) {
+        let mut command = vec![
+            ResponseType::BulkString(Bytes::from_static(b"RESTORE")),
+            ResponseType::BulkString(key),
+            ResponseType::BulkString(at_ms.to_string().into_bytes().into()),
+            ResponseType::BulkString(Bytes::copy_from_slice(&payload)),
+            ResponseType::BulkString(Bytes::from_static(b"ABSTTL")),
+        ];
+        if replace {
+            command.push(ResponseType::BulkString(Bytes::from_static(b"REPLACE")));
+        }
+        client.also_propagate(command);
+    }
+    write_ok(response_buff)?;
+    Ok(Outcome::Done)
+}
+
+async fn execute_copy(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let source = arguments[0].bytes().unwrap_or_default();
+    let destination = arguments[1].bytes().unwrap_or_default();
+    let mut destination_db = client.session.selected_db;
+    let mut replace = false;
+
+    let mut options = arguments[2..].iter().map(|a| a.string().unwrap_or_default());
+    while let Some(option) = options.next() {
+        match option.to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "DB" => {
+                let Some(db) = options.next().and_then(|db| db.parse::<usize>().ok()) else {
+                    return fail(response_buff, b"ERR value is not an integer or out of range");
+                };
+                if db >= DATABASES {
+                    return fail(response_buff, b"ERR DB index is out of range");
+                }
+                destination_db = db;
+            }
+            _ => {
+                return fail(response_buff, b"ERR syntax error");
+            }
+        }
+    }
+
+    if destination_db == client.session.selected_db && source == destination {
+        return fail(response_buff, b"ERR source and destination objects are the same");
+    }
+
+    let copied = db_copy(client.session.selected_db, &source, destination_db, destination, replace).await;
+    if !copied {
+        client.suppress_propagation();
+    }
+    write_integer(response_buff, copied as i64)?;
+    Ok(Outcome::Done)
+}
+
+/// BITCOUNT key [start end [BYTE|BIT]] and BITPOS key bit [start [end [BYTE|BIT]]]
+async fn execute_bit_search(
+    client: &mut RedisClientConnection,
+    command: Command,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>
+) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let (target, range_arguments) = if command == Command::Bitpos {
+        match arguments[1].string().as_deref() {
+            Some("0") => (false, &arguments[2..]),
+            Some("1") => (true, &arguments[2..]),
+            _ => {
+                return fail(response_buff, b"ERR The bit argument must be 1 or 0.");
+            }
+        }
+    } else {
+        (true, &arguments[1..])
+    };
+
+    // BITCOUNT takes both ends or neither, BITPOS may leave out the end
+    let too_few = command == Command::Bitcount && range_arguments.len() == 1;
+    if too_few || range_arguments.len() > 3 {
+        return fail(response_buff, b"ERR syntax error");
+    }
+    let mut bounds = [0, -1];
+    for (bound, argument) in bounds.iter_mut().zip(range_arguments) {
+        let Some(value) = argument.string().and_then(|value| value.parse::<i64>().ok()) else {
+            return fail(response_buff, b"ERR value is not an integer or out of range");
+        };
+        *bound = value;
+    }
+    let end_given = range_arguments.len() >= 2;
+    let bit_unit = match range_arguments.get(2).and_then(|unit| unit.string()) {
+        None => false,
+        Some(unit) if unit.eq_ignore_ascii_case("byte") => false,
+        Some(unit) if unit.eq_ignore_ascii_case("bit") => true,
+        Some(_) => {
+            return fail(response_buff, b"ERR syntax error");
+        }
+    };
+
+    let value = match db_get(client.session.selected_db, &key).await? {
+        Some(DataType::String(value)) => value,
+        Some(_) => {
+            return fail(response_buff, ValueError::WrongType.to_string().as_bytes());
+        }
+        // A missing key is an empty string, whose padding is all clear bits
+        None => {
+            let reply = if command == Command::Bitpos && target { -1 } else { 0 };
+            write_integer(response_buff, reply)?;
+            return Ok(Outcome::Done);
+        }
+    };
+
+    let bits = if bit_unit {
+        normalize_range(bounds[0], bounds[1], value.len() * 8)
+    } else {
+        let bytes = normalize_range(bounds[0], bounds[1], value.len());
+        bytes.start * 8..bytes.end * 8
+    };
+    let reply = if command == Command::Bitcount {
+        count_bits(&value, bits) as i64
+    } else {
+        match find_bit(&value, bits.clone(), target) {
+            Some(index) => index as i64,
+            // Without an end, the string counts as padded with clear bits past its end
+            None if !target && !end_given && !bits.is_empty() => bits.end as i64,
+            None => -1,
+        }
+    };
+    write_integer(response_buff, reply)?;
+    Ok(Outcome::Done)
+}
+
+/// LPOP and RPOP key [count]. Without a count a single element is popped and sent on its own,
+/// with one the reply is always an array, even for a single element.
+async fn execute_pop(client: &mut RedisClientConnection, command: Command, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    if arguments.len() > 2 {
+        let name = if command == Command::Lpop { "lpop" } else { "rpop" };
+        return fail(response_buff, format!("ERR wrong number of arguments for '{}' command", name).as_bytes());
+    }
+
+    let key = arguments[0].bytes().unwrap_or_default();
+    let end = if command == Command::Lpop { ListEnd::Left } else { ListEnd::Right };
+    let count = match arguments.get(1) {
+        None => None,
+        Some(count) => match count.string().and_then(|count| count.parse::<i64>().ok()) {
+            Some(count) if count >= 0 => Some(count as usize),
+            _ => {
+                return fail(response_buff, b"ERR value is out of range, must be positive");
+            }
+        },
+    };
+
+    let popped = match db_pop(client.session.selected_db, key, end, count.unwrap_or(1)).await {
+        Ok(popped) => popped,
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+    match (popped, count) {
+        (Some(elements), Some(_)) => {
+            let elements = elements.into_iter().map(ResponseType::BulkString).collect();
+            write_resp(response_buff, &ResponseType::Array(elements)).await?;
+        }
+        (Some(elements), None) => match elements.into_iter().next() {
+            Some(element) => write_bulk_string(response_buff, &element)?,
+            None => write_nil_bulk_string(response_buff)?,
+        },
+        (None, Some(_)) => write_resp(response_buff, &ResponseType::NullArray).await?,
+        (None, None) => write_nil_bulk_string(response_buff)?,
+    }
+    Ok(Outcome::Done)
+}
+
+/// Parses the timeout of a blocking command, in seconds with a fraction. 0 waits forever and
+/// comes back as None.
+fn parse_timeout(timeout: &ResponseType) -> Result<Option<Duration>, &'static str> {
+    let Some(seconds) = timeout.string().and_then(|timeout| timeout.parse::<f64>().ok()).filter(|seconds| seconds.is_finite()) else {
+        return Err("ERR timeout is not a float or out of range");
+    };
+    if seconds < 0.0 {
+        return Err("ERR timeout is negative");
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(_) => Err("ERR timeout is out of range"),
+    }
+}
+
+/// What a command that pops from one of several keys does, the blocking ones once one of the keys
+/// they wait on can serve them
+enum BlockingPop {
+    /// BLPOP and BRPOP, one element from the first key holding a list
+    Pop { keys: Vec<Bytes>, end: ListEnd },
+    /// LMPOP and BLMPOP, up to `count` elements from the first key holding a list
+    MultiPop { keys: Vec<Bytes>, end: ListEnd, count: usize },
+    /// BLMOVE, which waits on its source
+    Move { source: Bytes, destination: Bytes, from: ListEnd, to: ListEnd },
+    /// BZPOPMIN and BZPOPMAX, the member with the lowest or highest score from the first key
+    /// holding a sorted set
+    ScorePop { keys: Vec<Bytes>, end: ScoreEnd },
+    /// BZMPOP, up to `count` members from the first key holding a sorted set
+    ScoreMultiPop { keys: Vec<Bytes>, end: ScoreEnd, count: usize },
+}
+
+impl BlockingPop {
+    fn keys(&self) -> Vec<Bytes> {
+        match self {
+            BlockingPop::Pop { keys, .. }
+            | BlockingPop::MultiPop { keys, .. }
+            | BlockingPop::ScorePop { keys, .. }
+            | BlockingPop::ScoreMultiPop { keys, .. } => keys.clone(),
+            BlockingPop::Move { source, .. } => vec![source.clone()],
+        }
+    }
+}
+
+fn list_end_name(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "LEFT",
+        ListEnd::Right => "RIGHT",
+    }
+}
+
+fn bulk_string(value: &str) -> ResponseType {
+    ResponseType::BulkString(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+/// Serves a pop if one of its keys can, returning the reply. What was popped
+/// is replicated as the non-blocking command that would have done the same.
+async fn try_pop(client: &mut RedisClientConnection, pop: &BlockingPop) -> Result<Option<ResponseType>, ValueError> {
+    let db_id = client.session.selected_db;
+    match pop {
+        BlockingPop::Pop { keys, end } | BlockingPop::MultiPop { keys, end, .. } => {
+            let count = match pop {
+                BlockingPop::MultiPop { count, .. } => *count,
+                _ => 1,
+            };
+            for key in keys.iter() {
+                let Some(popped) = db_pop(db_id, key.clone(), *end, count).await?.filter(|popped| !popped.is_empty()) else {
+                    continue;
+                };
+
+                let command = if *end == ListEnd::Left { "LPOP" } else { "RPOP" };
+                let elements = popped.into_iter().map(ResponseType::BulkString);
+                let reply = if let BlockingPop::Pop { .. } = pop {
+                    client.also_propagate(vec![bulk_string(command), ResponseType::BulkString(key.clone())]);
+                    [ResponseType::BulkString(key.clone())].into_iter().chain(elements).collect()
+                } else {
+                    let elements: Vec<ResponseType> = elements.collect();
+                    client.also_propagate(vec![bulk_string(command), ResponseType::BulkString(key.clone()), bulk_string(&elements.len().to_string())]);
+                    vec![ResponseType::BulkString(key.clone()), ResponseType::Array(elements)]
+                };
+                return Ok(Some(ResponseType::Array(reply)));
+            }
+            Ok(None)
+        }
+
+        BlockingPop::Move { source, destination, from, to } => {
+            let Some(element) = db_list_move(db_id, source.clone(), destination.clone(), *from, *to).await? else {
+                return Ok(None);
+            };
+            client.also_propagate(vec![
+                bulk_string("LMOVE"),
+                ResponseType::BulkString(source.clone()),
+                ResponseType::BulkString(destination.clone()),
+                bulk_string(list_end_name(*from)),
+                bulk_string(list_end_name(*to)),
+            ]);
+            Ok(Some(ResponseType::BulkString(element)))
+        }
+
+        BlockingPop::ScorePop { keys, end } => {
+            let Some((key, popped)) = try_zset_pop(client, keys, *end, 1).await? else {
+                return Ok(None);
+            };
+            let mut reply = vec![ResponseType::BulkString(key)];
+            for (member, score) in popped {
+                reply.push(ResponseType::BulkString(member));
+                reply.push(bulk_string(&format_double(score)));
+            }
+            Ok(Some(ResponseType::Array(reply)))
+        }
+
+        BlockingPop::ScoreMultiPop { keys, end, count } => {
+            let Some((key, popped)) = try_zset_pop(client, keys, *end, *count).await? else {
+                return Ok(None);
+            };
+            Ok(Some(ResponseType::Array(vec![ResponseType::BulkString(key), scored_pairs(popped)])))
+        }
+    }
+}
+
+/// Pops up to `count` members from the first of `keys` holding a sorted set, returning the key
+/// and what was popped. Replicated as the ZPOPMIN or ZPOPMAX that does the same.
+async fn try_zset_pop(client: &mut RedisClientConnection, keys: &[Bytes], end: ScoreEnd, count: usize) -> Result<Option<(Bytes, Vec<(Bytes, f64)>)>, ValueError> {
+    for key in keys.iter() {
+        let Some(popped) = db_zpop(client.session.selected_db, key.clone(), end, count).await?.filter(|popped| !popped.is_empty()) else {
+            continue;
+        };
+        let command = if end == ScoreEnd::Min { "ZPOPMIN" } else { "ZPOPMAX" };
+        client.also_propagate(vec![bulk_string(command), ResponseType::BulkString(key.clone()), bulk_string(&popped.len().to_string())]);
+        return Ok(Some((key.clone(), popped)));
+    }
+    Ok(None)
+}
+
+/// Popped members as the array of member and score pairs ZMPOP replies with
+fn scored_pairs(popped: Vec<(Bytes, f64)>) -> ResponseType {
+    ResponseType::Array(
+        popped
+            .into_iter()
+            .map(|(member, score)| ResponseType::Array(vec![ResponseType::BulkString(member), bulk_string(&format_double(score))]))
+            .collect()
+    )
+}
+
+/// Serves a blocking pop straight away if it can, otherwise waits on its keys until it
+/// can or `timeout` runs out
+async fn execute_blocking(
+    client: &mut RedisClientConnection,
+    pop: BlockingPop,
+    timeout: Option<Duration>,
+    response_buff: &mut Writer<Vec<u8>>
+) -> CommandResult {
+    let expired = async move {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(expired);
+
+    let mut blocked: Option<BlockedKeys> = None;
+    loop {
+        client.hold_exclusion().await;
+        match try_pop(client, &pop).await {
+            Ok(Some(reply)) => {
+                write_resp(response_buff, &reply).await?;
+                return Ok(Outcome::Done);
+            }
+            // A key of the wrong type is only an error before blocking, one that becomes
+            // something else while the client waits is passed over
+            Err(e) if blocked.is_none() => {
+                return fail(response_buff, e.to_string().as_bytes());
+            }
+            _ => {}
+        }
+
+        // Nothing can be pushed in the middle of a transaction, and the master's stream can't
+        // stall, so those behave as if the timeout ran out straight away
+        if client.pending_writes.is_some() || client.is_master_link {
+            break;
+        }
+        // A transaction may run while this client waits
+        client.exclusion = None;
+
+        let blocked = match blocked.as_ref() {
+            Some(blocked) => blocked,
+            None => {
+                // Replies to the commands pipelined before this one aren't held back by it
+                client.stream.flush().await?;
+                blocked.insert(BlockedKeys::new(client.session.selected_db, pop.keys()))
+            }
+        };
+        tokio::select! {
+            _ = blocked.ready() => {}
+            _ = &mut expired => break,
+            // Whatever the client sends meanwhile waits its turn in the read buffer
+            read = client.fill_read_buffer() => {
+                if read? == 0 {
+                    return Err(RespProtocolError::ConnectionClosed.into());
+                }
+            }
+        }
+    }
+
+    client.suppress_propagation();
+    write_nil_array(response_buff)?;
+    Ok(Outcome::Done)
+}
+
+/// The numkeys key [key ...] block commands taking a variable number of keys start with. Returns
+/// the keys and the arguments after them.
+fn parse_numkeys(arguments: &[ResponseType]) -> Result<(Vec<Bytes>, &[ResponseType]), &'static str> {
+    let Some(numkeys) = arguments[0].string().and_then(|numkeys| numkeys.parse::<i64>().ok()) else {
+        return Err("ERR numkeys should be greater than 0");
+    };
+    if numkeys <= 0 {
+        return Err("ERR numkeys should be greater than 0");
+    }
+    let numkeys = numkeys as usize;
+    if numkeys >= arguments.len() {
+        return Err("ERR Number of keys can't be greater than number of args");
+    }
+
+    let keys = arguments[1..=numkeys].iter().map(|key| key.bytes().unwrap_or_default()).collect();
+    Ok((keys, &arguments[numkeys + 1..]))
+}
+
+/// Reads the `numkeys key [key ...] end [COUNT count]` of LMPOP and ZMPOP and their blocking
+/// forms, the end being LEFT|RIGHT or MIN|MAX as `parse_end` reads it
+fn parse_multi_pop<E>(arguments: &[ResponseType], parse_end: impl Fn(&str) -> Option<E>) -> Result<(Vec<Bytes>, E, usize), &'static str> {
+    let (keys, rest) = parse_numkeys(arguments)?;
+    let Some(end) = rest.first().and_then(|end| end.string()).and_then(|end| parse_end(&end)) else {
+        return Err("ERR syntax error");
+    };
+    let count = match &rest[1..] {
+        [] => 1,
+        [option, count] if option.string().is_some_and(|option| option.eq_ignore_ascii_case("count")) => {
+            match count.string().and_then(|count| count.parse::<i64>().ok()) {
+                Some(count) if count > 0 => count as usize,
+                _ => return Err("ERR count should be greater than 0"),
+            }
+        }
+        _ => return Err("ERR syntax error"),
+    };
+    Ok((keys, end, count))
+}
+
+/// BLPOP, BRPOP, BLMOVE, BLMPOP, BZPOPMIN, BZPOPMAX and BZMPOP
+async fn execute_blocking_pop(client: &mut RedisClientConnection, command: Command, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let timeout_index = match command {
+        Command::Blmpop | Command::Bzmpop => 0,
+        _ => arguments.len() - 1,
+    };
+    let timeout = match parse_timeout(&arguments[timeout_index]) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            return fail(response_buff, e.as_bytes());
+        }
+    };
+
+    let pop = match command {
+        Command::Blmove => {
+            let ends = arguments[2..4].iter().map(|end| end.string().and_then(|end| ListEnd::parse(&end))).collect::<Option<Vec<_>>>();
+            let Some(ends) = ends else {
+                return fail(response_buff, b"ERR syntax error");
+            };
+            BlockingPop::Move {
+                source: arguments[0].bytes().unwrap_or_default(),
+                destination: arguments[1].bytes().unwrap_or_default(),
+                from: ends[0],
+                to: ends[1],
+            }
+        }
+        Command::Blmpop => match parse_multi_pop(&arguments[1..], ListEnd::parse) {
+            Ok((keys, end, count)) => BlockingPop::MultiPop { keys, end, count },
+            Err(e) => {
+                return fail(response_buff, e.as_bytes());
+            }
+        },
+        Command::Bzmpop => match parse_multi_pop(&arguments[1..], ScoreEnd::parse) {
+            Ok((keys, end, count)) => BlockingPop::ScoreMultiPop { keys, end, count },
+            Err(e) => {
+                return fail(response_buff, e.as_bytes());
+            }
+        },
+        Command::Bzpopmin | Command::Bzpopmax => BlockingPop::ScorePop {
+            keys: arguments[..timeout_index].iter().map(|key| key.bytes().unwrap_or_default()).collect(),
+            end: if command == Command::Bzpopmin { ScoreEnd::Min } else { ScoreEnd::Max },
+        },
+        _ => BlockingPop::Pop {
+            keys: arguments[..timeout_index].iter().map(|key| key.bytes().unwrap_or_default()).collect(),
+            end: if command == Command::Blpop { ListEnd::Left } else { ListEnd::Right },
+        },
+    };
+    execute_blocking(client, pop, timeout, response_buff).await
+}
+
+/// HRANDFIELD key [count [WITHVALUES]]. A positive count picks distinct fields, a negative one
+/// picks that many with repetition.
+async fn execute_hrandfield(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let count = match arguments.get(1) {
+        Some(count) => match count.string().and_then(|count| count.parse::<i64>().ok()) {
+            Some(count) => Some(count),
+            None => {
+                return fail(response_buff, ValueError::NotAnInteger.to_string().as_bytes());
+            }
+        },
+        None => None,
+    };
+    let with_values = match arguments.get(2) {
+        Some(modifier) if modifier.string().is_some_and(|modifier| modifier.eq_ignore_ascii_case("withvalues")) && arguments.len() == 3 => true,
+        Some(_) => {
+            return fail(response_buff, b"ERR syntax error");
+        }
+        None => false,
+    };
+    // The reply to a negative count is allocated up front, twice over with the values
+    if let Some(count) = count {
+        if count.unsigned_abs() > (i64::MAX / 2) as u64 {
+            return fail(response_buff, b"ERR value is out of range");
+        }
+    }
+
+    let key = arguments[0].bytes().unwrap_or_default();
+    let reply = db_read(client.session.selected_db, &key, move |value| {
+        let DataType::Hash(hash) = value else {
+            return Err(ValueError::WrongType);
+        };
+        let fields = hash.fields();
+
+        let Some(count) = count else {
+            let field = sample_distinct(fields.keys(), 1).pop().cloned();
+            return Ok(field.map_or(ResponseType::NullBulkString, ResponseType::BulkString));
+        };
+
+        let picked = if count >= 0 {
+            sample_distinct(fields.iter(), count as usize)
+        } else {
+            sample_with_repetition(fields.iter(), fields.len(), count.unsigned_abs() as usize)
+        };
+        Ok(ResponseType::Array(
+            picked
+                .into_iter()
+                .flat_map(|(field, value)| {
+                    let value = with_values.then(|| ResponseType::BulkString(value.clone()));
+                    std::iter::once(ResponseType::BulkString(field.clone())).chain(value)
+                })
+                .collect()
+        ))
+    }).await;
+
+    let reply = match reply.transpose() {
+        Ok(Some(reply)) => reply,
+        // A missing key reads as an empty hash
+        Ok(None) if count.is_some() => ResponseType::Array(Vec::new()),
+        Ok(None) => ResponseType::NullBulkString,
+        Err(e) => {
+            return fail(response_buff, e.to_string().as_bytes());
+        }
+    };
+    write_resp(response_buff, &reply).await?;
+    Ok(Outcome::Done)
+}
+
+/// BITOP op destination key [key ...]. The sources are read as shared references to the stored
+/// bytes rather than copies, and combined in a single pass.
+async fn execute_bitop(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let Some(op) = arguments[0].string().and_then(|op| BitOp::parse(&op)) else {
+        return fail(response_buff, b"ERR syntax error");
+    };
+    let destination = arguments[1].bytes().unwrap_or_default();
+    let keys = &arguments[2..];
+    if op == BitOp::Not && keys.len() != 1 {
+        return fail(response_buff, b"ERR BITOP NOT must be called with a single source key.");
+    }
+
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        match db_get(client.session.selected_db, &key.bytes().unwrap_or_default()).await? {
+            Some(DataType::String(value)) => sources.push(value),
+            Some(_) => {
+                return fail(response_buff, ValueError::WrongType.to_string().as_bytes());
+            }
+            None => sources.push(Bytes::new()),
+        }
+    }
+
+    let result = combine(op, &sources.iter().map(|source| source.as_ref()).collect::<Vec<_>>());
+    let length = result.len();
+    // An empty result removes the destination rather than storing an empty string
+    if result.is_empty() {
+        db_delete(client.session.selected_db, &destination).await;
+    } else {
+        db_set_expiring_at(client.session.selected_db, destination, result.into(), None).await?;
+    }
+    write_integer(response_buff, length as i64)?;
+    Ok(Outcome::Done)
+}
+
+/// Strings are limited to 512MB, so bit offsets are too
+const MAX_BIT_OFFSET: usize = 512 * 1024 * 1024 * 8 - 1;
+
+fn parse_bit_offset(offset: &str) -> Option<usize> {
+    offset.parse::<usize>().ok().filter(|offset| *offset <= MAX_BIT_OFFSET)
+}
+
+/// Replicates a write of a string with a relative time to live as SET with the absolute one, which
+/// would otherwise expire later on a replica that applies it late
+fn propagate_set_at(client: &mut RedisClientConnection, key: Bytes, value: Bytes, expiration: SystemTime) {
+    client.also_propagate(vec![
+        ResponseType::BulkString(Bytes::from_static(b"SET")),
+        ResponseType::BulkString(key),
+        ResponseType::BulkString(value),
+        ResponseType::BulkString(Bytes::from_static(b"PXAT")),
+        ResponseType::BulkString(unix_millis(expiration).to_string().into_bytes().into()),
+    ]);
+}
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, which take a time to live or, when `absolute`, a UNIX
+/// time in units of `unit_ms` milliseconds. Replicas are sent the absolute expiration so it
+/// doesn't move with the replication delay, or a DEL when it has already passed.
+async fn execute_expire(
+    client: &mut RedisClientConnection,
+    arguments: &[ResponseType],
+    response_buff: &mut Writer<Vec<u8>>,
+    name: &str,
+    unit_ms: i64,
+    absolute: bool
+) -> CommandResult {
+    let key = arguments[0].bytes().unwrap_or_default();
+    let Some(ttl) = arguments[1].string().and_then(|ttl| ttl.parse::<i64>().ok()) else {
+        return fail(response_buff, b"ERR value is not an integer or out of range");
+    };
+
+    let mut condition = ExpireCondition::default();
+    for option in &arguments[2..] {
+        let option = option.string().unwrap_or_default();
+        match option.to_uppercase().as_str() {
+            "NX" => condition.nx = true,
+            "XX" => condition.xx = true,
+            "GT" => condition.gt = true,
+            "LT" => condition.lt = true,
+            _ => {
+                return fail(response_buff, format!("ERR Unsupported option {}", option).as_bytes());
+            }
+        }
+    }
+    if condition.nx && (condition.xx || condition.gt || condition.lt) {
+        return fail(response_buff, b"ERR NX and XX, GT or LT options at the same time are not compatible");
+    }
+    if condition.gt && condition.lt {
+        return fail(response_buff, b"ERR GT and LT options at the same time are not compatible");
+    }
+
+    let base_ms = if absolute { 0 } else { unix_millis(clock::now()) };
+    let Some(at_ms) = ttl.checked_mul(unit_ms).and_then(|ttl| ttl.checked_add(base_ms)) else {
+        return fail(response_buff, format!("ERR invalid expire time in '{}' command", name).as_bytes());
+    };
+    let expiration = from_unix_millis(at_ms);
+
+    match db_set_expiry(client.session.selected_db, &key, expiration, condition).await {
+        ExpiryChange::Unchanged => {
+            client.suppress_propagation();
+            write_integer(response_buff, 0)?;
+        }
+        ExpiryChange::Updated => {
+            client.also_propagate(vec![
+                ResponseType::BulkString(Bytes::from_static(b"PEXPIREAT")),
+                ResponseType::BulkString(key.clone()),
+                ResponseType::BulkString(at_ms.to_string().into_bytes().into()),
+            ]);
+            write_integer(response_buff, 1)?;
+        }
+        ExpiryChange::Deleted => {
+            client.also_propagate(vec![
+                ResponseType::BulkString(Bytes::from_static(b"DEL")),
+                ResponseType::BulkString(key),
+            ]);
+            write_integer(response_buff, 1)?;
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+async fn execute_cluster(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+    let parse_slot = |argument: Option<&ResponseType>| {
+        argument
+            .and_then(|a| a.string())
+            .and_then(|slot| slot.parse::<u16>().ok())
+            .filter(|slot| (*slot as usize) < CLUSTER_SLOTS)
+    };
+
+    match subcommand.as_str() {
+        "myid" => {
+            let id = CLUSTER.read().await.myself.id.clone();
+            write_bulk_string(response_buff, id.as_bytes())?;
+        }
+
+        "keyslot" => {
+            let key = arguments.get(1).and_then(|a| a.bytes()).unwrap_or_default();
+            write_integer(response_buff, key_hash_slot(&key) as i64)?;
+        }
+
+        "meet" => {
+            let ip = arguments.get(1).and_then(|a| a.string());
+            let port = arguments.get(2).and_then(|a| a.string()).and_then(|p| p.parse::<u16>().ok());
+            let (Some(ip), Some(port)) = (ip, port) else {
+                return fail(response_buff, b"ERR Invalid node address specified");
+            };
+
+            match meet(ip, port).await {
+                Ok(_) => write_ok(response_buff)?,
+                Err(e) => return fail(response_buff, format!("ERR {}", e).as_bytes()),
+            }
+        }
+
+        "addslots" | "delslots" => {
+            let mut slots = Vec::new();
+            for argument in arguments[1..].iter() {
+                let Some(slot) = parse_slot(Some(argument)) else {
+                    return fail(response_buff, b"ERR Invalid or out of range slot");
+                };
+                slots.push(slot);
+            }
+
+            let mut cluster = CLUSTER.write().await;
+            let adding = subcommand == "addslots";
+            if let Some(slot) = slots.iter().find(|slot| cluster.is_slot_assigned(**slot) == adding) {
+                let error = if adding { format!("ERR Slot {} is already busy", slot) } else { format!("ERR Slot {} is already unassigned", slot) };
+                return fail(response_buff, error.as_bytes());
+            }
+
+            let owner = adding.then(|| cluster.myself.id.clone());
+            for slot in slots {
+                cluster.assign_slot(slot, owner.clone());
+            }
+            write_ok(response_buff)?;
+        }
+
+        "setslot" => {
+            let Some(slot) = parse_slot(arguments.get(1)) else {
+                return fail(response_buff, b"ERR Invalid or out of range slot");
+            };
+            let state = arguments.get(2).and_then(|a| a.string()).unwrap_or_default().to_lowercase();
+            let node_id = arguments.get(3).and_then(|a| a.string());
+
+            match CLUSTER.write().await.set_slot(slot, &state, node_id.as_deref()) {
+                Ok(_) => write_ok(response_buff)?,
+                Err(e) => return fail(response_buff, e.as_bytes()),
+            }
+        }
+
+        "getkeysinslot" => {
+            let count = arguments.get(2).and_then(|a| a.string()).and_then(|c| c.parse::<usize>().ok());
+            let (Some(slot), Some(count)) = (parse_slot(arguments.get(1)), count) else {
+                return fail(response_buff, b"ERR Invalid slot or number of keys");
+            };
+
+            let keys = db_keys_in_slot(client.session.selected_db, slot, count)
+                .await
+                .into_iter()
+                .map(ResponseType::BulkString)
+                .collect();
+            write_resp(response_buff, &ResponseType::Array(keys)).await?;
+        }
+
+        "countkeysinslot" => {
+            let Some(slot) = parse_slot(arguments.get(1)) else {
+                return fail(response_buff, b"ERR Invalid slot");
+            };
+            let count = db_count_keys_in_slot(client.session.selected_db, slot).await;
+            write_integer(response_buff, count as i64)?;
+        }
+
+        "nodes" => {
+            let nodes = CLUSTER.read().await.describe_nodes();
+            write_bulk_string(response_buff, nodes.as_bytes())?;
+        }
+
+        "info" => {
+            let cluster = CLUSTER.read().await;
+            let assigned = cluster.assigned_slots();
+            let state = if assigned == CLUSTER_SLOTS { "ok" } else { "fail" };
+            let info = format!(
+                "cluster_enabled:1\r\ncluster_state:{}\r\ncluster_slots_assigned:{}\r\ncluster_known_nodes:{}\r\n",
+                state, assigned, cluster.nodes.len() + 1
+            );
+            write_bulk_string(response_buff, info.as_bytes())?;
+        }
+
+        _ => return fail(response_buff, format!("ERR unknown subcommand '{}'", subcommand).as_bytes()),
+    }
+
+    Ok(Outcome::Done)
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password]
+/// [AUTH2 username password] [KEYS key [key ...]]
+async fn execute_migrate(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let host = arguments[0].string().unwrap_or_default();
+    let port = arguments[1].string().and_then(|p| p.parse::<u16>().ok());
+    let db_id = arguments[3].string().and_then(|d| d.parse::<usize>().ok());
+    let timeout = arguments[4].string().and_then(|t| t.parse::<i64>().ok());
+    let (Some(port), Some(db_id), Some(timeout)) = (port, db_id, timeout) else {
+        return fail(response_buff, b"ERR value is not an integer or out of range");
+    };
+
+    let mut copy = false;
+    let mut options = MigrateOptions {
+        replace: false,
+        auth: None,
+    };
+    let mut keys = vec![arguments[2].bytes().unwrap_or_default()];
+    let mut options_iter = arguments[5..].iter();
+    while let Some(option) = options_iter.next().and_then(|a| a.string()) {
+        match option.to_lowercase().as_str() {
+            "copy" => copy = true,
+            "replace" => options.replace = true,
+            "auth" => {
+                let Some(password) = options_iter.next().and_then(|a| a.string()) else {
+                    return fail(response_buff, b"ERR syntax error");
+                };
+                options.auth = Some((None, password));
+            }
+            "auth2" => {
+                let (Some(username), Some(password)) = (options_iter.next().and_then(|a| a.string()), options_iter.next().and_then(|a| a.string())) else {
+                    return fail(response_buff, b"ERR syntax error");
+                };
+                options.auth = Some((Some(username), password));
+            }
+            "keys" => {
+                if !keys[0].is_empty() {
+                    return fail(response_buff, b"ERR When using MIGRATE KEYS option, the key argument must be set to the empty string");
+                }
+                keys = options_iter.by_ref().filter_map(|a| a.bytes()).collect();
+            }
+            _ => {
+                return fail(response_buff, b"ERR syntax error");
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    for key in keys {
+        if let Some((value, expiration)) = db_get_with_expiration(client.session.selected_db, &key).await {
+            entries.push((key, value, expiration));
+        }
+    }
+    if entries.is_empty() {
+        write_simple_string(response_buff, b"NOKEY")?;
+        return Ok(Outcome::Done);
+    }
+
+    let timeout = Duration::from_millis(if timeout <= 0 { 1000 } else { timeout as u64 });
+    match tokio::time::timeout(timeout, migrate_keys(&host, port, db_id, &entries, &options)).await {
+        Err(_) => return fail(response_buff, b"IOERR error or timeout writing to target instance"),
+        Ok(Err(e)) => {
+            let message = e.to_string();
+            let error = if message.starts_with("BUSYKEY") { message } else { format!("ERR {}", message) };
+            return fail(response_buff, error.as_bytes());
+        }
+        Ok(Ok(_)) => {
+            if !copy {
+                let mut delete = vec![ResponseType::BulkString(Bytes::from_static(b"DEL"))];
+                for (key, _, _) in entries.iter() {
+                    db_delete(client.session.selected_db, key).await;
+                    delete.push(ResponseType::BulkString(key.clone()));
+                }
+                // Replicas drop the moved keys rather than running the migration themselves
+                client.also_propagate(delete);
+            } else {
+                client.suppress_propagation();
+            }
+            write_ok(response_buff)?;
+        }
+    }
+
+    Ok(Outcome::Done)
+}
+
+const INFO_SECTIONS: [&str; 5] = ["clients", "memory", "persistence", "stats", "replication"];
+
+async fn info_section(section: &str) -> String {
+    match section {
+        "clients" => clients_info(),
+        "memory" => memory_info().await,
+        "stats" => stats_info(),
+        "persistence" => persistence_info(),
+        "replication" => replication_info().await,
+        _ => String::new(),
+    }
+}
+
+fn clients_info() -> String {
+    let mut info = String::new();
+    info.push_str("# Clients\n");
+    info.push_str(&format!("connected_clients:{}\n", CLIENTS.connected()));
+    info.push_str(&format!("blocked_clients:{}\n", blocked_clients()));
+    info
+}
+
+async fn memory_info() -> String {
+    let mut info = String::new();
+    info.push_str("# Memory\n");
+    let stats = allocator_stats();
+    let rss = process_rss();
+    if let Some(stats) = stats.as_ref() {
+        info.push_str(&format!("used_memory:{}\n", stats.allocated));
+    }
+    if let Some(rss) = rss {
+        info.push_str(&format!("used_memory_rss:{}\n", rss));
+    }
+    info.push_str(&format!("mem_allocator:{}\n", allocator_name()));
+    if let Some(stats) = stats.as_ref() {
+        info.push_str(&format!("allocator_allocated:{}\n", stats.allocated));
+        info.push_str(&format!("allocator_active:{}\n", stats.active));
+        info.push_str(&format!("allocator_resident:{}\n", stats.resident));
+        info.push_str(&format!("allocator_frag_ratio:{:.2}\n", stats.fragmentation_ratio()));
+        if let Some(rss) = rss {
+            info.push_str(&format!("mem_fragmentation_ratio:{:.2}\n", ratio(rss, stats.allocated)));
+        }
+    }
+    info.push_str(&format!("mem_clients_normal:{}\n", CLIENTS.total_memory()));
+    info.push_str(&format!("maxmemory_clients:{}\n", CONFIG.read().await.maxmemory_clients));
+    info
+}
+
+fn stats_info() -> String {
+    let mut info = String::new();
+    info.push_str("# Stats\n");
+    info.push_str(&format!("keyspace_hits:{}\n", keyspace_hits()));
+    info.push_str(&format!("keyspace_misses:{}\n", keyspace_misses()));
+    info.push_str(&format!("evicted_clients:{}\n", CLIENTS.evicted_clients.load(Ordering::Relaxed)));
+    info
+}
+
+fn persistence_info() -> String {
+    let mut info = String::new();
+    info.push_str("# Persistence\n");
+    let in_progress = LOADING.in_progress.load(Ordering::Relaxed);
+    info.push_str(&format!("loading:{}\n", in_progress as u8));
+    if in_progress {
+        let total_bytes = LOADING.total_bytes.load(Ordering::Relaxed);
+        let loaded_bytes = LOADING.loaded_bytes.load(Ordering::Relaxed);
+        let percent = if total_bytes > 0 {
+            loaded_bytes as f64 / total_bytes as f64 * 100.0
+        } else {
+            0.0
+        };
+        info.push_str(&format!("loading_start_time:{}\n", LOADING.start_time.load(Ordering::Relaxed)));
+        info.push_str(&format!("loading_total_bytes:{}\n", total_bytes));
+        info.push_str(&format!("loading_loaded_bytes:{}\n", loaded_bytes));
+        info.push_str(&format!("loading_loaded_perc:{:.2}\n", percent));
+    }
+    info.push_str(&format!("rdb_changes_since_last_save:{}\n", changes_since_last_save()));
+    info.push_str(&format!("rdb_bgsave_in_progress:{}\n", save_in_progress() as u8));
+    info.push_str(&format!("rdb_last_save_time:{}\n", last_save_time()));
+    let status = if last_bgsave_failed() { "err" } else { "ok" };
+    info.push_str(&format!("rdb_last_bgsave_status:{}\n", status));
+    info
+}
+
+async fn replication_info() -> String {
+    let mut replication_info = String::new();
+    replication_info.push_str("# Replication\n");
+    let replication = REPLICATION.read().await;
+    if let Some(replica_of) = CONFIG.read().await.replica_of.as_ref() {
+        replication_info.push_str("role:slave\n");
+        replication_info.push_str(&format!("master_host:{}\n", replica_of.host));
+        replication_info.push_str(&format!("master_port:{}\n", replica_of.port));
+        let link_status = if replication.master_link_up { "up" } else { "down" };
+        replication_info.push_str(&format!("master_link_status:{}\n", link_status));
+    } else {
+        replication_info.push_str("role:master\n");
+        let replicas = replication.connected_replicas();
+        replication_info.push_str(&format!("connected_slaves:{}\n", replicas.len()));
+        for (i, replica) in replicas.iter().enumerate() {
+            replication_info.push_str(&format!(
+                "slave{}:ip={},port={},state=online,offset={},lag=0\n",
+                i, replica.ip, replica.port, replica.ack_offset
+            ));
+        }
+    }
+    replication_info.push_str(&format!("master_replid:{}\n", replication.replid));
+    replication_info.push_str(&format!("master_repl_offset:{}\n", replication.offset));
+    replication_info
+}
+
+fn command_info(spec: &CommandSpec) -> ResponseType {
+    let mut flags: Vec<ResponseType> = spec.flags
+        .names()
+        .into_iter()
+        .map(|flag| ResponseType::SimpleString(flag.to_string()))
+        .collect();
+    // Like in Redis, the keys of these aren't at the positions given after the flags
+    if spec.numkeys > 0 {
+        flags.push(ResponseType::SimpleString("movablekeys".to_string()));
+    }
+    let categories = spec.flags
+        .acl_categories()
+        .into_iter()
+        .map(|category| ResponseType::SimpleString(category.to_string()))
+        .collect();
+
+    ResponseType::Array(vec![
+        ResponseType::BulkString(Bytes::copy_from_slice(spec.name.as_bytes())),
+        ResponseType::Integer(spec.arity as i64),
+        ResponseType::Array(flags),
+        ResponseType::Integer(spec.first_key as i64),
+        ResponseType::Integer(spec.last_key as i64),
+        ResponseType::Integer(spec.key_step as i64),
+        ResponseType::Array(categories),
+    ])
+}
+
+/// Runs the queued commands of a MULTI, holding back their writes so they reach the replication
+/// stream as a single MULTI/EXEC block. No other client's command runs until that block has been
+/// replicated, so replicas see everything in the order it was applied. Every queued command gets
+/// a reply, one that fails replies with its error and the rest still run, like in Redis.
+fn execute_transaction<'a>(client: &'a mut RedisClientConnection, queued: Vec<Vec<ResponseType>>, buffer: &'a mut Writer<Vec<u8>>)
+    -> BoxFuture<'a, CommandResult> {
+    Box::pin(async move {
+        let _exclusive = EXCLUSION.write().await;
+        buffer.write_all(format!("*{}\r\n", queued.len()).as_bytes())?;
+
+        client.pending_writes = Some(Vec::new());
+        for request in queued.iter() {
+            let command = request[0].string().unwrap_or_default();
+            let result = match CommandSpec::lookup(command.as_str()) {
+                Some(spec) => dispatch(client, spec, &command, &request[1..], buffer).await,
+                None => Err(anyhow::Error::msg(format!("unknown command '{}'", command))),
+            };
+            if let Err(e) = result {
+                write_simple_error(buffer, format!("ERR {}", e).as_bytes())?;
+            }
+        }
+
+        let writes = client.pending_writes.take().unwrap_or_default();
+        if !writes.is_empty() {
+            propagate_transaction(&writes).await?;
+        }
+        Ok(Outcome::Done)
+    })
+}
+
+/// EVAL script numkeys [key ...] [arg ...], FCALL function numkeys [key ...] [arg ...] and their
+/// read only forms, which only let the script call commands that don't write. FCALL_RO also
+/// refuses functions that weren't declared no-writes.
+async fn execute_script(client: &mut RedisClientConnection, command: Command, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let Some(numkeys) = arguments[1].string().and_then(|numkeys| numkeys.parse::<i64>().ok()) else {
+        return fail(response_buff, b"ERR value is not an integer or out of range");
+    };
+    if numkeys < 0 {
+        return fail(response_buff, b"ERR Number of keys can't be negative");
+    }
+    let rest: Vec<Bytes> = arguments[2..].iter().filter_map(|argument| argument.bytes()).collect();
+    if numkeys as usize > rest.len() {
+        return fail(response_buff, b"ERR Number of keys can't be greater than number of args");
+    }
+    let (keys, args) = rest.split_at(numkeys as usize);
+
+    let body = arguments[0].bytes().unwrap_or_default();
+    let started = match command {
+        Command::Eval | Command::EvalRo => {
+            ScriptRun::eval(&body, keys.to_vec(), args.to_vec()).map(|run| (run, command == Command::EvalRo))
+        }
+        _ => ScriptRun::fcall(&String::from_utf8_lossy(&body), keys.to_vec(), args.to_vec()).and_then(|(run, no_writes)| {
+            if command == Command::FcallRo && !no_writes {
+                return Err("ERR Can not execute a script with write flag using *_ro command.".to_string());
+            }
+            Ok((run, command == Command::FcallRo || no_writes))
+        }),
+    };
+    match started {
+        Ok((run, read_only)) => run_script(client, run, read_only, response_buff).await,
+        Err(e) => fail(response_buff, e.as_bytes()),
+    }
+}
+
+/// Runs a script to the end, carrying out the commands it calls on behalf of `client`. Like a
+/// transaction nothing else runs in between, and the script's writes are replicated as one
+/// MULTI/EXEC block rather than the script itself.
+fn run_script<'a>(client: &'a mut RedisClientConnection, mut run: ScriptRun, read_only: bool, response_buff: &'a mut Writer<Vec<u8>>)
+    -> BoxFuture<'a, CommandResult> {
+    Box::pin(async move {
+        // A script run by EXEC is already inside its transaction. The read only forms come in
+        // holding the lock shared, which has to be let go of before taking it for ourselves.
+        let in_transaction = client.pending_writes.is_some();
+        let _exclusive = if in_transaction {
+            None
+        } else {
+            client.exclusion = None;
+            let exclusive = EXCLUSION.write().await;
+            client.pending_writes = Some(Vec::new());
+            Some(exclusive)
+        };
+
+        // SELECT inside a script only lasts until the script ends
+        let selected_db = client.session.selected_db;
+        let mut reply = None;
+        let result = loop {
+            match run.resume(reply.take()) {
+                ScriptStep::Done(result) => break Ok(result),
+                ScriptStep::Call(command) => match script_call(client, command, read_only).await {
+                    Ok(called) => reply = Some(called),
+                    Err(e) => break Err(e),
+                },
+            }
+        };
+        client.session.selected_db = selected_db;
+
+        if !in_transaction {
+            let writes = client.pending_writes.take().unwrap_or_default();
+            if !writes.is_empty() {
+                propagate_transaction(&writes).await?;
+            }
+        }
+        let result = result?;
+        write_resp(response_buff, &result).await?;
+        Ok(if matches!(result, ResponseType::Error(_)) { Outcome::Failed } else { Outcome::Done })
+    })
+}
+
+/// Runs a command a script called and returns its reply, or the error it's refused with. The
+/// checks a client's command goes through apply, replicas refuse writes for instance.
+async fn script_call(client: &mut RedisClientConnection, command: Vec<Bytes>, read_only: bool) -> Result<ResponseType, anyhow::Error> {
+    let Some((name, arguments)) = command.split_first() else {
+        return Ok(ResponseType::Error("ERR Please specify at least one argument for this redis lib call".to_string()));
+    };
+    let name = String::from_utf8_lossy(name).into_owned();
+    let arguments: Vec<ResponseType> = arguments.iter().cloned().map(ResponseType::BulkString).collect();
+    let spec = CommandSpec::lookup(&name);
+    let refusal = match spec {
+        None => Some("ERR Unknown Redis command called from script".to_string()),
+        Some(spec) if spec.flags.contains(CommandFlags::NOSCRIPT) => Some("ERR This Redis command is not allowed from script".to_string()),
+        Some(spec) if read_only && spec.is_write() => Some("ERR Write commands are not allowed from read-only scripts.".to_string()),
+        Some(_) => command_rejection(client, spec, &name, &arguments).await,
+    };
+    let (Some(spec), None) = (spec, refusal.as_ref()) else {
+        return Ok(ResponseType::Error(refusal.unwrap_or_default()));
+    };
+
+    let mut buffer = Vec::with_capacity(64).writer();
+    let _ = dispatch(client, spec, &name, &arguments, &mut buffer).await?;
+    let reply = RedisClientConnection::parse_frame(&Bytes::from(buffer.into_inner()))?;
+    Ok(reply)
+}
+
+/// FUNCTION LOAD [REPLACE] code, FUNCTION DELETE library, FUNCTION FLUSH and FUNCTION LIST.
+/// Libraries that change are replicated, so replicas can run the same functions.
+async fn execute_function(client: &mut RedisClientConnection, arguments: &[ResponseType], response_buff: &mut Writer<Vec<u8>>) -> CommandResult {
+    let subcommand = arguments[0].string().unwrap_or_default().to_lowercase();
+    let changes_libraries = matches!(subcommand.as_str(), "load" | "delete" | "flush");
+    if changes_libraries && !client.is_master_link && CONFIG.read().await.replica_of.is_some() {
+        return fail(response_buff, b"READONLY You can't write against a read only replica.");
+    }
+
+    match (subcommand.as_str(), &arguments[1..]) {
+        ("load", [code]) | ("load", [_, code]) => {
+            let replace = arguments.len() == 3;
+            if replace && !arguments[1].string().is_some_and(|option| option.eq_ignore_ascii_case("replace")) {
+                return fail(response_buff, format!("ERR Unknown option given: {}", arguments[1].string().unwrap_or_default()).as_bytes());
+            }
+            match load_library(&code.string().unwrap_or_default(), replace) {
+                Ok(name) => write_bulk_string(response_buff, name.as_bytes())?,
+                Err(e) => return fail(response_buff, e.as_bytes()),
+            }
+        }
+
+        ("delete", [name]) => {
+            if !delete_library(&name.string().unwrap_or_default()) {
+                return fail(response_buff, b"ERR Library not found");
+            }
+            write_ok(response_buff)?;
+        }
+
+        // ASYNC and SYNC make no difference, there's nothing to free in the background
+        ("flush", []) | ("flush", [_]) => {
+            flush_libraries();
+            write_ok(response_buff)?;
+        }
+
+        ("list", []) => {
+            let bulk = |s: &str| ResponseType::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+            let libraries = list_libraries()
+                .into_iter()
+                .map(|(name, functions)| {
+                    let functions = functions
+                        .into_iter()
+                        .map(|function| ResponseType::Array(vec![
+                            bulk("name"),
+                            bulk(&function.name),
+                            bulk("flags"),
+                            ResponseType::Array(if function.no_writes { vec![bulk("no-writes")] } else { Vec::new() }),
+                        ]))
+                        .collect();
+                    ResponseType::Array(vec![
+                        bulk("library_name"),
+                        bulk(&name),
+                        bulk("engine"),
+                        bulk("LUA"),
+                        bulk("functions"),
+                        ResponseType::Array(functions),
+                    ])
+                })
+                .collect();
+            write_resp(response_buff, &ResponseType::Array(libraries)).await?;
+        }
+
+        _ => return fail(response_buff, format!("ERR unknown subcommand or wrong number of arguments for '{}'", subcommand).as_bytes()),
+    }
+
+    if changes_libraries {
+        let mut write = vec![ResponseType::BulkString(Bytes::from_static(b"FUNCTION"))];
+        write.extend_from_slice(arguments);
+        client.also_propagate(write);
+    }
+    Ok(Outcome::Done)
+}
+
+
+pub fn write_resp<'a>(buffer: &'a mut Writer<Vec<u8>>, value: &'a ResponseType)
+    -> BoxFuture<'a, Result<(), anyhow::Error>> {
+    Box::pin(async move {
+        match value {
+            ResponseType::Array(elements) => {
+                write_array(buffer, elements).await?;
+            }
+
+            ResponseType::BulkString(s) => {
+                write_bulk_string(buffer, s)?;
+            }
+
+            ResponseType::SimpleString(s) => {
+                write_simple_string(buffer, s.as_bytes())?;
+            }
+
+            ResponseType::Error(s) => {
+                write_simple_error(buffer, s.as_bytes())?;
+            }
+
+            ResponseType::Integer(i) => {
+                write_integer(buffer, *i)?;
+            }
+
+            ResponseType::NullBulkString => {
+                write_nil_bulk_string(buffer)?;
+            }
+
+            ResponseType::NullArray => {
+                write_nil_array(buffer)?;
+            }
         }
 
         Ok(())
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use crate::clock;
+
+/// Every open connection, so limits that span clients can be enforced from one place
+pub static CLIENTS: Lazy<ClientRegistry> = Lazy::new(ClientRegistry::new);
+
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: Mutex<HashMap<u64, Arc<ClientHandle>>>,
+    /// Sum of the memory every client reports for its buffers
+    total_memory: AtomicUsize,
+    pub evicted_clients: AtomicU64,
+}
+
+/// The part of a connection's state other tasks need to see
+pub struct ClientHandle {
+    pub id: u64,
+    memory: AtomicUsize,
+    /// Set through CLIENT NO-EVICT, and for replication links which are never evicted
+    pub no_evict: AtomicBool,
+    /// Set once the client is picked for eviction, from then on its memory no longer counts
+    evicting: AtomicBool,
+    evicted: Notify,
+    created: Instant,
+    /// Kept up to date by the connection itself, for CLIENT INFO and CLIENT LIST
+    pub details: Mutex<ClientDetails>,
+}
+
+/// What CLIENT LIST shows about a connection
+#[derive(Clone, Debug, Default)]
+pub struct ClientDetails {
+    pub addr: String,
+    pub laddr: String,
+    pub name: String,
+    /// Redis' one letter flags, such as S for a replica or x inside MULTI
+    pub flags: String,
+    pub db: usize,
+    pub channels: usize,
+    pub patterns: usize,
+    /// Commands queued since MULTI, -1 outside a transaction
+    pub queued: i64,
+    pub query_buffer: usize,
+    pub output_buffer: usize,
+    pub last_command: String,
+    pub user: String,
+    pub resp: u8,
+    /// Set through CLIENT SETINFO
+    pub lib_name: String,
+    pub lib_ver: String,
+    pub last_interaction: Option<Instant>,
+}
+
+impl ClientHandle {
+    pub fn memory(&self) -> usize {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    /// The connection's line in CLIENT LIST, in the same format as Redis
+    pub fn describe(&self) -> String {
+        let details = self.details.lock().unwrap();
+        let now = clock::monotonic();
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} multi={} qbuf={} omem={} tot-mem={} events={} cmd={} user={} resp={} lib-name={} lib-ver={}",
+            self.id,
+            details.addr,
+            details.laddr,
+            details.name,
+            now.saturating_duration_since(self.created).as_secs(),
+            now.saturating_duration_since(details.last_interaction.unwrap_or(self.created)).as_secs(),
+            details.flags,
+            details.db,
+            details.channels,
+            details.patterns,
+            details.queued,
+            details.query_buffer,
+            details.output_buffer,
+            self.memory(),
+            if details.output_buffer > 0 { "rw" } else { "r" },
+            details.last_command,
+            details.user,
+            details.resp,
+            details.lib_name,
+            details.lib_ver,
+        )
+    }
+
+    /// Resolves once the client has been picked for eviction
+    pub async fn evicted(&self) {
+        self.evicted.notified().await
+    }
+}
+
+impl ClientRegistry {
+    fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            clients: Mutex::new(HashMap::new()),
+            total_memory: AtomicUsize::new(0),
+            evicted_clients: AtomicU64::new(0),
+        }
+    }
+
+    pub fn register(&self) -> Arc<ClientHandle> {
+        let handle = Arc::new(ClientHandle {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            memory: AtomicUsize::new(0),
+            no_evict: AtomicBool::new(false),
+            evicting: AtomicBool::new(false),
+            evicted: Notify::new(),
+            created: clock::monotonic(),
+            details: Mutex::new(ClientDetails::default()),
+        });
+        self.clients.lock().unwrap().insert(handle.id, handle.clone());
+        handle
+    }
+
+    pub fn deregister(&self, handle: &ClientHandle) {
+        self.clients.lock().unwrap().remove(&handle.id);
+        self.total_memory.fetch_sub(handle.memory.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Every open connection, in the order they connected
+    pub fn list(&self) -> Vec<Arc<ClientHandle>> {
+        let mut clients = self.clients.lock().unwrap().values().cloned().collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+
+    pub fn connected(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    pub fn total_memory(&self) -> usize {
+        self.total_memory.load(Ordering::Relaxed)
+    }
+
+    /// Records how much memory `handle` currently holds in buffers
+    pub fn update_memory(&self, handle: &ClientHandle, bytes: usize) {
+        if handle.evicting.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let previous = handle.memory.swap(bytes, Ordering::Relaxed);
+        if bytes >= previous {
+            self.total_memory.fetch_add(bytes - previous, Ordering::Relaxed);
+        } else {
+            self.total_memory.fetch_sub(previous - bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Disconnects the clients using the most memory until the total is back under `limit`,
+    /// skipping those marked no-evict. Returns the number of clients evicted.
+    pub fn evict_over(&self, limit: usize) -> usize {
+        if limit == 0 || self.total_memory() <= limit {
+            return 0;
+        }
+
+        let mut candidates = self.clients
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|client| !client.no_evict.load(Ordering::Relaxed) && !client.evicting.load(Ordering::Relaxed))
+            .cloned()
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|client| std::cmp::Reverse(client.memory()));
+
+        let mut evicted = 0;
+        for client in candidates {
+            if self.total_memory() <= limit {
+                break;
+            }
+
+            client.evicting.store(true, Ordering::Relaxed);
+            self.total_memory.fetch_sub(client.memory.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+            client.evicted.notify_one();
+            evicted += 1;
+        }
+
+        self.evicted_clients.fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use once_cell::sync::Lazy;
+
+static CLOCK: Lazy<RwLock<Arc<dyn Clock>>> = Lazy::new(|| RwLock::new(Arc::new(SystemClock)));
+
+/// Where the server gets the time from. Key expiry and the cron read it through `now` and
+/// `monotonic`, so tests can swap in a `ManualClock` and move time forward themselves.
+pub trait Clock: Send + Sync {
+    /// Wall clock time, which expirations are stored as
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time, for measuring intervals
+    fn monotonic(&self) -> Instant;
+}
+
+/// The operating system's clocks
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still until it is advanced
+pub struct ManualClock {
+    start: SystemTime,
+    start_monotonic: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Starts at the current system time
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    pub fn starting_at(start: SystemTime) -> Self {
+        Self {
+            start,
+            start_monotonic: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn monotonic(&self) -> Instant {
+        self.start_monotonic + *self.elapsed.lock().unwrap()
+    }
+}
+
+/// Replaces the clock for the whole process
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+pub fn now() -> SystemTime {
+    CLOCK.read().unwrap().now()
+}
+
+pub fn monotonic() -> Instant {
+    CLOCK.read().unwrap().monotonic()
+}
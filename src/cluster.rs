@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use crate::CONFIG;
+use crate::client::{RedisClientConnection, ResponseType};
+use crate::clock;
+use crate::command::{CommandFlags, CommandSpec};
+use crate::database::db_exists;
+use crate::persistence::{DataType, RdbWriter};
+use crate::util::{hash_tag, random_hex_string};
+
+pub const CLUSTER_SLOTS: usize = 16384;
+
+pub static CLUSTER: Lazy<Arc<RwLock<ClusterState>>> = Lazy::new(|| {
+    Arc::new(RwLock::new(ClusterState::new()))
+});
+
+#[derive(Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+}
+
+impl ClusterNode {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
+
+/// What this node knows of the cluster. There is no cluster bus, so the slot table only changes
+/// through CLUSTER commands sent to each node.
+pub struct ClusterState {
+    pub myself: ClusterNode,
+    /// Every other node met through CLUSTER MEET
+    pub nodes: Vec<ClusterNode>,
+    /// Id of the node serving each slot
+    slots: Vec<Option<String>>,
+    /// Slots this node is handing over, and the node receiving them
+    migrating: HashMap<u16, String>,
+    /// Slots this node is taking over, and the node they come from
+    importing: HashMap<u16, String>,
+}
+
+impl ClusterState {
+    fn new() -> Self {
+        Self {
+            myself: ClusterNode {
+                id: random_hex_string(40),
+                ip: "127.0.0.1".to_string(),
+                port: 0,
+            },
+            nodes: Vec::new(),
+            slots: vec![None; CLUSTER_SLOTS],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+
+    pub fn node(&self, id: &str) -> Option<&ClusterNode> {
+        if self.myself.id == id {
+            Some(&self.myself)
+        } else {
+            self.nodes.iter().find(|node| node.id == id)
+        }
+    }
+
+    pub fn slot_owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots[slot as usize].as_deref().and_then(|id| self.node(id))
+    }
+
+    pub fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    pub fn assign_slot(&mut self, slot: u16, node_id: Option<String>) {
+        self.slots[slot as usize] = node_id;
+    }
+
+    pub fn is_slot_assigned(&self, slot: u16) -> bool {
+        self.slots[slot as usize].is_some()
+    }
+
+    /// Handles CLUSTER SETSLOT `slot` IMPORTING|MIGRATING|STABLE|NODE [node-id]
+    pub fn set_slot(&mut self, slot: u16, state: &str, node_id: Option<&str>) -> Result<(), String> {
+        let node_id = match (state, node_id) {
+            ("stable", _) => None,
+            (_, Some(id)) => match self.node(id) {
+                Some(node) => Some(node.id.clone()),
+                None => return Err(format!("ERR I don't know about node {}", id)),
+            },
+            (_, None) => return Err("ERR syntax error".to_string()),
+        };
+
+        match state {
+            "migrating" => {
+                if self.slots[slot as usize].as_deref() != Some(self.myself.id.as_str()) {
+                    return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+                }
+                self.migrating.insert(slot, node_id.unwrap());
+            }
+
+            "importing" => {
+                if self.slots[slot as usize].as_deref() == Some(self.myself.id.as_str()) {
+                    return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+                }
+                self.importing.insert(slot, node_id.unwrap());
+            }
+
+            "stable" => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+
+            "node" => {
+                let node_id = node_id.unwrap();
+                // Once the slot is assigned to us an import is complete, and once it's assigned
+                // elsewhere so is the migration
+                if node_id == self.myself.id {
+                    self.importing.remove(&slot);
+                } else {
+                    self.migrating.remove(&slot);
+                }
+                self.slots[slot as usize] = Some(node_id);
+            }
+
+            _ => return Err("ERR Invalid CLUSTER SETSLOT action or number of arguments.".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// The reply to CLUSTER NODES
+    pub fn describe_nodes(&self) -> String {
+        let mut description = String::new();
+        for node in std::iter::once(&self.myself).chain(self.nodes.iter()) {
+            let flags = if node.id == self.myself.id { "myself,master" } else { "master" };
+            description.push_str(&format!("{} {}@{} {} - 0 0 0 connected", node.id, node.address(), node.port as u32 + 10000, flags));
+            for (start, end) in self.slot_ranges(&node.id) {
+                if start == end {
+                    description.push_str(&format!(" {}", start));
+                } else {
+                    description.push_str(&format!(" {}-{}", start, end));
+                }
+            }
+
+            if node.id == self.myself.id {
+                for (slot, target) in self.migrating.iter() {
+                    description.push_str(&format!(" [{}->-{}]", slot, target));
+                }
+                for (slot, source) in self.importing.iter() {
+                    description.push_str(&format!(" [{}-<-{}]", slot, source));
+                }
+            }
+            description.push('\n');
+        }
+        description
+    }
+
+    fn slot_ranges(&self, node_id: &str) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(node_id) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+}
+
+/// Records this node's own address, called once at startup in cluster mode
+pub async fn init_cluster(port: u16) {
+    CLUSTER.write().await.myself.port = port;
+}
+
+/// Connects to the node at `ip`:`port` to learn its id and adds it to the known nodes
+pub async fn meet(ip: String, port: u16) -> Result<(), anyhow::Error> {
+    let stream = TcpStream::connect((ip.as_str(), port)).await?;
+    let mut node = RedisClientConnection::new(stream);
+    node.send_command(&["CLUSTER", "MYID"]).await?;
+    let id = match node.read().await? {
+        ResponseType::BulkString(id) => String::from_utf8_lossy(&id).to_string(),
+        reply => anyhow::bail!("Unexpected reply to CLUSTER MYID: {}", reply),
+    };
+
+    let mut cluster = CLUSTER.write().await;
+    if cluster.node(&id).is_none() {
+        cluster.nodes.push(ClusterNode { id, ip, port });
+    }
+
+    Ok(())
+}
+
+/// CRC16/XMODEM, as used to map keys to slots
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key)) % CLUSTER_SLOTS as u16
+}
+
+/// Connection state that affects how keys in foreign slots are treated
+#[derive(Default)]
+pub struct ClusterFlags {
+    /// Set by ASKING, lets the next command touch a slot this node is importing
+    pub asking: bool,
+    /// Set by READONLY, lets a replica serve reads for the slots of its master
+    pub readonly: bool,
+}
+
+/// Returns the redirection or error for a command whose keys this node doesn't serve
+pub async fn cluster_redirection(spec: &CommandSpec, keys: &[Bytes], db_id: usize, flags: &ClusterFlags) -> Option<String> {
+    let mut slot = None;
+    for key in keys {
+        let key_slot = key_hash_slot(key);
+        if slot.is_some_and(|slot| slot != key_slot) {
+            return Some("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+        }
+        slot = Some(key_slot);
+    }
+    let slot = slot?;
+
+    let (owner, migrating_to, importing) = {
+        let cluster = CLUSTER.read().await;
+        let owner = cluster.slot_owner(slot).cloned();
+        let migrating_to = cluster.migrating.get(&slot).and_then(|id| cluster.node(id)).cloned();
+        (owner, migrating_to, cluster.importing.contains_key(&slot))
+    };
+
+    let is_mine = match owner.as_ref() {
+        Some(owner) => owner.id == CLUSTER.read().await.myself.id,
+        None => false,
+    };
+
+    if is_mine {
+        // Keys already moved out of a migrating slot are now found on the target
+        if let Some(target) = migrating_to {
+            let missing = keys.len() - db_exists(db_id, keys.to_vec()).await;
+
+            if missing == keys.len() {
+                return Some(format!("ASK {} {}", slot, target.address()));
+            } else if missing > 0 {
+                return Some("TRYAGAIN Multiple keys request during rehashing of slot".to_string());
+            }
+        }
+        return None;
+    }
+
+    if importing && flags.asking {
+        return None;
+    }
+
+    if let Some(owner) = owner.as_ref() {
+        if flags.readonly && spec.flags.contains(CommandFlags::READONLY) && is_replica_of(owner).await {
+            return None;
+        }
+        return Some(format!("MOVED {} {}", slot, owner.address()));
+    }
+
+    Some("CLUSTERDOWN Hash slot not served".to_string())
+}
+
+async fn is_replica_of(node: &ClusterNode) -> bool {
+    let config = CONFIG.read().await;
+    config
+        .replica_of
+        .as_ref()
+        .is_some_and(|master| master.port == node.port && (master.host == node.ip || master.host == "localhost"))
+}
+
+pub struct MigrateOptions {
+    pub replace: bool,
+    /// Username and password to authenticate with on the target
+    pub auth: Option<(Option<String>, String)>,
+}
+
+/// Copies `entries` to the node at `host`:`port`, going through ASKING so the target accepts
+/// them while it is importing their slot. Values of any type are sent as RESTORE commands with
+/// their DUMP payload and remaining time to live, and the target refuses a key it already holds
+/// unless `replace` is set.
+pub async fn migrate_keys(
+    host: &str,
+    port: u16,
+    db_id: usize,
+    entries: &[(Bytes, DataType, Option<SystemTime>)],
+    options: &MigrateOptions
+) -> Result<(), anyhow::Error> {
+    let stream = TcpStream::connect((host, port)).await?;
+    let mut target = RedisClientConnection::new(stream);
+
+    match options.auth.as_ref() {
+        Some((Some(username), password)) => expect_ok(&mut target, &["AUTH", username, password]).await?,
+        Some((None, password)) => expect_ok(&mut target, &["AUTH", password]).await?,
+        None => {}
+    }
+    expect_ok(&mut target, &["SELECT", db_id.to_string().as_str()]).await?;
+
+    let now = clock::now();
+    for (key, value, expiration) in entries {
+        // A time to live of 0 restores a key that doesn't expire
+        let ttl = match expiration {
+            Some(expiration) => match expiration.duration_since(now) {
+                Ok(remaining) => remaining.as_millis().max(1),
+                Err(_) => continue,
+            },
+            None => 0,
+        };
+        let ttl = ttl.to_string();
+        let payload = RdbWriter::dump(value)?;
+
+        let mut command: Vec<&[u8]> = vec![b"RESTORE", key, ttl.as_bytes(), &payload];
+        if options.replace {
+            command.push(b"REPLACE");
+        }
+        expect_ok(&mut target, &["ASKING"]).await?;
+        expect_ok(&mut target, &command).await?;
+    }
+
+    Ok(())
+}
+
+async fn expect_ok<T: AsRef<[u8]>>(target: &mut RedisClientConnection, command: &[T]) -> Result<(), anyhow::Error> {
+    target.send_command(command).await?;
+    match target.read().await? {
+        ResponseType::SimpleString(_) => Ok(()),
+        ResponseType::Error(e) if e.starts_with("BUSYKEY") => anyhow::bail!("{}", e),
+        reply => anyhow::bail!("Target instance replied with error: {}", reply),
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use crate::client::ResponseType;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Command {
+    Echo,
+    Ping,
+    #[allow(clippy::enum_variant_names)]
+    Command,
+    Select,
+    Set,
+    Get,
+    Setex,
+    Psetex,
+    Setnx,
+    Getset,
+    Setbit,
+    Getbit,
+    Bitcount,
+    Bitpos,
+    Bitop,
+    Config,
+    Keys,
+    Info,
+    Replconf,
+    Psync,
+    Sync,
+    Repltap,
+    Role,
+    Multi,
+    Exec,
+    Discard,
+    Client,
+    Cluster,
+    Asking,
+    Readonly,
+    Readwrite,
+    Migrate,
+    Module,
+    Debug,
+    Object,
+    Memory,
+    Touch,
+    Del,
+    Copy,
+    Dump,
+    Restore,
+    Incr,
+    Decr,
+    Incrby,
+    Decrby,
+    Exists,
+    Randomkey,
+    Scan,
+    Expire,
+    Pexpire,
+    Expireat,
+    Pexpireat,
+    Expiretime,
+    Pexpiretime,
+    Lpush,
+    Rpush,
+    Lpushx,
+    Rpushx,
+    Lpop,
+    Rpop,
+    Llen,
+    Lrange,
+    Lindex,
+    Lset,
+    Linsert,
+    Lrem,
+    Ltrim,
+    Blpop,
+    Brpop,
+    Lmove,
+    Blmove,
+    Blmpop,
+    Lmpop,
+    Hset,
+    Hmset,
+    Hget,
+    Hdel,
+    Hexists,
+    Hgetall,
+    Hkeys,
+    Hvals,
+    Hlen,
+    Hmget,
+    Hsetnx,
+    Hrandfield,
+    Hscan,
+    Hexpire,
+    Hpexpire,
+    Hexpireat,
+    Hpexpireat,
+    Httl,
+    Hpttl,
+    Hexpiretime,
+    Hpexpiretime,
+    Hpersist,
+    Sadd,
+    Srem,
+    Smembers,
+    Scard,
+    Sismember,
+    Smismember,
+    Sintercard,
+    Smove,
+    Sscan,
+    Zadd,
+    Zrem,
+    Zscore,
+    Zcard,
+    Zrange,
+    Zrangebyscore,
+    Zrevrangebyscore,
+    Zrangebylex,
+    Zrevrangebylex,
+    Zrevrange,
+    Zrank,
+    Zrevrank,
+    Zpopmin,
+    Zpopmax,
+    Zmpop,
+    Bzpopmin,
+    Bzpopmax,
+    Bzmpop,
+    Eval,
+    EvalRo,
+    Fcall,
+    FcallRo,
+    Function,
+    /// A command added by a loaded module
+    ModuleDefined,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommandFlags(u32);
+
+impl CommandFlags {
+    pub const NONE: Self = Self(0);
+    /// May modify the keyspace, so it's propagated and refused on read only replicas
+    pub const WRITE: Self = Self(1 << 0);
+    /// Only reads data
+    pub const READONLY: Self = Self(1 << 1);
+    /// Administrative command
+    pub const ADMIN: Self = Self(1 << 2);
+    /// Part of the pub/sub machinery, allowed while the client is subscribed
+    pub const PUBSUB: Self = Self(1 << 3);
+    /// May block the client
+    pub const BLOCKING: Self = Self(1 << 4);
+    /// Not allowed from scripts
+    pub const NOSCRIPT: Self = Self(1 << 5);
+    /// Allowed while the dataset is still loading
+    pub const LOADING_OK: Self = Self(1 << 6);
+    /// Allowed on a replica whose master link is down, even if stale data isn't served
+    pub const STALE_OK: Self = Self(1 << 7);
+
+    const NAMES: [(Self, &'static str); 8] = [
+        (Self::WRITE, "write"),
+        (Self::READONLY, "readonly"),
+        (Self::ADMIN, "admin"),
+        (Self::PUBSUB, "pubsub"),
+        (Self::BLOCKING, "blocking"),
+        (Self::NOSCRIPT, "noscript"),
+        (Self::LOADING_OK, "loading"),
+        (Self::STALE_OK, "stale"),
+    ];
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any of the flags in `other` are set
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Parses space separated flag names, as returned by `names`
+    pub fn parse(names: &str) -> Result<Self, String> {
+        names.split_whitespace().try_fold(Self::NONE, |flags, name| {
+            Self::NAMES
+                .iter()
+                .find(|(_, flag_name)| flag_name.eq_ignore_ascii_case(name))
+                .map(|(flag, _)| flags.union(*flag))
+                .ok_or_else(|| format!("unknown command flag '{}'", name))
+        })
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// The ACL categories a command falls into, derived from its flags
+    pub fn acl_categories(self) -> Vec<&'static str> {
+        let mut categories = Vec::new();
+        if self.contains(Self::WRITE) {
+            categories.push("@write");
+        }
+        if self.contains(Self::READONLY) {
+            categories.push("@read");
+        }
+        if self.contains(Self::ADMIN) {
+            categories.push("@admin");
+            categories.push("@dangerous");
+        }
+        if self.contains(Self::PUBSUB) {
+            categories.push("@pubsub");
+        }
+        if self.contains(Self::BLOCKING) {
+            categories.push("@blocking");
+        }
+        categories
+    }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub command: Command,
+    /// Number of arguments including the command name, negative means at least that many
+    pub arity: i32,
+    pub flags: CommandFlags,
+    pub first_key: i32,
+    pub last_key: i32,
+    pub key_step: i32,
+    /// Position of the argument saying how many keys follow it, for commands like LMPOP whose
+    /// keys can't be found from fixed positions. 0 if there's no such argument.
+    pub numkeys: i32,
+}
+
+impl CommandSpec {
+    const fn new(name: &'static str, command: Command, arity: i32, flags: CommandFlags) -> Self {
+        Self {
+            name,
+            command,
+            arity,
+            flags,
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            numkeys: 0,
+        }
+    }
+
+    const fn keys(mut self, first_key: i32, last_key: i32, key_step: i32) -> Self {
+        self.first_key = first_key;
+        self.last_key = last_key;
+        self.key_step = key_step;
+        self
+    }
+
+    const fn numkeys(mut self, numkeys: i32) -> Self {
+        self.numkeys = numkeys;
+        self
+    }
+
+    pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS_BY_NAME
+            .get(name.to_ascii_lowercase().as_str())
+            .copied()
+            .or_else(|| crate::module::lookup_command(name))
+    }
+
+    pub fn accepts_arity(&self, arity: usize) -> bool {
+        let arity = arity as i32;
+        if self.arity < 0 {
+            arity >= -self.arity
+        } else {
+            arity == self.arity
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.flags.contains(CommandFlags::WRITE)
+    }
+
+    /// The key arguments of a command, going by the key positions in its spec. `arguments`
+    /// excludes the command name.
+    pub fn key_arguments(&self, arguments: &[ResponseType]) -> Vec<Bytes> {
+        if self.numkeys > 0 {
+            let position = self.numkeys as usize;
+            let count = arguments
+                .get(position - 1)
+                .and_then(|numkeys| numkeys.string())
+                .and_then(|numkeys| numkeys.parse::<usize>().ok())
+                .unwrap_or(0);
+            return arguments.iter().skip(position).take(count).filter_map(|key| key.bytes()).collect();
+        }
+        if self.first_key <= 0 {
+            return Vec::new();
+        }
+
+        let argc = arguments.len() as i32 + 1;
+        let last_key = if self.last_key < 0 { argc + self.last_key } else { self.last_key.min(argc - 1) };
+        let mut keys = Vec::new();
+        let mut position = self.first_key;
+        while position <= last_key {
+            if let Some(key) = arguments.get(position as usize - 1).and_then(|a| a.bytes()) {
+                keys.push(key);
+            }
+            position += self.key_step.max(1);
+        }
+        keys
+    }
+}
+
+const WRITE: CommandFlags = CommandFlags::WRITE;
+const READONLY: CommandFlags = CommandFlags::READONLY;
+const ADMIN: CommandFlags = CommandFlags::ADMIN;
+const NOSCRIPT: CommandFlags = CommandFlags::NOSCRIPT;
+const LOADING_OK: CommandFlags = CommandFlags::LOADING_OK;
+const STALE_OK: CommandFlags = CommandFlags::STALE_OK;
+const BLOCKING: CommandFlags = CommandFlags::BLOCKING;
+
+/// The command table by lowercase name, for looking up each request's command
+static COMMANDS_BY_NAME: Lazy<HashMap<&'static str, &'static CommandSpec>> =
+    Lazy::new(|| COMMAND_TABLE.iter().map(|spec| (spec.name, spec)).collect());
+
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec::new("echo", Command::Echo, 2, CommandFlags::NONE),
+    CommandSpec::new("ping", Command::Ping, -1, CommandFlags::NONE),
+    CommandSpec::new("command", Command::Command, -1, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("select", Command::Select, 2, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("set", Command::Set, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("get", Command::Get, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("setex", Command::Setex, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("psetex", Command::Psetex, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("setnx", Command::Setnx, 3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("getset", Command::Getset, 3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("setbit", Command::Setbit, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("getbit", Command::Getbit, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("bitcount", Command::Bitcount, -2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("bitpos", Command::Bitpos, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("bitop", Command::Bitop, -4, WRITE).keys(2, -1, 1),
+    CommandSpec::new("config", Command::Config, -2, ADMIN.union(NOSCRIPT).union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("keys", Command::Keys, 2, READONLY),
+    CommandSpec::new("scan", Command::Scan, -2, READONLY),
+    CommandSpec::new("randomkey", Command::Randomkey, 1, READONLY),
+    CommandSpec::new("info", Command::Info, -1, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("replconf", Command::Replconf, -1, ADMIN.union(NOSCRIPT).union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("psync", Command::Psync, -3, ADMIN.union(NOSCRIPT)),
+    CommandSpec::new("sync", Command::Sync, 1, ADMIN.union(NOSCRIPT)),
+    CommandSpec::new("repltap", Command::Repltap, 1, ADMIN.union(NOSCRIPT)),
+    CommandSpec::new("role", Command::Role, 1, NOSCRIPT.union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("multi", Command::Multi, 1, NOSCRIPT.union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("exec", Command::Exec, 1, NOSCRIPT.union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("discard", Command::Discard, 1, NOSCRIPT.union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("client", Command::Client, -2, NOSCRIPT.union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("cluster", Command::Cluster, -2, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("asking", Command::Asking, 1, CommandFlags::NONE),
+    CommandSpec::new("readonly", Command::Readonly, 1, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("readwrite", Command::Readwrite, 1, LOADING_OK.union(STALE_OK)),
+    CommandSpec::new("migrate", Command::Migrate, -6, WRITE).keys(3, 3, 1),
+    CommandSpec::new("module", Command::Module, -2, ADMIN.union(NOSCRIPT)),
+    CommandSpec::new("debug", Command::Debug, -2, ADMIN.union(NOSCRIPT).union(LOADING_OK).union(STALE_OK)),
+    CommandSpec::new("object", Command::Object, -2, READONLY).keys(2, 2, 1),
+    CommandSpec::new("memory", Command::Memory, -2, READONLY).keys(2, 2, 1),
+    CommandSpec::new("touch", Command::Touch, -2, READONLY).keys(1, -1, 1),
+    CommandSpec::new("incr", Command::Incr, 2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("decr", Command::Decr, 2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("incrby", Command::Incrby, 3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("decrby", Command::Decrby, 3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("exists", Command::Exists, -2, READONLY).keys(1, -1, 1),
+    CommandSpec::new("expire", Command::Expire, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("pexpire", Command::Pexpire, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("expireat", Command::Expireat, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("pexpireat", Command::Pexpireat, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("expiretime", Command::Expiretime, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("pexpiretime", Command::Pexpiretime, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("copy", Command::Copy, -3, WRITE).keys(1, 2, 1),
+    CommandSpec::new("dump", Command::Dump, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("restore", Command::Restore, -4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("del", Command::Del, -2, WRITE).keys(1, -1, 1),
+    // Frees the values inline just like DEL for now
+    CommandSpec::new("unlink", Command::Del, -2, WRITE).keys(1, -1, 1),
+    CommandSpec::new("lpush", Command::Lpush, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("rpush", Command::Rpush, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("lpushx", Command::Lpushx, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("rpushx", Command::Rpushx, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("lpop", Command::Lpop, -2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("rpop", Command::Rpop, -2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("llen", Command::Llen, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("lrange", Command::Lrange, 4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("lindex", Command::Lindex, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("lset", Command::Lset, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("linsert", Command::Linsert, 5, WRITE).keys(1, 1, 1),
+    CommandSpec::new("lrem", Command::Lrem, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("ltrim", Command::Ltrim, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("blpop", Command::Blpop, -3, WRITE.union(BLOCKING)).keys(1, -2, 1),
+    CommandSpec::new("brpop", Command::Brpop, -3, WRITE.union(BLOCKING)).keys(1, -2, 1),
+    CommandSpec::new("lmove", Command::Lmove, 5, WRITE).keys(1, 2, 1),
+    CommandSpec::new("blmove", Command::Blmove, 6, WRITE.union(BLOCKING)).keys(1, 2, 1),
+    // The keys follow a count, so they can't be described by position
+    CommandSpec::new("blmpop", Command::Blmpop, -5, WRITE.union(BLOCKING)).numkeys(2),
+    CommandSpec::new("lmpop", Command::Lmpop, -4, WRITE).numkeys(1),
+    CommandSpec::new("hset", Command::Hset, -4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hmset", Command::Hmset, -4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hget", Command::Hget, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hdel", Command::Hdel, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hexists", Command::Hexists, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hgetall", Command::Hgetall, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hkeys", Command::Hkeys, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hvals", Command::Hvals, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hlen", Command::Hlen, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hmget", Command::Hmget, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hsetnx", Command::Hsetnx, 4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hrandfield", Command::Hrandfield, -2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hscan", Command::Hscan, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hexpire", Command::Hexpire, -6, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hpexpire", Command::Hpexpire, -6, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hexpireat", Command::Hexpireat, -6, WRITE).keys(1, 1, 1),
+    CommandSpec::new("hpexpireat", Command::Hpexpireat, -6, WRITE).keys(1, 1, 1),
+    CommandSpec::new("httl", Command::Httl, -5, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hpttl", Command::Hpttl, -5, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hexpiretime", Command::Hexpiretime, -5, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hpexpiretime", Command::Hpexpiretime, -5, READONLY).keys(1, 1, 1),
+    CommandSpec::new("hpersist", Command::Hpersist, -5, WRITE).keys(1, 1, 1),
+    CommandSpec::new("sadd", Command::Sadd, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("srem", Command::Srem, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("smembers", Command::Smembers, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("scard", Command::Scard, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("sismember", Command::Sismember, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("smismember", Command::Smismember, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("sintercard", Command::Sintercard, -3, READONLY).numkeys(1),
+    CommandSpec::new("smove", Command::Smove, 4, WRITE).keys(1, 2, 1),
+    CommandSpec::new("sscan", Command::Sscan, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zadd", Command::Zadd, -4, WRITE).keys(1, 1, 1),
+    CommandSpec::new("zrem", Command::Zrem, -3, WRITE).keys(1, 1, 1),
+    CommandSpec::new("zscore", Command::Zscore, 3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zcard", Command::Zcard, 2, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrange", Command::Zrange, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrangebyscore", Command::Zrangebyscore, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrevrangebyscore", Command::Zrevrangebyscore, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrangebylex", Command::Zrangebylex, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrevrangebylex", Command::Zrevrangebylex, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrevrange", Command::Zrevrange, -4, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrank", Command::Zrank, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zrevrank", Command::Zrevrank, -3, READONLY).keys(1, 1, 1),
+    CommandSpec::new("zpopmin", Command::Zpopmin, -2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("zpopmax", Command::Zpopmax, -2, WRITE).keys(1, 1, 1),
+    CommandSpec::new("zmpop", Command::Zmpop, -4, WRITE).numkeys(1),
+    CommandSpec::new("bzpopmin", Command::Bzpopmin, -3, WRITE.union(BLOCKING)).keys(1, -2, 1),
+    CommandSpec::new("bzpopmax", Command::Bzpopmax, -3, WRITE.union(BLOCKING)).keys(1, -2, 1),
+    CommandSpec::new("bzmpop", Command::Bzmpop, -5, WRITE.union(BLOCKING)).numkeys(2),
+    // Scripts aren't write commands themselves, the commands they call are checked one by one
+    CommandSpec::new("eval", Command::Eval, -3, NOSCRIPT).numkeys(2),
+    CommandSpec::new("eval_ro", Command::EvalRo, -3, READONLY.union(NOSCRIPT)).numkeys(2),
+    CommandSpec::new("fcall", Command::Fcall, -3, NOSCRIPT).numkeys(2),
+    CommandSpec::new("fcall_ro", Command::FcallRo, -3, READONLY.union(NOSCRIPT)).numkeys(2),
+    CommandSpec::new("function", Command::Function, -2, NOSCRIPT),
+];
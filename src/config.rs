@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+use crate::{Config, ReplicaOf, SaveRule, CONFIG};
+use crate::command::CommandFlags;
+use crate::logging::{parse_syslog_facility, set_log_level, syslog_facility_name, LogLevel, DEFAULT_SYSLOG_IDENT};
+use crate::server_log;
+use crate::storage::{storage_engine_exists, DEFAULT_STORAGE_ENGINE};
+use crate::systemd::Supervised;
+use crate::util::split_arguments;
+
+/// The config file the server was started with and the directives last read from it
+static CONFIG_FILE: Lazy<Mutex<Option<ConfigFile>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    UnknownParameter(String),
+    #[error("CONFIG SET failed (possibly related to argument '{0}') - can't set immutable config")]
+    Immutable(String),
+    #[error("CONFIG SET failed (possibly related to argument '{name}') - {reason}")]
+    InvalidValue {
+        name: String,
+        reason: String,
+    },
+    #[error("Bad directive or wrong number of arguments at line {line}: {reason}")]
+    BadDirective {
+        line: usize,
+        reason: String,
+    },
+    #[error("No config file to reload, the server was started without one")]
+    NoConfigFile,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A config parameter, with how to read it and how to parse and apply a new value. Every way of
+/// changing the config goes through `set`, so the file, CONFIG SET and reloads validate alike.
+struct Parameter {
+    name: &'static str,
+    /// Whether it can change while the server is running, the rest only take effect at startup
+    mutable: bool,
+    get: fn(&Config) -> String,
+    set: fn(&mut Config, &str) -> Result<(), String>,
+}
+
+const fn parameter(name: &'static str, mutable: bool, get: fn(&Config) -> String, set: fn(&mut Config, &str) -> Result<(), String>) -> Parameter {
+    Parameter { name, mutable, get, set }
+}
+
+static PARAMETERS: &[Parameter] = &[
+    parameter("dir", true, |c| c.dir.clone().unwrap_or_default(), |c, v| {
+        if !Path::new(v).is_dir() {
+            return Err("No such file or directory".to_string());
+        }
+        c.dir = Some(v.to_string());
+        Ok(())
+    }),
+    parameter("dbfilename", true, |c| c.db_filename.clone().unwrap_or_default(), |c, v| {
+        if v.is_empty() || v.contains('/') {
+            return Err("dbfilename can't be a path, just a filename".to_string());
+        }
+        c.db_filename = Some(v.to_string());
+        Ok(())
+    }),
+    parameter("port", false, |c| c.port.to_string(), |c, v| {
+        c.port = parse_number(v)?;
+        Ok(())
+    }),
+    parameter("replicaof", false, |c| {
+        c.replica_of.as_ref().map(|r| format!("{} {}", r.host, r.port)).unwrap_or_default()
+    }, |c, v| {
+        c.replica_of = parse_replica_of(v)?;
+        Ok(())
+    }),
+    parameter("replica-announce-ip", true, |c| c.replica_announce_ip.clone().unwrap_or_default(), |c, v| {
+        c.replica_announce_ip = Some(v.to_string()).filter(|ip| !ip.is_empty());
+        Ok(())
+    }),
+    parameter("replica-announce-port", true, |c| c.replica_announce_port.unwrap_or(0).to_string(), |c, v| {
+        c.replica_announce_port = Some(parse_number(v)?).filter(|port| *port != 0);
+        Ok(())
+    }),
+    parameter("replica-serve-stale-data", true, |c| yes_no(c.replica_serve_stale_data), |c, v| {
+        c.replica_serve_stale_data = parse_yes_no(v)?;
+        Ok(())
+    }),
+    parameter("list-max-listpack-size", true, |c| c.list_max_listpack_size.to_string(), |c, v| {
+        c.list_max_listpack_size = parse_number(v)?;
+        Ok(())
+    }),
+    parameter("hz", true, |c| c.hz.to_string(), |c, v| {
+        c.hz = parse_number(v)?;
+        Ok(())
+    }),
+    parameter("io-threads", false, |c| c.io_threads.to_string(), |c, v| {
+        c.io_threads = parse_number(v)?;
+        Ok(())
+    }),
+    parameter("keyspace-shards", false, |c| c.keyspace_shards.to_string(), |c, v| {
+        c.keyspace_shards = parse_number(v)?;
+        Ok(())
+    }),
+    parameter("maxmemory-clients", true, |c| c.maxmemory_clients.to_string(), |c, v| {
+        c.maxmemory_clients = parse_memory(v)?;
+        Ok(())
+    }),
+    parameter("cluster-enabled", false, |c| yes_no(c.cluster_enabled), |c, v| {
+        c.cluster_enabled = parse_yes_no(v)?;
+        Ok(())
+    }),
+    parameter("supervised", false, |c| c.supervised.name().to_string(), |c, v| {
+        c.supervised = Supervised::parse(v)?;
+        Ok(())
+    }),
+    parameter("loglevel", true, |c| c.loglevel.name().to_string(), |c, v| {
+        c.loglevel = LogLevel::parse(v)?;
+        Ok(())
+    }),
+    parameter("logfile", false, |c| c.logfile.clone().unwrap_or_default(), |c, v| {
+        c.logfile = Some(v.to_string()).filter(|path| !path.is_empty());
+        Ok(())
+    }),
+    parameter("audit-log-categories", true, |c| c.audit_log_categories.names().join(" "), |c, v| {
+        c.audit_log_categories = CommandFlags::parse(v)?;
+        Ok(())
+    }),
+    parameter("audit-log-file", false, |c| c.audit_log_file.clone().unwrap_or_default(), |c, v| {
+        c.audit_log_file = Some(v.to_string()).filter(|path| !path.is_empty());
+        Ok(())
+    }),
+    parameter("record-file", false, |c| c.record_file.clone().unwrap_or_default(), |c, v| {
+        c.record_file = Some(v.to_string()).filter(|path| !path.is_empty());
+        Ok(())
+    }),
+    parameter("storage-engine", false, |c| c.storage_engine.clone().unwrap_or_else(|| DEFAULT_STORAGE_ENGINE.to_string()), |c, v| {
+        if !storage_engine_exists(v) {
+            return Err(format!("unknown storage engine '{}'", v));
+        }
+        c.storage_engine = Some(v.to_lowercase());
+        Ok(())
+    }),
+    parameter("enable-fault-injection", false, |c| yes_no(c.enable_fault_injection), |c, v| {
+        c.enable_fault_injection = parse_yes_no(v)?;
+        Ok(())
+    }),
+    parameter("syslog-enabled", false, |c| yes_no(c.syslog_enabled), |c, v| {
+        c.syslog_enabled = parse_yes_no(v)?;
+        Ok(())
+    }),
+    parameter("syslog-ident", false, |c| c.syslog_ident.clone().unwrap_or_else(|| DEFAULT_SYSLOG_IDENT.to_string()), |c, v| {
+        c.syslog_ident = Some(v.to_string());
+        Ok(())
+    }),
+    parameter("syslog-facility", false, |c| syslog_facility_name(c.syslog_facility).to_string(), |c, v| {
+        c.syslog_facility = parse_syslog_facility(v)?;
+        Ok(())
+    }),
+    parameter("save", true, |c| save_rules_string(&c.save_rules), |c, v| {
+        c.save_rules = parse_save_rules(v)?;
+        Ok(())
+    }),
+    parameter("stop-writes-on-bgsave-error", true, |c| yes_no(c.stop_writes_on_bgsave_error), |c, v| {
+        c.stop_writes_on_bgsave_error = parse_yes_no(v)?;
+        Ok(())
+    }),
+    // AOF isn't implemented, so it can only be set to disabled. redis-benchmark asks for it.
+    parameter("appendonly", true, |_| "no".to_string(), |_, v| {
+        if parse_yes_no(v)? {
+            return Err("the append only file is not supported".to_string());
+        }
+        Ok(())
+    }),
+];
+
+fn lookup(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS.iter().find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn save_rules_string(rules: &[SaveRule]) -> String {
+    rules
+        .iter()
+        .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses `<seconds> <changes>` pairs, an empty value disables snapshotting
+pub fn parse_save_rules(value: &str) -> Result<Vec<SaveRule>, String> {
+    let parts = value.split_whitespace().collect::<Vec<_>>();
+    if parts.len() % 2 != 0 {
+        return Err("expected pairs of <seconds> <changes>".to_string());
+    }
+
+    parts
+        .chunks(2)
+        .map(|pair| {
+            let seconds = pair[0].parse::<u64>().map_err(|_| format!("invalid number of seconds '{}'", pair[0]))?;
+            let changes = pair[1].parse::<u64>().map_err(|_| format!("invalid number of changes '{}'", pair[1]))?;
+            Ok(SaveRule { seconds, changes })
+        })
+        .collect()
+}
+
+/// Parses a master to replicate as "<host> <port>", or "no one" for none
+pub fn parse_replica_of(value: &str) -> Result<Option<ReplicaOf>, String> {
+    let parts = value.split_whitespace().collect::<Vec<_>>();
+    match parts.as_slice() {
+        [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => Ok(None),
+        [host, port] => {
+            if host.starts_with('-') {
+                return Err(format!("invalid master host '{}'", host));
+            }
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or_else(|| format!("invalid master port '{}', expected a number from 1 to 65535", port))?;
+            Ok(Some(ReplicaOf { host: host.to_string(), port }))
+        }
+        [] => Err("expected a host and port, or 'no one'".to_string()),
+        [_] => Err(format!("expected a host and port, or 'no one', got '{}'", value.trim())),
+        _ => Err(format!("expected only a host and port, got '{}'", value.trim())),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+pub fn parse_yes_no(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("expected yes or no, got '{}'", value)),
+    }
+}
+
+/// Parses a memory size such as `64mb` or `1gb`, a plain number is in bytes
+pub fn parse_memory(value: &str) -> Result<usize, String> {
+    let lower = value.to_lowercase();
+    let split = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory unit in '{}'", value)),
+    };
+
+    let number = number.parse::<usize>().map_err(|_| format!("expected a memory size, got '{}'", value))?;
+    number.checked_mul(multiplier).ok_or_else(|| format!("memory size '{}' is out of range", value))
+}
+
+/// The current value of `name`, `None` if there's no such parameter
+pub fn config_get(config: &Config, name: &str) -> Option<(&'static str, String)> {
+    lookup(name).map(|parameter| (parameter.name, (parameter.get)(config)))
+}
+
+/// Applies `changes` to `config`, checking every value before any takes effect so a bad one
+/// leaves the config untouched. Immutable parameters are refused unless `startup` is set.
+pub fn apply_config(config: &mut Config, changes: &[(String, String)], startup: bool) -> Result<(), ConfigError> {
+    let mut updated = config.clone();
+    for (name, value) in changes {
+        let Some(parameter) = lookup(name) else {
+            return Err(ConfigError::UnknownParameter(name.clone()));
+        };
+        if !parameter.mutable && !startup {
+            return Err(ConfigError::Immutable(parameter.name.to_string()));
+        }
+        (parameter.set)(&mut updated, value).map_err(|reason| ConfigError::InvalidValue {
+            name: parameter.name.to_string(),
+            reason,
+        })?;
+    }
+
+    *config = updated;
+    Ok(())
+}
+
+/// CONFIG SET with any number of parameter and value pairs
+pub async fn config_set(changes: &[(String, String)]) -> Result<(), ConfigError> {
+    let mut config = CONFIG.write().await;
+    apply_config(&mut config, changes, false)?;
+    set_log_level(config.loglevel);
+    Ok(())
+}
+
+/// A parsed config file, in the redis.conf format of one `name value` directive per line
+pub struct ConfigFile {
+    path: PathBuf,
+    directives: HashMap<String, String>,
+}
+
+impl ConfigFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(Self {
+            path,
+            directives: parse_config(&contents)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn directives(&self) -> Vec<(String, String)> {
+        self.directives.iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+    }
+}
+
+/// Remembers `file` as the one to re-read on SIGHUP
+pub fn set_config_file(file: ConfigFile) {
+    *CONFIG_FILE.lock().unwrap() = Some(file);
+}
+
+/// Directives that may be given on several lines, each adding to the values before it like
+/// `save 900 1` followed by `save 300 10` does in redis.conf. An empty value starts over.
+const REPEATABLE: &[&str] = &["save"];
+
+fn parse_config(contents: &str) -> Result<HashMap<String, String>, ConfigError> {
+    let mut directives: HashMap<String, String> = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let arguments = split_arguments(line).ok_or_else(|| ConfigError::BadDirective {
+            line: i + 1,
+            reason: "unbalanced quotes".to_string(),
+        })?;
+        let Some((name, values)) = arguments.split_first() else {
+            continue;
+        };
+        if lookup(name).is_none() {
+            return Err(ConfigError::BadDirective {
+                line: i + 1,
+                reason: format!("unknown directive '{}'", name),
+            });
+        }
+        let (name, value) = (name.to_lowercase(), values.join(" "));
+        match directives.get_mut(&name) {
+            Some(previous) if REPEATABLE.contains(&name.as_str()) && !previous.is_empty() && !value.is_empty() => {
+                previous.push(' ');
+                previous.push_str(&value);
+            }
+            _ => {
+                directives.insert(name, value);
+            }
+        }
+    }
+    Ok(directives)
+}
+
+/// Re-reads the config file and applies the directives that changed in it since it was last
+/// read, returning their names. Values set since through CONFIG SET or on the command line are
+/// left alone unless the file changes them too. Immutable parameters are only reported.
+pub async fn reload_config_file() -> Result<Vec<String>, ConfigError> {
+    let path = match CONFIG_FILE.lock().unwrap().as_ref() {
+        Some(file) => file.path.clone(),
+        None => return Err(ConfigError::NoConfigFile),
+    };
+    let file = ConfigFile::read(&path)?;
+
+    let mut changes = Vec::new();
+    {
+        let previous = CONFIG_FILE.lock().unwrap();
+        let previous = previous.as_ref().map(|file| &file.directives);
+        for (name, value) in file.directives.iter() {
+            if previous.and_then(|directives| directives.get(name)) == Some(value) {
+                continue;
+            }
+            if lookup(name).is_some_and(|parameter| !parameter.mutable) {
+                server_log!(Warning, "Config file changes {} but it can only be set at startup, ignoring it", name);
+                continue;
+            }
+            changes.push((name.clone(), value.clone()));
+        }
+    }
+
+    config_set(&changes).await?;
+    set_config_file(file);
+
+    Ok(changes.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Reloads the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_sighup() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        server_log!(Warning, "Failed to install the SIGHUP handler, config reloading is disabled");
+        return;
+    };
+
+    while hangups.recv().await.is_some() {
+        match reload_config_file().await {
+            Ok(changed) if changed.is_empty() => server_log!(Notice, "Reloaded config file, nothing changed"),
+            Ok(changed) => server_log!(Notice, "Reloaded config file, changed {}", changed.join(", ")),
+            Err(e) => server_log!(Warning, "Failed to reload config file. {}", e),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use futures::future::BoxFuture;
+use crate::server_log;
+use crate::CONFIG;
+use crate::clock;
+use crate::clients::CLIENTS;
+use crate::database::{db_active_expire, db_background_save, rdb_path, save_rule_met, LOADING};
+use crate::replication::ping_replicas;
+
+pub const DEFAULT_HZ: u32 = 10;
+const MIN_HZ: u32 = 1;
+const MAX_HZ: u32 = 500;
+
+/// How often a master pings its replicas, matching repl-ping-replica-period
+const REPL_PING_REPLICA_PERIOD: Duration = Duration::from_secs(10);
+
+/// Number of times the cron has ticked since startup
+pub static CRON_LOOPS: AtomicU64 = AtomicU64::new(0);
+
+/// Periodic background work, run from the cron rather than each feature spawning its own loop.
+/// Jobs receive the length of a tick so they can keep their share of it bounded.
+struct Job {
+    name: &'static str,
+    /// Zero runs the job on every tick
+    period: Duration,
+    last_run: Option<Instant>,
+    run: fn(Duration) -> BoxFuture<'static, Result<(), anyhow::Error>>,
+}
+
+impl Job {
+    const fn new(name: &'static str, period: Duration, run: fn(Duration) -> BoxFuture<'static, Result<(), anyhow::Error>>) -> Self {
+        Self {
+            name,
+            period,
+            last_run: None,
+            run,
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        match self.last_run {
+            Some(last_run) => now.duration_since(last_run) >= self.period,
+            None => true,
+        }
+    }
+}
+
+fn jobs() -> Vec<Job> {
+    vec![
+        // Spend at most a quarter of each tick reclaiming expired keys
+        Job::new("active-expire", Duration::ZERO, |tick| Box::pin(async move {
+            db_active_expire(tick / 4).await;
+            Ok(())
+        })),
+        Job::new("client-eviction", Duration::ZERO, |_| Box::pin(async {
+            let limit = CONFIG.read().await.maxmemory_clients;
+            CLIENTS.evict_over(limit);
+            Ok(())
+        })),
+        Job::new("save-rules", Duration::from_secs(1), |_| Box::pin(async {
+            if LOADING.in_progress.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let config = CONFIG.read().await;
+            if let Some(rule) = save_rule_met(&config.save_rules) {
+                if db_background_save(rdb_path(&config)) {
+                    server_log!(Notice, "{} changes in {} seconds. Saving...", rule.changes, rule.seconds);
+                }
+            }
+            Ok(())
+        })),
+        Job::new("replica-ping", REPL_PING_REPLICA_PERIOD, |_| Box::pin(ping_replicas())),
+    ]
+}
+
+/// Runs the background jobs `hz` times a second until the process exits. The rate is re-read
+/// every tick so a changed `hz` takes effect straight away.
+pub async fn run_server_cron() {
+    let mut jobs = jobs();
+    loop {
+        let hz = CONFIG.read().await.hz.clamp(MIN_HZ, MAX_HZ);
+        let tick = Duration::from_micros(1_000_000 / hz as u64);
+        tokio::time::sleep(tick).await;
+
+        let now = clock::monotonic();
+        for job in jobs.iter_mut().filter(|job| job.is_due(now)) {
+            job.last_run = Some(now);
+            if let Err(e) = (job.run)(tick).await {
+                server_log!(Warning, "Cron job {} failed. {:?}", job.name, e);
+            }
+        }
+
+        CRON_LOOPS.fetch_add(1, Ordering::Relaxed);
+    }
+}
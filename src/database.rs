@@ -1,84 +1,492 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use bytes::Bytes;
+use futures::future::join_all;
 use once_cell::sync::Lazy;
-use tokio::sync::RwLock;
-use crate::persistence::{DataType, RdbReader};
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock};
+use crate::server_log;
+use crate::{Config, SaveRule, CONFIG};
+use crate::clock;
+use crate::memory::entry_usage;
+use crate::persistence::{DataType, ProgressReader, RdbData, RdbReadError, RdbReader, RdbWriter, RDB_VERSION};
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::list::{insert_at_pivot, trim, InsertPosition};
+use crate::zset::{AddOptions, AddOutcome, ScoreEnd, SortedSet};
+use crate::quicklist::{ListEnd, QuickList};
+use crate::replication::{replication_position, restore_replication_position};
+use crate::shard::shard_pool;
+use crate::storage::{new_storage, CacheEntry, Storage};
+use crate::telemetry::spawn_named;
+use crate::util::{glob_match, normalize_index, parse_integer, random_u64};
 
-type Database = HashMap<String, CacheEntry>;
+pub const DATABASES: usize = 16;
+const DEFAULT_DB_FILENAME: &str = "dump.rdb";
 
-static CACHE: Lazy<Arc<RwLock<HashMap<usize, Database>>>> = Lazy::new(|| {
-    let mut databases = HashMap::new();
-    for i in 0..16 {
-        databases.insert(i, Database::new());
+pub(crate) type Database = Box<dyn Storage>;
+/// Indexed by database id, which runs densely from 0 to DATABASES
+pub(crate) type Databases = Box<[Database]>;
+
+static CACHE: Lazy<Arc<RwLock<Databases>>> = Lazy::new(|| {
+    Arc::new(RwLock::new(new_databases()))
+});
+
+pub(crate) fn new_databases() -> Databases {
+    (0..DATABASES).map(|_| new_storage()).collect()
+}
+
+// Every access to the keyspace goes through one of these. Without keyspace shards they run
+// against the shared cache under its lock, with shards they're sent to the thread that owns the
+// key, or to every shard for operations over the whole keyspace.
+
+/// Reads the databases holding `key`
+async fn read_key<R, F>(key: &[u8], f: F) -> R
+where
+    F: FnOnce(&Databases) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match shard_pool() {
+        Some(pool) => pool.run(pool.shard_for(key), move |databases| f(databases)).await,
+        None => f(&*CACHE.read().await),
+    }
+}
+
+/// Modifies the databases holding `key`
+async fn write_key<R, F>(key: &[u8], f: F) -> R
+where
+    F: FnOnce(&mut Databases) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match shard_pool() {
+        Some(pool) => pool.run(pool.shard_for(key), f).await,
+        None => f(&mut *CACHE.write().await),
+    }
+}
+
+/// Reads every partition of the keyspace, returning one result per partition
+async fn read_all<R, F>(f: F) -> Vec<R>
+where
+    F: Fn(&Databases) -> R + Clone + Send + 'static,
+    R: Send + 'static,
+{
+    match shard_pool() {
+        Some(pool) => {
+            join_all((0..pool.len()).map(|shard| {
+                let f = f.clone();
+                pool.run(shard, move |databases| f(databases))
+            })).await
+        }
+        None => vec![f(&*CACHE.read().await)],
+    }
+}
+
+/// Modifies every partition of the keyspace, returning one result per partition
+async fn write_all<R, F>(f: F) -> Vec<R>
+where
+    F: Fn(&mut Databases) -> R + Clone + Send + 'static,
+    R: Send + 'static,
+{
+    match shard_pool() {
+        Some(pool) => join_all((0..pool.len()).map(|shard| pool.run(shard, f.clone()))).await,
+        None => vec![f(&mut *CACHE.write().await)],
+    }
+}
+
+pub struct LoadingState {
+    pub in_progress: AtomicBool,
+    /// Unix time in seconds
+    pub start_time: AtomicU64,
+    pub total_bytes: AtomicU64,
+    pub loaded_bytes: AtomicU64,
+}
+
+impl LoadingState {
+    const fn new() -> Self {
+        Self {
+            in_progress: AtomicBool::new(false),
+            start_time: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(0),
+            loaded_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Marks the dataset as loading, data commands are refused until `finish` is called
+    pub fn begin(&self) {
+        let now = clock::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.start_time.store(now, Ordering::Relaxed);
+        self.total_bytes.store(0, Ordering::Relaxed);
+        self.loaded_bytes.store(0, Ordering::Relaxed);
+        self.in_progress.store(true, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        self.in_progress.store(false, Ordering::Relaxed);
     }
-    Arc::new(RwLock::new(databases))
+}
+
+pub static LOADING: LoadingState = LoadingState::new();
+
+/// Keyspace changes that the last snapshot doesn't have yet
+static DIRTY: AtomicU64 = AtomicU64::new(0);
+/// Unix time in seconds the dataset last matched the snapshot on disk
+static LAST_SAVE: AtomicU64 = AtomicU64::new(0);
+/// Held while a snapshot is written, so only one is in progress at a time
+static SAVE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+/// Set while the last background save failed
+static BGSAVE_FAILED: AtomicBool = AtomicBool::new(false);
+/// Unix time in seconds the last background save was started
+static LAST_BGSAVE_ATTEMPT: AtomicU64 = AtomicU64::new(0);
+/// Seconds the save rules wait before trying again after a failed background save
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+/// What a write did to a key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyspaceEvent {
+    /// Given a new value
+    Set,
+    /// Given a new time to live
+    Expire,
+    /// Removed by a command
+    Deleted,
+    /// Removed because its time to live ran out
+    Expired,
+}
+
+/// A change to one key, as seen by the hooks registered with `on_keyspace_write`
+#[derive(Clone, Copy, Debug)]
+pub struct KeyspaceWrite<'a> {
+    pub db: usize,
+    pub key: &'a [u8],
+    pub event: KeyspaceEvent,
+}
+
+pub type KeyspaceHook = Box<dyn Fn(&KeyspaceWrite) + Send + Sync>;
+
+/// Called for every change to a key. Counting writes towards the save rules is the first
+/// subscriber, anything else that has to follow the keyspace, such as invalidating watched
+/// keys, registers here rather than in each command.
+static KEYSPACE_HOOKS: Lazy<std::sync::RwLock<Vec<KeyspaceHook>>> = Lazy::new(|| {
+    let count_dirty: KeyspaceHook = Box::new(|_| {
+        DIRTY.fetch_add(1, Ordering::Relaxed);
+    });
+    std::sync::RwLock::new(vec![count_dirty])
 });
 
-struct CacheEntry {
-    expiration: Option<SystemTime>,
-    value: DataType,
+/// Registers `hook` to be called for every change to a key from now on. Hooks run while the
+/// keyspace is locked, possibly on a keyspace shard's thread, so they must be quick and must not
+/// access the keyspace themselves. Replacing the whole dataset, as loading a snapshot does,
+/// isn't reported key by key.
+pub fn on_keyspace_write(hook: impl Fn(&KeyspaceWrite) + Send + Sync + 'static) {
+    KEYSPACE_HOOKS.write().unwrap().push(Box::new(hook));
+}
+
+/// The one place keyspace changes are announced from, every write to a key goes through here
+fn notify_write(db: usize, key: &[u8], event: KeyspaceEvent) {
+    let write = KeyspaceWrite { db, key, event };
+    for hook in KEYSPACE_HOOKS.read().unwrap().iter() {
+        hook(&write);
+    }
+}
+
+/// Key lookups by commands that found the key
+static KEYSPACE_HITS: AtomicU64 = AtomicU64::new(0);
+/// Key lookups by commands that didn't
+static KEYSPACE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+fn record_lookup(hit: bool) {
+    let counter = if hit { &KEYSPACE_HITS } else { &KEYSPACE_MISSES };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn keyspace_hits() -> u64 {
+    KEYSPACE_HITS.load(Ordering::Relaxed)
+}
+
+pub fn keyspace_misses() -> u64 {
+    KEYSPACE_MISSES.load(Ordering::Relaxed)
+}
+
+/// Zeroes the keyspace hits and misses, for CONFIG RESETSTAT
+pub fn reset_keyspace_stats() {
+    KEYSPACE_HITS.store(0, Ordering::Relaxed);
+    KEYSPACE_MISSES.store(0, Ordering::Relaxed);
+}
+
+pub fn changes_since_last_save() -> u64 {
+    DIRTY.load(Ordering::Relaxed)
+}
+
+pub fn last_save_time() -> u64 {
+    LAST_SAVE.load(Ordering::Relaxed)
 }
 
+pub fn last_bgsave_failed() -> bool {
+    BGSAVE_FAILED.load(Ordering::Relaxed)
+}
+
+pub fn save_in_progress() -> bool {
+    SAVE_LOCK.try_lock().is_err()
+}
+
+fn unix_time() -> u64 {
+    clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Marks the dataset as matching the snapshot on disk, as it does right after starting up
+pub(crate) fn reset_dirty() {
+    DIRTY.store(0, Ordering::Relaxed);
+    LAST_SAVE.store(unix_time(), Ordering::Relaxed);
+}
+
+#[tracing::instrument(name = "rdb.load", skip_all, fields(path = %db_file.as_ref().display()))]
 pub async fn db_load(db_file: impl AsRef<Path>) -> Result<(), anyhow::Error> {
-    let mut cache = CACHE.write().await;
-    cache.clear();
+    LOADING.begin();
+    let result = db_load_file(db_file).await;
+    LOADING.finish();
+    reset_dirty();
 
-    let data = match RdbReader::read(db_file).await {
-        Ok(r) => r,
-        Err(e) => {
-            println!("Failed to open database - {:?}", e);
-            return Ok(());
-        }
-    };
+    if let Err(e) = result {
+        server_log!(Warning, "Failed to open database - {:?}", e);
+    }
+    Ok(())
+}
+
+/// Aux fields holding the replication id and offset the saved dataset corresponds to
+const REPL_ID_AUX: &str = "repl-id";
+const REPL_OFFSET_AUX: &str = "repl-offset";
+
+async fn db_load_file(db_file: impl AsRef<Path>) -> Result<(), RdbReadError> {
+    let file = File::open(db_file).await?;
+    LOADING.total_bytes.store(file.metadata().await?.len(), Ordering::Relaxed);
+    let metadata = db_load_from(file).await?;
+
+    // Lets a restarted replica ask its master to carry on from where the snapshot left off
+    let replid = metadata.get(REPL_ID_AUX);
+    let offset = metadata.get(REPL_OFFSET_AUX).and_then(|offset| offset.parse::<u64>().ok());
+    if let (Some(replid), Some(offset)) = (replid, offset) {
+        restore_replication_position(replid.clone(), offset).await;
+    }
+    Ok(())
+}
 
+#[tracing::instrument(name = "rdb.load", skip_all, fields(bytes = rdb.len()))]
+pub async fn db_load_bytes(rdb: &[u8]) -> Result<(), anyhow::Error> {
+    LOADING.begin();
+    LOADING.total_bytes.store(rdb.len() as u64, Ordering::Relaxed);
+    let result = db_load_from(rdb).await.map(|_| ());
+    LOADING.finish();
+
+    Ok(result?)
+}
+
+/// Replaces the dataset with the one read from `source`, returning its aux fields
+async fn db_load_from(source: impl AsyncRead + Unpin + Send) -> Result<HashMap<String, String>, RdbReadError> {
+    let mut data = RdbReader::read_from(ProgressReader::new(source, &LOADING.loaded_bytes)).await?;
+    let metadata = std::mem::take(&mut data.metadata);
+    db_replace(data).await;
+    Ok(metadata)
+}
+
+async fn db_replace(data: RdbData) {
+    let partitions = shard_pool().map_or(1, |pool| pool.len());
+    let mut replacements = (0..partitions).map(|_| new_databases()).collect::<Vec<_>>();
     for (id, map) in data.databases {
         let expirations = data.expirations.get(&id);
-        let remapped = map
-            .into_iter()
-            .map(|(k, v)| {
-                let expiration = if let Some(expirations) = expirations {
-                    expirations.get(&k).cloned()
-                } else {
-                    None
-                };
-
-                (
-                    k,
-                    CacheEntry {
-                        expiration,
-                        value: v,
-                    }
-                )
-            })
-            .collect();
-        cache.insert(id, remapped);
+        for (k, v) in map {
+            let expiration = if let Some(expirations) = expirations {
+                expirations.get(&k).cloned()
+            } else {
+                None
+            };
+
+            let partition = shard_pool().map_or(0, |pool| pool.shard_for(&k));
+            if let Some(database) = replacements[partition].get_mut(id) {
+                database.set(k, CacheEntry::new(v, expiration));
+            }
+        }
     }
 
+    match shard_pool() {
+        Some(pool) => {
+            join_all(replacements.into_iter().enumerate().map(|(shard, replacement)| {
+                pool.run(shard, move |databases| *databases = replacement)
+            })).await;
+        }
+        None => *CACHE.write().await = replacements.pop().unwrap(),
+    }
+}
+
+/// Captures every live key so it can be shipped to a replica as an rdb payload
+#[tracing::instrument(name = "rdb.snapshot", skip_all)]
+pub async fn db_snapshot() -> RdbData {
+    let now = clock::now();
+    let partitions = read_all(move |cache| {
+        let mut databases: HashMap<usize, HashMap<Bytes, DataType>> = HashMap::new();
+        let mut expirations: HashMap<usize, HashMap<Bytes, SystemTime>> = HashMap::new();
+        for (id, database) in cache.iter().enumerate() {
+            database.for_each(&mut |key, entry| {
+                if entry.is_expired(now) {
+                    return;
+                }
+                databases
+                    .entry(id)
+                    .or_default()
+                    .insert(Bytes::copy_from_slice(key), entry.value.clone());
+
+                if let Some(expiration) = entry.expiration {
+                    expirations
+                        .entry(id)
+                        .or_default()
+                        .insert(Bytes::copy_from_slice(key), expiration);
+                }
+            });
+        }
+        (databases, expirations)
+    }).await;
+
+    let mut databases: HashMap<usize, HashMap<Bytes, DataType>> = HashMap::new();
+    let mut expirations: HashMap<usize, HashMap<Bytes, SystemTime>> = HashMap::new();
+    for (partition_databases, partition_expirations) in partitions {
+        for (id, keys) in partition_databases {
+            databases.entry(id).or_default().extend(keys);
+        }
+        for (id, keys) in partition_expirations {
+            expirations.entry(id).or_default().extend(keys);
+        }
+    }
+
+    RdbData {
+        rdb_version: RDB_VERSION,
+        metadata: HashMap::new(),
+        databases,
+        expirations,
+    }
+}
+
+/// Writes a snapshot of the dataset to `path`, waiting for one that is already being written
+#[tracing::instrument(name = "rdb.save", skip_all, fields(path = %path.as_ref().display()))]
+pub async fn db_save(path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+    let _saving = SAVE_LOCK.lock().await;
+    save_snapshot(path.as_ref()).await
+}
+
+/// Starts writing a snapshot to `path` in the background. Returns false without starting one if
+/// a snapshot is already being written.
+pub fn db_background_save(path: PathBuf) -> bool {
+    let Ok(saving) = SAVE_LOCK.try_lock() else {
+        return false;
+    };
+
+    LAST_BGSAVE_ATTEMPT.store(unix_time(), Ordering::Relaxed);
+    spawn_named("bgsave", async move {
+        let _saving = saving;
+        server_log!(Notice, "Background saving started");
+        let result = save_snapshot(&path).await;
+        BGSAVE_FAILED.store(result.is_err(), Ordering::Relaxed);
+        match result {
+            Ok(_) => server_log!(Notice, "Background saving terminated with success"),
+            Err(e) => server_log!(Warning, "Background saving error. {:?}", e),
+        }
+    });
+    true
+}
+
+/// Replaces `path` with `contents` so that a crash part way through leaves either the old file or
+/// the new one, never a truncated one. The data goes to a temporary file next to it first, which
+/// is synced and then renamed over the target.
+async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp_path = dir.join(format!("temp-{}.rdb", std::process::id()));
+
+    let result = async {
+        let mut file = File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        sync_dir(dir).await
+    }.await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Makes a rename in `dir` durable
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
-pub async fn _db_save(_file_path: &Path) {
-    todo!("Implement saving!")
+/// The first of `rules` that has been met, if the dataset is due to be saved
+pub fn save_rule_met(rules: &[SaveRule]) -> Option<SaveRule> {
+    let now = unix_time();
+    if last_bgsave_failed() && now.saturating_sub(LAST_BGSAVE_ATTEMPT.load(Ordering::Relaxed)) < BGSAVE_RETRY_DELAY {
+        return None;
+    }
+
+    let changes = changes_since_last_save();
+    let elapsed = now.saturating_sub(last_save_time());
+    rules
+        .iter()
+        .find(|rule| changes >= rule.changes && elapsed >= rule.seconds)
+        .copied()
+}
+
+/// Where snapshots are written, `dir` and `dbfilename` fall back to Redis' defaults
+pub fn rdb_path(config: &Config) -> PathBuf {
+    let dir = config.dir.as_deref().unwrap_or(".");
+    let filename = config.db_filename.as_deref().unwrap_or(DEFAULT_DB_FILENAME);
+    Path::new(dir).join(filename)
 }
 
-pub async fn db_get(db_id: usize, key: &String) -> Result<Option<DataType>, anyhow::Error> {
-    let (result, should_remove) = {
-        let cache = CACHE.read().await;
-        if let Some(database) = cache.get(&db_id) {
-            let mut is_valid = true;
-            if let Some(entry) = database.get(key) {
-                if let Some(expiration) = entry.expiration.as_ref() {
-                    if *expiration < SystemTime::now() {
-                        is_valid = false;
-                    }
-                }
+async fn save_snapshot(path: &Path) -> Result<(), anyhow::Error> {
+    // Writes that land while the snapshot is written still count for the next one
+    let dirty = DIRTY.load(Ordering::Relaxed);
+    // Taken first, so on a replica a write landing in between is at worst sent again by the
+    // master after a restart rather than lost
+    let (replid, offset) = replication_position().await;
+    let mut snapshot = db_snapshot().await;
+    snapshot.metadata.insert(REPL_ID_AUX.to_string(), replid);
+    snapshot.metadata.insert(REPL_OFFSET_AUX.to_string(), offset.to_string());
+    let rdb = RdbWriter::write(&snapshot)?;
+    write_atomically(path, &rdb).await?;
 
-                if is_valid {
-                    (Some(entry.value.clone()), false)
-                } else {
+    DIRTY.fetch_sub(dirty, Ordering::Relaxed);
+    LAST_SAVE.store(unix_time(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Looks a key up on behalf of a command, which counts as an access to it and towards the
+/// keyspace hits or misses
+pub async fn db_get(db_id: usize, key: &[u8]) -> Result<Option<DataType>, anyhow::Error> {
+    let owned_key = Bytes::copy_from_slice(key);
+    let (result, should_remove) = read_key(key, move |cache| {
+        if let Some(database) = cache.get(db_id) {
+            if let Some(mut entry) = database.get(&owned_key) {
+                if entry.is_expired(clock::now()) {
                     (None, true)
+                } else {
+                    database.touch(&owned_key, clock::now());
+                    entry.remove_expired_fields(clock::now());
+                    (Some(entry.value), false)
                 }
             } else {
                 (None, false)
@@ -86,36 +494,1135 @@ pub async fn db_get(db_id: usize, key: &String) -> Result<Option<DataType>, anyh
         } else {
             (None, false)
         }
-    };
+    }).await;
 
     if should_remove {
-        let mut cache = CACHE.write().await;
-        let database = cache.get_mut(&db_id).unwrap();
-        database.remove(key);
+        expire_if_needed(db_id, key).await;
     }
 
+    record_lookup(result.is_some());
     Ok(result)
 }
 
-pub async fn db_set(db_id: usize, key: String, value: String, timeout: Option<Duration>) -> Result<(), anyhow::Error> {
-    let mut cache = CACHE.write().await;
-    if let Some(database) = cache.get_mut(&db_id) {
-        let expiration = timeout.map(|timeout| SystemTime::now() + timeout);
-        let entry = CacheEntry {
-            value: DataType::String(value),
-            expiration,
-        };
-        database.insert(key, entry);
+/// Runs `read` on the value of `key` where it's stored, returning what it returns or None if the
+/// key doesn't exist. Unlike `db_get` the value isn't copied out, so reading part of a large
+/// list, hash, set or sorted set only costs as much as that part.
+pub async fn db_read<R, F>(db_id: usize, key: &[u8], read: F) -> Option<R>
+where
+    F: FnOnce(&DataType) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let owned_key = Bytes::copy_from_slice(key);
+    let (result, should_remove) = read_key(key, move |cache| {
+        let database = cache.get(db_id)?;
+        let now = clock::now();
+        let mut read = Some(read);
+        let mut result = None;
+        let mut expired = false;
+        database.visit(&owned_key, &mut |entry| {
+            if entry.is_expired(now) {
+                expired = true;
+                return;
+            }
+            let Some(read) = read.take() else {
+                return;
+            };
+            // Readers never see hash fields that have expired, the rare hash holding some is
+            // copied without them
+            result = Some(if entry.has_expired_fields(now) {
+                let mut entry = entry.clone();
+                entry.remove_expired_fields(now);
+                read(&entry.value)
+            } else {
+                read(&entry.value)
+            });
+        });
+        if result.is_some() {
+            database.touch(&owned_key, now);
+        }
+        Some((result, expired))
+    }).await.unwrap_or((None, false));
+
+    if should_remove {
+        expire_if_needed(db_id, key).await;
     }
 
+    record_lookup(result.is_some());
+    result
+}
+
+/// Removes a key a lookup found expired. Replicas report expired keys as missing but leave them
+/// in place, the master's DEL is what actually removes them so both sides stay consistent.
+async fn expire_if_needed(db_id: usize, key: &[u8]) {
+    if is_replica().await {
+        return;
+    }
+
+    let owned_key = Bytes::copy_from_slice(key);
+    write_key(key, move |cache| {
+        let database = cache.get_mut(db_id).unwrap();
+        if database.get(&owned_key).is_some_and(|entry| entry.is_expired(clock::now())) {
+            database.delete(&owned_key);
+            notify_write(db_id, &owned_key, KeyspaceEvent::Expired);
+        }
+    }).await;
+}
+
+/// Counts how many of `keys` exist, a key given more than once is counted each time. Expired
+/// keys are missing and get removed, like they do when read.
+pub async fn db_exists(db_id: usize, keys: Vec<Bytes>) -> usize {
+    let mut found = 0;
+    for key in keys {
+        let owned_key = key.clone();
+        let state = read_key(&key, move |cache| {
+            let now = clock::now();
+            let mut state = None;
+            cache.get(db_id)?.visit(&owned_key, &mut |entry| state = Some(!entry.is_expired(now)));
+            state
+        }).await;
+
+        if state == Some(false) {
+            expire_if_needed(db_id, &key).await;
+        }
+        let exists = state == Some(true);
+        record_lookup(exists);
+        found += exists as usize;
+    }
+    found
+}
+
+/// Records an access to each of `keys` that exists, returning how many did
+pub async fn db_touch(db_id: usize, keys: Vec<Bytes>) -> usize {
+    let mut touched = 0;
+    for key in keys {
+        let hit = read_key(&key.clone(), move |cache| {
+            cache.get(db_id).is_some_and(|database| database.touch(&key, clock::now()))
+        }).await;
+        record_lookup(hit);
+        touched += hit as usize;
+    }
+    touched
+}
+
+pub async fn db_set(db_id: usize, key: Bytes, value: Bytes, timeout: Option<Duration>) -> Result<(), anyhow::Error> {
+    db_set_expiring_at(db_id, key, value, timeout.map(|timeout| clock::now() + timeout)).await
+}
+
+/// Sets a key that expires at `expiration`, or never without one
+pub async fn db_set_expiring_at(db_id: usize, key: Bytes, value: Bytes, expiration: Option<SystemTime>) -> Result<(), anyhow::Error> {
+    write_key(&key.clone(), move |cache| {
+        if let Some(database) = cache.get_mut(db_id) {
+            notify_write(db_id, &key, KeyspaceEvent::Set);
+            database.set(key, CacheEntry::new(DataType::String(value), expiration));
+        }
+    }).await;
+
     Ok(())
 }
 
-pub async fn db_list_keys(db_id: usize) -> Result<Vec<String>, anyhow::Error> {
-    let cache = CACHE.read().await;
-    if let Some(database) = cache.get(&db_id) {
-        Ok(database.keys().cloned().collect::<Vec<_>>())
+/// Why a command can't work with the value a key holds. Each message is the error reply.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ValueError {
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR index out of range")]
+    IndexOutOfRange,
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+    #[error("ERR resulting score is not a number (NaN)")]
+    ScoreIsNan,
+}
+
+/// Adds `delta` to the integer stored as a string at `key`, which starts out as 0 if it doesn't
+/// exist, and returns the new value. The key keeps its time to live.
+pub async fn db_incr_by(db_id: usize, key: Bytes, delta: i64) -> Result<i64, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Err(ValueError::NotAnInteger);
+        };
+        let (current, expiration) = match database.get(&key).filter(|entry| !entry.is_expired(clock::now())) {
+            Some(CacheEntry { value: DataType::String(value), expiration, .. }) => {
+                (parse_integer(&value).ok_or(ValueError::NotAnInteger)?, expiration)
+            }
+            Some(_) => return Err(ValueError::WrongType),
+            None => (0, None),
+        };
+
+        let value = current.checked_add(delta).ok_or(ValueError::Overflow)?;
+        notify_write(db_id, &key, KeyspaceEvent::Set);
+        database.set(key, CacheEntry::new(DataType::String(value.to_string().into()), expiration));
+        Ok(value)
+    }).await
+}
+
+/// Sets or clears the bit at `offset` of the string at `key`, counting from the most significant
+/// bit of the first byte. The string is created or padded with zero bytes when it is too short.
+/// Returns the bit's previous value.
+pub async fn db_set_bit(db_id: usize, key: Bytes, offset: usize, bit: bool) -> Result<bool, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(false);
+        };
+        let (mut bytes, expiration) = match database.get(&key).filter(|entry| !entry.is_expired(clock::now())) {
+            Some(CacheEntry { value: DataType::String(value), expiration, .. }) => (value.to_vec(), expiration),
+            Some(_) => return Err(ValueError::WrongType),
+            None => (Vec::new(), None),
+        };
+
+        let (index, mask) = (offset / 8, 0x80u8 >> (offset % 8));
+        if bytes.len() <= index {
+            bytes.resize(index + 1, 0);
+        }
+        let previous = bytes[index] & mask != 0;
+        if bit {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+
+        notify_write(db_id, &key, KeyspaceEvent::Set);
+        database.set(key, CacheEntry::new(DataType::String(bytes.into()), expiration));
+        Ok(previous)
+    }).await
+}
+
+/// Sets a string that never expires, unless `key` already holds a value. Returns whether it did.
+pub async fn db_set_if_missing(db_id: usize, key: Bytes, value: Bytes) -> bool {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return false;
+        };
+        if database.get(&key).is_some_and(|entry| !entry.is_expired(clock::now())) {
+            return false;
+        }
+        notify_write(db_id, &key, KeyspaceEvent::Set);
+        database.set(key, CacheEntry::new(DataType::String(value), None));
+        true
+    }).await
+}
+
+/// Replaces the string at `key` with one that never expires, returning the old one
+pub async fn db_get_and_set(db_id: usize, key: Bytes, value: Bytes) -> Result<Option<Bytes>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(None);
+        };
+        let old = match database.get(&key).filter(|entry| !entry.is_expired(clock::now())) {
+            Some(CacheEntry { value: DataType::String(old), .. }) => Some(old),
+            Some(_) => return Err(ValueError::WrongType),
+            None => None,
+        };
+        notify_write(db_id, &key, KeyspaceEvent::Set);
+        database.set(key, CacheEntry::new(DataType::String(value), None));
+        Ok(old)
+    }).await
+}
+
+/// Hands the value at `key` to `update` to change where it's stored, once `value` has picked out
+/// the type the command works with. None if the key doesn't exist. Only what `update` touches is
+/// written, a large value isn't copied out and stored back.
+fn update_value<T, R>(database: &mut Database, key: &[u8], value: fn(&mut DataType) -> Option<&mut T>, update: impl FnOnce(&mut T) -> R) -> Result<Option<R>, ValueError> {
+    let now = clock::now();
+    let mut update = Some(update);
+    let mut result = Ok(None);
+    database.update(key, &mut |entry| {
+        if entry.is_expired(now) {
+            return;
+        }
+        // Fields that expired are removed for good before a hash is changed
+        entry.remove_expired_fields(now);
+        result = match (value(&mut entry.value), update.take()) {
+            (Some(value), Some(update)) => Ok(Some(update(value))),
+            _ => Err(ValueError::WrongType),
+        };
+    });
+    result
+}
+
+/// Announces a change a command made to the list, hash, set or sorted set at `key` in place.
+/// Redis never keeps empty ones around, so the key is removed once it's `emptied`.
+fn aggregate_changed(database: &mut Database, db_id: usize, key: &[u8], emptied: bool) {
+    if emptied {
+        database.delete(key);
+        notify_write(db_id, key, KeyspaceEvent::Deleted);
     } else {
-        Err(anyhow::Error::msg("Database doesn't exist"))
+        notify_write(db_id, key, KeyspaceEvent::Set);
     }
 }
+
+/// Stores an aggregate a command created under `key`, which doesn't hold a value
+fn aggregate_created(database: &mut Database, db_id: usize, key: Bytes, value: DataType) {
+    notify_write(db_id, &key, KeyspaceEvent::Set);
+    database.set(key, CacheEntry::new(value, None));
+}
+
+/// Runs `read` on the value at `key` where it's stored, once `value` has picked out the type the
+/// command works with. None if the key doesn't exist. Used by moves to check their destination
+/// before touching the source.
+fn read_value<T, R>(database: &Database, key: &[u8], value: fn(&DataType) -> Option<&T>, read: impl FnOnce(&T) -> R) -> Result<Option<R>, ValueError> {
+    let now = clock::now();
+    let mut read = Some(read);
+    let mut result = Ok(None);
+    database.visit(key, &mut |entry| {
+        if entry.is_expired(now) {
+            return;
+        }
+        result = match (value(&entry.value), read.take()) {
+            (Some(value), Some(read)) => Ok(Some(read(value))),
+            _ => Err(ValueError::WrongType),
+        };
+    });
+    result
+}
+
+fn list(value: &DataType) -> Option<&QuickList> {
+    match value {
+        DataType::List(list) => Some(list),
+        _ => None,
+    }
+}
+
+fn list_mut(value: &mut DataType) -> Option<&mut QuickList> {
+    match value {
+        DataType::List(list) => Some(list),
+        _ => None,
+    }
+}
+
+/// Pushes each of `values` in turn onto one end of the list at `key`, and returns its new length.
+/// A missing key starts out as an empty list, unless `create` isn't set, in which case nothing is
+/// pushed and the length is 0.
+pub async fn db_push(db_id: usize, key: Bytes, values: Vec<Bytes>, end: ListEnd, create: bool) -> Result<usize, ValueError> {
+    let fill = CONFIG.read().await.list_max_listpack_size;
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let push = |list: &mut QuickList, values: Vec<Bytes>| {
+            for value in values {
+                list.push(end, value);
+            }
+            list.len()
+        };
+        let mut values = Some(values);
+        let length = match update_value(database, &key, list_mut, |list| push(list, values.take().unwrap_or_default()))? {
+            Some(length) => {
+                aggregate_changed(database, db_id, &key, false);
+                length
+            }
+            None if create => {
+                let mut list = QuickList::new(fill);
+                let length = push(&mut list, values.take().unwrap_or_default());
+                aggregate_created(database, db_id, key, DataType::List(list));
+                length
+            }
+            None => 0,
+        };
+        Ok(length)
+    }).await
+}
+
+/// Removes up to `count` elements from one end of the list at `key`, in the order they were
+/// popped. None if the key doesn't exist.
+pub async fn db_pop(db_id: usize, key: Bytes, end: ListEnd, count: usize) -> Result<Option<Vec<Bytes>>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(None);
+        };
+        let popped = update_value(database, &key, list_mut, |list| {
+            let popped: Vec<Bytes> = (0..count.min(list.len())).filter_map(|_| list.pop(end)).collect();
+            (popped, list.is_empty())
+        })?;
+        let Some((popped, emptied)) = popped else {
+            return Ok(None);
+        };
+        if !popped.is_empty() {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(Some(popped))
+    }).await
+}
+
+/// Moves an element from one end of the list at `source` to one end of the list at `destination`,
+/// which is created if needed, returning it. None if the source doesn't exist.
+pub async fn db_list_move(db_id: usize, source: Bytes, destination: Bytes, from: ListEnd, to: ListEnd) -> Result<Option<Bytes>, ValueError> {
+    // The keys may live in different keyspace shards, so the move is a pop and a push. The
+    // destination is checked first so a move that can't happen leaves the source alone.
+    let destination_key = destination.clone();
+    read_key(&destination, move |cache| match cache.get(db_id) {
+        Some(database) => read_value(database, &destination_key, list, |_| ()).map(|_| ()),
+        None => Ok(()),
+    }).await?;
+
+    let Some(element) = db_pop(db_id, source.clone(), from, 1).await?.and_then(|popped| popped.into_iter().next()) else {
+        return Ok(None);
+    };
+    if let Err(e) = db_push(db_id, destination, vec![element.clone()], to, true).await {
+        // The destination changed type in between, the element goes back where it came from
+        db_push(db_id, source, vec![element], from, true).await?;
+        return Err(e);
+    }
+    Ok(Some(element))
+}
+
+/// Replaces the element at `index` of the list at `key`, a negative index counting back from the
+/// tail
+pub async fn db_list_set(db_id: usize, key: Bytes, index: i64, value: Bytes) -> Result<(), ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Err(ValueError::NoSuchKey);
+        };
+        update_value(database, &key, list_mut, |list| {
+            let index = normalize_index(index, list.len()).ok_or(ValueError::IndexOutOfRange)?;
+            list.set(index, value);
+            Ok(())
+        })?.ok_or(ValueError::NoSuchKey)??;
+        aggregate_changed(database, db_id, &key, false);
+        Ok(())
+    }).await
+}
+
+/// Inserts `value` before or after the first occurrence of `pivot` in the list at `key`. Returns
+/// the new length, None if the pivot wasn't found, or 0 if the key doesn't exist.
+pub async fn db_list_insert(db_id: usize, key: Bytes, position: InsertPosition, pivot: Bytes, value: Bytes) -> Result<Option<usize>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(Some(0));
+        };
+        let Some(length) = update_value(database, &key, list_mut, |list| insert_at_pivot(list, position, &pivot, value))? else {
+            return Ok(Some(0));
+        };
+        if length.is_some() {
+            aggregate_changed(database, db_id, &key, false);
+        }
+        Ok(length)
+    }).await
+}
+
+/// Removes elements equal to `value` from the list at `key` following LREM's `count`, see
+/// `QuickList::remove_matching`. Returns how many were removed.
+pub async fn db_list_remove(db_id: usize, key: Bytes, count: i64, value: Bytes) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let removed = update_value(database, &key, list_mut, |list| (list.remove_matching(&value, count), list.is_empty()))?;
+        let Some((removed, emptied)) = removed else {
+            return Ok(0);
+        };
+        if removed > 0 {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(removed)
+    }).await
+}
+
+/// Trims the list at `key` down to the elements between the inclusive `start` and `end`
+pub async fn db_list_trim(db_id: usize, key: Bytes, start: i64, end: i64) -> Result<(), ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(());
+        };
+        let trimmed = update_value(database, &key, list_mut, |list| {
+            let length = list.len();
+            trim(list, start, end);
+            (list.len() != length, list.is_empty())
+        })?;
+        if let Some((true, emptied)) = trimmed {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(())
+    }).await
+}
+
+fn hash_mut(value: &mut DataType) -> Option<&mut Hash> {
+    match value {
+        DataType::Hash(hash) => Some(hash),
+        _ => None,
+    }
+}
+
+/// Sets fields of the hash at `key`, which is created if needed. Fields that already exist are
+/// only overwritten with `replace`. Returns how many of the fields are new.
+pub async fn db_hash_set(db_id: usize, key: Bytes, pairs: Vec<(Bytes, Bytes)>, replace: bool) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        // Returns how many fields were added and whether any was set
+        let set = |hash: &mut Hash, pairs: Vec<(Bytes, Bytes)>| {
+            let mut added = 0;
+            let mut changed = false;
+            for (field, value) in pairs {
+                if replace || !hash.contains_key(&field) {
+                    added += hash.insert(field, value) as usize;
+                    changed = true;
+                }
+            }
+            (added, changed)
+        };
+        let mut pairs = Some(pairs);
+        match update_value(database, &key, hash_mut, |hash| set(hash, pairs.take().unwrap_or_default()))? {
+            Some((added, changed)) => {
+                if changed {
+                    aggregate_changed(database, db_id, &key, false);
+                }
+                Ok(added)
+            }
+            None => {
+                let mut hash = Hash::default();
+                let (added, _) = set(&mut hash, pairs.take().unwrap_or_default());
+                aggregate_created(database, db_id, key, DataType::Hash(hash));
+                Ok(added)
+            }
+        }
+    }).await
+}
+
+/// Removes fields from the hash at `key`, returning how many of them were there
+pub async fn db_hash_delete(db_id: usize, key: Bytes, fields_to_remove: Vec<Bytes>) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let removed = update_value(database, &key, hash_mut, |hash| {
+            (fields_to_remove.iter().filter(|field| hash.remove(field)).count(), hash.is_empty())
+        })?;
+        let Some((removed, emptied)) = removed else {
+            return Ok(0);
+        };
+        if removed > 0 {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(removed)
+    }).await
+}
+
+/// Makes each of `fields` in the hash at `key` expire at `expiration` if `condition` allows it,
+/// like `db_set_expiry` does for keys. Returns what happened to each field, None for one that
+/// isn't in the hash. Removing the last field removes the key.
+pub async fn db_hash_set_expiry(db_id: usize, key: Bytes, fields: Vec<Bytes>, expiration: SystemTime, condition: ExpireCondition) -> Result<Vec<Option<ExpiryChange>>, ValueError> {
+    let delete = expiration <= clock::now() && !is_replica().await;
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(vec![None; fields.len()]);
+        };
+        let field_count = fields.len();
+        let changes = update_value(database, &key, hash_mut, |hash| {
+            let changes: Vec<_> = fields
+                .into_iter()
+                .map(|field| {
+                    if !hash.contains_key(&field) {
+                        None
+                    } else if !condition.allows(hash.expiration(&field), expiration) {
+                        Some(ExpiryChange::Unchanged)
+                    } else if delete {
+                        hash.remove(&field);
+                        Some(ExpiryChange::Deleted)
+                    } else {
+                        hash.set_expiration(field, expiration);
+                        Some(ExpiryChange::Updated)
+                    }
+                })
+                .collect();
+            (changes, hash.is_empty())
+        })?;
+        let Some((changes, emptied)) = changes else {
+            return Ok(vec![None; field_count]);
+        };
+        if changes.iter().any(|change| matches!(change, Some(ExpiryChange::Updated | ExpiryChange::Deleted))) {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(changes)
+    }).await
+}
+
+/// Takes away the time to live of each of `fields` in the hash at `key`. Returns whether each
+/// one had one, None for a field that isn't in the hash.
+pub async fn db_hash_persist(db_id: usize, key: Bytes, fields: Vec<Bytes>) -> Result<Vec<Option<bool>>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(vec![None; fields.len()]);
+        };
+        let persisted = update_value(database, &key, hash_mut, |hash| {
+            fields
+                .iter()
+                .map(|field| hash.contains_key(field).then(|| hash.persist(field)))
+                .collect::<Vec<_>>()
+        })?;
+        let Some(persisted) = persisted else {
+            return Ok(vec![None; fields.len()]);
+        };
+        if persisted.contains(&Some(true)) {
+            aggregate_changed(database, db_id, &key, false);
+        }
+        Ok(persisted)
+    }).await
+}
+
+fn set(value: &DataType) -> Option<&Set> {
+    match value {
+        DataType::Set(members) => Some(members),
+        _ => None,
+    }
+}
+
+fn set_mut(value: &mut DataType) -> Option<&mut Set> {
+    match value {
+        DataType::Set(members) => Some(members),
+        _ => None,
+    }
+}
+
+/// Adds members to the set at `key`, which is created if needed. Returns how many of them weren't
+/// in the set yet.
+pub async fn db_set_add(db_id: usize, key: Bytes, new_members: Vec<Bytes>) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let add = |members: &mut Set, new_members: Vec<Bytes>| new_members.into_iter().filter(|member| members.insert(member.clone())).count();
+        let mut new_members = Some(new_members);
+        match update_value(database, &key, set_mut, |members| add(members, new_members.take().unwrap_or_default()))? {
+            Some(added) => {
+                if added > 0 {
+                    aggregate_changed(database, db_id, &key, false);
+                }
+                Ok(added)
+            }
+            None => {
+                let mut members = Set::default();
+                let added = add(&mut members, new_members.take().unwrap_or_default());
+                aggregate_created(database, db_id, key, DataType::Set(members));
+                Ok(added)
+            }
+        }
+    }).await
+}
+
+/// Removes members from the set at `key`, returning how many of them were there
+pub async fn db_set_remove(db_id: usize, key: Bytes, members_to_remove: Vec<Bytes>) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let removed = update_value(database, &key, set_mut, |members| {
+            (members_to_remove.iter().filter(|member| members.remove(member)).count(), members.is_empty())
+        })?;
+        let Some((removed, emptied)) = removed else {
+            return Ok(0);
+        };
+        if removed > 0 {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(removed)
+    }).await
+}
+
+/// Moves `member` from the set at `source` to the set at `destination`, which is created if
+/// needed. Returns whether the member was in the source.
+pub async fn db_set_move(db_id: usize, source: Bytes, destination: Bytes, member: Bytes) -> Result<bool, ValueError> {
+    // Like with lists the keys may live in different keyspace shards, so the move is a removal
+    // and an add. The destination is checked first so a move that can't happen leaves the
+    // source alone.
+    let (destination_key, wanted) = (destination.clone(), member.clone());
+    let in_destination = read_key(&destination, move |cache| match cache.get(db_id) {
+        Some(database) => read_value(database, &destination_key, set, |members| members.contains(&wanted)).map(|found| found.unwrap_or(false)),
+        None => Ok(false),
+    }).await?;
+    if source == destination {
+        return Ok(in_destination);
+    }
+
+    if db_set_remove(db_id, source.clone(), vec![member.clone()]).await? == 0 {
+        return Ok(false);
+    }
+    if let Err(e) = db_set_add(db_id, destination, vec![member.clone()]).await {
+        // The destination changed type in between, the member goes back where it came from
+        db_set_add(db_id, source, vec![member]).await?;
+        return Err(e);
+    }
+    Ok(true)
+}
+
+fn sorted_set_mut(value: &mut DataType) -> Option<&mut SortedSet> {
+    match value {
+        DataType::SortedSet(zset) => Some(zset),
+        _ => None,
+    }
+}
+
+/// ZADD: adds or updates members of the sorted set at `key`, which is created if needed, as far
+/// as `options` allow. Returns what happened to each member. A score can only become NaN with
+/// INCR, which takes a single member, so nothing is changed when one would.
+pub async fn db_zadd(db_id: usize, key: Bytes, members: Vec<(f64, Bytes)>, options: AddOptions) -> Result<Vec<AddOutcome>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(Vec::new());
+        };
+        let add = |zset: &mut SortedSet, members: Vec<(f64, Bytes)>| {
+            members
+                .into_iter()
+                .map(|(score, member)| zset.add(member, score, options))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ValueError::ScoreIsNan)
+        };
+        let changed = |outcomes: &[AddOutcome]| outcomes.iter().any(|outcome| matches!(outcome, AddOutcome::Added(_) | AddOutcome::Updated(_)));
+
+        let mut members = Some(members);
+        match update_value(database, &key, sorted_set_mut, |zset| add(zset, members.take().unwrap_or_default()))? {
+            Some(outcomes) => {
+                let outcomes = outcomes?;
+                if changed(&outcomes) {
+                    aggregate_changed(database, db_id, &key, false);
+                }
+                Ok(outcomes)
+            }
+            None => {
+                let mut zset = SortedSet::default();
+                let outcomes = add(&mut zset, members.take().unwrap_or_default())?;
+                if changed(&outcomes) {
+                    aggregate_created(database, db_id, key, DataType::SortedSet(zset));
+                }
+                Ok(outcomes)
+            }
+        }
+    }).await
+}
+
+/// Removes members from the sorted set at `key`, returning how many of them were there
+pub async fn db_zrem(db_id: usize, key: Bytes, members: Vec<Bytes>) -> Result<usize, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(0);
+        };
+        let removed = update_value(database, &key, sorted_set_mut, |zset| {
+            (members.iter().filter(|member| zset.remove(member)).count(), zset.is_empty())
+        })?;
+        let Some((removed, emptied)) = removed else {
+            return Ok(0);
+        };
+        if removed > 0 {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(removed)
+    }).await
+}
+
+/// Pops up to `count` members with the lowest or highest scores from the sorted set at `key`,
+/// along with their scores. None if the key doesn't exist.
+pub async fn db_zpop(db_id: usize, key: Bytes, end: ScoreEnd, count: usize) -> Result<Option<Vec<(Bytes, f64)>>, ValueError> {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return Ok(None);
+        };
+        let popped = update_value(database, &key, sorted_set_mut, |zset| {
+            let popped: Vec<(Bytes, f64)> = (0..count.min(zset.len())).filter_map(|_| zset.pop(end)).collect();
+            (popped, zset.is_empty())
+        })?;
+        let Some((popped, emptied)) = popped else {
+            return Ok(None);
+        };
+        if !popped.is_empty() {
+            aggregate_changed(database, db_id, &key, emptied);
+        }
+        Ok(Some(popped))
+    }).await
+}
+
+/// Stores `value` under `key`, unless the key already holds a value and `replace` isn't set.
+/// Returns whether it was stored.
+pub async fn db_insert(db_id: usize, key: Bytes, value: DataType, expiration: Option<SystemTime>, replace: bool) -> bool {
+    write_key(&key.clone(), move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return false;
+        };
+        if !replace && database.get(&key).is_some_and(|entry| !entry.is_expired(clock::now())) {
+            return false;
+        }
+        notify_write(db_id, &key, KeyspaceEvent::Set);
+        database.set(key, CacheEntry::new(value, expiration));
+        true
+    }).await
+}
+
+/// Copies the value and time to live of `source` in one database to `destination` in another,
+/// or the same one. An existing destination is only overwritten with `replace`. Returns whether
+/// the copy was made.
+pub async fn db_copy(source_db: usize, source: &[u8], destination_db: usize, destination: Bytes, replace: bool) -> bool {
+    let Some((value, expiration)) = db_get_with_expiration(source_db, source).await else {
+        return false;
+    };
+    db_insert(destination_db, destination, value, expiration, replace).await
+}
+
+/// A key's entry, read without counting as an access, for commands that inspect keys such as
+/// OBJECT
+pub async fn db_peek(db_id: usize, key: &[u8]) -> Option<CacheEntry> {
+    let owned_key = Bytes::copy_from_slice(key);
+    read_key(key, move |cache| cache.get(db_id)?.get(&owned_key).filter(|entry| !entry.is_expired(clock::now()))).await
+}
+
+/// Reads a key along with when it expires
+pub async fn db_get_with_expiration(db_id: usize, key: &[u8]) -> Option<(DataType, Option<SystemTime>)> {
+    let owned_key = Bytes::copy_from_slice(key);
+    read_key(key, move |cache| {
+        let entry = cache.get(db_id)?.get(&owned_key)?;
+        if entry.is_expired(clock::now()) {
+            return None;
+        }
+        Some((entry.value, entry.expiration))
+    }).await
+}
+
+/// When a key expires, None if it doesn't exist and Some(None) if it never expires. For
+/// EXPIRETIME and the like, which only need the time and not a copy of the value.
+pub async fn db_expiration(db_id: usize, key: &[u8]) -> Option<Option<SystemTime>> {
+    let owned_key = Bytes::copy_from_slice(key);
+    read_key(key, move |cache| {
+        let now = clock::now();
+        let mut expiration = None;
+        cache.get(db_id)?.visit(&owned_key, &mut |entry| {
+            if !entry.is_expired(now) {
+                expiration = Some(entry.expiration);
+            }
+        });
+        expiration
+    }).await
+}
+
+/// Which keys EXPIRE and the like may change the time to live of, from their NX, XX, GT and LT
+/// options. A key without one counts as never expiring.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ExpireCondition {
+    /// Only keys without a time to live
+    pub nx: bool,
+    /// Only keys with a time to live
+    pub xx: bool,
+    /// Only when the new expiration is later than the current one
+    pub gt: bool,
+    /// Only when the new expiration is earlier than the current one
+    pub lt: bool,
+}
+
+impl ExpireCondition {
+    fn allows(&self, current: Option<SystemTime>, new: SystemTime) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => !self.nx && (!self.gt || new > current) && (!self.lt || new < current),
+        }
+    }
+}
+
+/// What `db_set_expiry` did to a key
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpiryChange {
+    /// The key doesn't exist or the condition didn't hold
+    Unchanged,
+    Updated,
+    /// The expiration was already in the past, so the key was removed instead
+    Deleted,
+}
+
+/// Makes a key expire at `expiration` if `condition` allows it. On a master an expiration that
+/// has already passed deletes the key right away, replicas wait for the master's DEL.
+pub async fn db_set_expiry(db_id: usize, key: &[u8], expiration: SystemTime, condition: ExpireCondition) -> ExpiryChange {
+    let delete = expiration <= clock::now() && !is_replica().await;
+    let owned_key = Bytes::copy_from_slice(key);
+    write_key(key, move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return ExpiryChange::Unchanged;
+        };
+        let now = clock::now();
+        let mut current = None;
+        database.visit(&owned_key, &mut |entry| {
+            if !entry.is_expired(now) {
+                current = Some(entry.expiration);
+            }
+        });
+        let Some(current) = current else {
+            return ExpiryChange::Unchanged;
+        };
+        if !condition.allows(current, expiration) {
+            return ExpiryChange::Unchanged;
+        }
+
+        if delete {
+            database.delete(&owned_key);
+            notify_write(db_id, &owned_key, KeyspaceEvent::Deleted);
+            return ExpiryChange::Deleted;
+        }
+        database.update(&owned_key, &mut |entry| entry.expiration = Some(expiration));
+        notify_write(db_id, &owned_key, KeyspaceEvent::Expire);
+        ExpiryChange::Updated
+    }).await
+}
+
+/// Removes a key, returning whether it existed
+pub async fn db_delete(db_id: usize, key: &[u8]) -> bool {
+    let owned_key = Bytes::copy_from_slice(key);
+    write_key(key, move |cache| {
+        let Some(database) = cache.get_mut(db_id) else {
+            return false;
+        };
+        let Some(entry) = database.delete(&owned_key) else {
+            return false;
+        };
+        notify_write(db_id, &owned_key, KeyspaceEvent::Deleted);
+        !entry.is_expired(clock::now())
+    }).await
+}
+
+/// Removes each of `keys`, returning how many existed
+pub async fn db_remove(db_id: usize, keys: Vec<Bytes>) -> usize {
+    let mut removed = 0;
+    for key in keys {
+        removed += db_delete(db_id, &key).await as usize;
+    }
+    removed
+}
+
+/// Expired keys RANDOMKEY may run into before giving up, when most of the keyspace has expired
+/// but not been removed yet
+const RANDOM_KEY_ATTEMPTS: usize = 100;
+
+/// A live key picked uniformly at random, None if the database is empty
+pub async fn db_random_key(db_id: usize) -> Option<Bytes> {
+    for _ in 0..RANDOM_KEY_ATTEMPTS {
+        // One candidate from each partition, chosen between in proportion to their sizes
+        let candidates = read_all(move |cache| {
+            cache.get(db_id).map_or((0, None), |database| (database.len(), database.random_key()))
+        }).await;
+        let total = candidates.iter().map(|(len, _)| len).sum::<usize>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut index = (random_u64() % total as u64) as usize;
+        let (key, expiration) = candidates
+            .into_iter()
+            .find_map(|(len, candidate)| {
+                if index < len {
+                    return Some(candidate);
+                }
+                index -= len;
+                None
+            })
+            .flatten()?;
+        match expiration {
+            Some(expiration) if expiration < clock::now() => expire_if_needed(db_id, &key).await,
+            _ => return Some(key),
+        }
+    }
+    None
+}
+
+/// Every live key in a database that matches the glob `pattern`
+pub async fn db_list_keys(db_id: usize, pattern: &[u8]) -> Result<Vec<Bytes>, anyhow::Error> {
+    if db_id >= DATABASES {
+        return Err(anyhow::Error::msg("Database doesn't exist"));
+    }
+
+    let now = clock::now();
+    let pattern = Bytes::copy_from_slice(pattern);
+    let partitions = read_all(move |cache| {
+        let mut keys = Vec::new();
+        if let Some(database) = cache.get(db_id) {
+            database.for_each(&mut |key, entry| {
+                if !entry.is_expired(now) && glob_match(&pattern, key, false) {
+                    keys.push(Bytes::copy_from_slice(key));
+                }
+            });
+        }
+        keys
+    }).await;
+
+    Ok(partitions.into_iter().flatten().collect())
+}
+
+/// What SCAN returns keys for
+#[derive(Clone, Default, Debug)]
+pub struct ScanFilter {
+    /// Glob the keys have to match
+    pub pattern: Option<Bytes>,
+    /// Type the values have to be, as TYPE names it
+    pub type_name: Option<String>,
+}
+
+impl ScanFilter {
+    fn accepts(&self, key: &[u8], entry: &CacheEntry, now: SystemTime) -> bool {
+        if entry.is_expired(now) {
+            return false;
+        }
+        if let Some(pattern) = &self.pattern {
+            if !glob_match(pattern, key, false) {
+                return false;
+            }
+        }
+        match &self.type_name {
+            Some(type_name) => type_name.eq_ignore_ascii_case(entry.value.type_name()),
+            None => true,
+        }
+    }
+}
+
+/// Carries on a SCAN from `cursor`, visiting parts of the keyspace until at least `count` keys
+/// passed the filter or the whole keyspace has been covered. A filter that rarely matches stops
+/// early too, after `count` * 10 parts, so one call never walks everything. Returns the cursor
+/// to continue from, 0 once done, and the keys found.
+///
+/// A storage cursor walks the same fixed tables however the keyspace changes, so a key that is
+/// there for the whole iteration is returned at least once. Every partition is walked in step
+/// with the same cursor, which works because they all use the same engine.
+pub async fn db_scan(db_id: usize, mut cursor: usize, count: usize, filter: ScanFilter) -> (usize, Vec<Bytes>) {
+    let mut keys = Vec::new();
+    let mut steps_left = count.saturating_mul(10);
+    loop {
+        let filter = filter.clone();
+        let now = clock::now();
+        let partitions = read_all(move |cache| {
+            let mut keys = Vec::new();
+            let Some(database) = cache.get(db_id) else {
+                return (0, keys);
+            };
+            let next = database.scan(cursor, &mut |key, entry| {
+                if filter.accepts(key, entry, now) {
+                    keys.push(Bytes::copy_from_slice(key));
+                }
+            });
+            (next, keys)
+        }).await;
+
+        cursor = partitions.first().map_or(0, |(next, _)| *next);
+        keys.extend(partitions.into_iter().flat_map(|(_, keys)| keys));
+        steps_left -= 1;
+        if cursor == 0 || keys.len() >= count || steps_left == 0 {
+            return (cursor, keys);
+        }
+    }
+}
+
+/// Memory accounting of one database, over all of its partitions
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DatabaseMemory {
+    pub keys: usize,
+    /// Estimated bytes of the entries, table slots included
+    pub used: usize,
+}
+
+/// Memory accounting of every database, indexed by id
+pub async fn db_memory_stats() -> Vec<DatabaseMemory> {
+    let partitions = read_all(|cache| {
+        cache.iter().map(|database| DatabaseMemory { keys: database.len(), used: database.used_memory() }).collect::<Vec<_>>()
+    }).await;
+
+    let mut totals = vec![DatabaseMemory::default(); DATABASES];
+    for partition in partitions {
+        for (total, database) in totals.iter_mut().zip(partition) {
+            total.keys += database.keys;
+            total.used += database.used;
+        }
+    }
+    totals
+}
+
+/// Estimated bytes a key takes along with its value, None if it doesn't exist
+pub async fn db_memory_usage(db_id: usize, key: &[u8], samples: usize) -> Option<usize> {
+    let owned_key = Bytes::copy_from_slice(key);
+    read_key(key, move |cache| {
+        let now = clock::now();
+        let mut usage = None;
+        cache.get(db_id)?.visit(&owned_key, &mut |entry| {
+            if !entry.is_expired(now) {
+                usage = Some(entry_usage(&owned_key, entry, samples));
+            }
+        });
+        usage
+    }).await
+}
+
+/// How many keys of a database are in a cluster hash slot
+pub async fn db_count_keys_in_slot(db_id: usize, slot: u16) -> usize {
+    read_all(move |cache| cache.get(db_id).map_or(0, |database| database.count_keys_in_slot(slot)))
+        .await
+        .into_iter()
+        .sum()
+}
+
+/// Up to `count` keys of a database that are in a cluster hash slot
+pub async fn db_keys_in_slot(db_id: usize, slot: u16, count: usize) -> Vec<Bytes> {
+    read_all(move |cache| cache.get(db_id).map_or_else(Vec::new, |database| database.keys_in_slot(slot, count)))
+        .await
+        .into_iter()
+        .flatten()
+        .take(count)
+        .collect()
+}
+
+/// Every live key in a database along with its value and expiration, in no particular order
+pub async fn db_entries(db_id: usize) -> Vec<(Bytes, DataType, Option<SystemTime>)> {
+    let now = clock::now();
+    let partitions = read_all(move |cache| {
+        let mut entries = Vec::new();
+        if let Some(database) = cache.get(db_id) {
+            database.for_each(&mut |key, entry| {
+                if !entry.is_expired(now) {
+                    entries.push((Bytes::copy_from_slice(key), entry.value.clone(), entry.expiration));
+                }
+            });
+        }
+        entries
+    }).await;
+
+    partitions.into_iter().flatten().collect()
+}
+
+/// Database the active expire cycle starts at, each database keeps track of where it resumes
+static EXPIRE_CURSOR: AtomicUsize = AtomicUsize::new(0);
+/// Upper bound on the keys one active expire cycle looks at, so a large keyspace is swept over
+/// several cycles rather than all at once
+const ACTIVE_EXPIRE_KEYS_PER_CYCLE: usize = 10_000;
+
+/// Removes expired keys that nobody has asked for, a database at a time, stopping once `budget`
+/// has elapsed. Returns the number of keys removed.
+pub async fn db_active_expire(budget: Duration) -> usize {
+    // Replicas wait for the master to remove keys, the same as with lazy expiry
+    if is_replica().await {
+        return 0;
+    }
+
+    let start = Instant::now();
+    let now = clock::now();
+    let removed = write_all(move |cache| {
+        let databases = cache.len();
+        let mut examined = 0;
+        let mut removed = 0;
+        for _ in 0..databases {
+            let position = EXPIRE_CURSOR.fetch_add(1, Ordering::Relaxed) % databases;
+            if let Some(database) = cache.get_mut(position) {
+                let (looked_at, expired) = database.expire(now, ACTIVE_EXPIRE_KEYS_PER_CYCLE - examined, &mut |key| {
+                    notify_write(position, key, KeyspaceEvent::Expired);
+                });
+                examined += looked_at;
+                removed += expired;
+            }
+
+            if examined >= ACTIVE_EXPIRE_KEYS_PER_CYCLE || start.elapsed() >= budget {
+                break;
+            }
+        }
+        removed
+    }).await;
+
+    removed.into_iter().sum()
+}
+
+async fn is_replica() -> bool {
+    CONFIG.read().await.replica_of.is_some()
+}
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+use bytes::Bytes;
+use crate::util::random_u64;
+
+/// Hashes the keys of the keyspace and of the maps inside values. ahash is several times faster
+/// than the standard SipHash on short keys, and is still seeded randomly for every map. The
+/// std-hasher feature switches back to SipHash.
+#[cfg(not(feature = "std-hasher"))]
+pub type KeyHasher = ahash::RandomState;
+#[cfg(feature = "std-hasher")]
+pub type KeyHasher = std::collections::hash_map::RandomState;
+
+pub type KeyMap<K, V> = HashMap<K, V, KeyHasher>;
+pub type KeySet<K> = HashSet<K, KeyHasher>;
+
+/// Number of tables a dict is split across
+pub const SHARDS: usize = 256;
+
+/// A map keyed by binary safe strings, split across a fixed number of independent tables.
+///
+/// A single HashMap holding millions of keys reallocates and moves every entry at once when it
+/// grows, stalling every client waiting on the database lock for the whole rehash. Here each
+/// table only holds a slice of the keys, so growing one moves 1/256th of the dataset and the
+/// tables grow at different moments rather than all together.
+#[derive(Debug)]
+pub struct Dict<V> {
+    shards: Vec<KeyMap<Bytes, V>>,
+    hasher: KeyHasher,
+    len: usize,
+}
+
+impl<V> Dict<V> {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| KeyMap::default()).collect(),
+            hasher: KeyHasher::default(),
+            len: 0,
+        }
+    }
+
+    // hash_one needs Rust 1.71
+    #[allow(clippy::manual_hash_one)]
+    fn shard_of(&self, key: &[u8]) -> usize {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % SHARDS
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.shards[self.shard_of(key)].get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let shard = self.shard_of(key);
+        self.shards[shard].get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.shards[self.shard_of(key)].contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: Bytes, value: V) -> Option<V> {
+        let shard = self.shard_of(&key);
+        let previous = self.shards[shard].insert(key, value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let shard = self.shard_of(key);
+        let removed = self.shards[shard].remove(key);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        for shard in self.shards.iter_mut() {
+            *shard = KeyMap::default();
+        }
+        self.len = 0;
+    }
+
+    /// Runs `retain` over a single table, for work that has to be spread over many calls.
+    /// Returns how many entries were looked at and how many were removed.
+    pub fn retain_shard(&mut self, shard: usize, mut f: impl FnMut(&Bytes, &mut V) -> bool) -> (usize, usize) {
+        let table = &mut self.shards[shard];
+        let examined = table.len();
+        table.retain(|k, v| f(k, v));
+        let removed = examined - table.len();
+        self.len -= removed;
+        (examined, removed)
+    }
+
+    /// An entry picked uniformly at random. Only the table it's in gets walked, so this takes
+    /// around len / SHARDS steps rather than len.
+    pub fn random_entry(&self) -> Option<(&Bytes, &V)> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut index = (random_u64() % self.len as u64) as usize;
+        for shard in &self.shards {
+            if index < shard.len() {
+                return shard.iter().nth(index);
+            }
+            index -= shard.len();
+        }
+        None
+    }
+
+    /// Iterates over a single table
+    pub fn shard_iter(&self, shard: usize) -> impl Iterator<Item = (&Bytes, &V)> {
+        self.shards[shard].iter()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &V)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.shards.iter().flat_map(|shard| shard.keys())
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&Bytes, &mut V) -> bool) {
+        for shard in self.shards.iter_mut() {
+            shard.retain(|k, v| f(k, v));
+        }
+        self.len = self.shards.iter().map(|shard| shard.len()).sum();
+    }
+}
+
+impl<V> Default for Dict<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> FromIterator<(Bytes, V)> for Dict<V> {
+    fn from_iter<T: IntoIterator<Item = (Bytes, V)>>(iter: T) -> Self {
+        let mut dict = Dict::new();
+        for (key, value) in iter {
+            dict.insert(key, value);
+        }
+        dict
+    }
+}
+
+impl<V> IntoIterator for Dict<V> {
+    type Item = (Bytes, V);
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<KeyMap<Bytes, V>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.shards.into_iter().flatten()
+    }
+}
+
+/// Where an element falls in the order `scan_by_hash` walks its collection in
+#[allow(clippy::manual_hash_one)]
+fn scan_position<K: Hash + ?Sized>(hasher: &KeyHasher, element: &K) -> u64 {
+    let mut state = hasher.build_hasher();
+    element.hash(&mut state);
+    state.finish()
+}
+
+/// Carries on a walk over the `elements` of a KeyMap or KeySet from `cursor`, as HSCAN and SSCAN
+/// do, returning the cursor to continue from, 0 once done, and around `count` of the items. Each
+/// element comes with the item it stands for, `hasher` is the collection's own.
+///
+/// The elements are walked in order of their hash and the cursor is the hash to carry on from.
+/// A collection's hasher is seeded when it's created and kept through every change, and that
+/// order doesn't depend on the table layout, so elements added or removed along the way don't
+/// move the others around: one that is there for the whole walk is returned exactly once.
+/// Elements sharing a hash are always returned together.
+pub fn scan_by_hash<'a, K, T>(hasher: &KeyHasher, elements: impl Iterator<Item = (&'a K, T)>, cursor: u64, count: usize) -> (u64, Vec<T>)
+where
+    K: Hash + ?Sized + 'a,
+{
+    let mut ahead: Vec<(u64, T)> = elements
+        .map(|(element, item)| (scan_position(hasher, element), item))
+        .filter(|(position, _)| *position >= cursor)
+        .collect();
+    if ahead.len() <= count {
+        return (0, ahead.into_iter().map(|(_, item)| item).collect());
+    }
+
+    // Everything before the first element left out goes now, which leaves out its whole group
+    // of equal hashes. Should that be the group the batch starts with, it goes on its own instead.
+    let count = count.max(1);
+    ahead.select_nth_unstable_by_key(count, |(position, _)| *position);
+    let boundary = ahead[count].0;
+    let next = if ahead[..count].iter().any(|(position, _)| *position < boundary) {
+        ahead.retain(|(position, _)| *position < boundary);
+        boundary
+    } else {
+        ahead.retain(|(position, _)| *position == boundary);
+        boundary.wrapping_add(1)
+    };
+    (next, ahead.into_iter().map(|(_, item)| item).collect())
+}
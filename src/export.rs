@@ -0,0 +1,85 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+use crate::database::db_entries;
+use crate::persistence::DataType;
+
+/// Dumps a database as JSON, with keys sorted so that two servers holding the same data produce
+/// the same output and can be diffed. Expirations are absolute unix times in milliseconds, which
+/// unlike a remaining TTL don't change between two exports.
+///
+/// `{"db":0,"keys":[{"key":"foo","type":"string","value":"bar","expires_at":null}]}`
+pub async fn export_database(db_id: usize) -> String {
+    let mut entries = db_entries(db_id).await;
+    entries.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+
+    let mut json = format!("{{\"db\":{},\"keys\":[", db_id);
+    for (i, (key, value, expiration)) in entries.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str("{\"key\":");
+        write_json_bytes(&mut json, key);
+        let _ = write!(json, ",\"type\":\"{}\",\"value\":", value.type_name());
+        write_json_value(&mut json, value);
+        json.push_str(",\"expires_at\":");
+        match expiration {
+            Some(expiration) => {
+                let ms = expiration.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+                let _ = write!(json, "{}", ms);
+            }
+            None => json.push_str("null"),
+        }
+        json.push('}');
+    }
+    json.push_str("]}");
+    json
+}
+
+fn write_json_value(json: &mut String, value: &DataType) {
+    match value {
+        DataType::String(bytes) => write_json_bytes(json, bytes),
+        // The other types aren't held in memory yet
+        _ => json.push_str("null"),
+    }
+}
+
+/// Writes `bytes` as a JSON string. Values aren't necessarily UTF-8, so bytes that aren't part of
+/// a valid sequence are written as `\u00XX` escapes of their value.
+fn write_json_bytes(json: &mut String, bytes: &[u8]) {
+    json.push('"');
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                write_json_chars(json, valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                write_json_chars(json, std::str::from_utf8(valid).unwrap_or_default());
+                let invalid_len = e.error_len().unwrap_or(invalid.len());
+                for byte in &invalid[..invalid_len] {
+                    let _ = write!(json, "\\u{:04x}", byte);
+                }
+                rest = &invalid[invalid_len..];
+            }
+        }
+    }
+    json.push('"');
+}
+
+fn write_json_chars(json: &mut String, string: &str) {
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::SystemTime;
+use bytes::Bytes;
+use crate::dict::{scan_by_hash, KeyMap};
+use crate::persistence::{is_small_hash, HashFields};
+
+/// A hash's fields along with when the ones given a time to live expire. Expired fields are
+/// still here until something removes them, readers have to leave them out.
+#[derive(Clone, Default, Debug)]
+pub struct Hash {
+    fields: HashFields,
+    /// Only fields with a time to live have an entry
+    expirations: KeyMap<Bytes, SystemTime>,
+    /// Bytes held by the fields and their values
+    size: usize,
+}
+
+impl From<HashFields> for Hash {
+    fn from(fields: HashFields) -> Self {
+        let size = fields.iter().map(|(field, value)| field.len() + value.len()).sum();
+        Hash {
+            fields,
+            expirations: KeyMap::default(),
+            size,
+        }
+    }
+}
+
+impl Hash {
+    pub fn fields(&self) -> &HashFields {
+        &self.fields
+    }
+
+    pub fn into_fields(self) -> HashFields {
+        self.fields
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Bytes held by the fields and their values, not counting the tables' own bookkeeping
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Sets a field, which loses any time to live it had like a key that's overwritten does.
+    /// Returns whether the field is new.
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> bool {
+        self.expirations.remove(&field);
+        let added = field.len() + value.len();
+        let previous = self.fields.insert(field.clone(), value);
+        self.size += added;
+        if let Some(previous) = &previous {
+            self.size -= field.len() + previous.len();
+        }
+        previous.is_none()
+    }
+
+    /// Returns whether the field was there
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        self.expirations.remove(field);
+        let Some((field, value)) = self.fields.remove_entry(field) else {
+            return false;
+        };
+        self.size -= field.len() + value.len();
+        true
+    }
+
+    pub fn expiration(&self, field: &[u8]) -> Option<SystemTime> {
+        self.expirations.get(field).copied()
+    }
+
+    /// Makes a field that's in the hash expire at `expiration`
+    pub fn set_expiration(&mut self, field: Bytes, expiration: SystemTime) {
+        if self.fields.contains_key(&field) {
+            self.expirations.insert(field, expiration);
+        }
+    }
+
+    /// Takes away a field's time to live, returning whether it had one
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        self.expirations.remove(field).is_some()
+    }
+
+    pub fn expirations(&self) -> impl ExactSizeIterator<Item = (&Bytes, &SystemTime)> {
+        self.expirations.iter()
+    }
+
+    pub fn has_expirations(&self) -> bool {
+        !self.expirations.is_empty()
+    }
+
+    /// Whether any field expired before `now`
+    pub fn has_expired_fields(&self, now: SystemTime) -> bool {
+        self.expirations.values().any(|expiration| *expiration < now)
+    }
+
+    /// Whether every field expired before `now`, which leaves the hash as good as gone
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        !self.fields.is_empty()
+            && self.expirations.len() == self.fields.len()
+            && self.expirations.values().all(|expiration| *expiration < now)
+    }
+
+    /// Removes the fields that expired before `now`, returning how many there were
+    pub fn remove_expired(&mut self, now: SystemTime) -> usize {
+        if !self.has_expired_fields(now) {
+            return 0;
+        }
+        let fields = &mut self.fields;
+        let size = &mut self.size;
+        let before = fields.len();
+        self.expirations.retain(|field, expiration| {
+            let keep = *expiration >= now;
+            if !keep {
+                if let Some((field, value)) = fields.remove_entry(field) {
+                    *size -= field.len() + value.len();
+                }
+            }
+            keep
+        });
+        before - fields.len()
+    }
+}
+
+/// Carries on an HSCAN from `cursor`, see `scan_by_hash`. Hashes small enough for Redis to keep
+/// as a listpack are returned whole in one go, like Redis does.
+pub fn scan_fields(fields: &HashFields, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+    if is_small_hash(fields) {
+        return (0, fields.iter().collect());
+    }
+    scan_by_hash(fields.hasher(), fields.iter().map(|(field, value)| (field, (field, value))), cursor, count)
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use once_cell::sync::OnceCell;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{channel, Sender, Receiver};
+
+pub const DEFAULT_IO_THREADS: usize = 1;
+
+/// Batches of replies a connection can get ahead of its socket before command execution waits
+const MAX_QUEUED_BATCHES: usize = 64;
+
+/// Pool that performs socket writes when io-threads is above 1
+static IO_RUNTIME: OnceCell<Runtime> = OnceCell::new();
+
+/// Starts the write pool. Like Redis, `io_threads` counts the main thread, so 4 means three
+/// dedicated threads and 1 keeps writes on the connection's own task.
+pub fn start_io_threads(io_threads: usize) -> Result<(), anyhow::Error> {
+    if io_threads <= 1 {
+        return Ok(());
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(io_threads - 1)
+        .thread_name("io-thread")
+        .enable_all()
+        .build()?;
+    let _ = IO_RUNTIME.set(runtime);
+
+    Ok(())
+}
+
+/// The write side of a connection. Replies are collected until `flush`, which either writes
+/// them out directly or, with io threads enabled, hands the whole batch to a writer task on the
+/// pool so the connection can go on to execute the next pipeline while it is sent.
+pub enum ReplyWriter {
+    Direct(BufWriter<OwnedWriteHalf>),
+    Threaded {
+        pending: Vec<u8>,
+        batches: Sender<Vec<u8>>,
+        /// Bytes handed to the writer task that haven't reached the socket yet
+        in_flight: Arc<AtomicUsize>,
+    },
+}
+
+impl ReplyWriter {
+    pub fn new(stream: OwnedWriteHalf) -> Self {
+        let Some(runtime) = IO_RUNTIME.get() else {
+            return ReplyWriter::Direct(BufWriter::new(stream));
+        };
+
+        let (batches, receiver) = channel(MAX_QUEUED_BATCHES);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        runtime.spawn(write_batches(stream, receiver, in_flight.clone()));
+        ReplyWriter::Threaded {
+            pending: Vec::new(),
+            batches,
+            in_flight,
+        }
+    }
+
+    pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            ReplyWriter::Direct(stream) => stream.write_all(data).await,
+            ReplyWriter::Threaded { pending, .. } => {
+                pending.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reply bytes held in memory that haven't been written to the socket
+    pub fn buffered(&self) -> usize {
+        match self {
+            ReplyWriter::Direct(stream) => stream.buffer().len(),
+            ReplyWriter::Threaded { pending, in_flight, .. } => pending.len() + in_flight.load(Ordering::Relaxed),
+        }
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ReplyWriter::Direct(stream) => stream.flush().await,
+            ReplyWriter::Threaded { pending, batches, in_flight } => {
+                if pending.is_empty() {
+                    return Ok(());
+                }
+
+                let batch = std::mem::take(pending);
+                in_flight.fetch_add(batch.len(), Ordering::Relaxed);
+                batches
+                    .send(batch)
+                    .await
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection writer has stopped"))
+            }
+        }
+    }
+}
+
+async fn write_batches(mut stream: OwnedWriteHalf, mut batches: Receiver<Vec<u8>>, in_flight: Arc<AtomicUsize>) {
+    while let Some(batch) = batches.recv().await {
+        if stream.write_all(&batch).await.is_err() {
+            return;
+        }
+        in_flight.fetch_sub(batch.len(), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,131 @@
+pub mod allocator;
+pub mod aof;
+pub mod bitmap;
+pub mod blocking;
+pub mod client;
+pub mod clients;
+pub mod clock;
+pub mod cluster;
+pub mod command;
+pub mod config;
+pub mod cron;
+pub mod database;
+pub mod dict;
+pub mod export;
+pub mod hash;
+pub mod io_threads;
+pub mod list;
+pub mod logging;
+pub mod memory;
+pub mod module;
+pub mod persistence;
+pub mod quicklist;
+pub mod recorder;
+pub mod replication;
+pub mod script;
+pub mod server;
+pub mod session;
+pub mod set;
+pub mod shard;
+pub mod skiplist;
+pub mod storage;
+pub mod systemd;
+pub mod telemetry;
+pub mod util;
+pub mod zset;
+
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use once_cell::sync::Lazy;
+
+pub static CONFIG: Lazy<Arc<RwLock<Config>>> = Lazy::new(|| { Arc::new(RwLock::new(Config::default())) });
+
+#[derive(Clone)]
+pub struct Config {
+    pub dir: Option<String>,
+    pub db_filename: Option<String>,
+    pub port: u16,
+    pub replica_of: Option<ReplicaOf>,
+    pub replica_announce_ip: Option<String>,
+    pub replica_announce_port: Option<u16>,
+    pub replica_serve_stale_data: bool,
+    /// Entries per list node when positive, or a node size class in bytes when negative
+    pub list_max_listpack_size: i64,
+    /// How many times a second background jobs run
+    pub hz: u32,
+    /// Threads used for socket writes, including the main one
+    pub io_threads: usize,
+    /// Threads the keyspace is partitioned across, 1 keeps it in a single locked cache
+    pub keyspace_shards: usize,
+    /// Memory all clients together may use for their buffers before the heaviest are
+    /// disconnected, 0 for no limit
+    pub maxmemory_clients: usize,
+    pub cluster_enabled: bool,
+    pub supervised: systemd::Supervised,
+    pub loglevel: logging::LogLevel,
+    /// Log to this file instead of stdout
+    pub logfile: Option<String>,
+    pub syslog_enabled: bool,
+    pub syslog_ident: Option<String>,
+    pub syslog_facility: u8,
+    /// Snapshot the dataset once any of these is met, none disables snapshotting
+    pub save_rules: Vec<SaveRule>,
+    /// Refuse writes while the last background save failed, so the failure doesn't go unnoticed
+    pub stop_writes_on_bgsave_error: bool,
+    /// Capture file every received command is appended to, for replaying later
+    pub record_file: Option<String>,
+    /// Commands with any of these flags are written to the audit log
+    pub audit_log_categories: command::CommandFlags,
+    /// Where the audit log goes, none disables it
+    pub audit_log_file: Option<String>,
+    /// Engine the keyspace is stored with, the in memory one when not set
+    pub storage_engine: Option<String>,
+    /// Allows the DEBUG subcommands that crash the server, for testing recovery
+    pub enable_fault_injection: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReplicaOf {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Met once at least `changes` writes have happened and `seconds` have passed since the last save
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl Config {
+    const fn default() -> Self {
+        Self {
+            dir: None,
+            db_filename: None,
+            port: 6379,
+            replica_of: None,
+            replica_announce_ip: None,
+            replica_announce_port: None,
+            replica_serve_stale_data: true,
+            list_max_listpack_size: quicklist::DEFAULT_FILL,
+            hz: cron::DEFAULT_HZ,
+            io_threads: io_threads::DEFAULT_IO_THREADS,
+            keyspace_shards: 1,
+            maxmemory_clients: 0,
+            cluster_enabled: false,
+            supervised: systemd::Supervised::No,
+            loglevel: logging::LogLevel::Notice,
+            logfile: None,
+            syslog_enabled: false,
+            syslog_ident: None,
+            syslog_facility: logging::DEFAULT_SYSLOG_FACILITY,
+            save_rules: Vec::new(),
+            stop_writes_on_bgsave_error: true,
+            record_file: None,
+            audit_log_categories: command::CommandFlags::WRITE.union(command::CommandFlags::ADMIN),
+            audit_log_file: None,
+            storage_engine: None,
+            enable_fault_injection: false,
+        }
+    }
+}
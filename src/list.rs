@@ -0,0 +1,41 @@
+use bytes::Bytes;
+use crate::quicklist::QuickList;
+use crate::util::normalize_range;
+
+/// Which side of the pivot LINSERT puts the new element on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+impl InsertPosition {
+    pub fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("before") {
+            Some(InsertPosition::Before)
+        } else if name.eq_ignore_ascii_case("after") {
+            Some(InsertPosition::After)
+        } else {
+            None
+        }
+    }
+}
+
+/// Inserts `value` next to the first element equal to `pivot`, counting from the head. Returns
+/// the new length of the list, or None if the pivot isn't in it.
+pub fn insert_at_pivot(list: &mut QuickList, position: InsertPosition, pivot: &[u8], value: Bytes) -> Option<usize> {
+    let index = list.position(pivot)?;
+    let index = match position {
+        InsertPosition::Before => index,
+        InsertPosition::After => index + 1,
+    };
+    list.insert(index, value);
+    Some(list.len())
+}
+
+/// Keeps only the elements between the inclusive `start` and `end` of LTRIM, which resolve like
+/// LRANGE's. A range that misses the list empties it.
+pub fn trim(list: &mut QuickList, start: i64, end: i64) {
+    let range = normalize_range(start, end, list.len());
+    list.retain_range(range.start, range.end);
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use once_cell::sync::Lazy;
+use time::macros::format_description;
+use crate::Config;
+
+static LOGGER: Lazy<Mutex<Logger>> = Lazy::new(|| Mutex::new(Logger {
+    level: LogLevel::Notice,
+    file: None,
+    syslog: None,
+}));
+
+/// Kept apart from the server log so it can be retained and shipped on its own terms
+static AUDIT_LOG: Lazy<Mutex<Option<(PathBuf, File)>>> = Lazy::new(|| Mutex::new(None));
+static AUDIT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Logs a message at one of the `LogLevel`s, e.g. `server_log!(Notice, "Listening on {}", addr)`
+#[macro_export]
+macro_rules! server_log {
+    ($level:ident, $($arg:tt)+) => {
+        $crate::logging::log($crate::logging::LogLevel::$level, format_args!($($arg)+))
+    };
+}
+
+/// Writes an entry to the audit log, e.g. `audit!("cmd={} result={}", name, result)`
+#[macro_export]
+macro_rules! audit {
+    ($($arg:tt)+) => {
+        $crate::logging::audit(format_args!($($arg)+))
+    };
+}
+
+/// Log verbosity, matching the loglevel config parameter
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(format!("expected debug, verbose, notice or warning, got '{}'", value)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        }
+    }
+
+    /// The character Redis marks each level with in its log lines
+    fn marker(self) -> char {
+        match self {
+            LogLevel::Debug => '.',
+            LogLevel::Verbose => '-',
+            LogLevel::Notice => '*',
+            LogLevel::Warning => '#',
+        }
+    }
+
+    fn syslog_severity(self) -> u8 {
+        match self {
+            LogLevel::Debug => 7,
+            LogLevel::Verbose => 6,
+            LogLevel::Notice => 5,
+            LogLevel::Warning => 4,
+        }
+    }
+}
+
+const SYSLOG_FACILITIES: [(&str, u8); 9] = [
+    ("user", 1),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+pub const DEFAULT_SYSLOG_FACILITY: u8 = 16;
+pub const DEFAULT_SYSLOG_IDENT: &str = "redis";
+
+pub fn parse_syslog_facility(value: &str) -> Result<u8, String> {
+    SYSLOG_FACILITIES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, code)| *code)
+        .ok_or_else(|| format!("expected user or local0 to local7, got '{}'", value))
+}
+
+pub fn syslog_facility_name(code: u8) -> &'static str {
+    SYSLOG_FACILITIES
+        .iter()
+        .find(|(_, facility)| *facility == code)
+        .map(|(name, _)| *name)
+        .unwrap_or("local0")
+}
+
+struct Logger {
+    level: LogLevel,
+    /// Where log lines go instead of stdout, kept open until it is reopened for rotation
+    file: Option<(PathBuf, File)>,
+    syslog: Option<Syslog>,
+}
+
+#[cfg(unix)]
+struct Syslog {
+    socket: std::os::unix::net::UnixDatagram,
+    ident: String,
+    facility: u8,
+}
+
+#[cfg(not(unix))]
+struct Syslog;
+
+pub fn log(level: LogLevel, message: fmt::Arguments) {
+    let mut logger = LOGGER.lock().unwrap();
+    if level < logger.level {
+        return;
+    }
+
+    let line = format!("{} {} {} {}\n", std::process::id(), timestamp(), level.marker(), message);
+    match logger.file.as_mut() {
+        Some((_, file)) => {
+            let _ = file.write_all(line.as_bytes());
+        }
+        None => print!("{}", line),
+    }
+
+    #[cfg(unix)]
+    if let Some(syslog) = logger.syslog.as_ref() {
+        let priority = syslog.facility * 8 + level.syslog_severity();
+        let entry = format!("<{}>{}[{}]: {}", priority, syslog.ident, std::process::id(), message);
+        let _ = syslog.socket.send(entry.as_bytes());
+    }
+}
+
+fn timestamp() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(format_description!("[day] [month repr:short] [year] [hour]:[minute]:[second].[subsecond digits:3]"))
+        .unwrap_or_default()
+}
+
+pub fn audit_enabled() -> bool {
+    AUDIT_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes an entry to the audit log, if there is one
+pub fn audit(message: fmt::Arguments) {
+    let mut audit_log = AUDIT_LOG.lock().unwrap();
+    if let Some((_, file)) = audit_log.as_mut() {
+        let line = format!("{} {} {}\n", std::process::id(), timestamp(), message);
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+/// Points logging at the destinations in `config`
+pub fn configure_logging(config: &Config) -> io::Result<()> {
+    let file = match config.logfile.as_ref() {
+        Some(path) => Some((PathBuf::from(path), open_log_file(path)?)),
+        None => None,
+    };
+    let syslog = if config.syslog_enabled {
+        connect_syslog(config)
+    } else {
+        None
+    };
+
+    let audit_log = match config.audit_log_file.as_ref() {
+        Some(path) => Some((PathBuf::from(path), open_log_file(path)?)),
+        None => None,
+    };
+    AUDIT_ENABLED.store(audit_log.is_some(), Ordering::Relaxed);
+    *AUDIT_LOG.lock().unwrap() = audit_log;
+
+    let syslog_missing = config.syslog_enabled && syslog.is_none();
+    {
+        let mut logger = LOGGER.lock().unwrap();
+        logger.level = config.loglevel;
+        logger.file = file;
+        logger.syslog = syslog;
+    }
+
+    if syslog_missing {
+        crate::server_log!(Warning, "syslog is enabled but no syslog socket could be found");
+    }
+
+    Ok(())
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOGGER.lock().unwrap().level = level;
+}
+
+fn open_log_file(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Reopens the log files by name, so lines go to fresh files once logrotate has moved the old
+/// ones aside
+pub fn reopen_log_file() -> io::Result<()> {
+    if let Some((path, file)) = LOGGER.lock().unwrap().file.as_mut() {
+        *file = open_log_file(&path.to_string_lossy())?;
+    }
+    if let Some((path, file)) = AUDIT_LOG.lock().unwrap().as_mut() {
+        *file = open_log_file(&path.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect_syslog(config: &Config) -> Option<Syslog> {
+    let socket = std::os::unix::net::UnixDatagram::unbound().ok()?;
+    ["/dev/log", "/var/run/syslog"]
+        .iter()
+        .find(|path| socket.connect(path).is_ok())?;
+    Some(Syslog {
+        socket,
+        ident: config.syslog_ident.clone().unwrap_or_else(|| DEFAULT_SYSLOG_IDENT.to_string()),
+        facility: config.syslog_facility,
+    })
+}
+
+#[cfg(not(unix))]
+fn connect_syslog(_config: &Config) -> Option<Syslog> {
+    None
+}
+
+/// Reopens the log file whenever the process receives SIGUSR1, for logrotate's postrotate step
+#[cfg(unix)]
+pub async fn reopen_on_sigusr1() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Ok(mut signals) = signal(SignalKind::user_defined1()) else {
+        crate::server_log!(Warning, "Failed to install the SIGUSR1 handler, the log file can't be reopened");
+        return;
+    };
+
+    while signals.recv().await.is_some() {
+        match reopen_log_file() {
+            Ok(_) => crate::server_log!(Notice, "Reopened the log file"),
+            Err(e) => crate::server_log!(Warning, "Failed to reopen the log file. {}", e),
+        }
+    }
+}
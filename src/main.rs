@@ -1,47 +1,21 @@
-mod client;
-mod database;
-mod persistence;
-mod util;
+use std::future::Future;
+use clap::Parser;
 
-use std::path::Path;
-use std::str::FromStr;
-use tokio::net::TcpListener;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use once_cell::sync::Lazy;
-use clap::{arg, Parser};
-
-use crate::client::*;
-use crate::database::db_load;
-
-static CONFIG: Lazy<Arc<RwLock<Config>>> = Lazy::new(|| { Arc::new(RwLock::new(Config::default())) });
-
-struct Config {
-    dir: Option<String>,
-    db_filename: Option<String>,
-    port: u16,
-    replica_of: Option<ReplicaOf>
-}
-
-#[allow(unused)]
-struct ReplicaOf {
-    host: String,
-    port: u16,
-}
-
-impl Config {
-    const fn default() -> Self {
-        Self {
-            dir: None,
-            db_filename: None,
-            port: 6379,
-            replica_of: None,
-        }
-    }
-}
+use redis_starter_rust::aof::{check_aof, truncate_aof};
+use redis_starter_rust::command::CommandFlags;
+use redis_starter_rust::config::{parse_memory, parse_replica_of, parse_save_rules, parse_yes_no};
+use redis_starter_rust::logging::{parse_syslog_facility, LogLevel};
+use redis_starter_rust::ReplicaOf;
+use redis_starter_rust::server::{Server, ServerBuilder};
+use redis_starter_rust::server_log;
+use redis_starter_rust::systemd::{listen_fds, Supervised};
+use redis_starter_rust::telemetry::{init_otlp, shutdown_telemetry};
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// A redis.conf style file, command line options take precedence over it
+    config_file: Option<String>,
+
     #[arg(long)]
     dir: Option<String>,
 
@@ -51,79 +25,291 @@ struct Args {
     #[arg(long)]
     port: Option<u16>,
 
-    #[clap(number_of_values = 2, name = "replicaof")]
-    #[arg(long)]
+    /// Master to replicate, as "<host> <port>" or as two values
+    #[arg(long = "replicaof", num_args = 1..=2, value_names = ["HOST", "PORT"])]
     replica_of: Option<Vec<String>>,
+
+    #[arg(long)]
+    replica_announce_ip: Option<String>,
+
+    #[arg(long)]
+    replica_announce_port: Option<u16>,
+
+    #[arg(long, value_parser = parse_yes_no)]
+    replica_serve_stale_data: Option<bool>,
+
+    #[arg(long, allow_hyphen_values = true)]
+    list_max_listpack_size: Option<i64>,
+
+    #[arg(long)]
+    hz: Option<u32>,
+
+    #[arg(long)]
+    io_threads: Option<usize>,
+
+    #[arg(long)]
+    keyspace_shards: Option<usize>,
+
+    /// Engine to store the keyspace with
+    #[arg(long)]
+    storage_engine: Option<String>,
+
+    /// Allows DEBUG PANIC, SEGFAULT and OOM to crash the server
+    #[arg(long, value_parser = parse_yes_no)]
+    enable_fault_injection: Option<bool>,
+
+    #[arg(long, value_parser = parse_memory)]
+    maxmemory_clients: Option<usize>,
+
+    #[arg(long, value_parser = parse_yes_no)]
+    cluster_enabled: Option<bool>,
+
+    /// Snapshotting rules as "<seconds> <changes>" pairs, "" disables snapshotting
+    #[arg(long)]
+    save: Option<String>,
+
+    #[arg(long, value_parser = parse_yes_no)]
+    stop_writes_on_bgsave_error: Option<bool>,
+
+    /// Collector to export tracing spans to over OTLP, such as http://localhost:4317
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    #[arg(long, value_parser = Supervised::parse)]
+    supervised: Option<Supervised>,
+
+    #[arg(long, value_parser = LogLevel::parse)]
+    loglevel: Option<LogLevel>,
+
+    #[arg(long)]
+    logfile: Option<String>,
+
+    #[arg(long)]
+    audit_log_file: Option<String>,
+
+    /// Flags of the commands to audit, such as "write admin"
+    #[arg(long, value_parser = CommandFlags::parse)]
+    audit_log_categories: Option<CommandFlags>,
+
+    /// Appends every received command to this file, for redis-replay to play back
+    #[arg(long)]
+    record_file: Option<String>,
+
+    #[arg(long, value_parser = parse_yes_no)]
+    syslog_enabled: Option<bool>,
+
+    #[arg(long)]
+    syslog_ident: Option<String>,
+
+    #[arg(long, value_parser = parse_syslog_facility)]
+    syslog_facility: Option<u8>,
+
+    /// Checks an append only file instead of starting the server, like redis-check-aof
+    #[arg(long, value_name = "FILE")]
+    check_aof: Option<String>,
+
+    /// With --check-aof, truncates the file to its last valid command
+    #[arg(long, requires = "check_aof")]
+    fix: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    handle_arguments().await?;
-    load_database().await?;
-    let port = CONFIG.read().await.port;
-    run_server(port).await?;
+    let args = Args::parse();
+    if let Some(path) = args.check_aof.as_ref() {
+        let valid = run_check_aof(path, args.fix)?;
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
+    #[cfg(feature = "console")]
+    redis_starter_rust::telemetry::init_console()?;
+
+    if let Some(endpoint) = args.otlp_endpoint.as_ref() {
+        init_otlp(endpoint)?;
+    }
 
-    Ok(())
+    let shutdown = shutdown_requested()?;
+    let mut server = server_from_arguments(args)?.spawn().await?;
+    let result = tokio::select! {
+        result = server.wait() => result,
+        _ = shutdown => {
+            server_log!(Notice, "Received shutdown signal, stopping");
+            server.shutdown().await
+        }
+    };
+    shutdown_telemetry();
+
+    result
 }
 
-async fn handle_arguments() -> Result<(), anyhow::Error> {
+/// Reports on the append only file at `path`, returning whether it is valid once done
+fn run_check_aof(path: &str, fix: bool) -> Result<bool, anyhow::Error> {
+    let check = check_aof(path)?;
+    if let Some(error) = check.error.as_ref() {
+        println!("{}", error);
+    }
+    println!(
+        "AOF analyzed: filename={}, size={}, ok_up_to={}, ok_up_to_line={}, diff={}",
+        path, check.size, check.valid_up_to, check.valid_up_to_line, check.size - check.valid_up_to
+    );
 
-    let args = Args::parse();
+    if check.is_valid() {
+        println!("AOF {} is valid", path);
+        return Ok(true);
+    }
+    if !fix {
+        println!("AOF {} is not valid. Use the --fix option to try fixing it.", path);
+        return Ok(false);
+    }
+
+    println!(
+        "This will shrink the AOF {} from {} bytes, with {} bytes, to {} bytes",
+        path, check.size, check.size - check.valid_up_to, check.valid_up_to
+    );
+    truncate_aof(path, &check)?;
+    println!("Successfully truncated AOF {}", path);
+    Ok(true)
+}
+
+/// Resolves on SIGINT, or SIGTERM which is how supervisors such as systemd stop the server. The
+/// handlers are installed straight away so a signal arriving during startup isn't missed.
+fn shutdown_requested() -> Result<impl Future<Output = ()>, anyhow::Error> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        Ok(async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        })
+    }
+
+    #[cfg(not(unix))]
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+fn server_from_arguments(args: Args) -> Result<ServerBuilder, anyhow::Error> {
+    let mut server = Server::builder();
+    if let Some(path) = args.config_file {
+        server = server.config_file(path)?;
+    }
 
-    let mut config = CONFIG.write().await;
     if let Some(dir) = args.dir {
-        config.dir = Some(dir);
+        server = server.dir(dir);
     }
 
     if let Some(db_filename) = args.dbfilename {
-        config.db_filename = Some(db_filename);
+        server = server.db_filename(db_filename);
     }
 
     if let Some(port) = args.port {
-        config.port = port;
+        server = server.port(port);
     }
 
-    if let Some(replica) = args.replica_of {
-        config.replica_of = Some(ReplicaOf {
-            host: replica[0].clone(),
-            port: u16::from_str(replica[1].as_str())?,
-        });
+    if let Some(ip) = args.replica_announce_ip {
+        server = server.replica_announce_ip(ip);
     }
 
-    Ok(())
-}
+    if let Some(port) = args.replica_announce_port {
+        server = server.replica_announce_port(port);
+    }
 
-async fn load_database() -> Result<(), anyhow::Error> {
-    let config = CONFIG.read().await;
-    if config.dir.is_none() || config.db_filename.is_none() {
-        return Ok(());
+    if let Some(serve_stale_data) = args.replica_serve_stale_data {
+        server = server.replica_serve_stale_data(serve_stale_data);
     }
 
-    let path = Path::new(config.dir.as_ref().unwrap());
-    let path = path.join(config.db_filename.as_ref().unwrap());
-    db_load(path).await?;
+    if let Some(fill) = args.list_max_listpack_size {
+        server = server.list_max_listpack_size(fill);
+    }
 
-    Ok(())
-}
+    if let Some(hz) = args.hz {
+        server = server.hz(hz);
+    }
 
-async fn run_server(port: u16) -> tokio::io::Result<()> {
-    let bind_addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(bind_addr.clone()).await.unwrap();
-    println!("Listening on {}", bind_addr);
-    loop {
-        let (stream, addr) = listener.accept().await?;
-        println!("Accepted connection from {}", addr);
-
-        tokio::spawn(async move {
-            let mut client = RedisClientConnection::new(stream);
-            match client.process().await {
-                Ok(_) => {
-                    println!("Client disconnected without error");
-                }
-                Err(e) => {
-                    println!("Encountered error while processing client. {:?}", e);
-                }
-            }
-        });
+    if let Some(io_threads) = args.io_threads {
+        server = server.io_threads(io_threads);
+    }
+
+    if let Some(keyspace_shards) = args.keyspace_shards {
+        server = server.keyspace_shards(keyspace_shards);
+    }
+
+    if let Some(storage_engine) = args.storage_engine {
+        server = server.storage_engine(storage_engine);
     }
-}
\ No newline at end of file
+
+    if let Some(enabled) = args.enable_fault_injection {
+        server = server.enable_fault_injection(enabled);
+    }
+
+    if let Some(maxmemory_clients) = args.maxmemory_clients {
+        server = server.maxmemory_clients(maxmemory_clients);
+    }
+
+    if let Some(cluster_enabled) = args.cluster_enabled {
+        server = server.cluster_enabled(cluster_enabled);
+    }
+
+    if let Some(save) = args.save {
+        server = server.save_rules(parse_save_rules(&save).map_err(anyhow::Error::msg)?);
+    }
+
+    if let Some(stop_writes) = args.stop_writes_on_bgsave_error {
+        server = server.stop_writes_on_bgsave_error(stop_writes);
+    }
+
+    if let Some(supervised) = args.supervised {
+        server = server.supervised(supervised);
+    }
+
+    if let Some(loglevel) = args.loglevel {
+        server = server.loglevel(loglevel);
+    }
+
+    if let Some(logfile) = args.logfile {
+        server = server.logfile(logfile);
+    }
+
+    if let Some(path) = args.audit_log_file {
+        server = server.audit_log_file(path);
+    }
+
+    if let Some(categories) = args.audit_log_categories {
+        server = server.audit_log_categories(categories);
+    }
+
+    if let Some(path) = args.record_file {
+        server = server.record_file(path);
+    }
+
+    if let Some(syslog_enabled) = args.syslog_enabled {
+        server = server.syslog_enabled(syslog_enabled);
+    }
+
+    if let Some(ident) = args.syslog_ident {
+        server = server.syslog_ident(ident);
+    }
+
+    if let Some(facility) = args.syslog_facility {
+        server = server.syslog_facility(facility);
+    }
+
+    // Under socket activation systemd has already bound the port
+    if let Some(listener) = listen_fds()? {
+        server = server.listener(listener);
+    }
+
+    if let Some(replica_of) = args.replica_of {
+        let replica_of = parse_replica_of(&replica_of.join(" ")).map_err(|e| anyhow::anyhow!("--replicaof: {}", e))?;
+        if let Some(ReplicaOf { host, port }) = replica_of {
+            server = server.replica_of(host, port);
+        }
+    }
+
+    Ok(server)
+}
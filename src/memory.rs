@@ -0,0 +1,123 @@
+use std::mem::size_of;
+use std::time::SystemTime;
+use bytes::Bytes;
+use crate::hash::Hash;
+use crate::persistence::{DataType, HashFields};
+use crate::set::Set;
+use crate::quicklist::QuickList;
+use crate::zset::SortedSet;
+use crate::storage::CacheEntry;
+
+/// Approximate bytes a keyspace entry takes beyond its key and value, i.e. its slot in the table
+pub const ENTRY_OVERHEAD: usize = size_of::<(Bytes, CacheEntry)>();
+
+/// Estimates how much memory something holds on the heap, for MEMORY USAGE and the dataset
+/// accounting behind MEMORY STATS. The numbers are approximations, allocator rounding and
+/// fragmentation aren't accounted for.
+pub trait MemoryUsage {
+    /// Bytes held, looking at no more than `samples` elements of a collection and assuming the
+    /// rest are alike. 0 counts every element, from the sizes collections keep as they change
+    /// rather than by walking them, since the keyspace asks for it on every write.
+    fn memory_usage(&self, samples: usize) -> usize;
+}
+
+impl MemoryUsage for DataType {
+    fn memory_usage(&self, samples: usize) -> usize {
+        match self {
+            DataType::String(value) => value.len(),
+            DataType::List(list) => list.memory_usage(samples),
+            DataType::Hash(hash) => hash.memory_usage(samples),
+            DataType::Set(set) => set.memory_usage(samples),
+            DataType::SortedSet(zset) => zset.memory_usage(samples),
+            // These are placeholders that don't carry their elements yet
+            DataType::ZipMap
+            | DataType::ZipList
+            | DataType::IntSet
+            | DataType::SortedSetZipList
+            | DataType::HashMapZipList
+            | DataType::ListQuickList => 0,
+        }
+    }
+}
+
+impl MemoryUsage for QuickList {
+    fn memory_usage(&self, samples: usize) -> usize {
+        if samples == 0 {
+            return self.len() * size_of::<Bytes>() + self.size();
+        }
+        let sampled = samples.min(self.len());
+        if sampled == 0 {
+            return 0;
+        }
+        let bytes: usize = self.iter().take(sampled).map(|element| size_of::<Bytes>() + element.len()).sum();
+        bytes * self.len() / sampled
+    }
+}
+
+impl MemoryUsage for HashFields {
+    fn memory_usage(&self, samples: usize) -> usize {
+        let sampled = if samples == 0 { self.len() } else { samples.min(self.len()) };
+        if sampled == 0 {
+            return 0;
+        }
+        let bytes: usize = self.iter()
+            .take(sampled)
+            .map(|(field, value)| 2 * size_of::<Bytes>() + field.len() + value.len())
+            .sum();
+        bytes * self.len() / sampled
+    }
+}
+
+impl MemoryUsage for Set {
+    fn memory_usage(&self, samples: usize) -> usize {
+        if samples == 0 {
+            return self.len() * size_of::<Bytes>() + self.size();
+        }
+        let sampled = samples.min(self.len());
+        if sampled == 0 {
+            return 0;
+        }
+        let bytes: usize = self.iter().take(sampled).map(|member| size_of::<Bytes>() + member.len()).sum();
+        bytes * self.len() / sampled
+    }
+}
+
+/// Each member is held once, by both the score table and the ordered index
+impl MemoryUsage for SortedSet {
+    fn memory_usage(&self, samples: usize) -> usize {
+        if samples == 0 {
+            return self.len() * 2 * (size_of::<Bytes>() + size_of::<f64>()) + self.size();
+        }
+        let sampled = samples.min(self.len());
+        if sampled == 0 {
+            return 0;
+        }
+        let bytes: usize = self.iter()
+            .take(sampled)
+            .map(|(member, _)| 2 * (size_of::<Bytes>() + size_of::<f64>()) + member.len())
+            .sum();
+        bytes * self.len() / sampled
+    }
+}
+
+/// Fields that expire cost an entry in a second table on top
+impl MemoryUsage for Hash {
+    fn memory_usage(&self, samples: usize) -> usize {
+        let expirations = self.expirations().len() * (size_of::<Bytes>() + size_of::<SystemTime>());
+        if samples == 0 {
+            return self.len() * 2 * size_of::<Bytes>() + self.size() + expirations;
+        }
+        self.fields().memory_usage(samples) + expirations
+    }
+}
+
+impl MemoryUsage for CacheEntry {
+    fn memory_usage(&self, samples: usize) -> usize {
+        self.value.memory_usage(samples)
+    }
+}
+
+/// What a whole entry costs: its slot, its key and its value
+pub fn entry_usage(key: &[u8], entry: &CacheEntry, samples: usize) -> usize {
+    ENTRY_OVERHEAD + key.len() + entry.memory_usage(samples)
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use async_trait::async_trait;
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+use crate::client::ResponseType;
+use crate::clock;
+use crate::command::{Command, CommandFlags, CommandSpec, COMMAND_TABLE};
+use crate::database::{db_delete, db_expiration, db_get_with_expiration, db_insert, db_set};
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::persistence::{DataType, HashFields};
+use crate::quicklist::QuickList;
+use crate::zset::SortedSet;
+
+/// Modules loaded into this process and the commands they added
+static MODULES: Lazy<RwLock<Modules>> = Lazy::new(|| RwLock::new(Modules::default()));
+
+#[derive(Default)]
+struct Modules {
+    loaded: Vec<(&'static str, i64)>,
+    /// Keyed by lowercase command name
+    commands: HashMap<String, RegisteredCommand>,
+}
+
+struct RegisteredCommand {
+    spec: &'static CommandSpec,
+    handler: Arc<dyn CommandHandler>,
+}
+
+#[derive(Error, Debug)]
+pub enum ModuleError {
+    #[error("Module {0} is already loaded")]
+    AlreadyLoaded(String),
+    #[error("Command '{0}' already exists")]
+    CommandExists(String),
+    #[error("Command '{0}' has an invalid arity")]
+    InvalidArity(String),
+}
+
+/// A set of commands added to the server from outside the crate, in the spirit of Redis modules.
+/// Modules are compiled in and loaded with [`load_module`] before the server accepts connections.
+pub trait Module: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn version(&self) -> i64 {
+        1
+    }
+
+    fn commands(&self) -> Vec<ModuleCommand>;
+}
+
+/// Runs a module command. `arguments` excludes the command name and the returned value is sent
+/// to the client as the reply. An error is sent as `-ERR <message>`, unless its message already
+/// starts with an error code such as `WRONGTYPE`.
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    async fn call(&self, ctx: &mut ModuleContext, arguments: &[Bytes]) -> Result<ResponseType, anyhow::Error>;
+}
+
+/// A command a module adds to the dispatch table. Arity, flags and key positions mean the same
+/// as for built in commands, so a command flagged write is replicated and refused on replicas.
+pub struct ModuleCommand {
+    name: &'static str,
+    arity: i32,
+    flags: CommandFlags,
+    first_key: i32,
+    last_key: i32,
+    key_step: i32,
+    handler: Arc<dyn CommandHandler>,
+}
+
+impl ModuleCommand {
+    pub fn new(name: &'static str, arity: i32, flags: CommandFlags, handler: impl CommandHandler + 'static) -> Self {
+        Self {
+            name,
+            arity,
+            flags,
+            first_key: 0,
+            last_key: 0,
+            key_step: 0,
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn keys(mut self, first_key: i32, last_key: i32, key_step: i32) -> Self {
+        self.first_key = first_key;
+        self.last_key = last_key;
+        self.key_step = key_step;
+        self
+    }
+}
+
+/// A typed value as modules see it, copied out of or into the keyspace whole
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    String(Bytes),
+    /// Elements from head to tail
+    List(Vec<Bytes>),
+    /// Field value pairs in no particular order, without fields that have expired
+    Hash(Vec<(Bytes, Bytes)>),
+    /// Members in no particular order
+    Set(Vec<Bytes>),
+    /// Members with their scores, lowest score first
+    SortedSet(Vec<(Bytes, f64)>),
+}
+
+impl Value {
+    fn from_data_type(value: DataType) -> Result<Self, anyhow::Error> {
+        Ok(match value {
+            DataType::String(value) => Value::String(value),
+            DataType::List(list) => Value::List(list.iter().cloned().collect()),
+            DataType::Hash(mut hash) => {
+                hash.remove_expired(clock::now());
+                Value::Hash(hash.into_fields().into_iter().collect())
+            }
+            DataType::Set(set) => Value::Set(set.into_members().into_iter().collect()),
+            DataType::SortedSet(zset) => Value::SortedSet(zset.iter().map(|(member, score)| (member.clone(), score)).collect()),
+            other => anyhow::bail!("Unsupported {} encoding", other.type_name()),
+        })
+    }
+
+    /// None for an empty aggregate, which like in Redis can't be stored
+    fn into_data_type(self) -> Option<DataType> {
+        Some(match self {
+            Value::String(value) => DataType::String(value),
+            Value::List(elements) if !elements.is_empty() => DataType::List(elements.into_iter().collect::<QuickList>()),
+            Value::Hash(pairs) if !pairs.is_empty() => DataType::Hash(Hash::from(pairs.into_iter().collect::<HashFields>())),
+            Value::Set(members) if !members.is_empty() => DataType::Set(members.into_iter().collect::<Set>()),
+            Value::SortedSet(members) if !members.is_empty() => {
+                let mut zset = SortedSet::default();
+                for (member, score) in members {
+                    zset.insert(member, score);
+                }
+                DataType::SortedSet(zset)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// The handle a module command works through, scoped to the calling client's database
+pub struct ModuleContext {
+    db_id: usize,
+}
+
+impl ModuleContext {
+    pub(crate) fn new(db_id: usize) -> Self {
+        Self { db_id }
+    }
+
+    pub fn selected_db(&self) -> usize {
+        self.db_id
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Value>, anyhow::Error> {
+        match db_get_with_expiration(self.db_id, key.as_bytes()).await {
+            Some((value, _)) => Ok(Some(Value::from_data_type(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `value` under `key` whatever the key held before. An empty list, hash, set or
+    /// sorted set removes the key instead.
+    pub async fn set(&self, key: &str, value: Value, ttl: Option<Duration>) -> Result<(), anyhow::Error> {
+        if let Value::SortedSet(members) = &value {
+            if members.iter().any(|(_, score)| score.is_nan()) {
+                anyhow::bail!("resulting score is not a number (NaN)");
+            }
+        }
+        if let Value::String(value) = value {
+            return db_set(self.db_id, Bytes::copy_from_slice(key.as_bytes()), value, ttl).await;
+        }
+
+        match value.into_data_type() {
+            Some(value) => {
+                let expiration = ttl.map(|ttl| clock::now() + ttl);
+                db_insert(self.db_id, Bytes::copy_from_slice(key.as_bytes()), value, expiration, true).await;
+            }
+            None => {
+                db_delete(self.db_id, key.as_bytes()).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Time left before `key` expires, `None` if it doesn't exist or never expires
+    pub async fn ttl(&self, key: &str) -> Option<Duration> {
+        db_expiration(self.db_id, key.as_bytes()).await??.duration_since(clock::now()).ok()
+    }
+
+    /// Removes `key`, returning whether it existed
+    pub async fn delete(&self, key: &str) -> bool {
+        db_delete(self.db_id, key.as_bytes()).await
+    }
+}
+
+/// Adds the commands of `module` to the dispatch table. Nothing is registered if any of them
+/// clashes with an existing command.
+pub fn load_module(module: &dyn Module) -> Result<(), ModuleError> {
+    let mut modules = MODULES.write().unwrap();
+    if modules.loaded.iter().any(|(name, _)| name.eq_ignore_ascii_case(module.name())) {
+        return Err(ModuleError::AlreadyLoaded(module.name().to_string()));
+    }
+
+    let commands = module.commands();
+    for (i, command) in commands.iter().enumerate() {
+        let name = command.name.to_lowercase();
+        let duplicate = commands[..i].iter().any(|other| other.name.eq_ignore_ascii_case(&name));
+        let built_in = COMMAND_TABLE.iter().any(|spec| spec.name == name);
+        if duplicate || built_in || modules.commands.contains_key(&name) {
+            return Err(ModuleError::CommandExists(name));
+        }
+        if command.arity == 0 {
+            return Err(ModuleError::InvalidArity(name));
+        }
+    }
+
+    for command in commands {
+        // Specs live as long as the process, like the built in table
+        let spec: &'static CommandSpec = Box::leak(Box::new(CommandSpec {
+            name: command.name,
+            command: Command::ModuleDefined,
+            arity: command.arity,
+            flags: command.flags,
+            first_key: command.first_key,
+            last_key: command.last_key,
+            key_step: command.key_step,
+            numkeys: 0,
+        }));
+        modules.commands.insert(command.name.to_lowercase(), RegisteredCommand {
+            spec,
+            handler: command.handler,
+        });
+    }
+    modules.loaded.push((module.name(), module.version()));
+
+    Ok(())
+}
+
+/// The name and version of every loaded module
+pub fn loaded_modules() -> Vec<(&'static str, i64)> {
+    MODULES.read().unwrap().loaded.clone()
+}
+
+pub(crate) fn lookup_command(name: &str) -> Option<&'static CommandSpec> {
+    let modules = MODULES.read().unwrap();
+    if modules.commands.is_empty() {
+        return None;
+    }
+    modules.commands.get(&name.to_lowercase()).map(|command| command.spec)
+}
+
+pub(crate) fn command_specs() -> Vec<&'static CommandSpec> {
+    MODULES.read().unwrap().commands.values().map(|command| command.spec).collect()
+}
+
+pub(crate) async fn call_command(name: &str, ctx: &mut ModuleContext, arguments: &[Bytes]) -> Result<ResponseType, anyhow::Error> {
+    let handler = MODULES
+        .read()
+        .unwrap()
+        .commands
+        .get(&name.to_lowercase())
+        .map(|command| command.handler.clone());
+    match handler {
+        Some(handler) => handler.call(ctx, arguments).await,
+        None => anyhow::bail!("unknown command '{}'", name),
+    }
+}
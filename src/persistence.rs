@@ -1,22 +1,39 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{self, AtomicU64};
+use std::task::{Context, Poll};
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
+use bytes::{BufMut, Bytes};
 use thiserror::Error;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
 use async_trait::async_trait;
+use crate::dict::{KeyMap, KeySet};
+use crate::hash::Hash;
+use crate::set::Set;
+use crate::quicklist::QuickList;
+use crate::util::parse_integer;
+use crate::zset::{parse_score, SortedSet};
+
+pub const RDB_VERSION: u16 = 11;
+
+/// The fields of a hash and their values
+pub type HashFields = KeyMap<Bytes, Bytes>;
+
+/// The members of a set
+pub type SetMembers = KeySet<Bytes>;
 
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub enum DataType {
-    String(String),
-    List,
-    Set,
-    SortedSet,
-    Hash,
+    /// Reference counted so handing a value out of the cache doesn't copy it
+    String(Bytes),
+    List(QuickList),
+    Set(Set),
+    SortedSet(SortedSet),
+    Hash(Hash),
     ZipMap,
     ZipList,
     IntSet,
@@ -25,11 +42,102 @@ pub enum DataType {
     ListQuickList,
 }
 
+impl DataType {
+    /// The name TYPE reports, the same for every encoding of a type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DataType::String(_) => "string",
+            DataType::List(_) | DataType::ZipList | DataType::ListQuickList => "list",
+            DataType::Set(_) | DataType::IntSet => "set",
+            DataType::SortedSet(_) | DataType::SortedSetZipList => "zset",
+            DataType::Hash(_) | DataType::ZipMap | DataType::HashMapZipList => "hash",
+        }
+    }
+
+    /// The name OBJECT ENCODING reports. Compact encodings loaded from older files are named
+    /// after the listpack Redis converts them to on load.
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            // Redis stores strings that read as an integer as the integer itself
+            DataType::String(value) if parse_integer(value).is_some() => "int",
+            DataType::String(value) if value.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            DataType::String(_) => "raw",
+            // Like Redis, a list only becomes a quicklist once it outgrows a single listpack
+            DataType::List(list) if list.node_count() <= 1 => "listpack",
+            DataType::List(_) | DataType::ListQuickList => "quicklist",
+            DataType::ZipList | DataType::SortedSetZipList | DataType::ZipMap | DataType::HashMapZipList => "listpack",
+            // Small hashes with fields that expire get a listpack of their own in Redis
+            DataType::Hash(hash) if is_small_hash(hash.fields()) && hash.has_expirations() => "listpackex",
+            DataType::Hash(hash) if is_small_hash(hash.fields()) => "listpack",
+            DataType::Set(set) if is_integer_set(set.members()) => "intset",
+            DataType::Set(set) if is_small_set(set.members()) => "listpack",
+            DataType::Set(_) | DataType::Hash(_) => "hashtable",
+            DataType::IntSet => "intset",
+            DataType::SortedSet(zset) if is_small_sorted_set(zset) => "listpack",
+            DataType::SortedSet(_) => "skiplist",
+        }
+    }
+
+    /// The reference count OBJECT REFCOUNT reports. Every key owns its own value here, but Redis
+    /// shares small integers between keys and reports those as never freed.
+    pub fn refcount(&self) -> i64 {
+        match self {
+            DataType::String(value) if parse_integer(value).is_some_and(|n| (0..SHARED_INTEGERS).contains(&n)) => SHARED_REFCOUNT,
+            _ => 1,
+        }
+    }
+}
+
+/// Longest string Redis allocates together with its object header
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// Most fields, and longest field or value, of a hash Redis keeps as a listpack, going by the
+/// default hash-max-listpack-entries and hash-max-listpack-value
+const HASH_MAX_LISTPACK_ENTRIES: usize = 128;
+const HASH_MAX_LISTPACK_VALUE: usize = 64;
+
+pub(crate) fn is_small_hash(fields: &HashFields) -> bool {
+    fields.len() <= HASH_MAX_LISTPACK_ENTRIES
+        && fields.iter().all(|(field, value)| field.len() <= HASH_MAX_LISTPACK_VALUE && value.len() <= HASH_MAX_LISTPACK_VALUE)
+}
+
+/// Most members of a set of integers Redis keeps as an intset, and most members, and longest
+/// member, of one it keeps as a listpack, going by the default set-max-intset-entries,
+/// set-max-listpack-entries and set-max-listpack-value
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+const SET_MAX_LISTPACK_ENTRIES: usize = 128;
+const SET_MAX_LISTPACK_VALUE: usize = 64;
+
+pub(crate) fn is_integer_set(members: &SetMembers) -> bool {
+    members.len() <= SET_MAX_INTSET_ENTRIES && members.iter().all(|member| parse_integer(member).is_some())
+}
+
+pub(crate) fn is_small_set(members: &SetMembers) -> bool {
+    members.len() <= SET_MAX_LISTPACK_ENTRIES && members.iter().all(|member| member.len() <= SET_MAX_LISTPACK_VALUE)
+}
+
+/// Most members, and longest member, of a sorted set Redis keeps as a listpack, going by the
+/// default zset-max-listpack-entries and zset-max-listpack-value
+const ZSET_MAX_LISTPACK_ENTRIES: usize = 128;
+const ZSET_MAX_LISTPACK_VALUE: usize = 64;
+
+fn is_small_sorted_set(zset: &SortedSet) -> bool {
+    zset.len() <= ZSET_MAX_LISTPACK_ENTRIES && zset.iter().all(|(member, _)| member.len() <= ZSET_MAX_LISTPACK_VALUE)
+}
+
+/// Redis keeps one shared object for each integer below this and points every key holding
+/// it there
+const SHARED_INTEGERS: i64 = 10000;
+
+/// The reference count Redis reports for shared objects, which are never freed
+const SHARED_REFCOUNT: i64 = i32::MAX as i64;
+
+
 pub struct RdbData {
     pub rdb_version: u16,
     pub metadata: HashMap<String, String>,
-    pub databases: HashMap<usize, HashMap<String, DataType>>,
-    pub expirations: HashMap<usize, HashMap<String, SystemTime>>,
+    pub databases: HashMap<usize, HashMap<Bytes, DataType>>,
+    pub expirations: HashMap<usize, HashMap<Bytes, SystemTime>>,
 }
 
 #[derive(Error, Debug)]
@@ -57,16 +165,55 @@ pub enum RdbReadError {
 
     #[error("Attempted to read key without a database selected")]
     AttemptReadKeyWithoutDatabaseSelected,
+
+    #[error("Value type {0} can't be read yet")]
+    UnsupportedValueType(u8),
+
+    #[error("String encoding {0} can't be read yet")]
+    UnsupportedStringEncoding(usize),
+
+    #[error("DUMP payload version or checksum are wrong")]
+    InvalidDumpPayload,
+
+    #[error("Sorted set score is not a number")]
+    InvalidScore,
+}
+
+#[derive(Error, Debug)]
+pub enum RdbWriteError {
+    #[error("DataType can't be written yet: {0:?}")]
+    UnsupportedDataType(Box<DataType>),
+}
+
+/// Counts the bytes read through it so loading progress can be reported
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: &'static AtomicU64,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, progress: &'static AtomicU64) -> Self {
+        Self {
+            inner,
+            progress,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.progress.fetch_add((buf.filled().len() - before) as u64, atomic::Ordering::Relaxed);
+        result
+    }
 }
 
 pub struct RdbReader;
 
 impl RdbReader {
-    pub async fn read(path: impl AsRef<Path>) -> Result<RdbData, RdbReadError> {
-        let mut reader = {
-            let file = File::open(path).await?;
-            BufReader::new(file)
-        };
+    pub async fn read_from(source: impl AsyncRead + Unpin + Send) -> Result<RdbData, RdbReadError> {
+        let mut reader = BufReader::new(source);
 
         if !Self::is_rdb_file(&mut reader).await? {
             return Err(RdbReadError::NotRedisDatabase);
@@ -80,11 +227,20 @@ impl RdbReader {
         };
 
         let mut metadata = HashMap::new();
-        let mut databases: HashMap<usize, HashMap<String, DataType>> = HashMap::new();
-        let mut expirations: HashMap<usize, HashMap<String, SystemTime>> = HashMap::new();
+        let mut databases: HashMap<usize, HashMap<Bytes, DataType>> = HashMap::new();
+        let mut expirations: HashMap<usize, HashMap<Bytes, SystemTime>> = HashMap::new();
         let mut current_database: Option<usize> = None;
         let mut next_expiration: Option<SystemTime> = None;
+        let mut entries_until_yield = 1024;
         loop {
+            // Reads are mostly served from the buffer and never suspend, yield now and again so
+            // a large load doesn't starve the connections waiting on it.
+            entries_until_yield -= 1;
+            if entries_until_yield == 0 {
+                entries_until_yield = 1024;
+                tokio::task::yield_now().await;
+            }
+
             let opcode = reader.read_u8().await?;
             match opcode {
                 0xFA => {
@@ -155,9 +311,24 @@ impl RdbReader {
         })
     }
 
-    async fn is_rdb_file(reader: &mut BufReader<File>) -> Result<bool, RdbReadError> {
-        reader.seek(SeekFrom::Start(0)).await?;
+    /// Reads back a value serialized by `RdbWriter::dump`, after checking its footer
+    pub async fn restore(payload: &[u8]) -> Result<DataType, RdbReadError> {
+        let Some(body_length) = payload.len().checked_sub(DUMP_FOOTER_LENGTH) else {
+            return Err(RdbReadError::InvalidDumpPayload);
+        };
+        let (body, footer) = payload.split_at(body_length);
+        let version = u16::from_le_bytes([footer[0], footer[1]]);
+        let checksum = u64::from_le_bytes(footer[2..].try_into().unwrap());
+        if version > RDB_VERSION || crc64(0, &payload[..body_length + 2]) != checksum {
+            return Err(RdbReadError::InvalidDumpPayload);
+        }
 
+        let mut reader = BufReader::new(body);
+        let value_type = reader.read_u8().await?;
+        BufReader::read_value_type(&mut reader, value_type).await
+    }
+
+    async fn is_rdb_file(reader: &mut (impl AsyncRead + Unpin)) -> Result<bool, RdbReadError> {
         let mut buff = [0u8; 5];
         reader.read_exact(&mut buff).await?;
         Ok(buff.cmp(b"REDIS") == Ordering::Equal)
@@ -165,13 +336,14 @@ impl RdbReader {
 }
 
 #[async_trait]
-trait RdbBufReader {
+trait RdbBufReader: AsyncRead + Unpin + Send + Sized {
     async fn read_length_encoded_int(&mut self) -> Result<usize, RdbReadError>;
     async fn read_string_encoded(&mut self) -> Result<String, RdbReadError>;
+    async fn read_bytes_encoded(&mut self) -> Result<Vec<u8>, RdbReadError>;
     async fn read_expiry_timestamp(&mut self, opcode: u8) -> Result<ExpiryTimestamp, RdbReadError>;
-    async fn read_key_value(&mut self, known_type: Option<u8>) -> Result<(String, DataType), RdbReadError>;
+    async fn read_key_value(&mut self, known_type: Option<u8>) -> Result<(Bytes, DataType), RdbReadError>;
 
-    async fn read_length_encoding(reader: &mut BufReader<File>) -> Result<(LengthEncoding, usize), RdbReadError> {
+    async fn read_length_encoding(reader: &mut Self) -> Result<(LengthEncoding, usize), RdbReadError> {
         let length = reader.read_u8().await?;
         let (encoding, length) = {
             let mask = 0b11000000u8;
@@ -187,10 +359,10 @@ trait RdbBufReader {
         Ok((encoding, length as usize))
     }
 
-    async fn interpret_length_encoding(reader: &mut BufReader<File>, length_encoding: LengthEncoding, length: usize) -> Result<usize, RdbReadError> {
+    async fn interpret_length_encoding(reader: &mut Self, length_encoding: LengthEncoding, length: usize) -> Result<usize, RdbReadError> {
         let value = match length_encoding {
             LengthEncoding::Remaining6Bits => length,
-            LengthEncoding::DiscardRemainingGetNext4Bytes => reader.read_u32_le().await? as usize,
+            LengthEncoding::DiscardRemainingGetNext4Bytes => reader.read_u32().await? as usize,
             LengthEncoding::RemainingAndNextByte => (length << 8) | (reader.read_u8().await? as usize),
             LengthEncoding::SpecialFormat => return Err(RdbReadError::SpecialFormatInvalidIntEncoded),
         };
@@ -198,10 +370,95 @@ trait RdbBufReader {
         Ok(value)
     }
 
-    async fn read_value_type(reader: &mut BufReader<File>, value_type: u8) -> Result<DataType, RdbReadError> {
+    async fn read_value_type(reader: &mut Self, value_type: u8) -> Result<DataType, RdbReadError> {
         let value = match value_type {
-            0 => DataType::String(reader.read_string_encoded().await?),
-            _ => todo!("DataType isn't handled yet!")
+            0 => DataType::String(reader.read_bytes_encoded().await?.into()),
+            // A plain list of strings, which every version of Redis still reads
+            1 => {
+                let length = reader.read_length_encoded_int().await?;
+                let mut list = QuickList::default();
+                for _ in 0..length {
+                    list.push_back(reader.read_bytes_encoded().await?.into());
+                }
+                DataType::List(list)
+            }
+            // Member strings, the same as a list
+            2 => {
+                let length = reader.read_length_encoded_int().await?;
+                let mut members = Set::default();
+                for _ in 0..length {
+                    members.insert(reader.read_bytes_encoded().await?.into());
+                }
+                DataType::Set(members)
+            }
+            // Members, each followed by its score as a string behind a one byte length, where 253
+            // to 255 stand for NaN and the infinities
+            3 => {
+                let length = reader.read_length_encoded_int().await?;
+                let mut zset = SortedSet::default();
+                for _ in 0..length {
+                    let member = reader.read_bytes_encoded().await?;
+                    let score = match reader.read_u8().await? {
+                        253 => f64::NAN,
+                        254 => f64::INFINITY,
+                        255 => f64::NEG_INFINITY,
+                        length => {
+                            let mut score = vec![0; length as usize];
+                            reader.read_exact(&mut score).await?;
+                            parse_score(&score).unwrap_or(f64::NAN)
+                        }
+                    };
+                    if score.is_nan() {
+                        return Err(RdbReadError::InvalidScore);
+                    }
+                    zset.insert(member.into(), score);
+                }
+                DataType::SortedSet(zset)
+            }
+            // Members, each followed by its score as a little endian double
+            5 => {
+                let length = reader.read_length_encoded_int().await?;
+                let mut zset = SortedSet::default();
+                for _ in 0..length {
+                    let member = reader.read_bytes_encoded().await?;
+                    let score = reader.read_f64_le().await?;
+                    if score.is_nan() {
+                        return Err(RdbReadError::InvalidScore);
+                    }
+                    zset.insert(member.into(), score);
+                }
+                DataType::SortedSet(zset)
+            }
+            // Field and value strings, one pair after another
+            4 => {
+                let length = reader.read_length_encoded_int().await?;
+                let mut fields = HashFields::default();
+                for _ in 0..length {
+                    let field = reader.read_bytes_encoded().await?;
+                    let value = reader.read_bytes_encoded().await?;
+                    fields.insert(field.into(), value.into());
+                }
+                DataType::Hash(fields.into())
+            }
+            // A hash with fields that expire, as Redis 7.4 writes it: the earliest expiration,
+            // then each field's as an offset from it, 0 for none, ahead of the field and value
+            22 => {
+                let earliest = reader.read_u64_le().await?;
+                let length = reader.read_length_encoded_int().await?;
+                let mut hash = Hash::default();
+                for _ in 0..length {
+                    let offset = reader.read_length_encoded_int().await? as u64;
+                    let field: Bytes = reader.read_bytes_encoded().await?.into();
+                    let value = reader.read_bytes_encoded().await?;
+                    hash.insert(field.clone(), value.into());
+                    if offset != 0 {
+                        let expiration = SystemTime::UNIX_EPOCH + Duration::from_millis(earliest + offset - 1);
+                        hash.set_expiration(field, expiration);
+                    }
+                }
+                DataType::Hash(hash)
+            }
+            _ => return Err(RdbReadError::UnsupportedValueType(value_type)),
         };
 
         Ok(value)
@@ -209,7 +466,7 @@ trait RdbBufReader {
 }
 
 #[async_trait]
-impl RdbBufReader for BufReader<File> {
+impl<R: AsyncRead + Unpin + Send> RdbBufReader for BufReader<R> {
     async fn read_length_encoded_int(&mut self) -> Result<usize, RdbReadError> {
         let (encoding, length) = Self::read_length_encoding(self).await?;
         let value = Self::interpret_length_encoding(self, encoding, length).await?;
@@ -218,23 +475,28 @@ impl RdbBufReader for BufReader<File> {
     }
 
     async fn read_string_encoded(&mut self) -> Result<String, RdbReadError> {
+        let bytes = self.read_bytes_encoded().await?;
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    async fn read_bytes_encoded(&mut self) -> Result<Vec<u8>, RdbReadError> {
         let (encoding, length) = Self::read_length_encoding(self).await?;
         if encoding == LengthEncoding::SpecialFormat {
             let value = match length {
                 0 => self.read_u8().await? as usize,
                 1 => self.read_u16_le().await? as usize,
                 2 => self.read_u32_le().await? as usize,
-                3 => todo!("Compressed string not implemented"),
-                _ => panic!("Invalid SpecialFormat for string encoding! {}", length)
+                // LZF compressed
+                _ => return Err(RdbReadError::UnsupportedStringEncoding(length)),
             };
 
-            Ok(value.to_string())
+            Ok(value.to_string().into_bytes())
         } else {
             let length = Self::interpret_length_encoding(self, encoding, length).await?;
             let mut buff = vec![0; length];
             self.read_exact(&mut buff).await?;
 
-            Ok(String::from_utf8_lossy(&buff).to_string())
+            Ok(buff)
         }
     }
 
@@ -247,19 +509,183 @@ impl RdbBufReader for BufReader<File> {
         Ok(value)
     }
 
-    async fn read_key_value(&mut self, known_type: Option<u8>) -> Result<(String, DataType), RdbReadError> {
+    async fn read_key_value(&mut self, known_type: Option<u8>) -> Result<(Bytes, DataType), RdbReadError> {
         let value_type = if let Some(known_type) = known_type {
             known_type
         } else {
             self.read_u8().await?
         };
 
-        let key = self.read_string_encoded().await?;
+        let key = self.read_bytes_encoded().await?.into();
         let value = Self::read_value_type(self, value_type).await?;
         Ok((key, value))
     }
 }
 
+pub struct RdbWriter;
+
+impl RdbWriter {
+    pub fn write(data: &RdbData) -> Result<Vec<u8>, RdbWriteError> {
+        let mut buffer = Vec::new();
+        buffer.put_slice(b"REDIS");
+        buffer.put_slice(format!("{:04}", data.rdb_version).as_bytes());
+
+        for (key, value) in data.metadata.iter() {
+            buffer.put_u8(0xFA);
+            Self::write_string_encoded(&mut buffer, key.as_bytes());
+            Self::write_string_encoded(&mut buffer, value.as_bytes());
+        }
+
+        let mut database_ids = data.databases.keys().copied().collect::<Vec<_>>();
+        database_ids.sort();
+        for id in database_ids {
+            let database = &data.databases[&id];
+            if database.is_empty() {
+                continue;
+            }
+
+            let expirations = data.expirations.get(&id);
+            buffer.put_u8(0xFE);
+            Self::write_length_encoded_int(&mut buffer, id);
+            buffer.put_u8(0xFB);
+            Self::write_length_encoded_int(&mut buffer, database.len());
+            Self::write_length_encoded_int(&mut buffer, expirations.map_or(0, |e| e.len()));
+
+            for (key, value) in database.iter() {
+                if let Some(expiration) = expirations.and_then(|e| e.get(key)) {
+                    let ms = expiration
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    buffer.put_u8(0xFC);
+                    buffer.put_u64_le(ms);
+                }
+
+                Self::write_key_value(&mut buffer, key, value)?;
+            }
+        }
+
+        buffer.put_u8(0xFF);
+        // A zeroed checksum tells readers that checksumming is disabled.
+        buffer.put_u64_le(0);
+
+        Ok(buffer)
+    }
+
+    fn write_length_encoded_int(buffer: &mut Vec<u8>, length: usize) {
+        if length < (1 << 6) {
+            buffer.put_u8(length as u8);
+        } else if length < (1 << 14) {
+            buffer.put_u8(0b01000000 | (length >> 8) as u8);
+            buffer.put_u8(length as u8);
+        } else {
+            buffer.put_u8(0b10000000);
+            buffer.put_u32(length as u32);
+        }
+    }
+
+    fn write_string_encoded(buffer: &mut Vec<u8>, string: &[u8]) {
+        Self::write_length_encoded_int(buffer, string.len());
+        buffer.put_slice(string);
+    }
+
+    /// Serializes a single value the way DUMP does: its type and encoding as they would appear in
+    /// an RDB file, then the RDB version and a CRC64 of everything before it
+    pub fn dump(value: &DataType) -> Result<Vec<u8>, RdbWriteError> {
+        let mut buffer = Vec::new();
+        buffer.put_u8(Self::value_type(value)?);
+        Self::write_value(&mut buffer, value)?;
+        buffer.put_u16_le(RDB_VERSION);
+        let checksum = crc64(0, &buffer);
+        buffer.put_u64_le(checksum);
+        Ok(buffer)
+    }
+
+    fn write_key_value(buffer: &mut Vec<u8>, key: &[u8], value: &DataType) -> Result<(), RdbWriteError> {
+        buffer.put_u8(Self::value_type(value)?);
+        Self::write_string_encoded(buffer, key);
+        Self::write_value(buffer, value)
+    }
+
+    /// The type byte that precedes a value
+    fn value_type(value: &DataType) -> Result<u8, RdbWriteError> {
+        match value {
+            DataType::String(_) => Ok(0),
+            DataType::List(_) => Ok(1),
+            DataType::Set(_) => Ok(2),
+            DataType::SortedSet(_) => Ok(5),
+            DataType::Hash(hash) if hash.has_expirations() => Ok(22),
+            DataType::Hash(_) => Ok(4),
+            _ => Err(RdbWriteError::UnsupportedDataType(Box::new(value.clone()))),
+        }
+    }
+
+    fn write_value(buffer: &mut Vec<u8>, value: &DataType) -> Result<(), RdbWriteError> {
+        match value {
+            DataType::String(string) => Self::write_string_encoded(buffer, string),
+            DataType::List(list) => {
+                Self::write_length_encoded_int(buffer, list.len());
+                for element in list.iter() {
+                    Self::write_string_encoded(buffer, element);
+                }
+            }
+            DataType::Set(members) => {
+                Self::write_length_encoded_int(buffer, members.len());
+                for member in members.iter() {
+                    Self::write_string_encoded(buffer, member);
+                }
+            }
+            DataType::SortedSet(zset) => {
+                Self::write_length_encoded_int(buffer, zset.len());
+                for (member, score) in zset.iter() {
+                    Self::write_string_encoded(buffer, member);
+                    buffer.put_f64_le(score);
+                }
+            }
+            DataType::Hash(hash) if hash.has_expirations() => {
+                let millis = |expiration: SystemTime| expiration.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                let earliest = hash.expirations().map(|(_, expiration)| millis(*expiration)).min().unwrap_or_default();
+                buffer.put_u64_le(earliest);
+                Self::write_length_encoded_int(buffer, hash.len());
+                for (field, value) in hash.fields().iter() {
+                    let offset = hash.expiration(field).map_or(0, |expiration| millis(expiration) - earliest + 1);
+                    Self::write_length_encoded_int(buffer, offset as usize);
+                    Self::write_string_encoded(buffer, field);
+                    Self::write_string_encoded(buffer, value);
+                }
+            }
+            DataType::Hash(hash) => {
+                Self::write_length_encoded_int(buffer, hash.len());
+                for (field, value) in hash.fields().iter() {
+                    Self::write_string_encoded(buffer, field);
+                    Self::write_string_encoded(buffer, value);
+                }
+            }
+            _ => return Err(RdbWriteError::UnsupportedDataType(Box::new(value.clone()))),
+        }
+
+        Ok(())
+    }
+}
+
+/// The RDB version and checksum that end a DUMP payload
+const DUMP_FOOTER_LENGTH: usize = 10;
+
+/// Bit reversed form of the Jones polynomial Redis checksums with
+const CRC64_POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+/// Carries a CRC64 over `data` on from `crc`, using the same variant as Redis so checksums
+/// agree with its DUMP payloads and RDB files
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC64_POLYNOMIAL } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
 #[allow(unused)]
 enum ExpiryTimestamp {
     Seconds(u32),
@@ -0,0 +1,458 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::Bytes;
+
+/// Byte limits per node for the negative list-max-listpack-size settings, -1 through -5
+const NODE_SIZE_LIMITS: [usize; 5] = [4096, 8192, 16384, 32768, 65536];
+
+pub const DEFAULT_FILL: i64 = -2;
+
+/// Largest packed threshold DEBUG QUICKLIST-PACKED-THRESHOLD accepts
+pub const MAX_PACKED_THRESHOLD: usize = 4 * 1024 * 1024 * 1024;
+
+/// Entries bigger than this get a node to themselves rather than sharing one, like quicklist's
+/// packed_threshold. Process wide, and only changed through DEBUG QUICKLIST-PACKED-THRESHOLD.
+static PACKED_THRESHOLD: AtomicUsize = AtomicUsize::new(1 << 30);
+
+pub fn packed_threshold() -> usize {
+    PACKED_THRESHOLD.load(Ordering::Relaxed)
+}
+
+pub fn set_packed_threshold(bytes: usize) {
+    PACKED_THRESHOLD.store(bytes, Ordering::Relaxed);
+}
+
+/// One end of a list, the head is on the left
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    pub fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("left") {
+            Some(ListEnd::Left)
+        } else if name.eq_ignore_ascii_case("right") {
+            Some(ListEnd::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// A list stored as a chain of small blocks, so inserting into or removing from the middle only
+/// shifts the elements of one block rather than the whole list.
+///
+/// `fill` follows list-max-listpack-size: a positive value caps the number of entries per block,
+/// a negative one caps its size in bytes (-1 is 4KB up to -5 for 64KB).
+#[derive(Debug, Clone)]
+pub struct QuickList {
+    nodes: VecDeque<Node>,
+    len: usize,
+    /// Bytes held by the entries, the sum of the node sizes
+    size: usize,
+    fill: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    entries: VecDeque<Bytes>,
+    size: usize,
+}
+
+impl Node {
+    /// Whether the node holds a single entry over the packed threshold, which nothing joins
+    fn is_plain(&self) -> bool {
+        self.entries.len() == 1 && self.size > packed_threshold()
+    }
+
+    fn with_entry(value: Bytes) -> Self {
+        let mut node = Node::default();
+        node.push_back(value);
+        node
+    }
+
+    fn push_back(&mut self, value: Bytes) {
+        self.size += value.len();
+        self.entries.push_back(value);
+    }
+
+    fn push_front(&mut self, value: Bytes) {
+        self.size += value.len();
+        self.entries.push_front(value);
+    }
+
+    fn insert(&mut self, offset: usize, value: Bytes) {
+        self.size += value.len();
+        self.entries.insert(offset, value);
+    }
+
+    fn remove(&mut self, offset: usize) -> Option<Bytes> {
+        let value = self.entries.remove(offset)?;
+        self.size -= value.len();
+        Some(value)
+    }
+
+    fn pop_front(&mut self) -> Option<Bytes> {
+        self.remove(0)
+    }
+
+    fn pop_back(&mut self) -> Option<Bytes> {
+        let value = self.entries.pop_back()?;
+        self.size -= value.len();
+        Some(value)
+    }
+
+    fn split_off(&mut self, offset: usize) -> Node {
+        let entries = self.entries.split_off(offset);
+        let size = entries.iter().map(|e| e.len()).sum();
+        self.size -= size;
+        Node {
+            entries,
+            size,
+        }
+    }
+}
+
+impl QuickList {
+    pub fn new(fill: i64) -> Self {
+        Self {
+            nodes: VecDeque::new(),
+            len: 0,
+            size: 0,
+            fill: if fill == 0 { DEFAULT_FILL } else { fill },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes held by the entries, not counting the list's own bookkeeping
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of blocks backing the list, a list in a single block is reported as a listpack
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Entries and bytes held by each block from head to tail, for DEBUG LISTPACK
+    pub fn node_sizes(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.nodes.iter().map(|node| (node.entries.len(), node.size))
+    }
+
+    /// Whether `node` has room for `count` more entries totalling `bytes`. An empty node always
+    /// accepts, so an element bigger than the limit still gets a node of its own.
+    fn fits(&self, node: &Node, count: usize, bytes: usize) -> bool {
+        if node.entries.is_empty() {
+            return true;
+        }
+        if node.is_plain() || (count == 1 && bytes > packed_threshold()) {
+            return false;
+        }
+
+        if self.fill > 0 {
+            node.entries.len() + count <= self.fill as usize
+        } else {
+            let limit = NODE_SIZE_LIMITS[((-self.fill - 1) as usize).min(NODE_SIZE_LIMITS.len() - 1)];
+            node.size + bytes <= limit
+        }
+    }
+
+    pub fn push_back(&mut self, value: Bytes) {
+        self.len += 1;
+        self.size += value.len();
+        match self.nodes.back() {
+            Some(node) if self.fits(node, 1, value.len()) => self.nodes.back_mut().unwrap().push_back(value),
+            _ => self.nodes.push_back(Node::with_entry(value)),
+        }
+    }
+
+    pub fn push_front(&mut self, value: Bytes) {
+        self.len += 1;
+        self.size += value.len();
+        match self.nodes.front() {
+            Some(node) if self.fits(node, 1, value.len()) => self.nodes.front_mut().unwrap().push_front(value),
+            _ => self.nodes.push_front(Node::with_entry(value)),
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        let node = self.nodes.front_mut()?;
+        let value = node.pop_front()?;
+        if node.entries.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        self.size -= value.len();
+        Some(value)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let node = self.nodes.back_mut()?;
+        let value = node.pop_back()?;
+        if node.entries.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        self.size -= value.len();
+        Some(value)
+    }
+
+    pub fn push(&mut self, end: ListEnd, value: Bytes) {
+        match end {
+            ListEnd::Left => self.push_front(value),
+            ListEnd::Right => self.push_back(value),
+        }
+    }
+
+    pub fn pop(&mut self, end: ListEnd) -> Option<Bytes> {
+        match end {
+            ListEnd::Left => self.pop_front(),
+            ListEnd::Right => self.pop_back(),
+        }
+    }
+
+    /// Finds the node holding `index` and the offset of the entry within it
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+
+        // Walk from whichever end is closer
+        if index < self.len / 2 {
+            let mut remaining = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if remaining < node.entries.len() {
+                    return Some((i, remaining));
+                }
+                remaining -= node.entries.len();
+            }
+        } else {
+            let mut remaining = self.len - 1 - index;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if remaining < node.entries.len() {
+                    return Some((i, node.entries.len() - 1 - remaining));
+                }
+                remaining -= node.entries.len();
+            }
+        }
+
+        None
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Bytes> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].entries.get(offset)
+    }
+
+    /// Replaces the entry at `index`, returning false if it's out of range
+    pub fn set(&mut self, index: usize, value: Bytes) -> bool {
+        let Some((node, offset)) = self.locate(index) else {
+            return false;
+        };
+
+        let node = &mut self.nodes[node];
+        self.size = self.size - node.entries[offset].len() + value.len();
+        node.size = node.size - node.entries[offset].len() + value.len();
+        node.entries[offset] = value;
+        true
+    }
+
+    /// Inserts `value` so it ends up at `index`, shifting the entries after it along. An index
+    /// equal to the length appends.
+    pub fn insert(&mut self, index: usize, value: Bytes) {
+        if index >= self.len {
+            self.push_back(value);
+            return;
+        }
+        if index == 0 {
+            self.push_front(value);
+            return;
+        }
+
+        let (node_index, offset) = self.locate(index).unwrap();
+        self.len += 1;
+        self.size += value.len();
+        if self.fits(&self.nodes[node_index], 1, value.len()) {
+            self.nodes[node_index].insert(offset, value);
+            return;
+        }
+
+        // Inserting at the head of a full node can go on the tail of the one before it
+        if offset == 0 && self.fits(&self.nodes[node_index - 1], 1, value.len()) {
+            self.nodes[node_index - 1].push_back(value);
+            return;
+        }
+
+        // Otherwise split the full node around the insertion point
+        let tail = self.nodes[node_index].split_off(offset);
+        if self.fits(&self.nodes[node_index], 1, value.len()) {
+            self.nodes[node_index].push_back(value);
+            self.nodes.insert(node_index + 1, tail);
+        } else {
+            self.nodes.insert(node_index + 1, Node::with_entry(value));
+            self.nodes.insert(node_index + 2, tail);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Bytes> {
+        let (node_index, offset) = self.locate(index)?;
+        let value = self.nodes[node_index].remove(offset)?;
+        self.len -= 1;
+        self.size -= value.len();
+        self.compact_node(node_index);
+        Some(value)
+    }
+
+    /// Drops `node_index` if it emptied, or folds it into a neighbour when both fit in one node,
+    /// so removals don't leave a long tail of nearly empty nodes behind.
+    fn compact_node(&mut self, node_index: usize) {
+        if self.nodes[node_index].entries.is_empty() {
+            self.nodes.remove(node_index);
+            return;
+        }
+
+        if node_index + 1 < self.nodes.len() {
+            self.try_merge(node_index);
+        }
+        if node_index > 0 {
+            self.try_merge(node_index - 1);
+        }
+    }
+
+    /// Moves the entries of the node after `node_index` into it if they fit
+    fn try_merge(&mut self, node_index: usize) -> bool {
+        let next = &self.nodes[node_index + 1];
+        if next.is_plain() || !self.fits(&self.nodes[node_index], next.entries.len(), next.size) {
+            return false;
+        }
+
+        let next = self.nodes.remove(node_index + 1).unwrap();
+        let node = &mut self.nodes[node_index];
+        node.size += next.size;
+        node.entries.extend(next.entries);
+        true
+    }
+
+    /// Index of the first entry equal to `value`
+    pub fn position(&self, value: &[u8]) -> Option<usize> {
+        self.iter().position(|entry| entry.as_ref() == value)
+    }
+
+    /// Removes entries equal to `value` following LREM semantics: up to `count` from the head
+    /// when positive, up to -`count` from the tail when negative, every one when zero. Returns
+    /// the number removed.
+    pub fn remove_matching(&mut self, value: &[u8], count: i64) -> usize {
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let mut removed = 0;
+
+        let node_order: Vec<usize> = if count < 0 {
+            (0..self.nodes.len()).rev().collect()
+        } else {
+            (0..self.nodes.len()).collect()
+        };
+
+        for node_index in node_order {
+            if removed == limit {
+                break;
+            }
+
+            let node = &mut self.nodes[node_index];
+            let mut offset = if count < 0 { node.entries.len() } else { 0 };
+            loop {
+                if removed == limit {
+                    break;
+                }
+
+                let current = if count < 0 {
+                    if offset == 0 {
+                        break;
+                    }
+                    offset - 1
+                } else {
+                    if offset >= node.entries.len() {
+                        break;
+                    }
+                    offset
+                };
+
+                if node.entries[current].as_ref() == value {
+                    node.remove(current);
+                    self.size -= value.len();
+                    removed += 1;
+                    if count < 0 {
+                        offset -= 1;
+                    }
+                } else if count < 0 {
+                    offset -= 1;
+                } else {
+                    offset += 1;
+                }
+            }
+        }
+
+        self.len -= removed;
+        if removed > 0 {
+            self.rebalance();
+        }
+        removed
+    }
+
+    /// Keeps only the entries in `start..end`, the rest of the list is dropped
+    pub fn retain_range(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len);
+        if start >= end {
+            self.nodes.clear();
+            self.len = 0;
+            self.size = 0;
+            return;
+        }
+
+        for _ in end..self.len {
+            self.pop_back();
+        }
+        for _ in 0..start {
+            self.pop_front();
+        }
+    }
+
+    /// Drops empty nodes and merges neighbours that fit together after a bulk removal
+    fn rebalance(&mut self) {
+        self.nodes.retain(|node| !node.entries.is_empty());
+
+        let mut node_index = 0;
+        while node_index + 1 < self.nodes.len() {
+            if !self.try_merge(node_index) {
+                node_index += 1;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Bytes> {
+        self.nodes.iter().flat_map(|node| node.entries.iter())
+    }
+}
+
+impl Default for QuickList {
+    fn default() -> Self {
+        Self::new(DEFAULT_FILL)
+    }
+}
+
+impl FromIterator<Bytes> for QuickList {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let mut list = QuickList::default();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+use std::time::SystemTime;
+use bytes::{BufMut, Bytes};
+use once_cell::sync::OnceCell;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use crate::client::{write_resp, ResponseType};
+use crate::telemetry::spawn_named;
+use crate::clock;
+use crate::server_log;
+
+/// Captured commands on their way to the file, only set when recording was asked for
+static RECORDER: OnceCell<UnboundedSender<Bytes>> = OnceCell::new();
+
+/// A command read back from a capture file
+pub struct RecordedCommand {
+    /// Unix time in microseconds the server received the command
+    pub timestamp: u64,
+    pub client_id: u64,
+    pub db: usize,
+    pub command: Vec<ResponseType>,
+}
+
+impl RecordedCommand {
+    /// Captures are a sequence of RESP arrays, each a command prefixed with its timestamp,
+    /// client id and db, so they can be read back with the same parser as requests.
+    pub fn from_frame(frame: ResponseType) -> Option<Self> {
+        let ResponseType::Array(mut elements) = frame else {
+            return None;
+        };
+        if elements.len() < 4 {
+            return None;
+        }
+
+        let command = elements.split_off(3);
+        let field = |element: &ResponseType| element.string()?.parse::<u64>().ok();
+        Some(Self {
+            timestamp: field(&elements[0])?,
+            client_id: field(&elements[1])?,
+            db: field(&elements[2])? as usize,
+            command,
+        })
+    }
+}
+
+/// Appends every command the server receives to `path` from now on. The file is written from a
+/// background task so connections don't wait on it.
+pub async fn start_recording(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path.as_ref()).await?;
+    let (sender, mut receiver) = unbounded_channel::<Bytes>();
+    if RECORDER.set(sender).is_err() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "commands are already being recorded"));
+    }
+
+    let path = path.as_ref().display().to_string();
+    spawn_named("recorder", async move {
+        let mut writer = BufWriter::new(file);
+        while let Some(record) = receiver.recv().await {
+            let mut result = writer.write_all(&record).await;
+            // Write out whatever else has queued up before flushing
+            while let (Ok(_), Ok(record)) = (&result, receiver.try_recv()) {
+                result = writer.write_all(&record).await;
+            }
+            if let Err(e) = result.and(writer.flush().await) {
+                server_log!(Warning, "Failed to write to the capture file {}, recording stopped. {}", path, e);
+                return;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Captures a command from `client_id` against `db`, if recording
+pub async fn record(client_id: u64, db: usize, command: &[ResponseType]) -> Result<(), anyhow::Error> {
+    let Some(recorder) = RECORDER.get() else {
+        return Ok(());
+    };
+
+    let timestamp = clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    let mut buffer = Vec::with_capacity(64).writer();
+    buffer.get_mut().put_slice(format!("*{}\r\n", command.len() + 3).as_bytes());
+    for field in [timestamp.to_string(), client_id.to_string(), db.to_string()] {
+        write_resp(&mut buffer, &ResponseType::BulkString(field.into_bytes().into())).await?;
+    }
+    for element in command {
+        write_resp(&mut buffer, element).await?;
+    }
+
+    // The writer only goes away after a failed write, which it has already logged
+    let _ = recorder.send(Bytes::from(buffer.into_inner()));
+    Ok(())
+}
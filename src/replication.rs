@@ -0,0 +1,362 @@
+use std::sync::Arc;
+use bytes::buf::Writer;
+use bytes::{BufMut, Bytes};
+use once_cell::sync::Lazy;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::RwLock;
+use crate::server_log;
+use crate::client::{write_resp, RedisClientConnection, ResponseType};
+use crate::database::{db_load_bytes, db_snapshot};
+use crate::persistence::RdbWriter;
+use crate::util::random_hex_string;
+
+pub static REPLICATION: Lazy<Arc<RwLock<ReplicationState>>> = Lazy::new(|| {
+    Arc::new(RwLock::new(ReplicationState::new()))
+});
+
+pub struct ReplicationState {
+    pub replid: String,
+    pub offset: u64,
+    pub master_link_up: bool,
+    /// The database the replication stream currently has selected, None forces the next write to
+    /// emit a SELECT
+    selected_db: Option<usize>,
+    replicas: Vec<AttachedReplica>,
+    next_replica_id: u64,
+    /// Consumers of the write stream that aren't replicas, see `tap_writes`
+    taps: Vec<UnboundedSender<TappedWrite>>,
+    /// Set once the dataset is known to match `replid` at `offset`, after a sync or when both
+    /// were restored along with a snapshot. A replica then asks its master to continue from
+    /// there instead of sending everything again.
+    resumable: bool,
+}
+
+#[derive(Clone)]
+pub struct ReplicaInfo {
+    /// The address the replica can be reached at, as announced by it during the handshake
+    pub ip: String,
+    pub port: u16,
+    pub ack_offset: u64,
+}
+
+struct AttachedReplica {
+    id: u64,
+    info: ReplicaInfo,
+    sender: UnboundedSender<Bytes>,
+}
+
+/// Writes as they went down the replication stream
+#[derive(Clone, Debug)]
+pub struct TappedWrite {
+    /// The replication offset just past `frames`
+    pub offset: u64,
+    /// One or more complete RESP command frames. A SELECT comes first when the write is to a
+    /// different database than the last one, and a transaction arrives whole within MULTI/EXEC.
+    pub frames: Bytes,
+}
+
+impl ReplicationState {
+    fn new() -> Self {
+        Self {
+            replid: random_hex_string(40),
+            offset: 0,
+            master_link_up: false,
+            selected_db: None,
+            replicas: Vec::new(),
+            next_replica_id: 0,
+            taps: Vec::new(),
+            resumable: false,
+        }
+    }
+
+    pub fn connected_replicas(&self) -> Vec<ReplicaInfo> {
+        self.replicas
+            .iter()
+            .filter(|r| !r.sender.is_closed())
+            .map(|r| r.info.clone())
+            .collect()
+    }
+
+    /// Whether anything is listening to the write stream, replicas or taps
+    fn has_live_replicas(&self) -> bool {
+        self.replicas.iter().any(|r| !r.sender.is_closed()) || self.taps.iter().any(|t| !t.is_closed())
+    }
+
+    fn has_replicas(&mut self) -> bool {
+        self.replicas.retain(|r| !r.sender.is_closed());
+        self.taps.retain(|t| !t.is_closed());
+        !self.replicas.is_empty() || !self.taps.is_empty()
+    }
+
+    async fn write_select(&mut self, buffer: &mut Writer<Vec<u8>>, db_id: usize) -> Result<(), anyhow::Error> {
+        let select = ResponseType::Array(vec![
+            ResponseType::BulkString(Bytes::from_static(b"SELECT")),
+            ResponseType::BulkString(db_id.to_string().into_bytes().into()),
+        ]);
+        write_resp(buffer, &select).await?;
+        self.selected_db = Some(db_id);
+
+        Ok(())
+    }
+
+    async fn write_command(&mut self, buffer: &mut Writer<Vec<u8>>, db_id: usize, command: &[ResponseType]) -> Result<(), anyhow::Error> {
+        if self.selected_db != Some(db_id) {
+            self.write_select(buffer, db_id).await?;
+        }
+
+        write_resp(buffer, &ResponseType::Array(command.to_vec())).await
+    }
+
+    /// Sends `data` to the replicas, returning it for anything else that wants a copy
+    fn send_to_replicas(&mut self, data: Vec<u8>) -> Bytes {
+        let data = Bytes::from(data);
+        self.offset += data.len() as u64;
+        for replica in self.replicas.iter() {
+            let _ = replica.sender.send(data.clone());
+        }
+        data
+    }
+
+    /// Sends writes to the replicas and taps
+    fn send(&mut self, data: Vec<u8>) {
+        let frames = self.send_to_replicas(data);
+        for tap in self.taps.iter() {
+            let _ = tap.send(TappedWrite {
+                offset: self.offset,
+                frames: frames.clone(),
+            });
+        }
+    }
+}
+
+pub struct FullResync {
+    pub replica_id: u64,
+    pub replid: String,
+    pub offset: u64,
+    pub rdb: Vec<u8>,
+    pub stream: UnboundedReceiver<Bytes>,
+}
+
+/// Registers a new replica, returning the snapshot it should load and the stream of writes that
+/// follow it.
+#[tracing::instrument(name = "replication.full_resync", skip_all, fields(replica.address = %ip, replica.port = port))]
+pub async fn attach_replica(ip: String, port: u16) -> Result<FullResync, anyhow::Error> {
+    let mut state = REPLICATION.write().await;
+    let rdb = RdbWriter::write(&db_snapshot().await)?;
+    let (sender, stream) = unbounded_channel();
+    let replica_id = state.next_replica_id;
+    state.next_replica_id += 1;
+    state.replicas.push(AttachedReplica {
+        id: replica_id,
+        info: ReplicaInfo {
+            ip,
+            port,
+            ack_offset: 0,
+        },
+        sender,
+    });
+    state.selected_db = None;
+
+    Ok(FullResync {
+        replica_id,
+        replid: state.replid.clone(),
+        offset: state.offset,
+        rdb,
+        stream,
+    })
+}
+
+/// Subscribes to every write propagated from now on, for change data capture without the full
+/// resync a replica goes through. Returns the replication id and the offset the first write
+/// will start at along with the stream. The consumer is dropped once the receiver is.
+pub async fn tap_writes() -> (String, u64, UnboundedReceiver<TappedWrite>) {
+    let mut state = REPLICATION.write().await;
+    let (sender, stream) = unbounded_channel();
+    state.taps.push(sender);
+    // The first write the tap sees has to say which database it's in
+    state.selected_db = None;
+
+    (state.replid.clone(), state.offset, stream)
+}
+
+/// Sends a write command to every attached replica, selecting `db_id` first if the stream is
+/// currently pointed at a different database.
+pub async fn propagate(db_id: usize, command: &[ResponseType]) -> Result<(), anyhow::Error> {
+    // Checked under the shared lock first so writes on a master without replicas don't serialize
+    // on the replication state.
+    if !REPLICATION.read().await.has_live_replicas() {
+        return Ok(());
+    }
+
+    let mut state = REPLICATION.write().await;
+    if !state.has_replicas() {
+        return Ok(());
+    }
+
+    let mut buffer = Vec::with_capacity(64).writer();
+    state.write_command(&mut buffer, db_id, command).await?;
+    state.send(buffer.into_inner());
+
+    Ok(())
+}
+
+/// Sends the writes of a transaction wrapped in MULTI/EXEC as one unit, so a replica can never
+/// apply only part of it.
+pub async fn propagate_transaction(writes: &[(usize, Vec<ResponseType>)]) -> Result<(), anyhow::Error> {
+    if !REPLICATION.read().await.has_live_replicas() {
+        return Ok(());
+    }
+
+    let mut state = REPLICATION.write().await;
+    if !state.has_replicas() || writes.is_empty() {
+        return Ok(());
+    }
+
+    let mut buffer = Vec::with_capacity(256).writer();
+    let (first_db, _) = writes[0];
+    if state.selected_db != Some(first_db) {
+        state.write_select(&mut buffer, first_db).await?;
+    }
+
+    write_resp(&mut buffer, &ResponseType::Array(vec![ResponseType::BulkString(Bytes::from_static(b"MULTI"))])).await?;
+    for (db_id, command) in writes.iter() {
+        state.write_command(&mut buffer, *db_id, command).await?;
+    }
+    write_resp(&mut buffer, &ResponseType::Array(vec![ResponseType::BulkString(Bytes::from_static(b"EXEC"))])).await?;
+
+    state.send(buffer.into_inner());
+
+    Ok(())
+}
+
+/// Sends a PING down the replication stream so replicas can tell an idle master from a broken
+/// link
+pub async fn ping_replicas() -> Result<(), anyhow::Error> {
+    if !REPLICATION.read().await.has_live_replicas() {
+        return Ok(());
+    }
+
+    let mut buffer = Vec::with_capacity(16).writer();
+    write_resp(&mut buffer, &ResponseType::Array(vec![ResponseType::BulkString(Bytes::from_static(b"PING"))])).await?;
+    // Not a write, so taps don't see it
+    REPLICATION.write().await.send_to_replicas(buffer.into_inner());
+
+    Ok(())
+}
+
+/// The replication id and offset the dataset is currently at
+pub async fn replication_position() -> (String, u64) {
+    let state = REPLICATION.read().await;
+    (state.replid.clone(), state.offset)
+}
+
+/// Picks up the replication id and offset saved along with the dataset that was just loaded
+pub async fn restore_replication_position(replid: String, offset: u64) {
+    let mut state = REPLICATION.write().await;
+    state.replid = replid;
+    state.offset = offset;
+    state.resumable = true;
+}
+
+pub async fn acknowledge(replica_id: u64, offset: u64) {
+    let mut state = REPLICATION.write().await;
+    if let Some(replica) = state.replicas.iter_mut().find(|r| r.id == replica_id) {
+        replica.info.ack_offset = offset;
+    }
+}
+
+pub async fn advance_offset(consumed: usize) {
+    REPLICATION.write().await.offset += consumed as u64;
+}
+
+/// Connects to the master, performs the handshake and resync, then applies the command stream
+/// until the link drops.
+pub async fn run_replica_link(host: String, port: u16, announce_ip: Option<String>, announce_port: u16) -> Result<(), anyhow::Error> {
+    let mut master = sync_with_master(&host, port, announce_ip, announce_port).await?;
+    master.set_master_link();
+    let result = master.process().await;
+    REPLICATION.write().await.master_link_up = false;
+
+    result
+}
+
+/// The handshake and resync, leaving the connection ready to receive the command stream. Asks
+/// for a partial resync when the dataset is at a known position, which the master accepts with
+/// +CONTINUE if it still has the writes since then, or else answers with a full resync.
+#[tracing::instrument(name = "replication.sync", skip(announce_ip, announce_port))]
+async fn sync_with_master(host: &str, port: u16, announce_ip: Option<String>, announce_port: u16) -> Result<RedisClientConnection, anyhow::Error> {
+    let stream = TcpStream::connect((host, port)).await?;
+    let mut master = RedisClientConnection::new(stream);
+
+    master.send_command(&["PING"]).await?;
+    expect_simple_string(master.read().await?, "PONG")?;
+
+    master.send_command(&["REPLCONF", "listening-port", announce_port.to_string().as_str()]).await?;
+    expect_simple_string(master.read().await?, "OK")?;
+
+    if let Some(announce_ip) = announce_ip {
+        master.send_command(&["REPLCONF", "ip-address", announce_ip.as_str()]).await?;
+        expect_simple_string(master.read().await?, "OK")?;
+    }
+
+    master.send_command(&["REPLCONF", "capa", "psync2"]).await?;
+    expect_simple_string(master.read().await?, "OK")?;
+
+    let resume_from = {
+        let state = REPLICATION.read().await;
+        state.resumable.then(|| (state.replid.clone(), state.offset))
+    };
+    match resume_from.as_ref() {
+        Some((replid, offset)) => {
+            master.send_command(&["PSYNC", replid.as_str(), (offset + 1).to_string().as_str()]).await?;
+        }
+        None => master.send_command(&["PSYNC", "?", "-1"]).await?,
+    }
+    let reply = master.read().await?;
+
+    if let ResponseType::SimpleString(s) = &reply {
+        if s == "CONTINUE" || s.starts_with("CONTINUE ") {
+            let mut state = REPLICATION.write().await;
+            // The master's id changes when it was itself promoted, it says so here
+            if let Some(replid) = s.split(' ').nth(1) {
+                state.replid = replid.to_string();
+            }
+            state.master_link_up = true;
+            server_log!(Notice, "Partial resynchronization with master {}:{} accepted at offset {}", host, port, state.offset);
+            return Ok(master);
+        }
+    }
+
+    let (replid, offset) = match &reply {
+        ResponseType::SimpleString(s) if s.starts_with("FULLRESYNC ") => {
+            let mut parts = s.split(' ').skip(1);
+            match (parts.next(), parts.next().and_then(|o| o.parse::<u64>().ok())) {
+                (Some(replid), Some(offset)) => (replid.to_string(), offset),
+                _ => anyhow::bail!("Malformed FULLRESYNC reply from master: {}", s),
+            }
+        }
+        _ => anyhow::bail!("Unexpected reply to PSYNC: {}", reply),
+    };
+
+    let rdb = master.read_rdb_payload().await?;
+    db_load_bytes(&rdb).await?;
+    server_log!(Notice, "Synchronized with master {}:{}, {} byte rdb", host, port, rdb.len());
+
+    {
+        let mut state = REPLICATION.write().await;
+        state.replid = replid;
+        state.offset = offset;
+        state.master_link_up = true;
+        state.resumable = true;
+    }
+
+    Ok(master)
+}
+
+fn expect_simple_string(reply: ResponseType, expected: &str) -> Result<(), anyhow::Error> {
+    match reply {
+        ResponseType::SimpleString(s) if s.eq_ignore_ascii_case(expected) => Ok(()),
+        _ => anyhow::bail!("Expected +{} from master but received {}", expected, reply),
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use bytes::Bytes;
+use mlua::{Function, Lua, MultiValue, RegistryKey, Table, Thread, ThreadStatus, Value};
+use once_cell::sync::Lazy;
+use crate::client::ResponseType;
+
+/// The `redis` library scripts see. Commands are run by yielding them to whoever resumes the
+/// script, which needs the client and can't do it from inside Lua.
+const REDIS_LIBRARY: &str = r#"
+local yield = coroutine.yield
+redis = {}
+function redis.pcall(...)
+    return yield(...)
+end
+function redis.call(...)
+    local reply = yield(...)
+    if type(reply) == 'table' and reply.err ~= nil then
+        error(reply, 0)
+    end
+    return reply
+end
+function redis.error_reply(message)
+    return {err = message}
+end
+function redis.status_reply(message)
+    return {ok = message}
+end
+"#;
+
+/// Wraps a script's body so an error it raises becomes its error reply
+const RUNNER: &str = r#"
+local body = ...
+return function()
+    local ok, result = pcall(body)
+    if ok then
+        return result
+    end
+    if type(result) == 'table' and result.err ~= nil then
+        return result
+    end
+    return {err = 'ERR ' .. tostring(result)}
+end
+"#;
+
+/// Lets a library's code declare its functions, keeping them in `__functions` along with their
+/// flags. Both `redis.register_function(name, callback)` and the named argument form work.
+const REGISTER_FUNCTION: &str = r#"
+__functions = {}
+function redis.register_function(name, callback)
+    local flags = {}
+    if type(name) == 'table' then
+        callback = name.callback
+        flags = name.flags or {}
+        name = name.function_name
+    end
+    if type(name) ~= 'string' or type(callback) ~= 'function' then
+        error('ERR wrong arguments given to redis.register_function', 0)
+    end
+    if __functions[name] ~= nil then
+        error('ERR Function ' .. name .. ' already exists', 0)
+    end
+    __functions[name] = {callback = callback, flags = flags}
+end
+"#;
+
+/// What a script asks for each time it stops
+pub enum ScriptStep {
+    /// Run this command and resume the script with its reply
+    Call(Vec<Bytes>),
+    /// The script's reply
+    Done(ResponseType),
+}
+
+/// A script part way through running, as a coroutine that yields the commands it calls
+pub struct ScriptRun {
+    lua: Lua,
+    /// The coroutine, kept in the registry so the run can be held across awaits
+    thread: RegistryKey,
+}
+
+impl ScriptRun {
+    /// An EVAL script, which sees its keys and arguments as the KEYS and ARGV globals
+    pub fn eval(script: &[u8], keys: Vec<Bytes>, args: Vec<Bytes>) -> Result<Self, String> {
+        let lua = new_lua()?;
+        let body = lua
+            .load(script)
+            .set_name("user_script")
+            .into_function()
+            .map_err(|e| format!("ERR Error compiling script (new function): {}", e))?;
+        let setup = || -> mlua::Result<()> {
+            lua.globals().set("KEYS", strings_table(&lua, &keys)?)?;
+            lua.globals().set("ARGV", strings_table(&lua, &args)?)
+        };
+        setup().map_err(|e| format!("ERR {}", e))?;
+        let thread = start(&lua, body)?;
+        Ok(Self { lua, thread })
+    }
+
+    /// FCALL of a function from a loaded library, which is passed its keys and arguments. Also
+    /// returns whether the function is flagged no-writes.
+    pub fn fcall(name: &str, keys: Vec<Bytes>, args: Vec<Bytes>) -> Result<(Self, bool), String> {
+        let code = LIBRARIES
+            .lock()
+            .unwrap()
+            .values()
+            .find(|library| library.functions.iter().any(|function| function.name == name))
+            .map(|library| library.code.clone())
+            .ok_or_else(|| "ERR Function not found".to_string())?;
+
+        let lua = new_lua()?;
+        let functions = register_functions(&lua, &code)?;
+        let no_writes = functions.iter().any(|function| function.name == name && function.no_writes);
+        let body = || -> mlua::Result<Function> {
+            let registered: Table = lua.globals().get("__functions")?;
+            let callback: Function = registered.get::<_, Table>(name)?.get("callback")?;
+            let bind = lua.load("local callback, keys, args = ... return function() return callback(keys, args) end");
+            bind.call((callback, strings_table(&lua, &keys)?, strings_table(&lua, &args)?))
+        };
+        let body = body().map_err(|e| format!("ERR {}", e))?;
+        let thread = start(&lua, body)?;
+        Ok((Self { lua, thread }, no_writes))
+    }
+
+    /// Runs the script until it calls a command or finishes. `reply` is the reply to the command
+    /// it last called, None the first time.
+    pub fn resume(&mut self, reply: Option<ResponseType>) -> ScriptStep {
+        let step = || -> mlua::Result<ScriptStep> {
+            let thread: Thread = self.lua.registry_value(&self.thread)?;
+            let yielded: MultiValue = match reply {
+                Some(reply) => thread.resume(to_lua(&self.lua, reply)?)?,
+                None => thread.resume(())?,
+            };
+            if thread.status() == ThreadStatus::Resumable {
+                return Ok(ScriptStep::Call(command_arguments(yielded)?));
+            }
+            Ok(ScriptStep::Done(yielded.into_iter().next().map(from_lua).unwrap_or(ResponseType::NullBulkString)))
+        };
+        step().unwrap_or_else(|e| ScriptStep::Done(ResponseType::Error(format!("ERR {}", e))))
+    }
+}
+
+/// The coroutine that runs `body`, kept in the registry so the run can be held across awaits
+fn start(lua: &Lua, body: Function) -> Result<RegistryKey, String> {
+    let thread = || -> mlua::Result<RegistryKey> {
+        let runner: Function = lua.load(RUNNER).call(body)?;
+        let thread = lua.create_thread(runner)?;
+        lua.create_registry_value(thread)
+    };
+    thread().map_err(|e| format!("ERR {}", e))
+}
+
+/// A loaded function library
+struct Library {
+    code: String,
+    functions: Vec<FunctionInfo>,
+}
+
+#[derive(Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    /// Declared with the no-writes flag, so it may be run by FCALL_RO
+    pub no_writes: bool,
+}
+
+/// Loaded function libraries by name. Like scripts they're gone on restart, they aren't saved
+/// with the dataset.
+static LIBRARIES: Lazy<Mutex<BTreeMap<String, Library>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// FUNCTION LOAD: checks `code` and registers the functions it declares, returning the library's
+/// name. Its first line names it, as in `#!lua name=mylib`.
+pub fn load_library(code: &str, replace: bool) -> Result<String, String> {
+    let (shebang, body) = code.split_once('\n').unwrap_or((code, ""));
+    let Some(shebang) = shebang.strip_prefix("#!") else {
+        return Err("ERR Missing library metadata".to_string());
+    };
+    let mut parts = shebang.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let Some(name) = name.filter(|name| is_valid_name(name)) else {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    };
+
+    // The metadata line is left out of what Lua sees, an empty line keeps the line numbers
+    let code = format!("\n{}", body);
+    let functions = register_functions(&new_lua()?, &code)?;
+    if functions.is_empty() {
+        return Err("ERR No functions registered".to_string());
+    }
+    if let Some(function) = functions.iter().find(|function| !is_valid_name(&function.name)) {
+        return Err(format!("ERR Function names can only contain letters, numbers, or underscores(_) and must be at least one character long: {}", function.name));
+    }
+
+    let mut libraries = LIBRARIES.lock().unwrap();
+    if libraries.contains_key(&name) && !replace {
+        return Err(format!("ERR Library '{}' already exists", name));
+    }
+    let taken = libraries
+        .iter()
+        .filter(|(library, _)| **library != name)
+        .flat_map(|(_, library)| library.functions.iter())
+        .find(|existing| functions.iter().any(|function| function.name == existing.name));
+    if let Some(existing) = taken {
+        return Err(format!("ERR Function {} already exists", existing.name));
+    }
+    libraries.insert(name.clone(), Library { code, functions });
+    Ok(name)
+}
+
+/// FUNCTION DELETE, returning whether the library was loaded
+pub fn delete_library(name: &str) -> bool {
+    LIBRARIES.lock().unwrap().remove(name).is_some()
+}
+
+/// FUNCTION FLUSH
+pub fn flush_libraries() {
+    LIBRARIES.lock().unwrap().clear();
+}
+
+/// FUNCTION LIST: each library's name along with its functions, in name order
+pub fn list_libraries() -> Vec<(String, Vec<FunctionInfo>)> {
+    LIBRARIES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, library)| (name.clone(), library.functions.clone()))
+        .collect()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+fn new_lua() -> Result<Lua, String> {
+    let lua = Lua::new();
+    lua.load(REDIS_LIBRARY).exec().map_err(|e| format!("ERR {}", e))?;
+    Ok(lua)
+}
+
+/// Runs a library's code, returning the functions it registered. Commands can't be called while
+/// a library loads.
+fn register_functions(lua: &Lua, code: &str) -> Result<Vec<FunctionInfo>, String> {
+    let register = || -> mlua::Result<Vec<FunctionInfo>> {
+        lua.load(REGISTER_FUNCTION).exec()?;
+        lua.load(code).set_name("user_function").exec()?;
+        let registered: Table = lua.globals().get("__functions")?;
+        let mut functions = Vec::new();
+        for pair in registered.pairs::<String, Table>() {
+            let (name, function) = pair?;
+            let flags: Table = function.get("flags")?;
+            let no_writes = flags.sequence_values::<String>().any(|flag| matches!(flag.as_deref(), Ok("no-writes")));
+            functions.push(FunctionInfo { name, no_writes });
+        }
+        Ok(functions)
+    };
+    register().map_err(|e| match e {
+        mlua::Error::SyntaxError { message, .. } => format!("ERR Error compiling function: {}", message),
+        e => format!("ERR Error registering functions: {}", e),
+    })
+}
+
+fn strings_table<'lua>(lua: &'lua Lua, strings: &[Bytes]) -> mlua::Result<Table<'lua>> {
+    lua.create_sequence_from(strings.iter().map(|string| lua.create_string(string)).collect::<mlua::Result<Vec<_>>>()?)
+}
+
+/// The command a script passed to redis.call, whose arguments have to be strings or numbers
+fn command_arguments(yielded: MultiValue) -> mlua::Result<Vec<Bytes>> {
+    yielded
+        .into_iter()
+        .map(|argument| match argument {
+            Value::String(string) => Ok(Bytes::copy_from_slice(string.as_bytes())),
+            Value::Integer(integer) => Ok(Bytes::from(integer.to_string())),
+            Value::Number(number) => Ok(Bytes::from(number.to_string())),
+            _ => Err(mlua::Error::RuntimeError("Lua redis lib command arguments must be strings or integers".to_string())),
+        })
+        .collect()
+}
+
+/// A command's reply as the script sees it: nils are false, status and error replies are
+/// tables with an ok or an err field
+fn to_lua<'lua>(lua: &'lua Lua, reply: ResponseType) -> mlua::Result<Value<'lua>> {
+    Ok(match reply {
+        ResponseType::Integer(integer) => Value::Integer(integer),
+        ResponseType::BulkString(string) => Value::String(lua.create_string(&string)?),
+        ResponseType::NullBulkString | ResponseType::NullArray => Value::Boolean(false),
+        ResponseType::SimpleString(status) => Value::Table(lua.create_table_from([("ok", status)])?),
+        ResponseType::Error(error) => Value::Table(lua.create_table_from([("err", error)])?),
+        ResponseType::Array(elements) => {
+            let table = lua.create_table_with_capacity(elements.len(), 0)?;
+            for element in elements {
+                table.raw_push(to_lua(lua, element)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// The reply for a value a script returned. Numbers are truncated to integers, tables become
+/// arrays up to their first nil unless they have an ok or an err field.
+fn from_lua(value: Value) -> ResponseType {
+    match value {
+        Value::Integer(integer) => ResponseType::Integer(integer),
+        Value::Number(number) => ResponseType::Integer(number as i64),
+        Value::String(string) => ResponseType::BulkString(Bytes::copy_from_slice(string.as_bytes())),
+        Value::Boolean(true) => ResponseType::Integer(1),
+        Value::Table(table) => {
+            if let Ok(Value::String(error)) = table.raw_get::<_, Value>("err") {
+                return ResponseType::Error(error.to_string_lossy().into_owned());
+            }
+            if let Ok(Value::String(status)) = table.raw_get::<_, Value>("ok") {
+                return ResponseType::SimpleString(status.to_string_lossy().into_owned());
+            }
+            ResponseType::Array(table.sequence_values::<Value>().map_while(Result::ok).map(from_lua).collect())
+        }
+        _ => ResponseType::NullBulkString,
+    }
+}
@@ -0,0 +1,434 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use crate::server_log;
+use crate::{Config, ReplicaOf, SaveRule, CONFIG};
+use crate::client::RedisClientConnection;
+use crate::clock::{set_clock, Clock};
+use crate::command::CommandFlags;
+use crate::cluster::init_cluster;
+use crate::config::{apply_config, set_config_file, ConfigError, ConfigFile};
+#[cfg(unix)]
+use crate::config::reload_on_sighup;
+use crate::cron::run_server_cron;
+use crate::database::{changes_since_last_save, db_load, db_save, rdb_path, reset_dirty, LOADING};
+use crate::io_threads::start_io_threads;
+use crate::logging::{configure_logging, LogLevel};
+#[cfg(unix)]
+use crate::logging::reopen_on_sigusr1;
+use crate::module::{load_module, Module};
+use crate::recorder::start_recording;
+use crate::replication::run_replica_link;
+use crate::shard::start_keyspace_shards;
+use crate::storage::{index_slots, select_storage_engine, DEFAULT_STORAGE_ENGINE};
+use crate::systemd::{notify, Supervised};
+use crate::telemetry::{spawn_named, spawn_named_in};
+
+/// Entry point for running the server inside another application. The keyspace and config are
+/// process wide, so one server should be running in a process at a time.
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: Config::default(),
+            config_file: None,
+            listener: None,
+            modules: Vec::new(),
+            clock: None,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    config: Config,
+    config_file: Option<ConfigFile>,
+    listener: Option<std::net::TcpListener>,
+    modules: Vec<Box<dyn Module>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl ServerBuilder {
+    /// Applies the directives of a redis.conf style file. It is re-read on SIGHUP, and options
+    /// set on the builder after this take precedence over it.
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let file = ConfigFile::read(path)?;
+        apply_config(&mut self.config, &file.directives(), true)?;
+        self.config_file = Some(file);
+        Ok(self)
+    }
+
+    /// Port to listen on, 0 picks a free one that can be read back from the handle
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.dir = Some(dir.into());
+        self
+    }
+
+    pub fn db_filename(mut self, db_filename: impl Into<String>) -> Self {
+        self.config.db_filename = Some(db_filename.into());
+        self
+    }
+
+    pub fn replica_of(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.config.replica_of = Some(ReplicaOf {
+            host: host.into(),
+            port,
+        });
+        self
+    }
+
+    pub fn replica_announce_ip(mut self, ip: impl Into<String>) -> Self {
+        self.config.replica_announce_ip = Some(ip.into());
+        self
+    }
+
+    pub fn replica_announce_port(mut self, port: u16) -> Self {
+        self.config.replica_announce_port = Some(port);
+        self
+    }
+
+    pub fn replica_serve_stale_data(mut self, serve_stale_data: bool) -> Self {
+        self.config.replica_serve_stale_data = serve_stale_data;
+        self
+    }
+
+    pub fn list_max_listpack_size(mut self, fill: i64) -> Self {
+        self.config.list_max_listpack_size = fill;
+        self
+    }
+
+    pub fn hz(mut self, hz: u32) -> Self {
+        self.config.hz = hz;
+        self
+    }
+
+    pub fn io_threads(mut self, io_threads: usize) -> Self {
+        self.config.io_threads = io_threads;
+        self
+    }
+
+    pub fn keyspace_shards(mut self, keyspace_shards: usize) -> Self {
+        self.config.keyspace_shards = keyspace_shards;
+        self
+    }
+
+    pub fn maxmemory_clients(mut self, maxmemory_clients: usize) -> Self {
+        self.config.maxmemory_clients = maxmemory_clients;
+        self
+    }
+
+    pub fn cluster_enabled(mut self, cluster_enabled: bool) -> Self {
+        self.config.cluster_enabled = cluster_enabled;
+        self
+    }
+
+    /// Snapshot the dataset whenever one of `rules` is met
+    pub fn save_rules(mut self, rules: Vec<SaveRule>) -> Self {
+        self.config.save_rules = rules;
+        self
+    }
+
+    pub fn stop_writes_on_bgsave_error(mut self, stop_writes: bool) -> Self {
+        self.config.stop_writes_on_bgsave_error = stop_writes;
+        self
+    }
+
+    pub fn loglevel(mut self, loglevel: LogLevel) -> Self {
+        self.config.loglevel = loglevel;
+        self
+    }
+
+    /// Writes the log to `path` instead of stdout. SIGUSR1 reopens it after rotation.
+    pub fn logfile(mut self, path: impl Into<String>) -> Self {
+        self.config.logfile = Some(path.into());
+        self
+    }
+
+    /// Records who ran which write and admin commands to `path`
+    pub fn audit_log_file(mut self, path: impl Into<String>) -> Self {
+        self.config.audit_log_file = Some(path.into());
+        self
+    }
+
+    /// Audits commands with any of these flags instead of write and admin ones
+    pub fn audit_log_categories(mut self, categories: CommandFlags) -> Self {
+        self.config.audit_log_categories = categories;
+        self
+    }
+
+    /// Appends every received command to `path`, which the redis-replay binary can play back
+    pub fn record_file(mut self, path: impl Into<String>) -> Self {
+        self.config.record_file = Some(path.into());
+        self
+    }
+
+    /// Stores the keyspace with the engine registered as `name` through
+    /// `storage::register_storage_engine`
+    pub fn storage_engine(mut self, name: impl Into<String>) -> Self {
+        self.config.storage_engine = Some(name.into());
+        self
+    }
+
+    /// Allows DEBUG PANIC, SEGFAULT and OOM to bring the server down
+    pub fn enable_fault_injection(mut self, enabled: bool) -> Self {
+        self.config.enable_fault_injection = enabled;
+        self
+    }
+
+    pub fn syslog_enabled(mut self, syslog_enabled: bool) -> Self {
+        self.config.syslog_enabled = syslog_enabled;
+        self
+    }
+
+    pub fn syslog_ident(mut self, ident: impl Into<String>) -> Self {
+        self.config.syslog_ident = Some(ident.into());
+        self
+    }
+
+    /// One of the facility codes accepted by `logging::parse_syslog_facility`
+    pub fn syslog_facility(mut self, facility: u8) -> Self {
+        self.config.syslog_facility = facility;
+        self
+    }
+
+    /// Serves connections from an already bound, non-blocking listener instead of binding `port`,
+    /// such as one passed in through systemd socket activation
+    pub fn listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    pub fn supervised(mut self, supervised: Supervised) -> Self {
+        self.config.supervised = supervised;
+        self
+    }
+
+    /// Loads `module` when the server starts
+    pub fn module(mut self, module: impl Module + 'static) -> Self {
+        self.modules.push(Box::new(module));
+        self
+    }
+
+    /// Replaces the system clock, e.g. with a `ManualClock` to control when keys expire
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Binds the listener and starts serving in the background. Returns once the server is
+    /// accepting connections, which may be before the dataset has finished loading.
+    pub async fn spawn(self) -> Result<ServerHandle, anyhow::Error> {
+        configure_logging(&self.config)?;
+        if let Some(clock) = self.clock {
+            set_clock(clock);
+        }
+        for module in self.modules.iter() {
+            load_module(module.as_ref())?;
+        }
+        let storage_engine = self.config.storage_engine.as_deref().unwrap_or(DEFAULT_STORAGE_ENGINE);
+        select_storage_engine(storage_engine).map_err(anyhow::Error::msg)?;
+        index_slots(self.config.cluster_enabled);
+
+        let listener = match self.listener {
+            Some(listener) => TcpListener::from_std(listener)?,
+            None => TcpListener::bind(("127.0.0.1", self.config.port)).await?,
+        };
+        let local_addr = listener.local_addr()?;
+        server_log!(Notice, "Listening on {}", local_addr);
+
+        {
+            let mut config = CONFIG.write().await;
+            *config = self.config;
+            config.port = local_addr.port();
+        }
+
+        if let Some(path) = CONFIG.read().await.record_file.as_ref() {
+            start_recording(path).await?;
+            server_log!(Notice, "Recording commands to {}", path);
+        }
+
+        start_io_threads(CONFIG.read().await.io_threads)?;
+        start_keyspace_shards(CONFIG.read().await.keyspace_shards)?;
+
+        let mut background = Vec::new();
+
+        // Connections are accepted straight away and told to retry with -LOADING until the
+        // dataset is in memory, only then does a replica go on to sync with its master.
+        reset_dirty();
+        if has_database_file().await {
+            LOADING.begin();
+        }
+        background.push(spawn_named("replication", async {
+            if let Err(e) = load_database().await {
+                server_log!(Warning, "Failed to load database. {:?}", e);
+            }
+            run_replication().await;
+        }));
+
+        background.push(spawn_named("cron", run_server_cron()));
+
+        #[cfg(unix)]
+        if has_log_files().await {
+            background.push(spawn_named("reopen logs", reopen_on_sigusr1()));
+        }
+
+        if let Some(file) = self.config_file {
+            server_log!(Notice, "Using config file {}", file.path().display());
+            set_config_file(file);
+            #[cfg(unix)]
+            background.push(spawn_named("reload config", reload_on_sighup()));
+        }
+
+        if CONFIG.read().await.cluster_enabled {
+            init_cluster(local_addr.port()).await;
+        }
+
+        let (shutdown, shutdown_requested) = oneshot::channel();
+        let server = spawn_named("accept", run_server(listener, shutdown_requested));
+
+        let systemd = CONFIG.read().await.supervised.is_systemd();
+        if systemd {
+            let status = format!("READY=1\nSTATUS=Ready to accept connections on {}", local_addr);
+            if let Err(e) = notify(&status) {
+                server_log!(Warning, "Failed to notify systemd. {:?}", e);
+            }
+        }
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown,
+            server,
+            background,
+            systemd,
+        })
+    }
+}
+
+/// A running server. Dropping the handle leaves it running.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<tokio::io::Result<()>>,
+    background: Vec<JoinHandle<()>>,
+    /// Whether state changes are reported to systemd
+    systemd: bool,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections, closes the open ones and stops background work
+    pub async fn shutdown(self) -> Result<(), anyhow::Error> {
+        if self.systemd {
+            let _ = notify("STOPPING=1");
+        }
+        let _ = self.shutdown.send(());
+        let result = self.server.await;
+        for task in self.background {
+            task.abort();
+        }
+        result??;
+
+        let config = CONFIG.read().await.clone();
+        if !config.save_rules.is_empty() && changes_since_last_save() > 0 {
+            server_log!(Notice, "Saving the final RDB snapshot before exiting");
+            db_save(rdb_path(&config)).await?;
+            server_log!(Notice, "DB saved on disk");
+        }
+        Ok(())
+    }
+
+    /// Runs until the listener fails
+    pub async fn wait(&mut self) -> Result<(), anyhow::Error> {
+        (&mut self.server).await??;
+        Ok(())
+    }
+}
+
+async fn has_log_files() -> bool {
+    let config = CONFIG.read().await;
+    config.logfile.is_some() || config.audit_log_file.is_some()
+}
+
+async fn has_database_file() -> bool {
+    let config = CONFIG.read().await;
+    config.dir.is_some() && config.db_filename.is_some()
+}
+
+async fn load_database() -> Result<(), anyhow::Error> {
+    let config = CONFIG.read().await;
+    if config.dir.is_none() || config.db_filename.is_none() {
+        return Ok(());
+    }
+
+    let path = Path::new(config.dir.as_ref().unwrap());
+    let path = path.join(config.db_filename.as_ref().unwrap());
+    db_load(path).await?;
+
+    Ok(())
+}
+
+async fn run_replication() {
+    let (host, port, announce_ip, announce_port) = {
+        let config = CONFIG.read().await;
+        let Some(replica_of) = config.replica_of.as_ref() else {
+            return;
+        };
+        (
+            replica_of.host.clone(),
+            replica_of.port,
+            config.replica_announce_ip.clone(),
+            config.replica_announce_port.unwrap_or(config.port),
+        )
+    };
+
+    if let Err(e) = run_replica_link(host, port, announce_ip, announce_port).await {
+        server_log!(Warning, "Replication link to master failed. {:?}", e);
+    }
+}
+
+async fn run_server(listener: TcpListener, mut shutdown: oneshot::Receiver<()>) -> tokio::io::Result<()> {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, addr) = accepted?;
+                server_log!(Verbose, "Accepted connection from {}", addr);
+                // Replies are already batched per pipeline, so don't let Nagle hold them back
+                stream.set_nodelay(true)?;
+
+                spawn_named_in(&mut connections, &format!("client {}", addr), async move {
+                    let mut client = RedisClientConnection::new(stream);
+                    match client.process().await {
+                        Ok(_) => {
+                            server_log!(Verbose, "Client disconnected without error");
+                        }
+                        Err(e) => {
+                            server_log!(Verbose, "Encountered error while processing client. {:?}", e);
+                        }
+                    }
+                });
+            }
+
+            // Reap finished connections so the set doesn't grow with every client ever served
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+
+            // Only an explicit shutdown stops the server, not dropping the handle
+            Ok(()) = &mut shutdown => {
+                connections.shutdown().await;
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use bytes::Bytes;
+use crate::client::ResponseType;
+use crate::cluster::ClusterFlags;
+
+/// The user connections are authenticated as until they AUTH as someone else
+pub const DEFAULT_USER: &str = "default";
+
+/// Whether replies are sent back, set through CLIENT REPLY
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ReplyMode {
+    #[default]
+    On,
+    Off,
+    /// Drops the reply to the next command only
+    Skip,
+}
+
+/// State that lives as long as one client connection and changes what its commands do
+pub struct ClientSession {
+    /// Same as the id of the connection's entry in the client registry
+    pub id: u64,
+    /// Set through CLIENT SETNAME
+    pub name: Option<String>,
+    pub user: String,
+    /// The client library, as reported through CLIENT SETINFO
+    pub lib_name: Option<String>,
+    pub lib_ver: Option<String>,
+    /// 2 until the client negotiates RESP3 through HELLO
+    pub resp_version: u8,
+    pub selected_db: usize,
+    pub channels: HashSet<Bytes>,
+    pub patterns: HashSet<Bytes>,
+    /// Commands queued since MULTI
+    pub transaction: Option<Vec<Vec<ResponseType>>>,
+    /// Keys watched since WATCH, along with the db each is in
+    pub watched_keys: Vec<(usize, Bytes)>,
+    pub reply_mode: ReplyMode,
+    pub cluster_flags: ClusterFlags,
+}
+
+impl ClientSession {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            name: None,
+            user: DEFAULT_USER.to_string(),
+            lib_name: None,
+            lib_ver: None,
+            resp_version: 2,
+            selected_db: 0,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            transaction: None,
+            watched_keys: Vec::new(),
+            reply_mode: ReplyMode::default(),
+            cluster_flags: ClusterFlags::default(),
+        }
+    }
+
+    /// Whether the connection is in the subscribed state, where only pub/sub commands are allowed
+    pub fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+}
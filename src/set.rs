@@ -0,0 +1,86 @@
+use bytes::Bytes;
+use crate::dict::scan_by_hash;
+use crate::persistence::{is_integer_set, is_small_set, SetMembers};
+
+/// A set's members along with how many bytes they hold, kept up to date as members come and go
+#[derive(Clone, Default, Debug)]
+pub struct Set {
+    members: SetMembers,
+    size: usize,
+}
+
+impl From<SetMembers> for Set {
+    fn from(members: SetMembers) -> Self {
+        let size = members.iter().map(|member| member.len()).sum();
+        Set { members, size }
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut set = Set::default();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+impl Set {
+    pub fn members(&self) -> &SetMembers {
+        &self.members
+    }
+
+    pub fn into_members(self) -> SetMembers {
+        self.members
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Bytes held by the members, not counting the table's own bookkeeping
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        self.members.contains(member)
+    }
+
+    /// Returns whether the member is new
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        let length = member.len();
+        let added = self.members.insert(member);
+        if added {
+            self.size += length;
+        }
+        added
+    }
+
+    /// Returns whether the member was there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(member) = self.members.take(member) else {
+            return false;
+        };
+        self.size -= member.len();
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bytes> {
+        self.members.iter()
+    }
+}
+
+/// Carries on an SSCAN from `cursor`, see `scan_by_hash`. Sets small enough for Redis to keep as
+/// an intset or a listpack are returned whole in one go, like Redis does.
+pub fn scan_members(members: &SetMembers, cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+    if is_integer_set(members) || is_small_set(members) {
+        return (0, members.iter().collect());
+    }
+    scan_by_hash(members.hasher(), members.iter().map(|member| (member, member)), cursor, count)
+}
@@ -0,0 +1,94 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use crate::database::{new_databases, Databases};
+use crate::util::hash_tag;
+
+type Job = Box<dyn FnOnce(&mut Databases) + Send>;
+
+/// Set when the keyspace is partitioned across shard threads, otherwise every connection works
+/// on the shared cache under its lock
+static SHARD_POOL: OnceCell<ShardPool> = OnceCell::new();
+
+/// A keyspace split across threads that each run their own single threaded event loop and own
+/// their part of the data outright. Connections never touch shard data directly, they send the
+/// operation to the owning shard and wait for its answer, so the common single key commands
+/// never contend on a lock.
+pub struct ShardPool {
+    shards: Vec<UnboundedSender<Job>>,
+    hasher: RandomState,
+}
+
+/// Starts `count` keyspace shards. With fewer than two the shared, locked cache is used instead.
+pub fn start_keyspace_shards(count: usize) -> Result<(), anyhow::Error> {
+    if count <= 1 {
+        return Ok(());
+    }
+
+    let mut shards = Vec::with_capacity(count);
+    for i in 0..count {
+        let (sender, jobs) = unbounded_channel();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        std::thread::Builder::new()
+            .name(format!("shard-{}", i))
+            .spawn(move || runtime.block_on(run_shard(jobs)))?;
+        shards.push(sender);
+    }
+
+    let _ = SHARD_POOL.set(ShardPool {
+        shards,
+        hasher: RandomState::new(),
+    });
+
+    Ok(())
+}
+
+pub fn shard_pool() -> Option<&'static ShardPool> {
+    SHARD_POOL.get()
+}
+
+async fn run_shard(mut jobs: UnboundedReceiver<Job>) {
+    let mut databases = new_databases();
+    while let Some(job) = jobs.recv().await {
+        job(&mut databases);
+    }
+}
+
+impl ShardPool {
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// The shard that owns `key`. Only the `{hash tag}` part of a key is considered when it has
+    /// one, so related keys can be kept together for multi key commands.
+    // hash_one needs Rust 1.71
+    #[allow(clippy::manual_hash_one)]
+    pub fn shard_for(&self, key: &[u8]) -> usize {
+        let mut hasher = self.hasher.build_hasher();
+        hash_tag(key).hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    /// Runs `f` on the thread of `shard` against the data it owns
+    pub(crate) async fn run<R, F>(&self, shard: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Databases) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |databases| {
+            let _ = reply.send(f(databases));
+        });
+
+        if self.shards[shard].send(job).is_err() {
+            panic!("Keyspace shard {} has stopped", shard);
+        }
+        result.await.unwrap_or_else(|_| panic!("Keyspace shard {} dropped a request", shard))
+    }
+}
@@ -0,0 +1,321 @@
+use std::ops::Range;
+use bytes::Bytes;
+use crate::util::random_below;
+
+/// Levels a node can have, enough for 4^32 members
+const MAX_LEVEL: usize = 32;
+
+/// One in this many nodes on a level makes it onto the next one up
+const LEVEL_FANOUT: usize = 4;
+
+/// The header node, which holds no member and starts every level
+const HEAD: usize = 0;
+
+/// A node's link on one level: the next node on that level and how many members it skips ahead
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    forward: Option<usize>,
+    span: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    /// The previous node on the bottom level, None for the first member
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    fn new(member: Bytes, score: f64, level: usize) -> Self {
+        Node {
+            member,
+            score,
+            backward: None,
+            levels: vec![Level { forward: None, span: 0 }; level],
+        }
+    }
+
+    /// Whether this node comes before `(score, member)`, by score and then by member
+    fn precedes(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && self.member.as_ref() < member)
+    }
+}
+
+/// The ordered index of a sorted set, a skiplist like Redis's: members ordered by score and then
+/// by member, where each link also counts the members it skips. That makes finding a member's
+/// rank, or the member at a rank, as quick as finding a member, rather than a walk from the
+/// start. Nodes live in one vector and link to each other by position, freed slots get reused.
+#[derive(Clone, Debug)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    len: usize,
+    /// Levels in use, at least 1
+    level: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        SkipList {
+            nodes: vec![Node::new(Bytes::new(), 0.0, MAX_LEVEL)],
+            free: Vec::new(),
+            tail: None,
+            len: 0,
+            level: 1,
+        }
+    }
+}
+
+impl SkipList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        self.nodes[node].levels[level].span
+    }
+
+    fn random_level() -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && random_below(LEVEL_FANOUT) == 0 {
+            level += 1;
+        }
+        level
+    }
+
+    /// The last node before `(score, member)` on each level, along with its rank (counting the
+    /// header as 0), which is where inserting or removing it changes links
+    fn predecessors(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut node = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(node, i).filter(|next| self.nodes[*next].precedes(score, member)) {
+                rank[i] += self.span(node, i);
+                node = next;
+            }
+            update[i] = node;
+        }
+        (update, rank)
+    }
+
+    /// Adds a member, which must not be in the list already
+    pub fn insert(&mut self, member: Bytes, score: f64) {
+        let (mut update, mut rank) = self.predecessors(score, &member);
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node::new(member, score, level);
+        let node = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let previous = update[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[node].levels[i] = Level {
+                forward: self.forward(previous, i),
+                span: self.span(previous, i) - skipped,
+            };
+            self.nodes[previous].levels[i] = Level {
+                forward: Some(node),
+                span: skipped + 1,
+            };
+        }
+        // Links above the new node's levels now skip it as well
+        for (i, previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*previous].levels[i].span += 1;
+        }
+
+        self.nodes[node].backward = if update[0] == HEAD { None } else { Some(update[0]) };
+        match self.forward(node, 0) {
+            Some(next) => self.nodes[next].backward = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    /// Returns whether the member was there with that score
+    pub fn remove(&mut self, member: &[u8], score: f64) -> bool {
+        let (update, _) = self.predecessors(score, member);
+        let Some(node) = self.forward(update[0], 0) else {
+            return false;
+        };
+        if self.nodes[node].score != score || self.nodes[node].member.as_ref() != member {
+            return false;
+        }
+
+        for (i, previous) in update.iter().enumerate().take(self.level) {
+            if self.forward(*previous, i) == Some(node) {
+                self.nodes[*previous].levels[i] = Level {
+                    forward: self.forward(node, i),
+                    span: self.span(*previous, i) + self.span(node, i) - 1,
+                };
+            } else {
+                self.nodes[*previous].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[node].backward;
+        match self.forward(node, 0) {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+
+        // Leave the slot holding nothing until it's reused
+        self.nodes[node] = Node::new(Bytes::new(), 0.0, 0);
+        self.free.push(node);
+        self.len -= 1;
+        true
+    }
+
+    /// The 0 based rank of a member with the given score, if it's in the list
+    pub fn rank(&self, member: &[u8], score: f64) -> Option<usize> {
+        let mut rank = 0;
+        let mut node = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(node, i).filter(|next| {
+                let next = &self.nodes[*next];
+                next.precedes(score, member) || (next.score == score && next.member.as_ref() == member)
+            }) {
+                rank += self.span(node, i);
+                node = next;
+            }
+            if node != HEAD && self.nodes[node].score == score && self.nodes[node].member.as_ref() == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// How many members in a row from the first one `before` holds for. It has to hold for a
+    /// stretch of members from the first on and for none after, such as for all those below a
+    /// score.
+    pub fn count_while(&self, before: impl Fn(f64, &Bytes) -> bool) -> usize {
+        let mut rank = 0;
+        let mut node = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(node, i).filter(|next| before(self.nodes[*next].score, &self.nodes[*next].member)) {
+                rank += self.span(node, i);
+                node = next;
+            }
+        }
+        rank
+    }
+
+    /// The node at a 0 based rank
+    fn select(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut node = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(node, i).filter(|_| traversed + self.span(node, i) <= target) {
+                traversed += self.span(node, i);
+                node = next;
+            }
+            if traversed == target {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// The member and score at a 0 based rank
+    pub fn get(&self, rank: usize) -> Option<(&Bytes, f64)> {
+        self.select(rank).map(|node| (&self.nodes[node].member, self.nodes[node].score))
+    }
+
+    /// The members ranked `ranks.start` up to, but not including, `ranks.end`, lowest first
+    pub fn range(&self, ranks: Range<usize>) -> Iter<'_> {
+        let end = ranks.end.min(self.len);
+        if ranks.start >= end {
+            return Iter { list: self, front: None, back: None, remaining: 0 };
+        }
+        Iter {
+            list: self,
+            front: self.select(ranks.start),
+            back: self.select(end - 1),
+            remaining: end - ranks.start,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: self.forward(HEAD, 0),
+            back: self.tail,
+            remaining: self.len,
+        }
+    }
+}
+
+/// Walks a stretch of the skiplist from either end
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front?];
+        self.front = node.levels[0].forward;
+        self.remaining -= 1;
+        Some((&node.member, node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back?];
+        self.back = node.backward;
+        self.remaining -= 1;
+        Some((&node.member, node.score))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use crate::clock;
+use crate::cluster::key_hash_slot;
+use crate::dict::{Dict, KeySet, SHARDS};
+use crate::memory::entry_usage;
+use crate::persistence::DataType;
+
+/// The engine used unless the storage-engine parameter names another
+pub const DEFAULT_STORAGE_ENGINE: &str = "memory";
+
+/// Creates an empty database for an engine
+pub type StorageFactory = fn() -> Box<dyn Storage>;
+
+/// Engines that can be selected through storage-engine, by name
+static ENGINES: Lazy<RwLock<HashMap<String, StorageFactory>>> = Lazy::new(|| {
+    let mut engines = HashMap::new();
+    engines.insert(DEFAULT_STORAGE_ENGINE.to_string(), MemoryStorage::create as StorageFactory);
+    RwLock::new(engines)
+});
+
+/// Factory for the databases created from now on
+static SELECTED: Lazy<RwLock<StorageFactory>> = Lazy::new(|| RwLock::new(MemoryStorage::create));
+
+/// Set in cluster mode, where databases keep track of the keys in each hash slot
+static SLOT_INDEX: AtomicBool = AtomicBool::new(false);
+
+/// The LRU clock wraps around after this many seconds, like Redis' 24 bit one
+pub const LRU_CLOCK_MAX: u32 = (1 << 24) - 1;
+
+/// Seconds on the server clock, modulo `LRU_CLOCK_MAX`. Entries record when they were last
+/// accessed in it.
+pub fn lru_clock() -> u32 {
+    let seconds = clock::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    (seconds & LRU_CLOCK_MAX as u64) as u32
+}
+
+/// The LRU clock reading of the last time an entry was accessed. Updated through a shared
+/// reference, so reads can record an access without taking the keyspace's write lock.
+#[derive(Debug)]
+pub struct AccessTime(AtomicU32);
+
+impl AccessTime {
+    pub fn now() -> Self {
+        AccessTime(AtomicU32::new(lru_clock()))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn touch(&self) {
+        self.0.store(lru_clock(), Ordering::Relaxed);
+    }
+
+    /// Seconds since the last access, allowing for the clock having wrapped around once
+    pub fn idle_seconds(&self) -> u32 {
+        let (now, last) = (lru_clock(), self.get());
+        if now >= last {
+            now - last
+        } else {
+            LRU_CLOCK_MAX - last + now
+        }
+    }
+}
+
+impl Clone for AccessTime {
+    fn clone(&self) -> Self {
+        AccessTime(AtomicU32::new(self.get()))
+    }
+}
+
+/// A value along with when it expires
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub value: DataType,
+    pub expiration: Option<SystemTime>,
+    pub last_access: AccessTime,
+}
+
+impl CacheEntry {
+    /// An entry accessed just now
+    pub fn new(value: DataType, expiration: Option<SystemTime>) -> Self {
+        CacheEntry {
+            value,
+            expiration,
+            last_access: AccessTime::now(),
+        }
+    }
+
+    /// Whether the time to live ran out, or the value is a hash whose every field's did
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match &self.value {
+            _ if matches!(self.expiration, Some(expiration) if expiration < now) => true,
+            DataType::Hash(hash) => hash.is_expired(now),
+            _ => false,
+        }
+    }
+
+    /// Whether the value is a hash with fields that expired before `now`
+    pub fn has_expired_fields(&self, now: SystemTime) -> bool {
+        matches!(&self.value, DataType::Hash(hash) if hash.has_expired_fields(now))
+    }
+
+    /// Removes the hash fields that expired before `now`, leaving other values alone
+    pub fn remove_expired_fields(&mut self, now: SystemTime) {
+        if let DataType::Hash(hash) = &mut self.value {
+            hash.remove_expired(now);
+        }
+    }
+}
+
+/// Where the keys of one database are kept. The command layer only ever reaches the data
+/// through these operations, so an engine can keep it anywhere, e.g. on disk for datasets that
+/// don't fit in memory. Expired entries are still handed out, it's up to the caller to check.
+pub trait Storage: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<CacheEntry>;
+
+    /// Hands the entry under `key` to `visit` where it's kept, for reads that only need part of
+    /// a large value. Engines that can't lend out their entries visit a copy.
+    fn visit(&self, key: &[u8], visit: &mut dyn FnMut(&CacheEntry)) {
+        if let Some(entry) = self.get(key) {
+            visit(&entry);
+        }
+    }
+
+    /// Stores `entry` under `key`, replacing whatever was there
+    fn set(&mut self, key: Bytes, entry: CacheEntry);
+
+    /// Hands the entry under `key` to `update` to change where it's kept, for writes that only
+    /// touch part of a large value. Engines that can't lend out their entries store a changed
+    /// copy.
+    fn update(&mut self, key: &[u8], update: &mut dyn FnMut(&mut CacheEntry)) {
+        if let Some(mut entry) = self.get(key) {
+            update(&mut entry);
+            self.set(Bytes::copy_from_slice(key), entry);
+        }
+    }
+
+    /// Records an access to `key` if it holds an entry that hasn't expired by `now`. Returns
+    /// whether it did.
+    fn touch(&self, key: &[u8], now: SystemTime) -> bool;
+
+    /// Removes `key`, returning the entry it held
+    fn delete(&mut self, key: &[u8]) -> Option<CacheEntry>;
+
+    fn len(&self) -> usize;
+
+    /// Approximate bytes the entries take up, see `memory::entry_usage`
+    fn used_memory(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self);
+
+    /// A key picked uniformly at random along with when it expires, expired ones included
+    fn random_key(&self) -> Option<(Bytes, Option<SystemTime>)>;
+
+    /// Visits part of the entries, starting at `cursor`. Returns the cursor to continue from,
+    /// or 0 once every entry has been visited. A scan starts at 0.
+    fn scan(&self, cursor: usize, visit: &mut dyn FnMut(&[u8], &CacheEntry)) -> usize;
+
+    /// Visits every entry
+    fn for_each(&self, visit: &mut dyn FnMut(&[u8], &CacheEntry)) {
+        let mut cursor = 0;
+        loop {
+            cursor = self.scan(cursor, visit);
+            if cursor == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Removes entries that expired before `now`, looking at around `limit` of them and passing
+    /// the key of each one removed to `expired`. Hash fields that expired are removed from the
+    /// hashes that remain. Called by the active expire cycle, which expects
+    /// each call to carry on where the last one stopped so the whole keyspace is covered over
+    /// time. Returns how many entries were looked at and how many were removed.
+    fn expire(&mut self, now: SystemTime, limit: usize, expired: &mut dyn FnMut(&[u8])) -> (usize, usize);
+
+    /// How many keys are in a cluster hash slot, expired ones that are still stored included
+    fn count_keys_in_slot(&self, slot: u16) -> usize;
+
+    /// Up to `count` of the keys in a cluster hash slot
+    fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes>;
+}
+
+/// Makes `factory` available as the storage engine called `name`
+pub fn register_storage_engine(name: impl Into<String>, factory: StorageFactory) {
+    ENGINES.write().unwrap().insert(name.into().to_lowercase(), factory);
+}
+
+pub fn storage_engine_exists(name: &str) -> bool {
+    ENGINES.read().unwrap().contains_key(&name.to_lowercase())
+}
+
+/// Creates databases with the engine called `name` from now on. Has to happen before the
+/// keyspace is first used, the existing databases aren't moved over.
+pub fn select_storage_engine(name: &str) -> Result<(), String> {
+    let Some(factory) = ENGINES.read().unwrap().get(&name.to_lowercase()).copied() else {
+        return Err(format!("unknown storage engine '{}'", name));
+    };
+    *SELECTED.write().unwrap() = factory;
+    Ok(())
+}
+
+/// Has databases created from now on index their keys by cluster hash slot, so the keys of a
+/// slot can be found without going through the whole keyspace
+pub fn index_slots(enabled: bool) {
+    SLOT_INDEX.store(enabled, Ordering::Relaxed);
+}
+
+pub fn slots_indexed() -> bool {
+    SLOT_INDEX.load(Ordering::Relaxed)
+}
+
+/// An empty database of the selected engine
+pub fn new_storage() -> Box<dyn Storage> {
+    (SELECTED.read().unwrap())()
+}
+
+/// Keeps every key in memory, in a `Dict`
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: Dict<CacheEntry>,
+    /// Table the next expire call starts at
+    expire_cursor: usize,
+    /// The keys in each hash slot that has any, when slots are indexed
+    slots: Option<HashMap<u16, KeySet<Bytes>>>,
+    /// Sum of the usage of every entry, kept up to date as they come and go
+    used_memory: usize,
+}
+
+impl MemoryStorage {
+    fn create() -> Box<dyn Storage> {
+        Box::new(MemoryStorage {
+            slots: slots_indexed().then(HashMap::new),
+            ..Default::default()
+        })
+    }
+}
+
+fn unindex_key(slots: &mut Option<HashMap<u16, KeySet<Bytes>>>, key: &[u8]) {
+    let Some(slots) = slots.as_mut() else {
+        return;
+    };
+    let slot = key_hash_slot(key);
+    if let Some(keys) = slots.get_mut(&slot) {
+        keys.remove(key);
+        if keys.is_empty() {
+            slots.remove(&slot);
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &[u8]) -> Option<CacheEntry> {
+        self.entries.get(key).cloned()
+    }
+
+    fn visit(&self, key: &[u8], visit: &mut dyn FnMut(&CacheEntry)) {
+        if let Some(entry) = self.entries.get(key) {
+            visit(entry);
+        }
+    }
+
+    fn set(&mut self, key: Bytes, entry: CacheEntry) {
+        let key = match self.entries.get(&key) {
+            Some(previous) => {
+                self.used_memory -= entry_usage(&key, previous, 0);
+                key
+            }
+            // A key from a command is a slice of the connection's read buffer, a new one is
+            // copied so it doesn't keep the whole buffer alive
+            None => {
+                let key = Bytes::copy_from_slice(&key);
+                if let Some(slots) = self.slots.as_mut() {
+                    slots.entry(key_hash_slot(&key)).or_default().insert(key.clone());
+                }
+                key
+            }
+        };
+        self.used_memory += entry_usage(&key, &entry, 0);
+        self.entries.insert(key, entry);
+    }
+
+    fn update(&mut self, key: &[u8], update: &mut dyn FnMut(&mut CacheEntry)) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        self.used_memory -= entry_usage(key, entry, 0);
+        update(entry);
+        self.used_memory += entry_usage(key, entry, 0);
+    }
+
+    fn touch(&self, key: &[u8], now: SystemTime) -> bool {
+        let Some(entry) = self.entries.get(key).filter(|entry| !entry.is_expired(now)) else {
+            return false;
+        };
+        entry.last_access.touch();
+        true
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        let removed = self.entries.remove(key)?;
+        unindex_key(&mut self.slots, key);
+        self.used_memory -= entry_usage(key, &removed, 0);
+        Some(removed)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.used_memory = 0;
+        if let Some(slots) = self.slots.as_mut() {
+            slots.clear();
+        }
+    }
+
+    fn random_key(&self) -> Option<(Bytes, Option<SystemTime>)> {
+        self.entries.random_entry().map(|(key, entry)| (key.clone(), entry.expiration))
+    }
+
+    /// The cursor is the index of the next table to visit. One past the last table, which only
+    /// a client making cursors up would send, wraps around rather than overflowing.
+    fn scan(&self, cursor: usize, visit: &mut dyn FnMut(&[u8], &CacheEntry)) -> usize {
+        let shard = cursor % SHARDS;
+        for (key, entry) in self.entries.shard_iter(shard) {
+            visit(key, entry);
+        }
+        (shard + 1) % SHARDS
+    }
+
+    fn expire(&mut self, now: SystemTime, limit: usize, expired: &mut dyn FnMut(&[u8])) -> (usize, usize) {
+        let (mut examined, mut removed) = (0, 0);
+        for _ in 0..SHARDS {
+            let slots = &mut self.slots;
+            let used_memory = &mut self.used_memory;
+            let (looked_at, removed_here) = self.entries.retain_shard(self.expire_cursor, |key, entry| {
+                let is_expired = entry.is_expired(now);
+                if is_expired {
+                    unindex_key(slots, key);
+                    *used_memory -= entry_usage(key, entry, 0);
+                    expired(key);
+                } else if entry.has_expired_fields(now) {
+                    *used_memory -= entry_usage(key, entry, 0);
+                    entry.remove_expired_fields(now);
+                    *used_memory += entry_usage(key, entry, 0);
+                }
+                !is_expired
+            });
+            self.expire_cursor = (self.expire_cursor + 1) % SHARDS;
+            examined += looked_at;
+            removed += removed_here;
+            if examined >= limit {
+                break;
+            }
+        }
+        (examined, removed)
+    }
+
+    fn count_keys_in_slot(&self, slot: u16) -> usize {
+        match self.slots.as_ref() {
+            Some(slots) => slots.get(&slot).map_or(0, |keys| keys.len()),
+            None => self.entries.keys().filter(|key| key_hash_slot(key) == slot).count(),
+        }
+    }
+
+    fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes> {
+        match self.slots.as_ref() {
+            Some(slots) => slots.get(&slot).map_or_else(Vec::new, |keys| keys.iter().take(count).cloned().collect()),
+            None => self.entries.keys().filter(|key| key_hash_slot(key) == slot).take(count).cloned().collect(),
+        }
+    }
+}
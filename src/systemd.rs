@@ -0,0 +1,122 @@
+use std::io;
+use std::net::TcpListener;
+use crate::server_log;
+
+/// First file descriptor systemd passes for socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// How the server reports its state to a supervisor, matching the supervised config parameter
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Supervised {
+    No,
+    Systemd,
+    /// systemd when started by it, detected through NOTIFY_SOCKET
+    Auto,
+}
+
+impl Supervised {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "no" => Ok(Supervised::No),
+            "systemd" => Ok(Supervised::Systemd),
+            "auto" => Ok(Supervised::Auto),
+            _ => Err(format!("expected no, systemd or auto, got '{}'", value)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Supervised::No => "no",
+            Supervised::Systemd => "systemd",
+            Supervised::Auto => "auto",
+        }
+    }
+
+    /// Whether state updates should be sent to systemd
+    pub fn is_systemd(self) -> bool {
+        let has_socket = std::env::var_os("NOTIFY_SOCKET").is_some();
+        match self {
+            Supervised::No => false,
+            Supervised::Systemd => {
+                if !has_socket {
+                    server_log!(Warning, "systemd supervision requested, but NOTIFY_SOCKET is not set");
+                }
+                has_socket
+            }
+            Supervised::Auto => has_socket,
+        }
+    }
+}
+
+/// Sends `state`, such as `READY=1`, to the systemd notification socket
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let path = path.to_string_lossy().to_string();
+
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets need Linux")),
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Takes the listening socket systemd passed for socket activation, if it passed one to this
+/// process. Like sd_listen_fds, the environment is cleared so children don't inherit it.
+#[cfg(unix)]
+pub fn listen_fds() -> io::Result<Option<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if !for_us || count < 1 {
+        return Ok(None);
+    }
+    if count > 1 {
+        server_log!(Warning, "systemd passed {} sockets, only the first is used", count);
+    }
+
+    // Safety: systemd hands the descriptors from SD_LISTEN_FDS_START on to this process, and
+    // nothing else in it has claimed them.
+    let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Sends the spans recorded around commands, persistence and replication to the OTLP collector
+/// listening at `endpoint`, over gRPC. Has to be called from within the tokio runtime.
+#[cfg(feature = "otel")]
+pub fn init_otlp(endpoint: &str) -> Result<(), anyhow::Error> {
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, Resource};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]))
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    opentelemetry::global::set_tracer_provider(provider);
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init_otlp(_endpoint: &str) -> Result<(), anyhow::Error> {
+    anyhow::bail!("OTLP export needs the server to be built with the otel feature")
+}
+
+/// Flushes spans that haven't been exported yet
+pub fn shutdown_telemetry() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Serves the runtime's tasks to tokio-console, on the port in TOKIO_CONSOLE_BIND or 6669. Has
+/// to be called from within the tokio runtime.
+#[cfg(feature = "console")]
+pub fn init_console() -> Result<(), anyhow::Error> {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    tracing_subscriber::registry()
+        .with(console_subscriber::ConsoleLayer::builder().with_default_env().spawn())
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Spawns a long lived task, which tokio-console lists under `name`
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "console")]
+    return tokio::task::Builder::new().name(name).spawn(future).expect("failed to spawn task");
+
+    #[cfg(not(feature = "console"))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Like `spawn_named`, for a task that belongs to `set`
+pub fn spawn_named_in<F>(set: &mut JoinSet<F::Output>, name: &str, future: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "console")]
+    set.build_task().name(name).spawn(future).expect("failed to spawn task");
+
+    #[cfg(not(feature = "console"))]
+    {
+        let _ = name;
+        set.spawn(future);
+    }
+}
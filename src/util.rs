@@ -1,5 +1,8 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
-use std::time::SystemTime;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use time::macros::format_description;
 
 #[allow(unused)]
@@ -18,4 +21,297 @@ pub fn pretty_print_system_time_into(output: &mut impl io::Write, t: SystemTime)
             ),
         )
         .unwrap();
-}
\ No newline at end of file
+}
+
+/// Every RandomState is seeded differently, which is plenty for ids and sampling.
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+pub fn random_hex_string(length: usize) -> String {
+    let mut string = String::with_capacity(length + 16);
+    while string.len() < length {
+        string.push_str(&format!("{:016x}", random_u64()));
+    }
+    string.truncate(length);
+    string
+}
+
+/// A number picked uniformly from `0..bound`, which must not be 0
+pub fn random_below(bound: usize) -> usize {
+    (random_u64() % bound as u64) as usize
+}
+
+/// Picks `count` distinct items out of those `items` yields, each subset equally
+/// likely, or all of them if there aren't more than `count`. Goes over the items once, keeping
+/// only the picks (reservoir sampling).
+pub fn sample_distinct<T>(items: impl Iterator<Item = T>, count: usize) -> Vec<T> {
+    let mut picked = Vec::with_capacity(count.min(1024));
+    for (seen, item) in items.enumerate() {
+        if seen < count {
+            picked.push(item);
+        } else {
+            let slot = random_below(seen + 1);
+            if slot < count {
+                picked[slot] = item;
+            }
+        }
+    }
+    shuffle(&mut picked);
+    picked
+}
+
+/// Picks `count` items out of the `len` that `items` yields, each one independently so the same
+/// item can come up more than once. The picks are collected in a single pass over the items.
+pub fn sample_with_repetition<T: Clone>(items: impl Iterator<Item = T>, len: usize, count: usize) -> Vec<T> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let mut indices: Vec<usize> = (0..count).map(|_| random_below(len)).collect();
+    indices.sort_unstable();
+
+    let mut picked = Vec::with_capacity(count);
+    let mut wanted = indices.iter().peekable();
+    for (index, item) in items.enumerate() {
+        while wanted.next_if(|&&wanted| wanted == index).is_some() {
+            picked.push(item.clone());
+        }
+        if wanted.peek().is_none() {
+            break;
+        }
+    }
+    shuffle(&mut picked);
+    picked
+}
+
+/// Puts `items` in a random order
+pub fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, random_below(i + 1));
+    }
+}
+
+/// The part of `key` inside the first `{...}`, or the whole key if it has no non-empty tag. Keys
+/// sharing a tag are kept together, on one shard or in one cluster slot.
+pub fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(start) = key.iter().position(|b| *b == b'{') {
+        if let Some(length) = key[start + 1..].iter().position(|b| *b == b'}') {
+            if length > 0 {
+                return &key[start + 1..start + 1 + length];
+            }
+        }
+    }
+
+    key
+}
+
+/// Splits a line on whitespace, keeping quoted strings together. Double quoted strings may use
+/// `\n`, `\r` and `\t` escapes. Returns None if a quote is left open.
+pub fn split_arguments(line: &str) -> Option<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.peek().copied() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut argument = String::new();
+        if c == '"' || c == '\'' {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    q if q == c => break,
+                    '\\' if c == '"' => argument.push(match chars.next()? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        escaped => escaped,
+                    }),
+                    other => argument.push(other),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek().copied().filter(|c| !c.is_whitespace()) {
+                argument.push(c);
+                chars.next();
+            }
+        }
+        arguments.push(argument);
+    }
+    Some(arguments)
+}
+
+/// Milliseconds since the UNIX epoch, negative for times before it
+pub fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// The time `millis` milliseconds after the UNIX epoch. Expirations before it are all in the
+/// past anyway, so negative values are clamped to the epoch.
+pub fn from_unix_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+/// Parses a string as an i64 only if that's exactly how the number would be written, so no sign
+/// other than a leading minus, no leading zeros and no whitespace. This is what Redis accepts as
+/// an integer value.
+pub fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > 20 {
+        return None;
+    }
+    let value = std::str::from_utf8(bytes).ok()?.parse::<i64>().ok()?;
+    (value.to_string().as_bytes() == bytes).then_some(value)
+}
+
+/// Resolves a possibly negative index, where -1 is the last element, against a sequence of
+/// `length` elements. None if it falls outside the sequence.
+pub fn normalize_index(index: i64, length: usize) -> Option<usize> {
+    let length = i64::try_from(length).ok()?;
+    let index = if index < 0 { index.checked_add(length)? } else { index };
+    (0..length).contains(&index).then_some(index as usize)
+}
+
+/// Turns the inclusive `start` and `end` of LRANGE, GETRANGE, ZRANGE, LTRIM and BITCOUNT into
+/// the range of a sequence of `length` elements they select. Negative indexes count from the
+/// end, out of range ones are clamped to the sequence, and an empty range comes back when the
+/// two don't overlap it.
+pub fn normalize_range(start: i64, end: i64, length: usize) -> Range<usize> {
+    let Ok(signed_length) = i64::try_from(length) else {
+        return 0..0;
+    };
+    let resolve = |index: i64| if index < 0 { index.saturating_add(signed_length) } else { index };
+    let start = resolve(start).max(0);
+    let end = resolve(end).min(signed_length - 1);
+    if start > end {
+        return 0..0;
+    }
+    start as usize..end as usize + 1
+}
+
+/// Formats a double the way Redis replies with scores and floats: the shortest digits that read
+/// back as the same value, in %.17g layout, so exponents below -4 or from 17 up are written as
+/// e.g. `1.5e-05` or `1e+17`. Infinities are `inf` and `-inf`.
+pub fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value == 0.0 {
+        return format!("{}0", sign);
+    }
+
+    // {:e} gives the shortest round tripping digits, e.g. 1.2345e-5
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+    let digits = mantissa.replace('.', "");
+
+    if !(-4..17).contains(&exponent) {
+        let fraction = &digits[1..];
+        format!(
+            "{}{}{}{}e{}{:02}",
+            sign,
+            &digits[..1],
+            if fraction.is_empty() { "" } else { "." },
+            fraction,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else if exponent < 0 {
+        format!("{}0.{}{}", sign, "0".repeat((-exponent - 1) as usize), digits)
+    } else {
+        let integer_digits = exponent as usize + 1;
+        if digits.len() > integer_digits {
+            format!("{}{}.{}", sign, &digits[..integer_digits], &digits[integer_digits..])
+        } else {
+            format!("{}{}{}", sign, digits, "0".repeat(integer_digits - digits.len()))
+        }
+    }
+}
+
+/// Whether `string` matches the glob `pattern` the way KEYS, SCAN MATCH and PSUBSCRIBE read it:
+/// `*` matches any run of bytes, `?` any single byte, `[abc]`, `[a-z]` and `[^abc]` one byte in
+/// or out of a class, and `\` makes the byte after it literal.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| if nocase { a.eq_ignore_ascii_case(&b) } else { a == b };
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..string.len()).any(|start| glob_match(&pattern[p + 1..], &string[start..], nocase));
+            }
+            b'?' => s += 1,
+            b'[' => {
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+                let byte = string[s];
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        // An unterminated class runs to the end of the pattern
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(b']') => break,
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            matched |= pattern[p] == byte;
+                        }
+                        Some(&start) if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                            let end = pattern[p + 2];
+                            let (low, high) = if start <= end { (start, end) } else { (end, start) };
+                            matched |= if nocase {
+                                (low.to_ascii_lowercase()..=high.to_ascii_lowercase()).contains(&byte.to_ascii_lowercase())
+                            } else {
+                                (low..=high).contains(&byte)
+                            };
+                            p += 2;
+                        }
+                        Some(&other) => matched |= eq(other, byte),
+                    }
+                    p += 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            other => {
+                if !eq(other, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    while pattern.get(p) == Some(&b'*') {
+        p += 1;
+    }
+    p == pattern.len() && s == string.len()
+}
@@ -0,0 +1,236 @@
+use std::cmp::Ordering;
+use bytes::Bytes;
+use crate::dict::KeyMap;
+use crate::skiplist::SkipList;
+
+/// Parses a score the way Redis does, infinities included. NaN isn't a score.
+pub fn parse_score(bytes: &[u8]) -> Option<f64> {
+    std::str::from_utf8(bytes).ok()?.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// One end of a BYSCORE range: a score, exclusive when written with a leading `(`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        match bytes.strip_prefix(b"(") {
+            Some(score) => Some(ScoreBound { score: parse_score(score)?, exclusive: true }),
+            None => Some(ScoreBound { score: parse_score(bytes)?, exclusive: false }),
+        }
+    }
+}
+
+/// One end of a BYLEX range: `-` and `+` for the lowest and highest member there could be,
+/// otherwise a member behind `[` when inclusive or `(` when exclusive
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"-" => Some(LexBound::Min),
+            b"+" => Some(LexBound::Max),
+            [b'[', member @ ..] => Some(LexBound::Inclusive(Bytes::copy_from_slice(member))),
+            [b'(', member @ ..] => Some(LexBound::Exclusive(Bytes::copy_from_slice(member))),
+            _ => None,
+        }
+    }
+}
+
+/// The end of a sorted set ZPOPMIN, ZPOPMAX and ZMPOP take members from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScoreEnd {
+    Min,
+    Max,
+}
+
+impl ScoreEnd {
+    pub fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("min") {
+            Some(ScoreEnd::Min)
+        } else if name.eq_ignore_ascii_case("max") {
+            Some(ScoreEnd::Max)
+        } else {
+            None
+        }
+    }
+}
+
+/// The NX, XX, GT, LT and INCR options of ZADD
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AddOptions {
+    /// Only members that aren't in the set yet
+    pub nx: bool,
+    /// Only members that are in the set already
+    pub xx: bool,
+    /// Only updates that raise the score
+    pub gt: bool,
+    /// Only updates that lower the score
+    pub lt: bool,
+    /// The score is added to the current one rather than replacing it
+    pub incr: bool,
+}
+
+/// What ZADD did with one member, along with its score afterwards
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddOutcome {
+    Added(f64),
+    Updated(f64),
+    /// Already in the set with that score
+    Unchanged(f64),
+    /// The options ruled the change out
+    Skipped,
+}
+
+/// Incrementing a score gave NaN, e.g. adding -inf to inf
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ScoreIsNan;
+
+/// A sorted set: every member's score for lookups, and the members ordered by score, ties broken
+/// by comparing the members, for walking them in order and finding ranks. Members are reference
+/// counted so both can hold them without copying.
+#[derive(Clone, Default, Debug)]
+pub struct SortedSet {
+    scores: KeyMap<Bytes, f64>,
+    index: SkipList,
+    /// Bytes held by the members
+    size: usize,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Bytes held by the members, not counting the score table and the index
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets a member's score, returning whether the member is new
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        match previous {
+            Some(previous) => {
+                self.index.remove(&member, previous);
+            }
+            None => self.size += member.len(),
+        }
+        self.index.insert(member, score);
+        previous.is_none()
+    }
+
+    /// Returns whether the member was there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some((member, score)) = self.scores.remove_entry(member) else {
+            return false;
+        };
+        self.index.remove(&member, score);
+        self.size -= member.len();
+        true
+    }
+
+    /// ZADD for a single member
+    pub fn add(&mut self, member: Bytes, score: f64, options: AddOptions) -> Result<AddOutcome, ScoreIsNan> {
+        let Some(current) = self.score(&member) else {
+            if options.xx {
+                return Ok(AddOutcome::Skipped);
+            }
+            self.insert(member, score);
+            return Ok(AddOutcome::Added(score));
+        };
+
+        if options.nx {
+            return Ok(AddOutcome::Skipped);
+        }
+        let score = if options.incr { current + score } else { score };
+        if score.is_nan() {
+            return Err(ScoreIsNan);
+        }
+        if (options.gt && score <= current) || (options.lt && score >= current) {
+            return Ok(AddOutcome::Skipped);
+        }
+        if score == current {
+            return Ok(AddOutcome::Unchanged(score));
+        }
+        self.insert(member, score);
+        Ok(AddOutcome::Updated(score))
+    }
+
+    /// Removes the member with the lowest or the highest score, returning it and its score
+    pub fn pop(&mut self, end: ScoreEnd) -> Option<(Bytes, f64)> {
+        let (member, score) = match end {
+            ScoreEnd::Min => self.index.iter().next(),
+            ScoreEnd::Max => self.index.iter().next_back(),
+        }
+        .map(|(member, score)| (member.clone(), score))?;
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// The members and their scores, lowest score first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> + ExactSizeIterator {
+        self.index.iter()
+    }
+
+    /// A member's 0 based position when ordered by score, lowest first
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        self.index.rank(member, self.score(member)?)
+    }
+
+    /// The members ranked `ranks.start` up to, but not including, `ranks.end`, lowest score first
+    pub fn range_by_rank(&self, ranks: std::ops::Range<usize>) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.index.range(ranks)
+    }
+
+    /// The members with a score between `min` and `max`, lowest score first
+    pub fn range_by_score(&self, min: ScoreBound, max: ScoreBound) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        let start = self.index.count_while(|score, _| score < min.score || (min.exclusive && score == min.score));
+        let end = self.index.count_while(|score, _| score < max.score || (!max.exclusive && score == max.score));
+        self.index.range(start..end)
+    }
+
+    /// The members between `min` and `max` when compared byte by byte, in that order. Like in
+    /// Redis this only makes sense when every member has the same score, the one of the first
+    /// member is assumed.
+    pub fn range_by_lex(&self, min: &LexBound, max: &LexBound) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        let first = self.index.get(0).map_or(0.0, |(_, score)| score);
+        // How many members come before `bound`, or up to it as well when `inclusive`
+        let before = |bound: &Bytes, inclusive: bool| {
+            self.index.count_while(|score, member| {
+                match score.partial_cmp(&first).unwrap_or(Ordering::Equal).then_with(|| member.cmp(bound)) {
+                    Ordering::Less => true,
+                    Ordering::Equal => inclusive,
+                    Ordering::Greater => false,
+                }
+            })
+        };
+        // The range starts after the members below the minimum and ends after those up to the
+        // maximum, the member a bound names itself counting as below an exclusive minimum but
+        // not an inclusive one, and the other way around for the maximum
+        let position = |bound: &LexBound, is_max: bool| match bound {
+            LexBound::Min => 0,
+            LexBound::Max => self.len(),
+            LexBound::Inclusive(member) => before(member, is_max),
+            LexBound::Exclusive(member) => before(member, !is_max),
+        };
+        self.index.range(position(min, false)..position(max, true))
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::server::Server;
+
+async fn request(stream: &mut TcpStream, request: &[u8]) -> String {
+    stream.write_all(request).await.unwrap();
+    let mut reply = vec![0; 64];
+    let read = stream.read(&mut reply).await.unwrap();
+    String::from_utf8_lossy(&reply[..read]).to_string()
+}
+
+#[tokio::test]
+async fn keys_expire_when_the_clock_passes_their_ttl() {
+    let clock = Arc::new(ManualClock::new());
+    let server = Server::builder().port(0).clock(clock.clone()).spawn().await.unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+
+    let set = b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n$2\r\nPX\r\n$4\r\n1000\r\n";
+    let get = b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n";
+    assert_eq!(request(&mut stream, set).await, "+OK\r\n");
+
+    clock.advance(Duration::from_millis(999));
+    assert_eq!(request(&mut stream, get).await, "$3\r\nbar\r\n");
+
+    clock.advance(Duration::from_millis(2));
+    assert_eq!(request(&mut stream, get).await, "$-1\r\n");
+
+    server.shutdown().await.unwrap();
+}
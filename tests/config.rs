@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+use redis_starter_rust::config::ConfigFile;
+
+/// Reads `contents` as a config file, returning the value of the `name` directive
+fn directive(file_name: &str, contents: &str, name: &str) -> Option<String> {
+    let path: PathBuf = std::env::temp_dir().join(format!("{}-{}.conf", file_name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    let file = ConfigFile::read(&path);
+    std::fs::remove_file(&path).unwrap();
+    file.unwrap().directives().into_iter().find(|(directive, _)| directive == name).map(|(_, value)| value)
+}
+
+#[test]
+fn repeated_save_lines_add_up() {
+    let contents = "save 3600 1\nport 6380\nSAVE 300 100\nsave 60 10000\n";
+    assert_eq!(directive("repeated-save", contents, "save").as_deref(), Some("3600 1 300 100 60 10000"));
+}
+
+#[test]
+fn an_empty_save_line_starts_over() {
+    assert_eq!(directive("empty-save", "save 3600 1\nsave \"\"\n", "save").as_deref(), Some(""));
+    assert_eq!(directive("save-after-empty", "save \"\"\nsave 60 10000\n", "save").as_deref(), Some("60 10000"));
+}
+
+#[test]
+fn other_directives_keep_their_last_value() {
+    assert_eq!(directive("repeated-port", "port 6380\nport 6381\n", "port").as_deref(), Some("6381"));
+}
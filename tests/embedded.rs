@@ -0,0 +1,42 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use redis_starter_rust::server::Server;
+
+async fn request(stream: &mut TcpStream, request: &[u8]) -> String {
+    stream.write_all(request).await.unwrap();
+    let mut reply = vec![0; 64];
+    let read = stream.read(&mut reply).await.unwrap();
+    String::from_utf8_lossy(&reply[..read]).to_string()
+}
+
+#[tokio::test]
+async fn embedded_server_serves_until_shut_down() {
+    let server = Server::builder().port(0).spawn().await.unwrap();
+    let addr = server.local_addr();
+    assert_ne!(addr.port(), 0);
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(request(&mut stream, b"*1\r\n$4\r\nPING\r\n").await, "+PONG\r\n");
+    assert_eq!(request(&mut stream, b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n").await, "+OK\r\n");
+    assert_eq!(request(&mut stream, b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n").await, "$3\r\nbar\r\n");
+
+    server.shutdown().await.unwrap();
+
+    // Open connections are closed and no new ones are accepted
+    let mut buffer = [0; 16];
+    assert_eq!(stream.read(&mut buffer).await.unwrap_or(0), 0);
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn binary_keys_are_kept_apart() {
+    let server = Server::builder().port(0).spawn().await.unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+
+    assert_eq!(request(&mut stream, b"*3\r\n$3\r\nSET\r\n$1\r\n\xff\r\n$1\r\na\r\n").await, "+OK\r\n");
+    assert_eq!(request(&mut stream, b"*3\r\n$3\r\nSET\r\n$1\r\n\xfe\r\n$1\r\nb\r\n").await, "+OK\r\n");
+    assert_eq!(request(&mut stream, b"*2\r\n$3\r\nGET\r\n$1\r\n\xff\r\n").await, "$1\r\na\r\n");
+    assert_eq!(request(&mut stream, b"*2\r\n$3\r\nGET\r\n$1\r\n\xfe\r\n").await, "$1\r\nb\r\n");
+
+    server.shutdown().await.unwrap();
+}
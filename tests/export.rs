@@ -0,0 +1,43 @@
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+use redis_starter_rust::client::{RedisClientConnection, ResponseType};
+use redis_starter_rust::clock::ManualClock;
+use redis_starter_rust::server::Server;
+
+async fn run(connection: &mut RedisClientConnection, command: &[&[u8]]) -> ResponseType {
+    connection.send_command(command).await.unwrap();
+    connection.read().await.unwrap()
+}
+
+#[tokio::test]
+async fn export_is_sorted_and_stable() {
+    let clock = Arc::new(ManualClock::starting_at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)));
+    let server = Server::builder().port(0).clock(clock).spawn().await.unwrap();
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    let mut connection = RedisClientConnection::new(stream);
+
+    run(&mut connection, &[b"SET", b"zebra", b"last"]).await;
+    run(&mut connection, &[b"SET", b"apple", b"say \"hi\"\n", b"PX", b"5000"]).await;
+    run(&mut connection, &[b"SET", b"binary", &[0x66, 0xff, 0x6f]]).await;
+    run(&mut connection, &[b"SELECT", b"1"]).await;
+    run(&mut connection, &[b"SET", b"other", b"db"]).await;
+
+    let expected = concat!(
+        r#"{"db":0,"keys":["#,
+        r#"{"key":"apple","type":"string","value":"say \"hi\"\n","expires_at":1700000005000},"#,
+        r#"{"key":"binary","type":"string","value":"f\u00ffo","expires_at":null},"#,
+        r#"{"key":"zebra","type":"string","value":"last","expires_at":null}"#,
+        r#"]}"#
+    );
+    let export = run(&mut connection, &[b"DEBUG", b"EXPORT", b"0"]).await;
+    assert_eq!(export, ResponseType::BulkString(Bytes::copy_from_slice(expected.as_bytes())));
+
+    // Without a db it exports the selected one
+    let export = run(&mut connection, &[b"DEBUG", b"EXPORT"]).await;
+    let expected = r#"{"db":1,"keys":[{"key":"other","type":"string","value":"db","expires_at":null}]}"#;
+    assert_eq!(export, ResponseType::BulkString(Bytes::copy_from_slice(expected.as_bytes())));
+
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,48 @@
+use redis_starter_rust::util::format_double;
+
+#[test]
+fn formats_like_redis() {
+    let cases: &[(f64, &str)] = &[
+        (0.0, "0"),
+        (-0.0, "-0"),
+        (1.0, "1"),
+        (-3.0, "-3"),
+        (1.5, "1.5"),
+        (0.1, "0.1"),
+        (0.1 + 0.2, "0.30000000000000004"),
+        (10.5, "10.5"),
+        (100.0, "100"),
+        (2.71, "2.71"),
+        (-2.5e-3, "-0.0025"),
+        (0.0001, "0.0001"),
+        (0.00001, "1e-05"),
+        (1.5e-10, "1.5e-10"),
+        (123456789.123, "123456789.123"),
+        (9007199254740993.0, "9007199254740992"),
+        (1e16, "10000000000000000"),
+        (1e17, "1e+17"),
+        (1.25e17, "1.25e+17"),
+        (-1e300, "-1e+300"),
+        (f64::MAX, "1.7976931348623157e+308"),
+        (f64::MIN_POSITIVE, "2.2250738585072014e-308"),
+        (5e-324, "5e-324"),
+    ];
+
+    for (value, expected) in cases {
+        assert_eq!(format_double(*value), *expected, "formatting {:?}", value);
+    }
+}
+
+#[test]
+fn formats_infinities_and_nan() {
+    assert_eq!(format_double(f64::INFINITY), "inf");
+    assert_eq!(format_double(f64::NEG_INFINITY), "-inf");
+    assert_eq!(format_double(f64::NAN), "nan");
+}
+
+#[test]
+fn formatted_values_read_back_unchanged() {
+    for value in [0.1, 1.0 / 3.0, 2.0 / 3.0, 1e-7, 6.02214076e23, -987654.321, 1e21, 123e-20] {
+        assert_eq!(format_double(value).parse::<f64>().unwrap(), value);
+    }
+}
@@ -0,0 +1,65 @@
+use redis_starter_rust::util::glob_match;
+
+fn matches(pattern: &str, string: &str) -> bool {
+    glob_match(pattern.as_bytes(), string.as_bytes(), false)
+}
+
+#[test]
+fn literal_patterns_match_only_themselves() {
+    assert!(matches("session", "session"));
+    assert!(!matches("session", "sessions"));
+    assert!(!matches("session", "sessio"));
+    assert!(matches("", ""));
+    assert!(!matches("", "a"));
+}
+
+#[test]
+fn star_matches_any_run() {
+    assert!(matches("*", ""));
+    assert!(matches("*", "anything"));
+    assert!(matches("session:*", "session:"));
+    assert!(matches("session:*", "session:42"));
+    assert!(!matches("session:*", "user:42"));
+    assert!(matches("*:42", "session:42"));
+    assert!(matches("a*b*c", "aXXbYYc"));
+    assert!(matches("a**c", "abc"));
+    assert!(!matches("a*b*c", "aXXbYY"));
+}
+
+#[test]
+fn question_mark_matches_one_byte() {
+    assert!(matches("h?llo", "hello"));
+    assert!(matches("h?llo", "hallo"));
+    assert!(!matches("h?llo", "hllo"));
+    assert!(!matches("h?llo", "heello"));
+}
+
+#[test]
+fn classes_match_listed_bytes_and_ranges() {
+    assert!(matches("h[ae]llo", "hello"));
+    assert!(matches("h[ae]llo", "hallo"));
+    assert!(!matches("h[ae]llo", "hillo"));
+    assert!(matches("h[^e]llo", "hallo"));
+    assert!(!matches("h[^e]llo", "hello"));
+    assert!(matches("h[a-b]llo", "hbllo"));
+    assert!(!matches("h[a-b]llo", "hcllo"));
+    // Reversed ranges work too
+    assert!(matches("h[b-a]llo", "hallo"));
+    assert!(matches("key[0-9][0-9]", "key42"));
+}
+
+#[test]
+fn backslash_escapes_special_bytes() {
+    assert!(matches("a\\*b", "a*b"));
+    assert!(!matches("a\\*b", "aXb"));
+    assert!(matches("a\\?", "a?"));
+    assert!(matches("[\\]]", "]"));
+    assert!(matches("a\\[b", "a[b"));
+}
+
+#[test]
+fn nocase_ignores_ascii_case() {
+    assert!(glob_match(b"SESSION:*", b"session:1", true));
+    assert!(glob_match(b"[A-C]x", b"bX", true));
+    assert!(!glob_match(b"SESSION:*", b"session:1", false));
+}
@@ -0,0 +1,19 @@
+use bytes::Bytes;
+use redis_starter_rust::dict::{Dict, SHARDS};
+
+const KEYS: usize = 1 << 18;
+
+#[test]
+fn growing_a_dict_only_rehashes_one_small_table() {
+    let mut dict = Dict::new();
+    for i in 0..KEYS {
+        dict.insert(Bytes::from(format!("key:{}", i)), i);
+    }
+    assert_eq!(dict.len(), KEYS);
+
+    // An insert that grows a table moves every entry of that table and nothing else, so the
+    // biggest table bounds the entries a single insert can move. A single HashMap would move all
+    // of them when it last grows.
+    let largest = (0..SHARDS).map(|shard| dict.shard_iter(shard).count()).max().unwrap();
+    assert!(largest < 2 * KEYS / SHARDS, "largest table holds {} of {} keys", largest, KEYS);
+}
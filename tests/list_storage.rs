@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use bytes::Bytes;
+use redis_starter_rust::database::{db_list_insert, db_list_remove, db_push, db_read};
+use redis_starter_rust::list::InsertPosition;
+use redis_starter_rust::persistence::DataType;
+use redis_starter_rust::quicklist::ListEnd;
+use redis_starter_rust::storage::{register_storage_engine, select_storage_engine, CacheEntry, MemoryStorage, Storage};
+
+/// Entries copied out of the keyspace, which changing part of a list shouldn't need
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+/// The in memory engine, counting the entries it hands out as copies
+struct CountingStorage(MemoryStorage);
+
+impl Storage for CountingStorage {
+    fn get(&self, key: &[u8]) -> Option<CacheEntry> {
+        COPIES.fetch_add(1, Ordering::Relaxed);
+        self.0.get(key)
+    }
+
+    fn visit(&self, key: &[u8], visit: &mut dyn FnMut(&CacheEntry)) {
+        self.0.visit(key, visit)
+    }
+
+    fn set(&mut self, key: Bytes, entry: CacheEntry) {
+        self.0.set(key, entry)
+    }
+
+    fn update(&mut self, key: &[u8], update: &mut dyn FnMut(&mut CacheEntry)) {
+        self.0.update(key, update)
+    }
+
+    fn touch(&self, key: &[u8], now: SystemTime) -> bool {
+        self.0.touch(key, now)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        self.0.delete(key)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn used_memory(&self) -> usize {
+        self.0.used_memory()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    fn random_key(&self) -> Option<(Bytes, Option<SystemTime>)> {
+        self.0.random_key()
+    }
+
+    fn scan(&self, cursor: usize, visit: &mut dyn FnMut(&[u8], &CacheEntry)) -> usize {
+        self.0.scan(cursor, visit)
+    }
+
+    fn expire(&mut self, now: SystemTime, limit: usize, expired: &mut dyn FnMut(&[u8])) -> (usize, usize) {
+        self.0.expire(now, limit, expired)
+    }
+
+    fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.0.count_keys_in_slot(slot)
+    }
+
+    fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes> {
+        self.0.keys_in_slot(slot, count)
+    }
+}
+
+async fn node_sizes(key: &[u8]) -> Vec<(usize, usize)> {
+    db_read(0, key, |value| match value {
+        DataType::List(list) => list.node_sizes().collect(),
+        _ => Vec::new(),
+    }).await.unwrap_or_default()
+}
+
+/// Nodes of `after` that differ from `before`, lining up the unchanged ones at either end
+fn changed_nodes(before: &[(usize, usize)], after: &[(usize, usize)]) -> usize {
+    let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+    let suffix = before[prefix..].iter().rev().zip(after[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    after.len() - prefix - suffix
+}
+
+#[tokio::test]
+async fn changing_the_middle_of_a_list_only_touches_one_node() {
+    register_storage_engine("counting", || Box::new(CountingStorage(MemoryStorage::default())));
+    select_storage_engine("counting").unwrap();
+
+    let values = (0..20_000).map(|i| Bytes::from(format!("element:{}", i))).collect();
+    db_push(0, Bytes::from_static(b"list"), values, ListEnd::Right, true).await.unwrap();
+    let before = node_sizes(b"list").await;
+    assert!(before.len() > 10);
+
+    COPIES.store(0, Ordering::Relaxed);
+    let pivot = Bytes::from_static(b"element:10000");
+    let length = db_list_insert(0, Bytes::from_static(b"list"), InsertPosition::Before, pivot, Bytes::from_static(b"inserted")).await.unwrap();
+    assert_eq!(length, Some(20_001));
+    assert_eq!(COPIES.load(Ordering::Relaxed), 0);
+    // A node that was full splits in two
+    let after = node_sizes(b"list").await;
+    assert!(changed_nodes(&before, &after) <= 2);
+
+    let removed = db_list_remove(0, Bytes::from_static(b"list"), 0, Bytes::from_static(b"inserted")).await.unwrap();
+    assert_eq!(removed, 1);
+    assert_eq!(COPIES.load(Ordering::Relaxed), 0);
+    assert!(changed_nodes(&after, &node_sizes(b"list").await) <= 2);
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+use bytes::Bytes;
+use redis_starter_rust::quicklist::QuickList;
+use redis_starter_rust::util::random_below;
+
+fn value() -> Bytes {
+    // Mostly short entries drawn from a small pool so LREM finds repeats, with the odd large one
+    // so the byte limits get crossed too
+    if random_below(10) == 0 {
+        Bytes::from(vec![b'x'; 1000 + random_below(4000)])
+    } else {
+        Bytes::from(format!("v{}", random_below(20)))
+    }
+}
+
+fn assert_matches(list: &QuickList, model: &VecDeque<Bytes>, fill: i64) {
+    assert_eq!(list.len(), model.len(), "fill {fill}");
+    assert!(list.iter().eq(model.iter()), "fill {fill}");
+    assert!(list.iter().rev().eq(model.iter().rev()), "fill {fill}");
+    assert_eq!(list.size(), model.iter().map(|value| value.len()).sum::<usize>(), "fill {fill}");
+    assert!(list.node_count() <= model.len());
+    if fill > 0 {
+        // No node holds more than `fill` entries
+        assert!(list.node_count() * fill as usize >= model.len(), "fill {fill}");
+    }
+}
+
+#[test]
+fn quicklist_matches_a_vecdeque_model() {
+    for fill in [1, 2, 5, 128, -1, -2, -5] {
+        let mut list = QuickList::new(fill);
+        let mut model = VecDeque::new();
+        for round in 0..3000 {
+            match random_below(9) {
+                0 => {
+                    let value = value();
+                    list.push_back(value.clone());
+                    model.push_back(value);
+                }
+                1 => {
+                    let value = value();
+                    list.push_front(value.clone());
+                    model.push_front(value);
+                }
+                2 | 3 => {
+                    let index = random_below(model.len() + 1);
+                    let value = value();
+                    list.insert(index, value.clone());
+                    model.insert(index, value);
+                }
+                4 => {
+                    let index = random_below(model.len() + 1);
+                    assert_eq!(list.remove(index), model.remove(index));
+                }
+                5 => {
+                    assert_eq!(list.pop_front(), model.pop_front());
+                    assert_eq!(list.pop_back(), model.pop_back());
+                }
+                6 => {
+                    let index = random_below(model.len() + 1);
+                    let value = value();
+                    assert_eq!(list.set(index, value.clone()), index < model.len());
+                    if let Some(entry) = model.get_mut(index) {
+                        *entry = value;
+                    }
+                    assert_eq!(list.get(index), model.get(index));
+                }
+                7 if round % 50 == 0 => {
+                    // Trims rarely, otherwise the list never grows past a few entries
+                    let start = random_below(model.len() / 4 + 1);
+                    let end = model.len() - random_below(model.len() / 4 + 1);
+                    list.retain_range(start, end);
+                    model = model.into_iter().take(end).skip(start).collect();
+                }
+                _ => {
+                    let Some(target) = model.get(random_below(model.len() + 1)).cloned() else {
+                        continue;
+                    };
+                    let count = random_below(5) as i64 - 2;
+                    let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+                    let matching = (0..model.len()).filter(|&i| model[i] == target);
+                    let doomed: Vec<usize> = if count < 0 {
+                        matching.rev().take(limit).collect()
+                    } else {
+                        matching.take(limit).collect()
+                    };
+                    assert_eq!(list.remove_matching(&target, count), doomed.len());
+                    let mut index = 0;
+                    model.retain(|_| {
+                        index += 1;
+                        !doomed.contains(&(index - 1))
+                    });
+                }
+            }
+            assert_matches(&list, &model, fill);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use redis_starter_rust::util::{normalize_index, normalize_range};
+
+#[test]
+fn positive_ranges_are_inclusive() {
+    assert_eq!(normalize_range(0, 0, 5), 0..1);
+    assert_eq!(normalize_range(1, 3, 5), 1..4);
+    assert_eq!(normalize_range(0, 4, 5), 0..5);
+}
+
+#[test]
+fn negative_indexes_count_from_the_end() {
+    assert_eq!(normalize_range(0, -1, 5), 0..5);
+    assert_eq!(normalize_range(-2, -1, 5), 3..5);
+    assert_eq!(normalize_range(-5, -5, 5), 0..1);
+    assert_eq!(normalize_range(1, -2, 5), 1..4);
+}
+
+#[test]
+fn out_of_range_indexes_are_clamped() {
+    assert_eq!(normalize_range(-100, 2, 5), 0..3);
+    assert_eq!(normalize_range(2, 100, 5), 2..5);
+    assert_eq!(normalize_range(-100, 100, 5), 0..5);
+    assert_eq!(normalize_range(i64::MIN, i64::MAX, 5), 0..5);
+}
+
+#[test]
+fn ranges_outside_the_sequence_are_empty() {
+    assert!(normalize_range(5, 10, 5).is_empty());
+    assert!(normalize_range(3, 1, 5).is_empty());
+    assert!(normalize_range(-1, -2, 5).is_empty());
+    assert!(normalize_range(0, -6, 5).is_empty());
+    assert!(normalize_range(-100, -6, 5).is_empty());
+    assert!(normalize_range(0, -1, 0).is_empty());
+    assert!(normalize_range(0, 0, 0).is_empty());
+}
+
+#[test]
+fn indexes_resolve_within_the_sequence() {
+    assert_eq!(normalize_index(0, 3), Some(0));
+    assert_eq!(normalize_index(2, 3), Some(2));
+    assert_eq!(normalize_index(-1, 3), Some(2));
+    assert_eq!(normalize_index(-3, 3), Some(0));
+    assert_eq!(normalize_index(3, 3), None);
+    assert_eq!(normalize_index(-4, 3), None);
+    assert_eq!(normalize_index(0, 0), None);
+    assert_eq!(normalize_index(i64::MIN, 3), None);
+}
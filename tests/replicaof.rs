@@ -0,0 +1,59 @@
+use std::process::Command;
+use redis_starter_rust::ReplicaOf;
+use redis_starter_rust::config::parse_replica_of;
+
+fn master(host: &str, port: u16) -> Option<ReplicaOf> {
+    Some(ReplicaOf { host: host.to_string(), port })
+}
+
+#[test]
+fn parses_a_host_and_port() {
+    assert_eq!(parse_replica_of("localhost 6379"), Ok(master("localhost", 6379)));
+    assert_eq!(parse_replica_of("  10.0.0.1   6380 "), Ok(master("10.0.0.1", 6380)));
+    assert_eq!(parse_replica_of("::1 65535"), Ok(master("::1", 65535)));
+}
+
+#[test]
+fn no_one_means_no_master() {
+    assert_eq!(parse_replica_of("no one"), Ok(None));
+    assert_eq!(parse_replica_of("NO ONE"), Ok(None));
+}
+
+#[test]
+fn rejects_malformed_values() {
+    for value in ["", "localhost", "localhost 6379 extra", "localhost port", "localhost 0", "localhost 65536", "localhost -1", "-h 6379"] {
+        assert!(parse_replica_of(value).is_err(), "accepted {:?}", value);
+    }
+}
+
+#[test]
+fn explains_what_is_wrong() {
+    assert_eq!(
+        parse_replica_of("localhost 70000"),
+        Err("invalid master port '70000', expected a number from 1 to 65535".to_string())
+    );
+    assert_eq!(
+        parse_replica_of("localhost"),
+        Err("expected a host and port, or 'no one', got 'localhost'".to_string())
+    );
+}
+
+/// Runs the server with `args`, which are all expected to be rejected before it starts
+fn rejected_arguments(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust")).args(args).output().unwrap();
+    assert!(!output.status.success(), "started with {:?}", args);
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn command_line_reports_malformed_masters_without_panicking() {
+    for args in [
+        &["--replicaof", "localhost"][..],
+        &["--replicaof", "localhost", "port"],
+        &["--replicaof", "localhost 99999"],
+    ] {
+        let stderr = rejected_arguments(args);
+        assert!(stderr.contains("--replicaof"), "{:?} printed {}", args, stderr);
+        assert!(!stderr.contains("panicked"), "{:?} printed {}", args, stderr);
+    }
+}
@@ -0,0 +1,88 @@
+use bytes::{BufMut, Bytes};
+use redis_starter_rust::client::{write_resp, RedisClientConnection, ResponseType};
+
+async fn serialize(value: &ResponseType) -> Vec<u8> {
+    let mut buffer = Vec::new().writer();
+    write_resp(&mut buffer, value).await.unwrap();
+    buffer.into_inner()
+}
+
+fn bulk(bytes: &[u8]) -> ResponseType {
+    ResponseType::BulkString(Bytes::copy_from_slice(bytes))
+}
+
+#[tokio::test]
+async fn serializes_every_reply_type() {
+    let cases: Vec<(ResponseType, &[u8])> = vec![
+        (ResponseType::SimpleString("OK".to_string()), b"+OK\r\n"),
+        (ResponseType::Error("ERR unknown command".to_string()), b"-ERR unknown command\r\n"),
+        (ResponseType::Integer(0), b":0\r\n"),
+        (ResponseType::Integer(-42), b":-42\r\n"),
+        (ResponseType::Integer(i64::MAX), b":9223372036854775807\r\n"),
+        (bulk(b"hello"), b"$5\r\nhello\r\n"),
+        (bulk(b""), b"$0\r\n\r\n"),
+        (ResponseType::NullBulkString, b"$-1\r\n"),
+        (ResponseType::NullArray, b"*-1\r\n"),
+        (ResponseType::Array(vec![]), b"*0\r\n"),
+        (
+            ResponseType::Array(vec![bulk(b"a"), ResponseType::Integer(1), ResponseType::NullBulkString]),
+            b"*3\r\n$1\r\na\r\n:1\r\n$-1\r\n",
+        ),
+    ];
+
+    for (value, expected) in cases {
+        assert_eq!(serialize(&value).await, expected, "serializing {:?}", value);
+    }
+}
+
+#[tokio::test]
+async fn serializes_nested_arrays() {
+    let value = ResponseType::Array(vec![
+        ResponseType::Array(vec![bulk(b"key"), ResponseType::Array(vec![])]),
+        ResponseType::NullArray,
+        ResponseType::SimpleString("PONG".to_string()),
+    ]);
+
+    assert_eq!(
+        serialize(&value).await,
+        b"*3\r\n*2\r\n$3\r\nkey\r\n*0\r\n*-1\r\n+PONG\r\n"
+    );
+}
+
+#[tokio::test]
+async fn bulk_strings_keep_binary_data_intact() {
+    let binary = [0u8, 0xff, 0xfe, b'\r', b'\n', 0x80];
+    let serialized = serialize(&bulk(&binary)).await;
+
+    let mut expected = b"$6\r\n".to_vec();
+    expected.extend_from_slice(&binary);
+    expected.extend_from_slice(b"\r\n");
+    assert_eq!(serialized, expected);
+}
+
+#[tokio::test]
+async fn line_breaks_cannot_end_simple_strings_early() {
+    let error = ResponseType::Error("ERR bad\r\nthing".to_string());
+    assert_eq!(serialize(&error).await, b"-ERR bad  thing\r\n");
+}
+
+#[tokio::test]
+async fn serialized_replies_parse_back() {
+    let value = ResponseType::Array(vec![
+        bulk(&[0, 1, 2, 0xff]),
+        ResponseType::Integer(-7),
+        ResponseType::Error("WRONGTYPE".to_string()),
+        ResponseType::Array(vec![ResponseType::NullBulkString, ResponseType::NullArray]),
+    ]);
+    let serialized = serialize(&value).await;
+
+    let parsed = RedisClientConnection::parse_resp(&serialized).unwrap().unwrap();
+    assert_eq!(parsed.consumed, serialized.len());
+    assert_eq!(parsed.request, value);
+}
+
+#[test]
+fn bulk_strings_longer_than_the_limit_are_refused_up_front() {
+    assert!(RedisClientConnection::frame_length(b"*1\r\n$536870912\r\n").unwrap().is_none());
+    assert!(RedisClientConnection::frame_length(b"*1\r\n$536870913\r\n").is_err());
+}
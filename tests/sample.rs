@@ -0,0 +1,38 @@
+use std::collections::HashSet;
+use redis_starter_rust::util::{sample_distinct, sample_with_repetition};
+
+#[test]
+fn distinct_samples_never_repeat() {
+    for _ in 0..100 {
+        let picked = sample_distinct(0..20, 5);
+        assert_eq!(picked.len(), 5);
+        assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 5);
+        assert!(picked.iter().all(|item| (0..20).contains(item)));
+    }
+}
+
+#[test]
+fn distinct_sample_larger_than_the_items_takes_them_all() {
+    let mut picked = sample_distinct(0..5, 10);
+    picked.sort();
+    assert_eq!(picked, vec![0, 1, 2, 3, 4]);
+    assert!(sample_distinct(0..0, 3).is_empty());
+}
+
+#[test]
+fn distinct_samples_reach_every_item() {
+    let mut seen = HashSet::new();
+    for _ in 0..500 {
+        seen.extend(sample_distinct(0..10, 2));
+    }
+    assert_eq!(seen.len(), 10);
+}
+
+#[test]
+fn samples_with_repetition_have_the_requested_size() {
+    let picked = sample_with_repetition(0..3, 3, 50);
+    assert_eq!(picked.len(), 50);
+    assert!(picked.iter().all(|item| (0..3).contains(item)));
+    assert_eq!(sample_with_repetition(0..1, 1, 4), vec![0, 0, 0, 0]);
+    assert!(sample_with_repetition(0..0, 0, 4).is_empty());
+}
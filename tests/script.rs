@@ -0,0 +1,40 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use redis_starter_rust::server::Server;
+
+async fn request(stream: &mut TcpStream, arguments: &[&str]) -> String {
+    let mut request = format!("*{}\r\n", arguments.len());
+    for argument in arguments {
+        request.push_str(&format!("${}\r\n{}\r\n", argument.len(), argument));
+    }
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut reply = vec![0; 256];
+    let read = stream.read(&mut reply).await.unwrap();
+    String::from_utf8_lossy(&reply[..read]).to_string()
+}
+
+#[tokio::test]
+async fn read_only_scripts_can_read_but_not_write() {
+    let server = Server::builder().port(0).spawn().await.unwrap();
+    let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+
+    assert_eq!(request(&mut stream, &["SET", "key", "value"]).await, "+OK\r\n");
+    assert_eq!(request(&mut stream, &["EVAL_RO", "return redis.call('GET', KEYS[1])", "1", "key"]).await, "$5\r\nvalue\r\n");
+    assert_eq!(
+        request(&mut stream, &["EVAL_RO", "return redis.call('SET', KEYS[1], 'changed')", "1", "key"]).await,
+        "-ERR Write commands are not allowed from read-only scripts.\r\n"
+    );
+
+    let library = "#!lua name=readonly\n\
+        redis.register_function{function_name='peek', callback=function(keys) return redis.call('GET', keys[1]) end, flags={'no-writes'}}\n\
+        redis.register_function('poke', function(keys, args) return redis.call('SET', keys[1], args[1]) end)";
+    assert_eq!(request(&mut stream, &["FUNCTION", "LOAD", library]).await, "$8\r\nreadonly\r\n");
+    assert_eq!(request(&mut stream, &["FCALL_RO", "peek", "1", "key"]).await, "$5\r\nvalue\r\n");
+    assert_eq!(
+        request(&mut stream, &["FCALL_RO", "poke", "1", "key", "changed"]).await,
+        "-ERR Can not execute a script with write flag using *_ro command.\r\n"
+    );
+    assert_eq!(request(&mut stream, &["GET", "key"]).await, "$5\r\nvalue\r\n");
+
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,86 @@
+use bytes::Bytes;
+use redis_starter_rust::skiplist::SkipList;
+use redis_starter_rust::util::random_below;
+use redis_starter_rust::zset::{LexBound, ScoreBound, SortedSet};
+
+fn sorted_set(members: &[(&str, f64)]) -> SortedSet {
+    let mut zset = SortedSet::default();
+    for (member, score) in members {
+        zset.insert(Bytes::copy_from_slice(member.as_bytes()), *score);
+    }
+    zset
+}
+
+fn names<'a>(members: impl Iterator<Item = (&'a Bytes, f64)>) -> Vec<String> {
+    members.map(|(member, _)| String::from_utf8_lossy(member).into_owned()).collect()
+}
+
+fn score(bound: &str) -> ScoreBound {
+    ScoreBound::parse(bound.as_bytes()).unwrap()
+}
+
+fn lex(bound: &str) -> LexBound {
+    LexBound::parse(bound.as_bytes()).unwrap()
+}
+
+#[test]
+fn score_bounds_parse_exclusive_and_infinite_ends() {
+    assert_eq!(score("1.5"), ScoreBound { score: 1.5, exclusive: false });
+    assert_eq!(score("(2"), ScoreBound { score: 2.0, exclusive: true });
+    assert_eq!(score("-inf").score, f64::NEG_INFINITY);
+    assert!(ScoreBound::parse(b"(").is_none());
+    assert!(ScoreBound::parse(b"nan").is_none());
+}
+
+#[test]
+fn score_ranges_include_every_tie_at_an_inclusive_end() {
+    let zset = sorted_set(&[("a", 1.0), ("b", 1.0), ("c", 2.0), ("d", 3.0), ("e", 3.0)]);
+    assert_eq!(names(zset.range_by_score(score("1"), score("3"))), ["a", "b", "c", "d", "e"]);
+    assert_eq!(names(zset.range_by_score(score("(1"), score("(3"))), ["c"]);
+    assert_eq!(names(zset.range_by_score(score("3"), score("+inf")).rev()), ["e", "d"]);
+    assert_eq!(names(zset.range_by_score(score("3"), score("1"))), Vec::<String>::new());
+    assert_eq!(names(zset.range_by_score(score("(2"), score("(2"))), Vec::<String>::new());
+}
+
+#[test]
+fn lex_ranges_compare_members_byte_by_byte() {
+    let zset = sorted_set(&[("a", 0.0), ("b", 0.0), ("c", 0.0), ("d", 0.0)]);
+    assert_eq!(names(zset.range_by_lex(&lex("-"), &lex("+"))), ["a", "b", "c", "d"]);
+    assert_eq!(names(zset.range_by_lex(&lex("(a"), &lex("[c"))), ["b", "c"]);
+    assert_eq!(names(zset.range_by_lex(&lex("[b"), &lex("(b"))), Vec::<String>::new());
+    assert_eq!(names(zset.range_by_lex(&lex("+"), &lex("-"))), Vec::<String>::new());
+    assert!(LexBound::parse(b"b").is_none());
+}
+
+#[test]
+fn skiplist_ranks_match_a_sorted_list() {
+    let mut list = SkipList::default();
+    let mut model: Vec<(f64, Bytes)> = Vec::new();
+    for round in 0..2000 {
+        let member = Bytes::from(format!("m{}", random_below(300)));
+        let score = random_below(50) as f64;
+        match model.iter().position(|(_, existing)| *existing == member) {
+            Some(index) if round % 3 != 0 => {
+                let (score, member) = model.remove(index);
+                assert!(list.remove(&member, score));
+            }
+            Some(_) => {}
+            None => {
+                list.insert(member.clone(), score);
+                model.push((score, member));
+                model.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+        }
+    }
+
+    assert_eq!(list.len(), model.len());
+    for (rank, (score, member)) in model.iter().enumerate() {
+        assert_eq!(list.rank(member, *score), Some(rank));
+        assert_eq!(list.get(rank), Some((member, *score)));
+    }
+    assert_eq!(list.rank(b"missing", 1.0), None);
+    let backwards: Vec<_> = list.iter().rev().map(|(member, score)| (score, member.clone())).collect();
+    assert!(backwards.iter().eq(model.iter().rev()));
+    let middle: Vec<_> = list.range(5..15).map(|(member, score)| (score, member.clone())).collect();
+    assert_eq!(middle, model[5..15]);
+}